@@ -88,6 +88,7 @@ impl Plugin for Exec {
         let interface = types::NetInterface {
             mac_address: mac_address,
             subnets: Option::from(subnets),
+            admin_state: None,
         };
         interfaces.insert(name, interface);
 
@@ -96,6 +97,13 @@ impl Plugin for Exec {
             dns_server_ips: None,
             dns_search_domains: None,
             interfaces: Some(interfaces),
+            port_proxies: None,
+            default_gateways: None,
+            policy_routes: None,
+            host_routes: None,
+            nat64_prefix: None,
+            interface_index: None,
+            bridge: None,
         };
 
         Ok(response)
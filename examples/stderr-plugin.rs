@@ -33,6 +33,13 @@ impl Plugin for Exec {
             dns_server_ips: None,
             dns_search_domains: None,
             interfaces: None,
+            port_proxies: None,
+            default_gateways: None,
+            policy_routes: None,
+            host_routes: None,
+            nat64_prefix: None,
+            interface_index: None,
+            bridge: None,
         };
 
         Ok(response)
@@ -0,0 +1,187 @@
+//! Removes what is left of containers whose network namespace is gone
+//! without a teardown
+use crate::dns::aardvark::Aardvark;
+use crate::error::{NetavarkError, NetavarkErrorList, NetavarkResult};
+use crate::firewall::{self, FirewallDriver};
+use crate::network::core_utils;
+use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::netlink;
+use crate::network::state::{self, ContainerState};
+use clap::Parser;
+use log::{debug, info};
+use serde::Serialize;
+use std::os::fd::RawFd;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct Gc {
+    /// Only report the orphaned containers without removing anything.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+/// Orphaned container found by the gc
+#[derive(Debug, Serialize)]
+struct GcEntry {
+    container_id: String,
+    container_name: String,
+    network_namespace_path: String,
+    networks: Vec<String>,
+    /// false with --dry-run or when the cleanup failed
+    removed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Gc {
+    /// Only containers recorded by the setup are considered, their resources are
+    /// removed when no bind mount, process or open file descriptor holds their
+    /// namespace anymore. It is safe to run while containers are running.
+    pub fn exec(
+        &self,
+        config_dir: &str,
+        aardvark_bin: String,
+        plugin_directories: Option<Vec<String>>,
+        rootless: bool,
+    ) -> NetavarkResult<()> {
+        let mut report = Vec::new();
+        let orphans: Vec<ContainerState> = state::list(config_dir)?
+            .into_iter()
+            .filter(|s| !state::namespace_exists(s))
+            .collect();
+
+        if !orphans.is_empty() && !self.dry_run {
+            let firewall_driver = firewall::get_supported_firewall_driver()?;
+            let dns_port = core_utils::get_netavark_dns_port()?;
+            // the namespace is gone, the drivers only touch the host
+            let (mut hostns, _) = core_utils::open_netlink_sockets("/proc/self/ns/net")?;
+            let cleanup = Cleanup {
+                firewall: firewall_driver.as_ref(),
+                hostns_fd: hostns.fd,
+                dns_port,
+                config_dir,
+                aardvark_bin: &aardvark_bin,
+                plugin_directories: &plugin_directories,
+                rootless,
+            };
+
+            for orphan in &orphans {
+                let options = &orphan.options;
+                info!(
+                    "removing orphaned container {} with namespace {}",
+                    options.container_id, orphan.netns_path
+                );
+                let result = cleanup.run(orphan, &mut hostns.netlink).and_then(|_| {
+                    let networks: Vec<String> = options.network_info.keys().cloned().collect();
+                    state::remove(config_dir, &options.container_id, &networks)
+                });
+                report.push(match result {
+                    Ok(_) => entry(orphan, true, None),
+                    Err(err) => entry(orphan, false, Some(err.to_string())),
+                });
+            }
+        } else {
+            debug!("found {} orphaned containers", orphans.len());
+            for orphan in &orphans {
+                report.push(entry(orphan, false, None));
+            }
+        }
+
+        let out = serde_json::to_string_pretty(&report)?;
+        println!("{}", out);
+
+        Ok(())
+    }
+}
+
+fn entry(orphan: &ContainerState, removed: bool, error: Option<String>) -> GcEntry {
+    let mut networks: Vec<String> = orphan.options.network_info.keys().cloned().collect();
+    networks.sort();
+    GcEntry {
+        container_id: orphan.options.container_id.clone(),
+        container_name: orphan.options.container_name.clone(),
+        network_namespace_path: orphan.netns_path.clone(),
+        networks,
+        removed,
+        error,
+    }
+}
+
+/// Settings shared by the cleanup of all orphaned containers
+struct Cleanup<'a> {
+    firewall: &'a dyn FirewallDriver,
+    hostns_fd: RawFd,
+    dns_port: u16,
+    config_dir: &'a str,
+    aardvark_bin: &'a str,
+    plugin_directories: &'a Option<Vec<String>>,
+    rootless: bool,
+}
+
+impl Cleanup<'_> {
+    /// Remove the dns entries and the host side of all networks of the container.
+    fn run(&self, orphan: &ContainerState, host: &mut netlink::Socket) -> NetavarkResult<()> {
+        let options = &orphan.options;
+        let mut error_list = NetavarkErrorList::new();
+
+        if Path::new(self.aardvark_bin).exists() {
+            let path = Path::new(self.config_dir).join("aardvark-dns");
+            match path.into_os_string().into_string() {
+                Ok(path_string) => {
+                    let aardvark_interface = Aardvark::new(
+                        path_string,
+                        self.rootless,
+                        self.aardvark_bin.to_string(),
+                        self.dns_port,
+                    );
+                    if let Err(err) = aardvark_interface.delete_from_netavark_entries(options) {
+                        error_list.push(err.into());
+                    }
+                }
+                Err(_) => error_list.push(NetavarkError::msg(
+                    "Unable to parse aardvark config directory",
+                )),
+            }
+        }
+
+        for (net_name, network) in options.network_info.iter() {
+            let per_network_opts = match options.networks.get(net_name) {
+                Some(opts) => opts,
+                None => {
+                    error_list.push(NetavarkError::Message(format!(
+                        "network options for network {} not found",
+                        net_name
+                    )));
+                    continue;
+                }
+            };
+            let result = get_network_driver(
+                DriverInfo {
+                    firewall: self.firewall,
+                    container_id: &options.container_id,
+                    container_name: &options.container_name,
+                    container_dns_servers: &options.dns_servers,
+                    netns_host: self.hostns_fd,
+                    netns_container: self.hostns_fd,
+                    netns_path: &orphan.netns_path,
+                    network,
+                    per_network_opts,
+                    port_mappings: &options.port_mappings,
+                    dns_port: self.dns_port,
+                    config_dir: self.config_dir,
+                    link_order: None,
+                },
+                self.plugin_directories,
+            )
+            .and_then(|driver| driver.cleanup(host));
+            if let Err(err) = result {
+                error_list.push(NetavarkError::wrap(format!("network {}", net_name), err));
+            }
+        }
+
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
+        Ok(())
+    }
+}
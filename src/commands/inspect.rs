@@ -0,0 +1,324 @@
+//! Reports the netavark footprint on the host without changing anything
+use crate::error::{ErrorWrap, NetavarkError, NetavarkResult};
+use crate::firewall::{self, iptables::MAX_HASH_SIZE, FirewallChain, FirewallPortForward};
+use crate::network::constants::{
+    BRIDGE_MODE_L2_ONLY, DRIVER_BRIDGE, FIREWALL_NETNS_CONTAINER, OPTION_FIREWALL_NETNS,
+    OPTION_MODE, OPTION_PORT_FORWARD_MODE, PORT_FORWARD_MODE_PROXY,
+};
+use crate::network::core_utils::CoreUtils;
+use crate::network::netlink;
+use crate::network::state::{self, ContainerState};
+use crate::network::types::{Network, PortMapping};
+use clap::Parser;
+use log::{debug, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+
+#[derive(Parser, Debug)]
+pub struct Inspect {}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    firewall_driver: String,
+    containers: Vec<ContainerEntry>,
+    bridges: Vec<BridgeEntry>,
+    /// the DNAT rules of the firewall, not set when the firewall driver cannot list them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port_mappings: Option<Vec<FirewallPortForward>>,
+    /// not set when the firewall driver cannot list its chains
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chains: Option<Vec<FirewallChain>>,
+    /// inconsistencies between the recorded state and the host
+    issues: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ContainerEntry {
+    container_id: String,
+    container_name: String,
+    network_namespace_path: String,
+    namespace_exists: bool,
+    networks: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BridgeEntry {
+    name: String,
+    exists: bool,
+    /// recorded as created by netavark, so the teardown removes it
+    created_by_netavark: bool,
+    networks: Vec<String>,
+    containers: usize,
+}
+
+impl Inspect {
+    /// Only reads the state files, the links and the firewall rules. The
+    /// state of the containers is compared with the host and every mismatch
+    /// is reported as issue, nothing is fixed.
+    pub fn exec(&self, config_dir: &str) -> NetavarkResult<()> {
+        let mut issues = Vec::new();
+        let mut containers = state::list(config_dir)?;
+        containers.sort_by(|a, b| a.options.container_id.cmp(&b.options.container_id));
+
+        let mut container_entries = Vec::new();
+        for container in &containers {
+            let namespace_exists = state::namespace_exists(container);
+            if !namespace_exists {
+                issues.push(format!(
+                    "the namespace {} of container {} is gone without a teardown, netavark gc removes it",
+                    container.netns_path, container.options.container_id
+                ));
+            }
+            let mut networks: Vec<String> =
+                container.options.network_info.keys().cloned().collect();
+            networks.sort();
+            container_entries.push(ContainerEntry {
+                container_id: container.options.container_id.clone(),
+                container_name: container.options.container_name.clone(),
+                network_namespace_path: container.netns_path.clone(),
+                namespace_exists,
+                networks,
+            });
+        }
+
+        let bridges = inspect_bridges(config_dir, &containers, &mut issues)?;
+
+        let firewall_driver = firewall::get_supported_firewall_driver()?;
+        let port_mappings = match firewall_driver.list_port_forwards() {
+            Ok(forwards) => {
+                check_port_mappings(&forwards, &containers, &mut issues);
+                Some(forwards)
+            }
+            Err(err) => {
+                warn!("{}", err);
+                None
+            }
+        };
+        let chains = match firewall_driver.list_chains() {
+            Ok(chains) => {
+                check_network_chains(&chains, &containers, &mut issues);
+                Some(chains)
+            }
+            Err(err) => {
+                warn!("{}", err);
+                None
+            }
+        };
+
+        let report = Report {
+            firewall_driver: firewall_driver.driver_name().to_string(),
+            containers: container_entries,
+            bridges,
+            port_mappings,
+            chains,
+            issues,
+        };
+        let out = serde_json::to_string_pretty(&report)?;
+        println!("{}", out);
+
+        Ok(())
+    }
+}
+
+/// The bridges of the bridge networks of the recorded containers and the ones
+/// recorded as created by netavark.
+fn inspect_bridges(
+    config_dir: &str,
+    containers: &[ContainerState],
+    issues: &mut Vec<String>,
+) -> NetavarkResult<Vec<BridgeEntry>> {
+    // bridge name -> network names, number of containers
+    let mut used: BTreeMap<String, (Vec<String>, usize)> = BTreeMap::new();
+    for container in containers {
+        for network in container.options.network_info.values() {
+            if network.driver != DRIVER_BRIDGE {
+                continue;
+            }
+            if let Some(bridge) = &network.network_interface {
+                let entry = used.entry(bridge.clone()).or_default();
+                if !entry.0.contains(&network.name) {
+                    entry.0.push(network.name.clone());
+                }
+                entry.1 += 1;
+            }
+        }
+    }
+    let recorded = state::list_bridges(config_dir)?;
+    for name in &recorded {
+        used.entry(name.clone()).or_default();
+    }
+
+    let mut host = netlink::Socket::new().wrap("host netlink socket")?;
+    let mut bridges = Vec::new();
+    for (name, (mut networks, containers)) in used {
+        let index = match host.get_link(netlink::LinkID::Name(name.clone())) {
+            Ok(link) => Some(link.header.index),
+            Err(NetavarkError::Netlink(e)) if -e.code == libc::ENODEV => None,
+            Err(err) => return Err(err).wrap(format!("get bridge {}", name)),
+        };
+        let created_by_netavark =
+            index.is_some_and(|index| state::is_own_bridge(config_dir, &name, index));
+        networks.sort();
+        match index {
+            None if containers > 0 => issues.push(format!(
+                "bridge {} of network {} does not exist",
+                name,
+                networks.join(", ")
+            )),
+            None => issues.push(format!(
+                "bridge {} is recorded as created by netavark but does not exist",
+                name
+            )),
+            Some(_) if recorded.contains(&name) && !created_by_netavark => issues.push(format!(
+                "bridge {} is recorded as created by netavark but was replaced by another one",
+                name
+            )),
+            Some(_) => {}
+        }
+        bridges.push(BridgeEntry {
+            exists: index.is_some(),
+            name,
+            created_by_netavark,
+            networks,
+            containers,
+        });
+    }
+    Ok(bridges)
+}
+
+/// Only these networks forward the ports with DNAT rules of the host firewall.
+fn has_dnat_rules(network: &Network) -> bool {
+    let option = |name: &str| network.options.as_ref().and_then(|o| o.get(name));
+    network.driver == DRIVER_BRIDGE
+        && option(OPTION_MODE).map(String::as_str) != Some(BRIDGE_MODE_L2_ONLY)
+        && option(OPTION_PORT_FORWARD_MODE).map(String::as_str) != Some(PORT_FORWARD_MODE_PROXY)
+        && option(OPTION_FIREWALL_NETNS).map(String::as_str) != Some(FIREWALL_NETNS_CONTAINER)
+}
+
+/// The rule forwards the port mapping of the container.
+fn forwards_mapping(forward: &FirewallPortForward, id: &str, mapping: &PortMapping) -> bool {
+    // all addresses when empty or unspecified
+    let host_ip = mapping
+        .host_ip
+        .parse::<IpAddr>()
+        .ok()
+        .filter(|ip| !ip.is_unspecified());
+    forward.container_id.as_deref() == Some(id)
+        && mapping.protocol.split(',').any(|p| p == forward.protocol)
+        && forward.host_port == mapping.host_port
+        && forward.range == mapping.range.max(1)
+        && (host_ip.is_none() || forward.host_ip == host_ip)
+}
+
+/// protocol, ip family, host port -> host ip, container id
+type PortUsers<'a> = BTreeMap<(&'a str, bool, u16), Vec<(Option<IpAddr>, &'a str)>>;
+
+/// Compare the DNAT rules with the port mappings of the recorded containers,
+/// a host port may only be forwarded to one container.
+fn check_port_mappings(
+    forwards: &[FirewallPortForward],
+    containers: &[ContainerState],
+    issues: &mut Vec<String>,
+) {
+    for container in containers {
+        let id = container.options.container_id.as_str();
+        if !container.options.network_info.values().any(has_dnat_rules) {
+            continue;
+        }
+        for mapping in container.options.port_mappings.iter().flatten() {
+            if !forwards.iter().any(|f| forwards_mapping(f, id, mapping)) {
+                issues.push(format!(
+                    "port mapping {}/{} of container {} has no DNAT rule",
+                    mapping.host_port, mapping.protocol, id
+                ));
+            }
+        }
+    }
+
+    let mut used: PortUsers = BTreeMap::new();
+    for forward in forwards {
+        let id = match &forward.container_id {
+            Some(id) => id.as_str(),
+            None => {
+                issues.push(format!(
+                    "DNAT rule for host port {}/{} in chain {} is not used by any container",
+                    forward.host_port, forward.protocol, forward.chain
+                ));
+                continue;
+            }
+        };
+        let recorded = containers.iter().any(|c| {
+            c.options.container_id == id
+                && c.options
+                    .port_mappings
+                    .iter()
+                    .flatten()
+                    .any(|m| forwards_mapping(forward, id, m))
+        });
+        if !recorded {
+            issues.push(format!(
+                "DNAT rule for host port {}/{} to {} of container {} has no recorded port mapping",
+                forward.host_port, forward.protocol, forward.destination, id
+            ));
+        }
+
+        // a clashing range is only reported for its first port
+        let mut reported = false;
+        for i in 0..forward.range {
+            let port = forward.host_port.saturating_add(i);
+            let users = used
+                .entry((forward.protocol.as_str(), forward.ipv6, port))
+                .or_default();
+            if let Some((_, other)) = users.iter().find(|(ip, other)| {
+                *other != id
+                    && (ip.is_none() || forward.host_ip.is_none() || *ip == forward.host_ip)
+            }) {
+                if !reported {
+                    issues.push(format!(
+                        "host port {}/{} is used by the containers {} and {}",
+                        port, forward.protocol, other, id
+                    ));
+                    reported = true;
+                }
+            }
+            users.push((forward.host_ip, id));
+        }
+    }
+}
+
+/// The chains named after a network hash must belong to a network of a
+/// recorded container.
+fn check_network_chains(
+    chains: &[FirewallChain],
+    containers: &[ContainerState],
+    issues: &mut Vec<String>,
+) {
+    let hashes: HashSet<String> = containers
+        .iter()
+        .flat_map(|c| c.options.network_info.keys())
+        .map(|name| CoreUtils::create_network_hash(name, MAX_HASH_SIZE))
+        .collect();
+    for chain in chains {
+        let hash = match chain.name.rsplit_once('-') {
+            Some((_, hash))
+                if hash.len() == MAX_HASH_SIZE
+                    && hash
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c)) =>
+            {
+                hash
+            }
+            _ => continue,
+        };
+        if !hashes.contains(hash) {
+            debug!("chain {} has no recorded network", chain.name);
+            issues.push(format!(
+                "{} chain {} in table {} does not belong to a network of a recorded container",
+                if chain.ipv6 { "ip6tables" } else { "iptables" },
+                chain.name,
+                chain.table
+            ));
+        }
+    }
+}
@@ -1,5 +1,10 @@
 pub mod dhcp_proxy;
+pub mod gc;
+pub mod inspect;
+pub mod port_proxy;
+pub mod reload;
 pub mod setup;
+pub mod stats;
 pub mod teardown;
 pub mod update;
 pub mod version;
@@ -0,0 +1,17 @@
+//! Serves port mappings in userspace, started by setup for the proxy port forward mode
+use crate::error::NetavarkResult;
+use crate::network::port_proxy::{self, ProxyForward};
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+pub struct PortProxy {
+    /// Port to forward as proto,listen_addr,target_addr, can be set multiple times.
+    #[clap(long = "forward", required = true)]
+    forwards: Vec<ProxyForward>,
+}
+
+impl PortProxy {
+    pub fn exec(self) -> NetavarkResult<()> {
+        port_proxy::run(self.forwards)
+    }
+}
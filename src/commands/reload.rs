@@ -0,0 +1,104 @@
+//! Re-applies the firewall rules of already configured networks
+use crate::error::{NetavarkError, NetavarkErrorList, NetavarkResult};
+use crate::firewall;
+use crate::logging;
+use crate::network::core_utils;
+use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::{self};
+
+use clap::builder::NonEmptyStringValueParser;
+use clap::Parser;
+use log::debug;
+use std::os::fd::RawFd;
+
+#[derive(Parser, Debug)]
+pub struct Reload {
+    /// Network namespace path
+    #[clap(required_unless_present = "netns_fd", conflicts_with = "netns_fd", value_parser = NonEmptyStringValueParser::new())]
+    network_namespace_path: Option<String>,
+    /// Open file descriptor of the network namespace to use instead of a path.
+    #[clap(long)]
+    netns_fd: Option<RawFd>,
+}
+
+impl Reload {
+    /// The reload command adds the firewall rules of the given configuration
+    /// again, e.g. after they were flushed by a reload of the host firewall.
+    /// Interfaces, addresses and routes are not touched.
+    pub fn new(network_namespace_path: String) -> Self {
+        Self {
+            network_namespace_path: Some(network_namespace_path),
+            netns_fd: None,
+        }
+    }
+
+    pub fn exec(
+        &self,
+        input_file: Option<String>,
+        config_dir: &str,
+        plugin_directories: Option<Vec<String>>,
+    ) -> NetavarkResult<()> {
+        let network_namespace_path =
+            network::validation::netns_path(&self.network_namespace_path, self.netns_fd)?;
+        logging::set_netns(&network_namespace_path);
+        debug!("{:?}", "Reloading firewall rules..");
+        let network_options = network::types::NetworkOptions::load(input_file)?;
+
+        let mut error_list = NetavarkErrorList::new();
+
+        let dns_port = core_utils::get_netavark_dns_port()?;
+
+        let firewall_driver = firewall::get_supported_firewall_driver()?;
+
+        let (hostns, netns) = core_utils::open_netlink_sockets(&network_namespace_path)?;
+
+        for (net_name, network) in network_options.network_info.iter() {
+            let per_network_opts = match network_options.networks.get(net_name) {
+                Some(opts) => opts,
+                None => {
+                    error_list.push(NetavarkError::Message(format!(
+                        "network options for network {} not found",
+                        net_name
+                    )));
+                    continue;
+                }
+            };
+
+            let mut driver = match get_network_driver(
+                DriverInfo {
+                    firewall: firewall_driver.as_ref(),
+                    container_id: &network_options.container_id,
+                    container_name: &network_options.container_name,
+                    container_dns_servers: &network_options.dns_servers,
+                    netns_host: hostns.fd,
+                    netns_container: netns.fd,
+                    netns_path: &network_namespace_path,
+                    network,
+                    per_network_opts,
+                    port_mappings: &network_options.port_mappings,
+                    dns_port,
+                    config_dir,
+                    link_order: None,
+                },
+                &plugin_directories,
+            ) {
+                Ok(driver) => driver,
+                Err(err) => {
+                    error_list.push(err.with_network(net_name));
+                    continue;
+                }
+            };
+
+            if let Err(err) = driver.validate().and_then(|_| driver.reload()) {
+                error_list.push(err.with_network(net_name));
+            }
+        }
+
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
+
+        debug!("{:?}", "Reload complete");
+        Ok(())
+    }
+}
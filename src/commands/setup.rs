@@ -1,31 +1,132 @@
 //! Configures the given network namespace with provided specs
-use crate::dns::aardvark::Aardvark;
-use crate::error::{NetavarkError, NetavarkResult};
-use crate::firewall;
-use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::dns::aardvark::{Aardvark, AardvarkEntry};
+use crate::error::{ConfigError, NetavarkError, NetavarkErrorList, NetavarkResult};
+use crate::firewall::{self, FirewallDriver, SerialFirewall};
+use crate::hook::{self, HookInput};
+use crate::logging;
+use crate::network::connectivity;
+use crate::network::driver::{
+    check_network_drivers, get_network_driver, DriverInfo, LinkOrder, NetworkDriver,
+};
 use crate::network::netlink::LinkID;
+use crate::network::response::{
+    key_by_interface, serialize_response, validate_response_version, RESPONSE_VERSION_LATEST,
+};
+use crate::network::script::Script;
 use crate::network::{self};
-use crate::network::{core_utils, types};
+use crate::network::{core_utils, state, types};
 
 use clap::builder::NonEmptyStringValueParser;
 use clap::Parser;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use std::collections::HashMap;
 use std::fs::{self};
+use std::os::fd::RawFd;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Number of networks which are set up at the same time by default.
+const DEFAULT_MAX_PARALLEL: usize = 4;
+
+/// Fail when the container interface name already exists in the namespace.
+const INTERFACE_CONFLICT_ERROR: &str = "error";
+/// Use the next free name instead, e.g. eth1 when eth0 exists.
+const INTERFACE_CONFLICT_RENAME: &str = "rename";
+
+/// Fail the setup when a static ip is already in use on the host.
+const STATIC_IP_CONFLICT_ERROR: &str = "error";
+/// Only log a warning for each static ip which is already in use.
+const STATIC_IP_CONFLICT_WARN: &str = "warn";
+/// Do not look for conflicts at all.
+const STATIC_IP_CONFLICT_IGNORE: &str = "ignore";
+
+/// Key the response by the network name.
+const RESPONSE_KEY_NETWORK_NAME: &str = "network_name";
+/// Key the response by the container interface name.
+const RESPONSE_KEY_INTERFACE_NAME: &str = "interface_name";
+
+/// Fail the setup when a gateway does not answer.
+const VERIFY_CONNECTIVITY_ERROR: &str = "error";
+/// Only log a warning when a gateway does not answer.
+const VERIFY_CONNECTIVITY_WARN: &str = "warn";
+/// How long to wait for each gateway to answer.
+const VERIFY_CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Parser, Debug)]
 pub struct Setup {
     /// Network namespace path
-    #[clap(required = true, value_parser = NonEmptyStringValueParser::new())]
-    network_namespace_path: String,
+    #[clap(required_unless_present = "netns_fd", conflicts_with = "netns_fd", value_parser = NonEmptyStringValueParser::new())]
+    network_namespace_path: Option<String>,
+    /// Open file descriptor of the network namespace to use instead of a path.
+    #[clap(long)]
+    netns_fd: Option<RawFd>,
+    /// Version of the response format to print, defaults to the latest version.
+    #[clap(long)]
+    response_version: Option<u32>,
+    /// Key of the networks in the response: network_name or interface_name.
+    #[clap(long, default_value = RESPONSE_KEY_NETWORK_NAME, value_parser = [RESPONSE_KEY_NETWORK_NAME, RESPONSE_KEY_INTERFACE_NAME])]
+    response_key: String,
+    /// Do not write any sysctls, they must be configured by the operator beforehand.
+    #[clap(long)]
+    skip_sysctl: bool,
+    /// Set up the networks without forwarding the port mappings of the container.
+    #[clap(long)]
+    no_port_forward: bool,
+    /// What to do when an interface name is already used in the namespace: error or rename it to the next free name.
+    #[clap(long, default_value = INTERFACE_CONFLICT_ERROR, value_parser = [INTERFACE_CONFLICT_ERROR, INTERFACE_CONFLICT_RENAME])]
+    interface_conflict: String,
+    /// What to do when a static ip is already assigned on the host or used by a neighbor: error, warn or ignore.
+    #[clap(long, default_value = STATIC_IP_CONFLICT_WARN, value_parser = [STATIC_IP_CONFLICT_ERROR, STATIC_IP_CONFLICT_WARN, STATIC_IP_CONFLICT_IGNORE])]
+    static_ip_conflict: String,
+    /// Maximum number of networks to set up at the same time, 1 sets them up one after another.
+    #[clap(long, default_value_t = DEFAULT_MAX_PARALLEL, value_parser = parse_max_parallel)]
+    max_parallel: usize,
+    /// Ping the gateways from the namespace after the setup, on failure error (the default) or only warn.
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = VERIFY_CONNECTIVITY_ERROR, value_parser = [VERIFY_CONNECTIVITY_ERROR, VERIFY_CONNECTIVITY_WARN])]
+    verify_connectivity: Option<String>,
+    /// Only set up the namespace when the config is for this container id.
+    #[clap(long, value_parser = NonEmptyStringValueParser::new())]
+    container_id: Option<String>,
+    /// Command to run with `sh -c` after a successful setup.
+    #[clap(long, value_parser = NonEmptyStringValueParser::new())]
+    on_setup: Option<String>,
+    /// Fail the setup when the --on-setup command fails instead of only logging it.
+    #[clap(long, requires = "on_setup")]
+    hook_errors_fatal: bool,
+    /// Print the ip and iptables commands of the setup as shell script instead of running them.
+    #[clap(long, conflicts_with_all = ["netns_fd", "verify_connectivity", "on_setup"])]
+    emit_script: bool,
+}
+
+fn parse_max_parallel(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 impl Setup {
     /// The setup command configures the given network namespace with the given configuration, creating any interfaces and firewall rules necessary.
     pub fn new(network_namespace_path: String) -> Self {
         Self {
-            network_namespace_path,
+            network_namespace_path: Some(network_namespace_path),
+            netns_fd: None,
+            response_version: None,
+            response_key: RESPONSE_KEY_NETWORK_NAME.to_string(),
+            skip_sysctl: false,
+            no_port_forward: false,
+            interface_conflict: INTERFACE_CONFLICT_ERROR.to_string(),
+            static_ip_conflict: STATIC_IP_CONFLICT_WARN.to_string(),
+            max_parallel: DEFAULT_MAX_PARALLEL,
+            verify_connectivity: None,
+            container_id: None,
+            on_setup: None,
+            hook_errors_fatal: false,
+            emit_script: false,
         }
     }
 
@@ -37,14 +138,44 @@ impl Setup {
         plugin_directories: Option<Vec<String>>,
         rootless: bool,
     ) -> NetavarkResult<()> {
-        match network::validation::ns_checks(&self.network_namespace_path) {
-            Ok(_) => (),
-            Err(e) => {
-                return Err(NetavarkError::wrap("invalid namespace path", e));
+        let network_namespace_path =
+            network::validation::netns_path(&self.network_namespace_path, self.netns_fd)?;
+        logging::set_netns(&network_namespace_path);
+        // the script may be run on another host, the namespace must only exist there
+        if !self.emit_script {
+            match network::validation::ns_checks(&network_namespace_path) {
+                Ok(_) => (),
+                Err(e) => {
+                    return Err(NetavarkError::wrap("invalid namespace path", e));
+                }
             }
         }
+        let response_version = self.response_version.unwrap_or(RESPONSE_VERSION_LATEST);
+        validate_response_version(response_version)?;
+
+        if self.skip_sysctl {
+            info!("Skipping all sysctl writes, sysctls must be configured by the operator");
+            core_utils::skip_sysctl_writes();
+        }
+
         debug!("{:?}", "Setting up...");
-        let network_options = network::types::NetworkOptions::load(input_file)?;
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
+        if let Some(container_id) = &self.container_id {
+            // guard against a config which was generated for another container
+            if *container_id != network_options.container_id {
+                return Err(ConfigError::invalid_value(
+                    "container_id",
+                    format!(
+                        "container id {} does not match --container-id {}",
+                        network_options.container_id, container_id
+                    ),
+                ));
+            }
+        }
+        // fail before any network is set up when a driver is not supported
+        check_network_drivers(network_options.network_info.values(), &plugin_directories)?;
+        network_options.validate_interface_indexes()?;
+        network_options.validate_port_container_ips()?;
 
         let firewall_driver = match firewall::get_supported_firewall_driver() {
             Ok(driver) => driver,
@@ -55,42 +186,113 @@ impl Setup {
 
         let dns_port = core_utils::get_netavark_dns_port()?;
 
-        let (mut hostns, mut netns) =
-            core_utils::open_netlink_sockets(&self.network_namespace_path)?;
+        let no_port_mappings = None;
+        let port_mappings = match &network_options.port_mappings {
+            Some(mappings) if self.no_port_forward && !mappings.is_empty() => {
+                info!(
+                    "Skipping port forwarding for {} port mappings due to --no-port-forward",
+                    mappings.len()
+                );
+                &no_port_mappings
+            }
+            _ => &network_options.port_mappings,
+        };
+
+        if self.emit_script {
+            let script = emit_script(
+                &network_options,
+                firewall_driver.as_ref(),
+                &network_namespace_path,
+                port_mappings,
+                dns_port,
+                config_dir,
+                &plugin_directories,
+            )?;
+            print!("{}", script);
+            return Ok(());
+        }
+
+        let (mut hostns, mut netns) = core_utils::open_netlink_sockets(&network_namespace_path)?;
 
         // setup loopback, it should be safe to assume that 1 is the loopback index
         netns.netlink.set_up(LinkID::ID(1))?;
 
+        if self.interface_conflict == INTERFACE_CONFLICT_RENAME {
+            // the response contains the names which were actually used
+            core_utils::rename_conflicting_interfaces(
+                &mut netns.netlink,
+                &mut network_options.networks,
+            )?;
+        }
+
+        if self.static_ip_conflict != STATIC_IP_CONFLICT_IGNORE {
+            // nothing is set up yet, a conflict leaves the host untouched
+            let conflicts = core_utils::find_static_ip_conflicts(
+                &mut hostns.netlink,
+                &network_options.networks,
+            )?;
+            if self.static_ip_conflict == STATIC_IP_CONFLICT_ERROR && !conflicts.is_empty() {
+                return Err(NetavarkError::Message(conflicts.join(", ")));
+            }
+            for conflict in conflicts {
+                warn!("{}", conflict);
+            }
+        }
+
+        let max_parallel = self.max_parallel.min(network_options.network_info.len());
+        // the parallel setups still create the interfaces in network_order()
+        let link_order = LinkOrder::new(
+            network_options
+                .network_order()
+                .into_iter()
+                .cloned()
+                .collect(),
+        );
+        // the firewall drivers are not safe to be used concurrently
+        let serial_firewall;
+        let firewall_driver: &dyn FirewallDriver = if max_parallel > 1 {
+            serial_firewall = SerialFirewall::new(firewall_driver.as_ref());
+            &serial_firewall
+        } else {
+            firewall_driver.as_ref()
+        };
+
         let mut drivers = Vec::with_capacity(network_options.network_info.len());
 
-        // Perform per-network setup
-        for (net_name, network) in network_options.network_info.iter() {
+        // Perform per-network setup, the drivers are set up in this order
+        for net_name in network_options.network_order() {
+            let network = &network_options.network_info[net_name];
             let per_network_opts = network_options.networks.get(net_name).ok_or_else(|| {
-                NetavarkError::Message(format!(
-                    "network options for network {} not found",
-                    net_name
-                ))
+                ConfigError::missing_field(
+                    "networks",
+                    format!("network options for network {} not found", net_name),
+                )
+                .with_network(net_name)
             })?;
 
             let mut driver = get_network_driver(
                 DriverInfo {
-                    firewall: firewall_driver.as_ref(),
+                    firewall: firewall_driver,
                     container_id: &network_options.container_id,
                     container_name: &network_options.container_name,
                     container_dns_servers: &network_options.dns_servers,
                     netns_host: hostns.fd,
                     netns_container: netns.fd,
-                    netns_path: &self.network_namespace_path,
+                    netns_path: &network_namespace_path,
                     network,
                     per_network_opts,
-                    port_mappings: &network_options.port_mappings,
+                    port_mappings,
                     dns_port,
+                    config_dir,
+                    link_order: (max_parallel > 1).then_some(&link_order),
                 },
                 &plugin_directories,
             )?;
 
             // validate before we do anything
-            driver.validate()?;
+            driver
+                .validate()
+                .map_err(|err| err.with_network(net_name))?;
 
             drivers.push(driver);
         }
@@ -99,9 +301,47 @@ impl Setup {
 
         // Only now after we validated all drivers we setup each.
         // If there is an error we have to tear down all previous drivers.
-        for (i, driver) in drivers.iter().enumerate() {
-            let (status, aardvark_entry) =
-                match driver.setup((&mut hostns.netlink, &mut netns.netlink)) {
+        if max_parallel > 1 {
+            let results =
+                setup_parallel(&drivers, &network_namespace_path, max_parallel, &link_order);
+            let mut errors = Vec::new();
+            for (i, (driver, result)) in drivers.iter().zip(results).enumerate() {
+                match result {
+                    Ok((mut status, aardvark_entry)) => {
+                        status.interface_index = Some(i as u32);
+                        let _ = response.insert(driver.network_name(), status);
+                        if let Some(a) = aardvark_entry {
+                            aardvark_entries.push(a);
+                        }
+                    }
+                    Err(e) => errors.push(e.with_network(&driver.network_name())),
+                }
+            }
+            if !errors.is_empty() {
+                // now teardown the networks which were set up successfully
+                for dri in drivers
+                    .iter()
+                    .filter(|d| response.contains_key(&d.network_name()))
+                {
+                    if let Err(e) = dri.teardown((&mut hostns.netlink, &mut netns.netlink)) {
+                        error!(
+                            "failed to cleanup previous networks after setup failed: {}",
+                            e
+                        )
+                    }
+                }
+                if errors.len() == 1 {
+                    return Err(errors.remove(0));
+                }
+                let mut error_list = NetavarkErrorList::new();
+                errors.into_iter().for_each(|e| error_list.push(e));
+                return Err(NetavarkError::List(error_list));
+            }
+        } else {
+            for (i, driver) in drivers.iter().enumerate() {
+                let (mut status, aardvark_entry) = match driver
+                    .setup((&mut hostns.netlink, &mut netns.netlink))
+                {
                     Ok((s, a)) => (s, a),
                     Err(e) => {
                         // now teardown the already setup drivers
@@ -110,9 +350,9 @@ impl Setup {
                                 Ok(_) => {}
                                 Err(e) => {
                                     error!(
-                                    "failed to cleanup previous networks after setup failed: {}",
-                                    e
-                                )
+                                        "failed to cleanup previous networks after setup failed: {}",
+                                        e
+                                    )
                                 }
                             };
                         }
@@ -120,12 +360,68 @@ impl Setup {
                     }
                 };
 
-            let _ = response.insert(driver.network_name(), status);
-            if let Some(a) = aardvark_entry {
-                aardvark_entries.push(a);
+                status.interface_index = Some(i as u32);
+                let _ = response.insert(driver.network_name(), status);
+                if let Some(a) = aardvark_entry {
+                    aardvark_entries.push(a);
+                }
+            }
+        }
+
+        // report the default gateways the container actually uses
+        match core_utils::get_default_gateways(&mut netns.netlink) {
+            Ok(gateways) => {
+                for (interface, gateway) in gateways {
+                    if let Some(status) = response.values_mut().find(|status| {
+                        status
+                            .interfaces
+                            .as_ref()
+                            .map(|i| i.contains_key(&interface))
+                            .unwrap_or(false)
+                    }) {
+                        status
+                            .default_gateways
+                            .get_or_insert_with(Vec::new)
+                            .push(gateway);
+                    }
+                }
+            }
+            Err(e) => warn!("failed to get the default gateways: {}", e),
+        }
+
+        if let Some(mode) = &self.verify_connectivity {
+            let mut gateways: Vec<_> = response
+                .values()
+                .flat_map(|status| status.interfaces.iter().flatten())
+                .flat_map(|(_, interface)| interface.subnets.iter().flatten())
+                .filter_map(|subnet| subnet.gateway)
+                .collect();
+            gateways.sort();
+            gateways.dedup();
+            debug!("verifying connectivity to gateways {:?}", gateways);
+            if let Err(e) = connectivity::ping_gateways(
+                hostns.fd,
+                netns.fd,
+                &gateways,
+                VERIFY_CONNECTIVITY_TIMEOUT,
+            ) {
+                if mode == VERIFY_CONNECTIVITY_WARN {
+                    warn!("{}", e);
+                } else {
+                    for dri in drivers.iter() {
+                        if let Err(e) = dri.teardown((&mut hostns.netlink, &mut netns.netlink)) {
+                            error!(
+                                "failed to cleanup networks after the connectivity check failed: {}",
+                                e
+                            )
+                        }
+                    }
+                    return Err(e);
+                }
             }
         }
 
+        let mut aardvark = None;
         if Path::new(&aardvark_bin).exists() && !aardvark_entries.is_empty() {
             let path = Path::new(&config_dir).join("aardvark-dns");
 
@@ -162,13 +458,163 @@ impl Setup {
                 )
                 .into());
             }
+            aardvark = Some(aardvark_interface);
         } else {
             info!("dns disabled because aardvark-dns path does not exists");
         }
+        // the gc uses it to find the networks when the namespace is gone without a teardown
+        let mut recorded = network_options.clone();
+        recorded.port_mappings = port_mappings.clone();
+        if let Err(e) = state::add(config_dir, &network_namespace_path, &recorded) {
+            warn!("failed to record the container state: {}", e);
+        }
+
+        if let Some(command) = &self.on_setup {
+            let mut networks: Vec<String> = response.keys().cloned().collect();
+            networks.sort();
+            if let Err(e) = hook::run(
+                command,
+                &HookInput {
+                    event: hook::EVENT_SETUP,
+                    container_id: &network_options.container_id,
+                    container_name: &network_options.container_name,
+                    network_namespace_path: &network_namespace_path,
+                    networks,
+                    status: Some(&response),
+                },
+                self.hook_errors_fatal,
+            ) {
+                // the setup failed so do not leave the networks behind
+                for dri in drivers.iter() {
+                    if let Err(e) = dri.teardown((&mut hostns.netlink, &mut netns.netlink)) {
+                        error!("failed to cleanup networks after the hook failed: {}", e)
+                    }
+                }
+                if let Some(aardvark) = &aardvark {
+                    if let Err(e) = aardvark.delete_from_netavark_entries(&network_options) {
+                        error!(
+                            "failed to remove the dns entries after the hook failed: {}",
+                            e
+                        )
+                    }
+                }
+                let networks: Vec<String> = response.keys().cloned().collect();
+                if let Err(e) = state::remove(config_dir, &network_options.container_id, &networks)
+                {
+                    error!(
+                        "failed to forget the container after the hook failed: {}",
+                        e
+                    )
+                }
+                return Err(e);
+            }
+        }
+
+        if self.response_key == RESPONSE_KEY_INTERFACE_NAME {
+            response = key_by_interface(response, &network_options.networks);
+        }
         debug!("{:#?}", response);
-        let response_json = serde_json::to_string(&response)?;
+        let response_json = serialize_response(&response, response_version)?;
         println!("{}", response_json);
         debug!("{:?}", "Setup complete");
         Ok(())
     }
 }
+
+/// Build the script with the commands of the setup of all networks, nothing
+/// is changed on this host.
+fn emit_script(
+    network_options: &types::NetworkOptions,
+    firewall_driver: &dyn FirewallDriver,
+    netns_path: &str,
+    port_mappings: &Option<Vec<types::PortMapping>>,
+    dns_port: u16,
+    config_dir: &str,
+    plugin_directories: &Option<Vec<String>>,
+) -> NetavarkResult<Script> {
+    let mut script = Script::new(&network_options.container_id, netns_path);
+    script.comment("loopback");
+    script.container(&["ip", "link", "set", "lo", "up"]);
+    for net_name in network_options.network_order() {
+        let network = &network_options.network_info[net_name];
+        let per_network_opts = network_options.networks.get(net_name).ok_or_else(|| {
+            ConfigError::missing_field(
+                "networks",
+                format!("network options for network {} not found", net_name),
+            )
+            .with_network(net_name)
+        })?;
+        let mut driver = get_network_driver(
+            DriverInfo {
+                firewall: firewall_driver,
+                container_id: &network_options.container_id,
+                container_name: &network_options.container_name,
+                container_dns_servers: &network_options.dns_servers,
+                // the drivers do not enter any namespace for the script
+                netns_host: -1,
+                netns_container: -1,
+                netns_path,
+                network,
+                per_network_opts,
+                port_mappings,
+                dns_port,
+                config_dir,
+                link_order: None,
+            },
+            plugin_directories,
+        )?;
+        driver
+            .validate()
+            .and_then(|_| driver.emit_script(&mut script))
+            .map_err(|err| err.with_network(net_name))?;
+    }
+    Ok(script)
+}
+
+/// Set up the networks with up to `max_parallel` threads, every thread uses its
+/// own netlink sockets. The results are in the same order as the drivers.
+fn setup_parallel<'a>(
+    drivers: &'a [Box<dyn NetworkDriver + 'a>],
+    netns_path: &str,
+    max_parallel: usize,
+    link_order: &LinkOrder,
+) -> Vec<NetavarkResult<(types::StatusBlock, Option<AardvarkEntry<'a>>)>> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..drivers.len()).map(|_| None).collect::<Vec<_>>());
+
+    thread::scope(|scope| {
+        for _ in 0..max_parallel {
+            scope.spawn(|| {
+                let mut sockets = core_utils::open_netlink_sockets(netns_path);
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let driver = match drivers.get(i) {
+                        Some(driver) => driver,
+                        None => break,
+                    };
+                    let result = match &mut sockets {
+                        Ok((hostns, netns)) => {
+                            driver.setup((&mut hostns.netlink, &mut netns.netlink))
+                        }
+                        Err(e) => Err(NetavarkError::Message(format!(
+                            "failed to open netlink sockets: {}",
+                            e
+                        ))),
+                    };
+                    // a failed setup may not have taken its turn
+                    link_order.done(&driver.network_name());
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| Err(NetavarkError::msg("network setup did not run")))
+        })
+        .collect()
+}
@@ -0,0 +1,49 @@
+//! Prints the traffic counters of the containers of networks with the accounting option
+use crate::error::NetavarkResult;
+use crate::firewall;
+use clap::builder::NonEmptyStringValueParser;
+use clap::Parser;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Parser, Debug)]
+pub struct Stats {
+    /// Only print the counters of this container.
+    #[clap(long, value_parser = NonEmptyStringValueParser::new())]
+    container_id: Option<String>,
+}
+
+/// Traffic sent by the container in one network, ipv4 and ipv6 combined.
+#[derive(Debug, Default, Serialize)]
+struct Usage {
+    packets: u64,
+    bytes: u64,
+}
+
+impl Stats {
+    pub fn exec(&self) -> NetavarkResult<()> {
+        let firewall_driver = firewall::get_supported_firewall_driver()?;
+
+        // container id -> network name -> usage
+        let mut stats: BTreeMap<String, BTreeMap<String, Usage>> = BTreeMap::new();
+        for counter in firewall_driver.accounting_counters()? {
+            if let Some(id) = &self.container_id {
+                if *id != counter.container_id {
+                    continue;
+                }
+            }
+            let usage = stats
+                .entry(counter.container_id)
+                .or_default()
+                .entry(counter.network)
+                .or_default();
+            usage.packets += counter.packets;
+            usage.bytes += counter.bytes;
+        }
+
+        let out = serde_json::to_string_pretty(&stats)?;
+        println!("{}", out);
+
+        Ok(())
+    }
+}
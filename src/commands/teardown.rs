@@ -1,26 +1,47 @@
 use crate::dns::aardvark::Aardvark;
 use crate::error::{NetavarkError, NetavarkErrorList, NetavarkResult};
+use crate::hook::{self, HookInput};
 use crate::network::core_utils;
 use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::state;
 
-use crate::{firewall, network};
+use crate::{firewall, logging, network};
 use clap::builder::NonEmptyStringValueParser;
 use clap::Parser;
-use log::debug;
+use log::{debug, error, info, warn};
+use std::os::fd::RawFd;
 use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 pub struct Teardown {
     /// Network namespace path
-    #[clap(required = true, value_parser = NonEmptyStringValueParser::new())]
-    network_namespace_path: String,
+    #[clap(required_unless_present = "netns_fd", conflicts_with = "netns_fd", value_parser = NonEmptyStringValueParser::new())]
+    network_namespace_path: Option<String>,
+    /// Open file descriptor of the network namespace to use instead of a path.
+    #[clap(long)]
+    netns_fd: Option<RawFd>,
+    /// Stop forwarding new connections to the published ports this many seconds before the teardown.
+    #[clap(long, value_name = "SECONDS")]
+    drain_timeout: Option<u64>,
+    /// Command to run with `sh -c` after a successful teardown.
+    #[clap(long, value_parser = NonEmptyStringValueParser::new())]
+    on_teardown: Option<String>,
+    /// Fail the teardown when the --on-teardown command fails instead of only logging it.
+    #[clap(long, requires = "on_teardown")]
+    hook_errors_fatal: bool,
 }
 
 impl Teardown {
     /// The teardown command is the inverse of the setup command, undoing any configuration applied. Some interfaces may not be deleted (bridge interfaces, for example, will not be removed).
     pub fn new(network_namespace_path: String) -> Self {
         Self {
-            network_namespace_path,
+            network_namespace_path: Some(network_namespace_path),
+            netns_fd: None,
+            drain_timeout: None,
+            on_teardown: None,
+            hook_errors_fatal: false,
         }
     }
 
@@ -32,8 +53,19 @@ impl Teardown {
         plugin_directories: Option<Vec<String>>,
         rootless: bool,
     ) -> NetavarkResult<()> {
+        let network_namespace_path =
+            network::validation::netns_path(&self.network_namespace_path, self.netns_fd)?;
+        logging::set_netns(&network_namespace_path);
         debug!("{:?}", "Tearing down..");
-        let network_options = network::types::NetworkOptions::load(input_file)?;
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
+        // the teardown must not remove an interface which the setup did not create
+        if let Err(e) = state::use_recorded_interface_names(
+            config_dir,
+            &network_namespace_path,
+            &mut network_options,
+        ) {
+            warn!("failed to read the container state: {}", e);
+        }
 
         let mut error_list = NetavarkErrorList::new();
 
@@ -61,10 +93,12 @@ impl Teardown {
             Err(e) => return Err(e),
         };
 
-        let (mut hostns, mut netns) =
-            core_utils::open_netlink_sockets(&self.network_namespace_path)?;
+        let (mut hostns, mut netns) = core_utils::open_netlink_sockets(&network_namespace_path)?;
 
-        for (net_name, network) in network_options.network_info.iter() {
+        let mut drivers = Vec::with_capacity(network_options.network_info.len());
+        // in the reverse order of the setup
+        for net_name in network_options.network_order().into_iter().rev() {
+            let network = &network_options.network_info[net_name];
             let per_network_opts = match network_options.networks.get(net_name) {
                 Some(opts) => opts,
                 None => {
@@ -84,11 +118,13 @@ impl Teardown {
                     container_dns_servers: &network_options.dns_servers,
                     netns_host: hostns.fd,
                     netns_container: netns.fd,
-                    netns_path: &self.network_namespace_path,
+                    netns_path: &network_namespace_path,
                     network,
                     per_network_opts,
                     port_mappings: &network_options.port_mappings,
                     dns_port,
+                    config_dir,
+                    link_order: None,
                 },
                 &plugin_directories,
             ) {
@@ -99,19 +135,66 @@ impl Teardown {
                 }
             };
 
-            match driver.teardown((&mut hostns.netlink, &mut netns.netlink)) {
-                Ok(_) => {}
-                Err(err) => {
-                    error_list.push(err);
-                    continue;
+            drivers.push(driver);
+        }
+
+        let has_port_mappings = network_options
+            .port_mappings
+            .as_ref()
+            .is_some_and(|m| !m.is_empty());
+        match self.drain_timeout {
+            Some(timeout) if timeout > 0 && has_port_mappings => {
+                for driver in &drivers {
+                    if let Err(err) = driver.drain() {
+                        // the teardown removes the rules anyway
+                        error!(
+                            "failed to drain port mappings of network {}: {}",
+                            driver.network_name(),
+                            err
+                        );
+                    }
                 }
-            };
+                info!(
+                    "Draining connections for {} seconds before the teardown",
+                    timeout
+                );
+                thread::sleep(Duration::from_secs(timeout));
+            }
+            _ => {}
+        }
+
+        for driver in &drivers {
+            if let Err(err) = driver.teardown((&mut hostns.netlink, &mut netns.netlink)) {
+                error_list.push(err);
+            }
         }
 
         if !error_list.is_empty() {
             return Err(NetavarkError::List(error_list));
         }
 
+        let networks: Vec<String> = network_options.network_info.keys().cloned().collect();
+        if let Err(e) = state::remove(config_dir, &network_options.container_id, &networks) {
+            warn!("failed to update the container state: {}", e);
+        }
+
+        if let Some(command) = &self.on_teardown {
+            let mut networks: Vec<String> = network_options.network_info.keys().cloned().collect();
+            networks.sort();
+            hook::run(
+                command,
+                &HookInput {
+                    event: hook::EVENT_TEARDOWN,
+                    container_id: &network_options.container_id,
+                    container_name: &network_options.container_name,
+                    network_namespace_path: &network_namespace_path,
+                    networks,
+                    status: None,
+                },
+                self.hook_errors_fatal,
+            )?;
+        }
+
         debug!("{:?}", "Teardown complete");
         Ok(())
     }
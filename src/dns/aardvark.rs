@@ -1,9 +1,10 @@
 use crate::network::constants::DRIVER_BRIDGE;
-use crate::network::types;
+use crate::network::{core_utils, types};
 
 use fs2::FileExt;
 use nix::sys::signal::{self, Signal};
 use nix::unistd::Pid;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
@@ -18,6 +19,12 @@ const SYSTEMD_CHECK_PATH: &str = "/run/systemd/system";
 const SYSTEMD_RUN: &str = "systemd-run";
 const AARDVARK_COMMIT_LOCK: &str = "aardvark.lock";
 
+/// core_utils::check_read_only() with the io error used in this module
+fn check_read_only(change: fmt::Arguments) -> Result<()> {
+    core_utils::check_read_only(change)
+        .map_err(|e| std::io::Error::new(ErrorKind::PermissionDenied, e.to_string()))
+}
+
 #[derive(Clone, Debug)]
 pub struct AardvarkEntry<'a> {
     pub network_name: &'a str,
@@ -123,6 +130,7 @@ impl Aardvark {
     }
 
     pub fn notify(&self, start: bool) -> Result<()> {
+        check_read_only(format_args!("notify aardvark-dns"))?;
         let aardvark_pid = self.get_aardvark_pid();
         if aardvark_pid != -1 {
             match signal::kill(Pid::from_raw(aardvark_pid), Signal::SIGHUP) {
@@ -149,6 +157,10 @@ impl Aardvark {
         Ok(())
     }
     pub fn commit_entries(&self, entries: Vec<AardvarkEntry>) -> Result<()> {
+        check_read_only(format_args!(
+            "add the aardvark-dns entries to {}",
+            self.config
+        ))?;
         // Acquire fs lock to ensure other instance of aardvark cannot commit
         // or start aardvark instance till already running instance has not
         // completed its `commit` phase.
@@ -309,6 +321,10 @@ impl Aardvark {
         let file_content = fs::read_to_string(&path)?;
         let lines: Vec<&str> = file_content.split_terminator('\n').collect();
 
+        check_read_only(format_args!(
+            "delete the aardvark-dns entry of {}",
+            container_id
+        ))?;
         let mut idx = 0;
         let mut file = File::create(&path)?;
 
@@ -355,6 +371,10 @@ impl Aardvark {
             }
         };
 
+        check_read_only(format_args!(
+            "change the dns servers of network {}",
+            network_name
+        ))?;
         let mut file = File::create(&path)?;
 
         //for line in lines {
@@ -80,9 +80,92 @@ pub enum NetavarkError {
 
     DHCPProxy(tonic::Status),
 
+    Config(ConfigError),
+
     List(NetavarkErrorList),
 }
 
+/// An invalid configuration, serialized with its kind and the affected
+/// network and field so callers can point the user to the exact problem.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ConfigError {
+    /// the configuration could not be decoded
+    Decode {
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    /// a required field is not set
+    MissingField {
+        network: Option<String>,
+        field: String,
+        message: String,
+    },
+    /// a field is set to a value we cannot use
+    InvalidValue {
+        network: Option<String>,
+        field: String,
+        message: String,
+    },
+}
+
+impl ConfigError {
+    pub fn missing_field<F, S>(field: F, message: S) -> NetavarkError
+    where
+        F: Into<String>,
+        S: Into<String>,
+    {
+        NetavarkError::Config(ConfigError::MissingField {
+            network: None,
+            field: field.into(),
+            message: message.into(),
+        })
+    }
+
+    pub fn invalid_value<F, S>(field: F, message: S) -> NetavarkError
+    where
+        F: Into<String>,
+        S: Into<String>,
+    {
+        NetavarkError::Config(ConfigError::InvalidValue {
+            network: None,
+            field: field.into(),
+            message: message.into(),
+        })
+    }
+
+    fn set_network(&mut self, name: &str) {
+        match self {
+            ConfigError::MissingField { network, .. }
+            | ConfigError::InvalidValue { network, .. } => {
+                if network.is_none() {
+                    *network = Some(name.to_string());
+                }
+            }
+            ConfigError::Decode { .. } => {}
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Decode { message, .. }
+            | ConfigError::MissingField { message, .. }
+            | ConfigError::InvalidValue { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Internal struct for JSON output of configuration errors
+#[derive(Serialize)]
+struct JsonConfigError<'a> {
+    error: String,
+    #[serde(flatten)]
+    config: &'a ConfigError,
+}
+
 /// Internal struct for JSON output
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonError {
@@ -107,18 +190,36 @@ impl NetavarkError {
     /// Print the error in a standardized JSON format recognized by callers of
     /// Netavark.
     pub fn print_json(&self) {
-        let to_json = JsonError {
-            error: self.to_string(),
+        let error = self.to_string();
+        let json = match self.unwrap() {
+            NetavarkError::Config(config) => serde_json::to_string(&JsonConfigError {
+                error: error.clone(),
+                config,
+            }),
+            _ => serde_json::to_string(&JsonError {
+                error: error.clone(),
+            }),
         };
         println!(
             "{}",
-            serde_json::to_string(&to_json).unwrap_or(format!(
-                "Failed to serialize error message: {}",
-                to_json.error
-            ))
+            json.unwrap_or(format!("Failed to serialize error message: {}", error))
         );
     }
 
+    /// add the network name to a configuration error which does not have one yet
+    pub fn with_network(self, name: &str) -> NetavarkError {
+        match self {
+            NetavarkError::Config(mut config) => {
+                config.set_network(name);
+                NetavarkError::Config(config)
+            }
+            NetavarkError::Chain(msg, inner) => {
+                NetavarkError::Chain(msg, Box::new(inner.with_network(name)))
+            }
+            err => err,
+        }
+    }
+
     /// Get the exit code that Netavark should exit with
     pub fn get_exit_code(&self) -> i32 {
         match *self {
@@ -149,6 +250,7 @@ impl fmt::Display for NetavarkError {
             NetavarkError::Serde(e) => write!(f, "JSON Decoding error: {}", e),
             NetavarkError::Netlink(e) => write!(f, "Netlink error: {}", e),
             NetavarkError::DHCPProxy(e) => write!(f, "dhcp proxy error: {}", e),
+            NetavarkError::Config(e) => write!(f, "{}", e),
             NetavarkError::List(list) => {
                 if list.0.len() == 1 {
                     write!(f, "{}", list.0[0])
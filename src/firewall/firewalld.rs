@@ -26,6 +26,10 @@ pub fn new(conn: Connection) -> Result<Box<dyn firewall::FirewallDriver>, Netava
 }
 
 impl firewall::FirewallDriver for FirewallD {
+    fn driver_name(&self) -> &str {
+        firewall::FIREWALLD
+    }
+
     fn setup_network(&self, network_setup: internal_types::SetupNetwork) -> NetavarkResult<()> {
         let mut need_reload = false;
 
@@ -173,7 +177,12 @@ impl firewall::FirewallDriver for FirewallD {
         match setup_portfw.port_mappings {
             Some(ports) => {
                 for port in ports {
-                    if !port.host_ip.is_empty() {
+                    if port.container_ip.is_some() {
+                        if let Some(ip) = port.target_ip(&setup_portfw.container_ips, None) {
+                            port_forwarding_rules
+                                .append(Value::new(make_port_tuple(port, &ip.to_string())))?;
+                        }
+                    } else if !port.host_ip.is_empty() {
                         port_forwarding_rules
                             .append(Value::new(make_port_tuple(port, &port.host_ip)))?;
                     } else {
@@ -342,7 +351,12 @@ impl firewall::FirewallDriver for FirewallD {
                             _ => return Err(NetavarkError::msg("Port forwarding tuples must contain only strings, encountered a non-string object")),
                         };
                         debug!("IP string from firewalld is {}", port_ip);
-                        if port_ip != ipv4 && port_ip != ipv6 {
+                        let selected = teardown_pf
+                            .config
+                            .container_ips
+                            .iter()
+                            .any(|ip| ip.to_string() == port_ip);
+                        if port_ip != ipv4 && port_ip != ipv6 && !selected {
                             port_forwarding_rules.append(port_tuple.clone())?;
                         }
                     }
@@ -3,6 +3,8 @@ use crate::firewall::NetavarkResult;
 use crate::network::internal_types::{
     PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
 };
+use crate::network::script::Script;
+use std::path::Path;
 
 // Iptables driver - uses direct iptables commands via the iptables crate.
 pub struct Fwnone {}
@@ -12,6 +14,10 @@ pub fn new() -> NetavarkResult<Box<dyn firewall::FirewallDriver>> {
 }
 
 impl firewall::FirewallDriver for Fwnone {
+    fn driver_name(&self) -> &str {
+        firewall::NONE
+    }
+
     fn setup_network(&self, _network_setup: SetupNetwork) -> NetavarkResult<()> {
         Ok(())
     }
@@ -29,4 +35,23 @@ impl firewall::FirewallDriver for Fwnone {
     fn teardown_port_forward(&self, _tear: TeardownPortForward) -> NetavarkResult<()> {
         Ok(())
     }
+
+    // there are no chains
+    fn list_chains(&self) -> NetavarkResult<Vec<firewall::FirewallChain>> {
+        Ok(Vec::new())
+    }
+
+    // there are no rules to persist
+    fn persist_rules(&self, _dir: &Path) -> NetavarkResult<()> {
+        Ok(())
+    }
+
+    fn emit_script(
+        &self,
+        _network_setup: &SetupNetwork,
+        _setup_pw: &PortForwardConfig,
+        _script: &mut Script,
+    ) -> NetavarkResult<()> {
+        Ok(())
+    }
 }
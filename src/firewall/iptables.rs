@@ -1,20 +1,42 @@
 use crate::error::{NetavarkError, NetavarkResult};
 use crate::firewall;
 use crate::firewall::firewalld;
+use crate::firewall::varktables::helpers::{
+    command_error, filter_saved_rules, parse_accounting_rules, parse_iptables_version,
+    parse_netavark_chains, parse_port_forwards, retry_on_lock,
+};
 use crate::firewall::varktables::types::TeardownPolicy::OnComplete;
 use crate::firewall::varktables::types::{
-    create_network_chains, get_network_chains, get_port_forwarding_chains, TeardownPolicy,
+    create_network_chains, get_network_chains, get_port_forwarding_chains, network_chains_exist,
+    script_network_chains, TeardownPolicy, MANGLE, NETAVARK_ACCOUNTING,
 };
+use crate::network::constants::{IP6TABLES_PATH_ENV, IPTABLES_PATH_ENV};
+use crate::network::core_utils;
 use crate::network::internal_types::{
     PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
 };
+use crate::network::script::Script;
 use crate::network::types;
+use crate::wrap;
+use fs2::FileExt;
 use iptables;
 use iptables::IPTables;
 use log::{debug, warn};
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::process::Command;
 use zbus::blocking::Connection;
 
 pub(crate) const MAX_HASH_SIZE: usize = 13;
+/// mark bit of the port forwarding traffic which is masqueraded, the fwmark
+/// network option must not change it
+pub(crate) const HOSTPORT_MARK: u32 = 0x2000;
+
+/// iptables-restore file written by persist_rules()
+pub const PERSIST_RULES_FILE: &str = "netavark.rules";
+/// ip6tables-restore file written by persist_rules()
+pub const PERSIST_RULES6_FILE: &str = "netavark6.rules";
+const PERSIST_LOCK_FILE: &str = ".lock";
 
 // Iptables driver - uses direct iptables commands via the iptables crate.
 pub struct IptablesDriver {
@@ -24,14 +46,8 @@ pub struct IptablesDriver {
 
 pub fn new() -> NetavarkResult<Box<dyn firewall::FirewallDriver>> {
     // create an iptables connection
-    let ipt = match iptables::new(false) {
-        Ok(i) => i,
-        Err(e) => return Err(NetavarkError::Message(e.to_string())),
-    };
-    let ipt6 = match iptables::new(true) {
-        Ok(i) => i,
-        Err(e) => return Err(NetavarkError::Message(e.to_string())),
-    };
+    let ipt = connect(false)?;
+    let ipt6 = connect(true)?;
     let driver = IptablesDriver {
         conn: ipt,
         conn6: ipt6,
@@ -39,9 +55,211 @@ pub fn new() -> NetavarkResult<Box<dyn firewall::FirewallDriver>> {
     Ok(Box::new(driver))
 }
 
+/// The `-S` output of all rules of the table.
+fn list_rules(conn: &IPTables, table: &str) -> NetavarkResult<String> {
+    let output = match retry_on_lock(|| conn.execute(table, "-S")) {
+        Ok(o) => o,
+        Err(e) => return Err(command_error(conn, &["-t", table, "-S"], e)),
+    };
+    if !output.status.success() {
+        return Err(NetavarkError::Message(format!(
+            "command `{} -t {} -S` failed with {}: {}",
+            conn.cmd,
+            table,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn connect(is_ipv6: bool) -> NetavarkResult<IPTables> {
+    let env_var = if is_ipv6 {
+        IP6TABLES_PATH_ENV
+    } else {
+        IPTABLES_PATH_ENV
+    };
+    let path = match core_utils::get_binary_path(env_var)? {
+        Some(path) => path,
+        None => return iptables::new(is_ipv6).map_err(|e| NetavarkError::Message(e.to_string())),
+    };
+    // the iptables crate only looks up the binary in PATH, detect the
+    // supported flags the same way it does
+    let output = wrap!(
+        Command::new(&path).arg("--version").output(),
+        format!("failed to run {}", path)
+    )?;
+    let version = match parse_iptables_version(&String::from_utf8_lossy(&output.stdout)) {
+        Some(version) => version,
+        None => {
+            return Err(NetavarkError::Message(format!(
+                "failed to get the version of {}",
+                path
+            )))
+        }
+    };
+    debug!("using {} version {:?}", path, version);
+    Ok(IPTables {
+        // created at most twice per process
+        cmd: Box::leak(path.into_boxed_str()),
+        has_check: version > (1, 4, 10),
+        has_wait: version > (1, 4, 19),
+        is_numeric: false,
+    })
+}
+
 impl firewall::FirewallDriver for IptablesDriver {
+    fn driver_name(&self) -> &str {
+        firewall::IPTABLES
+    }
+
+    fn accounting_counters(&self) -> NetavarkResult<Vec<firewall::AccountingCounter>> {
+        let mut counters = Vec::new();
+        for conn in [&self.conn, &self.conn6] {
+            let args = format!("-S {} -v", NETAVARK_ACCOUNTING);
+            let output = match retry_on_lock(|| conn.execute(MANGLE, &args)) {
+                Ok(o) => o,
+                Err(e) => {
+                    return Err(command_error(
+                        conn,
+                        &["-t", MANGLE, "-S", NETAVARK_ACCOUNTING, "-v"],
+                        e,
+                    ))
+                }
+            };
+            // the chain only exists once a container used the accounting option
+            if output.status.success() {
+                counters.extend(parse_accounting_rules(&String::from_utf8_lossy(
+                    &output.stdout,
+                )));
+            }
+        }
+        Ok(counters)
+    }
+
+    fn list_chains(&self) -> NetavarkResult<Vec<firewall::FirewallChain>> {
+        let mut chains = Vec::new();
+        for (conn, ipv6) in [(&self.conn, false), (&self.conn6, true)] {
+            for table in ["filter", "nat", MANGLE, "raw"] {
+                for (name, rules) in parse_netavark_chains(&list_rules(conn, table)?) {
+                    chains.push(firewall::FirewallChain {
+                        table: table.to_string(),
+                        name,
+                        ipv6,
+                        rules,
+                    });
+                }
+            }
+        }
+        Ok(chains)
+    }
+
+    fn list_port_forwards(&self) -> NetavarkResult<Vec<firewall::FirewallPortForward>> {
+        let mut forwards = Vec::new();
+        for (conn, ipv6) in [(&self.conn, false), (&self.conn6, true)] {
+            forwards.extend(parse_port_forwards(&list_rules(conn, "nat")?, ipv6));
+        }
+        Ok(forwards)
+    }
+
+    fn persist_rules(&self, dir: &Path) -> NetavarkResult<()> {
+        wrap!(
+            fs::create_dir_all(dir),
+            format!("create rules directory {:?}", dir)
+        )?;
+        // serialize with other netavark processes so the last write has the latest rules
+        let lock_path = dir.join(PERSIST_LOCK_FILE);
+        let lock = wrap!(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&lock_path),
+            format!("open rules lock file {:?}", lock_path)
+        )?;
+        wrap!(
+            lock.lock_exclusive(),
+            format!("lock rules lock file {:?}", lock_path)
+        )?;
+
+        for (conn, file_name) in [
+            (&self.conn, PERSIST_RULES_FILE),
+            (&self.conn6, PERSIST_RULES6_FILE),
+        ] {
+            let save = format!("{}-save", conn.cmd);
+            let output = wrap!(
+                Command::new(&save).output(),
+                format!("failed to run {}", save)
+            )?;
+            if !output.status.success() {
+                return Err(NetavarkError::Message(format!(
+                    "command `{}` failed with {}: {}",
+                    save,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim_end()
+                )));
+            }
+            let rules = filter_saved_rules(&String::from_utf8_lossy(&output.stdout));
+
+            // replace the file atomically, a reboot must never find a partial file
+            let path = dir.join(file_name);
+            let tmp_path = dir.join(format!(".{}.tmp", file_name));
+            wrap!(
+                fs::write(&tmp_path, rules).and_then(|_| fs::rename(&tmp_path, &path)),
+                format!("write rules file {:?}", path)
+            )?;
+            debug!("persisted {} rules to {:?}", conn.cmd, path);
+        }
+        Ok(())
+    }
+
+    fn emit_script(
+        &self,
+        network_setup: &SetupNetwork,
+        setup_pw: &PortForwardConfig,
+        script: &mut Script,
+    ) -> NetavarkResult<()> {
+        let interface = match &network_setup.net.network_interface {
+            Some(iface) => iface,
+            None => return Err(NetavarkError::msg("failed to get interface")),
+        };
+        for network in &network_setup.firewall_subnets() {
+            let is_ipv6 = network.subnet.network().is_ipv6();
+            let conn = if is_ipv6 { &self.conn6 } else { &self.conn };
+            let chains = get_network_chains(
+                conn,
+                network.subnet,
+                is_ipv6,
+                interface.to_string(),
+                network_setup,
+            );
+            script_network_chains(&chains, script)?;
+        }
+
+        for (ip, subnet, conn, is_ipv6) in [
+            (
+                setup_pw.container_ip_v4,
+                setup_pw.subnet_v4,
+                &self.conn,
+                false,
+            ),
+            (
+                setup_pw.container_ip_v6,
+                setup_pw.subnet_v6,
+                &self.conn6,
+                true,
+            ),
+        ] {
+            if let (Some(ip), Some(subnet)) = (ip, subnet) {
+                let chains = get_port_forwarding_chains(conn, setup_pw, &ip, &subnet, is_ipv6)?;
+                script_network_chains(&chains, script)?;
+            }
+        }
+        Ok(())
+    }
+
     fn setup_network(&self, network_setup: SetupNetwork) -> NetavarkResult<()> {
-        let interface = match network_setup.net.network_interface {
+        let interface = match &network_setup.net.network_interface {
             Some(iface) => iface,
             None => {
                 return Err(std::io::Error::new(
@@ -52,35 +270,55 @@ impl firewall::FirewallDriver for IptablesDriver {
             }
         };
 
-        if let Some(subnet) = network_setup.net.subnets {
-            for network in subnet {
-                let is_ipv6 = network.subnet.network().is_ipv6();
-                let mut conn = &self.conn;
-                if is_ipv6 {
-                    conn = &self.conn6;
-                }
+        for network in &network_setup.firewall_subnets() {
+            let is_ipv6 = network.subnet.network().is_ipv6();
+            let mut conn = &self.conn;
+            if is_ipv6 {
+                conn = &self.conn6;
+            }
+
+            let chains = get_network_chains(
+                conn,
+                network.subnet,
+                is_ipv6,
+                interface.to_string(),
+                &network_setup,
+            );
 
-                let chains = get_network_chains(
-                    conn,
-                    network.subnet,
-                    &network_setup.network_hash_name,
-                    is_ipv6,
-                    interface.to_string(),
-                    network_setup.isolation,
-                );
+            create_network_chains(chains)?;
+
+            add_firewalld_if_possible(network);
+        }
+        Ok(())
+    }
 
-                create_network_chains(chains)?;
+    fn network_is_set_up(&self, network_setup: &SetupNetwork) -> NetavarkResult<bool> {
+        let interface = match &network_setup.net.network_interface {
+            Some(iface) => iface,
+            None => return Ok(false),
+        };
 
-                add_firewalld_if_possible(&network);
+        for network in &network_setup.firewall_subnets() {
+            let is_ipv6 = network.subnet.network().is_ipv6();
+            let conn = if is_ipv6 { &self.conn6 } else { &self.conn };
+            let chains = get_network_chains(
+                conn,
+                network.subnet,
+                is_ipv6,
+                interface.to_string(),
+                network_setup,
+            );
+            if !network_chains_exist(&chains)? {
+                return Ok(false);
             }
         }
-        Ok(())
+        Ok(true)
     }
 
     // teardown_network should only be called in the case of
     // a complete teardown.
     fn teardown_network(&self, tear: TearDownNetwork) -> NetavarkResult<()> {
-        let interface = match tear.config.net.network_interface {
+        let interface = match &tear.config.net.network_interface {
             Some(iface) => iface,
             None => {
                 return Err(std::io::Error::new(
@@ -92,39 +330,34 @@ impl firewall::FirewallDriver for IptablesDriver {
         };
 
         // Remove network specific general NAT rules
-        if let Some(subnet) = tear.config.net.subnets {
-            for network in subnet {
-                let is_ipv6 = network.subnet.network().is_ipv6();
-                let mut conn = &self.conn;
-                if is_ipv6 {
-                    conn = &self.conn6;
-                }
-                let chains = get_network_chains(
-                    conn,
-                    network.subnet,
-                    &tear.config.network_hash_name,
-                    is_ipv6,
-                    interface.to_string(),
-                    tear.config.isolation,
-                );
-
-                for c in &chains {
-                    c.remove_rules(tear.complete_teardown)?;
-                }
-                for c in chains {
-                    match &c.td_policy {
-                        None => {}
-                        Some(policy) => {
-                            if tear.complete_teardown && *policy == OnComplete {
-                                c.remove()?;
-                            }
-                        }
+        for network in &tear.config.firewall_subnets() {
+            let is_ipv6 = network.subnet.network().is_ipv6();
+            let mut conn = &self.conn;
+            if is_ipv6 {
+                conn = &self.conn6;
+            }
+            let chains = get_network_chains(
+                conn,
+                network.subnet,
+                is_ipv6,
+                interface.to_string(),
+                &tear.config,
+            );
+
+            for c in &chains {
+                c.remove_rules(tear.complete_teardown)?;
+            }
+            for c in chains {
+                match &c.td_policy {
+                    Some(policy) if tear.complete_teardown && *policy == OnComplete => {
+                        c.remove()?;
                     }
+                    _ => {}
                 }
+            }
 
-                if tear.complete_teardown {
-                    rm_firewalld_if_possible(&network)
-                }
+            if tear.complete_teardown {
+                rm_firewalld_if_possible(network)
             }
         }
         Result::Ok(())
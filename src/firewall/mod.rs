@@ -1,9 +1,14 @@
 use crate::error::{NetavarkError, NetavarkResult};
+use crate::network::core_utils;
 use crate::network::internal_types::{
     PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
 };
-use log::{debug, info};
+use crate::network::script::Script;
+use log::{debug, info, warn};
 use std::env;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
 use zbus::blocking::Connection;
 
 pub mod firewalld;
@@ -11,18 +16,261 @@ pub mod fwnone;
 pub mod iptables;
 mod varktables;
 
+// firewall driver names as used by NETAVARK_FW
+pub const IPTABLES: &str = "iptables";
+pub const FIREWALLD: &str = "firewalld";
+pub const NFTABLES: &str = "nftables";
+pub const NONE: &str = "none";
+
+/// Traffic sent by a container of a network with the accounting option.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AccountingCounter {
+    pub container_id: String,
+    pub network: String,
+    pub address: IpAddr,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Netavark chain of the firewall with the number of rules in it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FirewallChain {
+    pub table: String,
+    pub name: String,
+    pub ipv6: bool,
+    pub rules: usize,
+}
+
+/// Port forwarding found in the firewall rules.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FirewallPortForward {
+    /// None when no rule for the host port jumps to the DNAT rule
+    pub container_id: Option<String>,
+    pub network: Option<String>,
+    /// chain with the DNAT rule
+    pub chain: String,
+    pub protocol: String,
+    /// None for all addresses
+    pub host_ip: Option<IpAddr>,
+    pub host_port: u16,
+    pub range: u16,
+    /// address and port of the container
+    pub destination: String,
+    pub ipv6: bool,
+}
+
 /// Firewall drivers have the ability to set up per-network firewall forwarding
 /// and port mappings.
-pub trait FirewallDriver {
+pub trait FirewallDriver: Send + Sync {
     /// Set up firewall rules for the given network,
     fn setup_network(&self, network_setup: SetupNetwork) -> NetavarkResult<()>;
     /// Tear down firewall rules for the given network.
     fn teardown_network(&self, tear: TearDownNetwork) -> NetavarkResult<()>;
+    /// Check if all rules of setup_network() are in place, so another container
+    /// of the network only needs its own rules. Drivers which cannot check it
+    /// always set up the network again.
+    fn network_is_set_up(&self, _network_setup: &SetupNetwork) -> NetavarkResult<bool> {
+        Ok(false)
+    }
 
     /// Set up port-forwarding firewall rules for a given container.
     fn setup_port_forward(&self, setup_pw: PortForwardConfig) -> NetavarkResult<()>;
     /// Tear down port-forwarding firewall rules for a single container.
     fn teardown_port_forward(&self, teardown_pf: TeardownPortForward) -> NetavarkResult<()>;
+
+    /// Name of the firewall driver.
+    fn driver_name(&self) -> &str;
+
+    /// Write the current netavark rules into the given directory so that they
+    /// can be restored at boot, drivers which cannot do this only log it.
+    fn persist_rules(&self, _dir: &Path) -> NetavarkResult<()> {
+        warn!(
+            "persisting firewall rules is not supported by the {} firewall driver",
+            self.driver_name()
+        );
+        Ok(())
+    }
+
+    /// Read the counters of the accounting rules of all containers.
+    fn accounting_counters(&self) -> NetavarkResult<Vec<AccountingCounter>> {
+        Err(NetavarkError::Message(format!(
+            "accounting is not supported by the {} firewall driver",
+            self.driver_name()
+        )))
+    }
+
+    /// List the netavark chains without changing anything.
+    fn list_chains(&self) -> NetavarkResult<Vec<FirewallChain>> {
+        Err(NetavarkError::Message(format!(
+            "listing the chains is not supported by the {} firewall driver",
+            self.driver_name()
+        )))
+    }
+
+    /// List the port forwardings of the firewall rules without changing anything.
+    fn list_port_forwards(&self) -> NetavarkResult<Vec<FirewallPortForward>> {
+        Err(NetavarkError::Message(format!(
+            "listing the port forwardings is not supported by the {} firewall driver",
+            self.driver_name()
+        )))
+    }
+
+    /// Add the commands of setup_network() and setup_port_forward() to the
+    /// script instead of running them.
+    fn emit_script(
+        &self,
+        _network_setup: &SetupNetwork,
+        _setup_pw: &PortForwardConfig,
+        _script: &mut Script,
+    ) -> NetavarkResult<()> {
+        Err(NetavarkError::Message(format!(
+            "--emit-script is not supported by the {} firewall driver",
+            self.driver_name()
+        )))
+    }
+}
+
+/// Firewall driver which runs only one operation of the wrapped driver at a time,
+/// used when networks are set up in parallel. The drivers check for existing
+/// rules and chains before adding them which is racy when done concurrently.
+pub struct SerialFirewall<'a> {
+    driver: &'a dyn FirewallDriver,
+    lock: Mutex<()>,
+}
+
+impl<'a> SerialFirewall<'a> {
+    pub fn new(driver: &'a dyn FirewallDriver) -> Self {
+        SerialFirewall {
+            driver,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn serial<T>(&self, f: impl FnOnce() -> T) -> T {
+        // a poisoned lock only means another setup panicked, the rules are still fine
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    }
+}
+
+impl<'a> FirewallDriver for SerialFirewall<'a> {
+    fn setup_network(&self, network_setup: SetupNetwork) -> NetavarkResult<()> {
+        self.serial(|| self.driver.setup_network(network_setup))
+    }
+
+    fn teardown_network(&self, tear: TearDownNetwork) -> NetavarkResult<()> {
+        self.serial(|| self.driver.teardown_network(tear))
+    }
+
+    fn network_is_set_up(&self, network_setup: &SetupNetwork) -> NetavarkResult<bool> {
+        self.serial(|| self.driver.network_is_set_up(network_setup))
+    }
+
+    fn setup_port_forward(&self, setup_pw: PortForwardConfig) -> NetavarkResult<()> {
+        self.serial(|| self.driver.setup_port_forward(setup_pw))
+    }
+
+    fn teardown_port_forward(&self, teardown_pf: TeardownPortForward) -> NetavarkResult<()> {
+        self.serial(|| self.driver.teardown_port_forward(teardown_pf))
+    }
+
+    fn driver_name(&self) -> &str {
+        self.driver.driver_name()
+    }
+
+    fn persist_rules(&self, dir: &Path) -> NetavarkResult<()> {
+        self.serial(|| self.driver.persist_rules(dir))
+    }
+
+    fn accounting_counters(&self) -> NetavarkResult<Vec<AccountingCounter>> {
+        self.serial(|| self.driver.accounting_counters())
+    }
+
+    fn list_chains(&self) -> NetavarkResult<Vec<FirewallChain>> {
+        self.serial(|| self.driver.list_chains())
+    }
+
+    fn list_port_forwards(&self) -> NetavarkResult<Vec<FirewallPortForward>> {
+        self.serial(|| self.driver.list_port_forwards())
+    }
+
+    fn emit_script(
+        &self,
+        network_setup: &SetupNetwork,
+        setup_pw: &PortForwardConfig,
+        script: &mut Script,
+    ) -> NetavarkResult<()> {
+        self.driver.emit_script(network_setup, setup_pw, script)
+    }
+}
+
+/// Firewall driver which refuses every change of the wrapped driver, used in
+/// read-only mode. Reading the rules is still allowed.
+struct ReadOnlyFirewall {
+    driver: Box<dyn FirewallDriver>,
+}
+
+impl FirewallDriver for ReadOnlyFirewall {
+    fn setup_network(&self, network_setup: SetupNetwork) -> NetavarkResult<()> {
+        core_utils::check_read_only(format_args!(
+            "set up the firewall rules of network {}",
+            network_setup.net.name
+        ))
+    }
+
+    fn teardown_network(&self, tear: TearDownNetwork) -> NetavarkResult<()> {
+        core_utils::check_read_only(format_args!(
+            "tear down the firewall rules of network {}",
+            tear.config.net.name
+        ))
+    }
+
+    fn network_is_set_up(&self, network_setup: &SetupNetwork) -> NetavarkResult<bool> {
+        self.driver.network_is_set_up(network_setup)
+    }
+
+    fn setup_port_forward(&self, setup_pw: PortForwardConfig) -> NetavarkResult<()> {
+        core_utils::check_read_only(format_args!(
+            "set up the port forwarding of container {}",
+            setup_pw.container_id
+        ))
+    }
+
+    fn teardown_port_forward(&self, teardown_pf: TeardownPortForward) -> NetavarkResult<()> {
+        core_utils::check_read_only(format_args!(
+            "tear down the port forwarding of container {}",
+            teardown_pf.config.container_id
+        ))
+    }
+
+    fn driver_name(&self) -> &str {
+        self.driver.driver_name()
+    }
+
+    fn persist_rules(&self, dir: &Path) -> NetavarkResult<()> {
+        core_utils::check_read_only(format_args!("persist the firewall rules to {:?}", dir))
+    }
+
+    fn accounting_counters(&self) -> NetavarkResult<Vec<AccountingCounter>> {
+        self.driver.accounting_counters()
+    }
+
+    fn list_chains(&self) -> NetavarkResult<Vec<FirewallChain>> {
+        self.driver.list_chains()
+    }
+
+    fn list_port_forwards(&self) -> NetavarkResult<Vec<FirewallPortForward>> {
+        self.driver.list_port_forwards()
+    }
+
+    fn emit_script(
+        &self,
+        network_setup: &SetupNetwork,
+        setup_pw: &PortForwardConfig,
+        script: &mut Script,
+    ) -> NetavarkResult<()> {
+        self.driver.emit_script(network_setup, setup_pw, script)
+    }
 }
 
 /// Types of firewall backend
@@ -40,7 +288,7 @@ fn get_firewall_impl() -> NetavarkResult<FirewallImpl> {
     if let Ok(var) = env::var("NETAVARK_FW") {
         debug!("Forcibly using firewall driver {}", var);
         match var.to_lowercase().as_str() {
-            "firewalld" => {
+            FIREWALLD => {
                 let conn = match Connection::system() {
                     Ok(c) => c,
                     Err(e) => {
@@ -52,9 +300,9 @@ fn get_firewall_impl() -> NetavarkResult<FirewallImpl> {
                 };
                 return Ok(FirewallImpl::Firewalld(conn));
             }
-            "iptables" => return Ok(FirewallImpl::Iptables),
-            "nftables" => return Ok(FirewallImpl::Nftables),
-            "none" => return Ok(FirewallImpl::Fwnone),
+            IPTABLES => return Ok(FirewallImpl::Iptables),
+            NFTABLES => return Ok(FirewallImpl::Nftables),
+            NONE => return Ok(FirewallImpl::Fwnone),
             any => {
                 return Err(NetavarkError::Message(format!(
                     "Must provide a valid firewall backend, got {}",
@@ -88,6 +336,14 @@ fn get_firewall_impl() -> NetavarkResult<FirewallImpl> {
 /// Get the preferred firewall implementation for the current system
 /// configuration.
 pub fn get_supported_firewall_driver() -> NetavarkResult<Box<dyn FirewallDriver>> {
+    let driver = get_firewall_driver()?;
+    if core_utils::is_read_only() {
+        return Ok(Box::new(ReadOnlyFirewall { driver }));
+    }
+    Ok(driver)
+}
+
+fn get_firewall_driver() -> NetavarkResult<Box<dyn FirewallDriver>> {
     match get_firewall_impl() {
         Ok(fw) => match fw {
             FirewallImpl::Iptables => {
@@ -1,6 +1,145 @@
 use crate::error::{NetavarkError, NetavarkResult};
-use iptables::IPTables;
-use log::debug;
+use crate::firewall::varktables::types::{CONTAINER_DN_CHAIN, NETAVARK_HOSTPORT_DNAT};
+use crate::firewall::{AccountingCounter, FirewallPortForward};
+use crate::metrics;
+use ipnet::IpNet;
+use iptables::{error::IptablesError, IPTables};
+use log::{debug, warn};
+use std::env;
+use std::error::Error;
+use std::process::Output;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// env var with the number of seconds to retry iptables commands which failed
+/// because another process holds the xtables lock, 0 disables the retries
+pub const XTABLES_LOCK_TIMEOUT_ENV: &str = "NETAVARK_XTABLES_LOCK_TIMEOUT";
+const DEFAULT_XTABLES_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_LOCK_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Create the error for a failed iptables invocation. It contains the exact
+/// command line and the stderr output so that the failure can be reproduced.
+pub fn command_error(driver: &IPTables, args: &[&str], err: Box<dyn Error>) -> NetavarkError {
+    let mut command = vec![driver.cmd];
+    command.extend_from_slice(args);
+    if driver.has_wait {
+        command.push("--wait");
+    }
+    let command = command.join(" ");
+    match err.downcast_ref::<IptablesError>() {
+        Some(e) => NetavarkError::Message(format!(
+            "command `{}` failed with exit code {}: {}",
+            command,
+            e.code,
+            e.msg.trim_end()
+        )),
+        None => NetavarkError::Message(format!("command `{}` failed: {}", command, err)),
+    }
+}
+
+/// Version from the `iptables --version` output, e.g. "iptables v1.8.8 (nf_tables)".
+pub fn parse_iptables_version(output: &str) -> Option<(u32, u32, u32)> {
+    output.split_whitespace().find_map(|word| {
+        let mut parts = word.strip_prefix('v')?.splitn(3, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next()?.parse().ok()?;
+        Some((major, minor, patch))
+    })
+}
+
+fn xtables_lock_timeout() -> Duration {
+    static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+    *TIMEOUT.get_or_init(|| match env::var(XTABLES_LOCK_TIMEOUT_ENV) {
+        Ok(val) => match val.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(e) => {
+                warn!(
+                    "invalid {} \"{}\": {}, using {}s",
+                    XTABLES_LOCK_TIMEOUT_ENV,
+                    val,
+                    e,
+                    DEFAULT_XTABLES_LOCK_TIMEOUT.as_secs()
+                );
+                DEFAULT_XTABLES_LOCK_TIMEOUT
+            }
+        },
+        Err(_) => DEFAULT_XTABLES_LOCK_TIMEOUT,
+    })
+}
+
+/// iptables exits with 4 when it could not get the xtables lock, iptables-nft
+/// reports concurrent updates of the ruleset the same way.
+fn is_lock_error(err: &(dyn Error + 'static)) -> bool {
+    match err.downcast_ref::<IptablesError>() {
+        Some(e) => {
+            e.code == 4
+                && (e.msg.contains("xtables lock")
+                    || e.msg.contains("Resource temporarily unavailable"))
+        }
+        None => false,
+    }
+}
+
+/// Call `f` again with a growing delay as long as it fails because the
+/// xtables lock is held, until the NETAVARK_XTABLES_LOCK_TIMEOUT is reached.
+pub fn retry_on_lock<T>(f: impl FnMut() -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+    retry_on_lock_timeout(xtables_lock_timeout(), f)
+}
+
+fn retry_on_lock_timeout<T>(
+    timeout: Duration,
+    mut f: impl FnMut() -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(50);
+    loop {
+        match f() {
+            Err(e) if is_lock_error(e.as_ref()) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(e);
+                }
+                debug!("xtables lock is held, retry in {:?}", delay);
+                thread::sleep(delay.min(deadline - now));
+                delay = (delay * 2).min(MAX_LOCK_RETRY_DELAY);
+            }
+            res => return res,
+        }
+    }
+}
+
+fn output_to_result(output: Output) -> Result<Output, Box<dyn Error>> {
+    if !output.status.success() {
+        return Err(Box::new(IptablesError::from(output)));
+    }
+    Ok(output)
+}
+
+// check if the rule exists in the chain, the exists() API of the iptables
+// crate maps every failure to false so a held lock cannot be detected
+pub fn rule_exists(
+    driver: &IPTables,
+    table: &str,
+    chain: &str,
+    rule: &str,
+) -> Result<bool, Box<dyn Error>> {
+    if !driver.has_check {
+        return retry_on_lock(|| driver.exists(table, chain, rule));
+    }
+    retry_on_lock(|| {
+        let output = driver.execute(table, &format!("-C {} {}", chain, rule))?;
+        if output.status.success() {
+            return Ok(true);
+        }
+        let err: Box<dyn Error> = Box::new(IptablesError::from(output));
+        if is_lock_error(err.as_ref()) {
+            return Err(err);
+        }
+        Ok(false)
+    })
+}
 
 // append a rule to chain if it does not exist
 // Note: While there is an API provided for this exact thing, the API returns
@@ -12,22 +151,22 @@ pub fn append_unique(
     chain: &str,
     rule: &str,
 ) -> NetavarkResult<()> {
-    let exists = match driver.exists(table, chain, rule) {
+    let exists = match rule_exists(driver, table, chain, rule) {
         Ok(b) => b,
-        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+        Err(e) => return Err(command_error(driver, &["-t", table, "-C", chain, rule], e)),
     };
     if exists {
         debug_rule_exists(table, chain, rule.to_string());
         return Ok(());
     }
-    if let Err(e) = driver
-        .append(table, chain, rule)
-        .map(|_| debug_rule_create(table, chain, rule.to_string()))
-    {
-        return Err(NetavarkError::Message(format!(
-            "unable to append rule '{}' to table '{}': {}",
-            rule, table, e,
-        )));
+    if let Err(e) = retry_on_lock(|| driver.append(table, chain, rule)).map(|_| {
+        metrics::rule_programmed();
+        debug_rule_create(table, chain, rule.to_string())
+    }) {
+        return Err(NetavarkError::wrap(
+            "unable to append rule",
+            command_error(driver, &["-t", table, "-A", chain, rule], e),
+        ));
     }
     Result::Ok(())
 }
@@ -42,22 +181,26 @@ pub fn add_chain_unique(driver: &IPTables, table: &str, new_chain: &str) -> Neta
         debug_chain_exists(table, new_chain);
         return Ok(());
     }
-    match driver
-        .new_chain(table, new_chain)
+    match retry_on_lock(|| driver.new_chain(table, new_chain))
         .map(|_| debug_chain_create(table, new_chain))
     {
         Ok(_) => Ok(()),
-        Err(e) => Err(NetavarkError::Message(e.to_string())),
+        Err(e) => Err(command_error(driver, &["-t", table, "-N", new_chain], e)),
     }
 }
 
 // returns a bool as to whether the chain exists
-fn chain_exists(driver: &IPTables, table: &str, chain: &str) -> NetavarkResult<bool> {
-    let c = match driver.list_chains(table) {
-        Ok(b) => b,
-        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+pub fn chain_exists(driver: &IPTables, table: &str, chain: &str) -> NetavarkResult<bool> {
+    // list_chains() of the iptables crate ignores errors, use the output directly
+    let output = match retry_on_lock(|| driver.execute(table, "-S").and_then(output_to_result)) {
+        Ok(o) => o,
+        Err(e) => return Err(command_error(driver, &["-t", table, "-S"], e)),
     };
-    if c.iter().any(|i| i == chain) {
+    let output = String::from_utf8_lossy(&output.stdout);
+    if output.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        matches!(fields.next(), Some("-N") | Some("-P")) && fields.next() == Some(chain)
+    }) {
         debug_chain_exists(table, chain);
         return serde::__private::Result::Ok(true);
     }
@@ -71,19 +214,19 @@ pub fn remove_if_rule_exists(
     rule: &str,
 ) -> NetavarkResult<()> {
     // If the rule is not present, do not error
-    let exists = match driver.exists(table, chain, rule) {
+    let exists = match rule_exists(driver, table, chain, rule) {
         Ok(b) => b,
-        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+        Err(e) => return Err(command_error(driver, &["-t", table, "-C", chain, rule], e)),
     };
     if !exists {
         debug_rule_no_exists(table, chain, rule.to_string());
         return Ok(());
     }
-    if let Err(e) = driver.delete(table, chain, rule) {
-        return Err(NetavarkError::Message(format!(
-            "failed to remove rule '{}' from table '{}': {}",
-            rule, chain, e
-        )));
+    if let Err(e) = retry_on_lock(|| driver.delete(table, chain, rule)) {
+        return Err(NetavarkError::wrap(
+            "failed to remove rule",
+            command_error(driver, &["-t", table, "-D", chain, rule], e),
+        ));
     }
     Result::Ok(())
 }
@@ -116,3 +259,381 @@ fn debug_rule_no_exists(table: &str, chain: &str, rule: String) {
         rule, table, chain
     );
 }
+
+/// Keep only the netavark chains and the rules jumping into them from the
+/// output of iptables-save. The result can be loaded with `iptables-restore
+/// --noflush` without touching rules of other programs, tables without any
+/// netavark rules are dropped.
+pub fn filter_saved_rules(saved: &str) -> String {
+    let mut out = String::new();
+    let mut table: Option<&str> = None;
+    let mut lines: Vec<&str> = Vec::new();
+    for line in saved.lines() {
+        if line.starts_with('*') {
+            table = Some(line);
+            lines.clear();
+        } else if line == "COMMIT" {
+            if let (Some(t), false) = (table, lines.is_empty()) {
+                out.push_str(t);
+                out.push('\n');
+                for l in &lines {
+                    out.push_str(l);
+                    out.push('\n');
+                }
+                out.push_str("COMMIT\n");
+            }
+            table = None;
+        } else if line.starts_with(":NETAVARK")
+            || line.starts_with("-A NETAVARK")
+            || (line.starts_with("-A ") && line.contains(" -j NETAVARK"))
+        {
+            lines.push(line);
+        }
+    }
+    out
+}
+
+/// Get the netavark chains and the number of their rules from the
+/// `iptables -S` output of a table.
+pub fn parse_netavark_chains(output: &str) -> Vec<(String, usize)> {
+    let mut chains: Vec<(String, usize)> = Vec::new();
+    for line in output.lines() {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some("-N"), Some(name)) if name.starts_with("NETAVARK") => {
+                chains.push((name.to_string(), 0));
+            }
+            (Some("-A"), Some(name)) => {
+                if let Some(chain) = chains.iter_mut().find(|(n, _)| n == name) {
+                    chain.1 += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    chains
+}
+
+/// Get the counters of the container rules from the `iptables -S -v` output
+/// of the accounting chain, e.g.
+/// -A NETAVARK-ACCOUNTING -s 10.88.0.2/32 -m comment --comment "accounting name: podman id: 123" -c 12 3456 -j RETURN
+pub fn parse_accounting_rules(output: &str) -> Vec<AccountingCounter> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (_, comment) = line.split_once("--comment \"accounting name: ")?;
+            let (comment, _) = comment.split_once('"')?;
+            let (network, container_id) = comment.split_once(" id: ")?;
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let value_of = |flag: &str| {
+                fields
+                    .iter()
+                    .position(|f| *f == flag)
+                    .and_then(|i| fields.get(i + 1))
+            };
+            let address = value_of("-s")?.parse::<IpNet>().ok()?.addr();
+            let counters = fields.iter().position(|f| *f == "-c")?;
+            Some(AccountingCounter {
+                container_id: container_id.to_string(),
+                network: network.to_string(),
+                address,
+                packets: fields.get(counters + 1)?.parse().ok()?,
+                bytes: fields.get(counters + 2)?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Get the port forwardings from the `iptables -t nat -S` output. The DNAT
+/// rules of the NETAVARK-DN- chains are matched with the rules of
+/// NETAVARK-HOSTPORT-DNAT which jump to them, their comment names the
+/// container, e.g.
+/// -A NETAVARK-HOSTPORT-DNAT -p tcp -m tcp --dport 8080 -m comment --comment "dnat name: podman id: 123" -j NETAVARK-DN-1D8721804F16F
+/// -A NETAVARK-DN-1D8721804F16F -p tcp -m tcp --dport 8080 -j DNAT --to-destination 10.88.0.2:80
+pub fn parse_port_forwards(output: &str, ipv6: bool) -> Vec<FirewallPortForward> {
+    let value_of = |fields: &[&str], flag: &str| -> Option<String> {
+        fields
+            .iter()
+            .position(|f| *f == flag)
+            .and_then(|i| fields.get(i + 1))
+            .map(|v| v.to_string())
+    };
+
+    // jump chain, protocol, ports -> network, container id
+    let mut host_ports = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(..2) != Some(&["-A", NETAVARK_HOSTPORT_DNAT]) {
+            continue;
+        }
+        let comment = line
+            .split_once("--comment \"dnat name: ")
+            .and_then(|(_, c)| c.split_once('"'))
+            .and_then(|(c, _)| c.split_once(" id: "));
+        if let (Some((network, id)), Some(chain), Some(protocol), Some(ports)) = (
+            comment,
+            value_of(&fields, "-j"),
+            value_of(&fields, "-p"),
+            value_of(&fields, "--dport"),
+        ) {
+            host_ports.push((
+                (chain, protocol, ports),
+                (network.to_string(), id.to_string()),
+            ));
+        }
+    }
+
+    let mut forwards = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let chain = match fields.get(..2) {
+            Some(["-A", chain]) if chain.starts_with(CONTAINER_DN_CHAIN) => chain.to_string(),
+            _ => continue,
+        };
+        if value_of(&fields, "-j").as_deref() != Some("DNAT") {
+            continue;
+        }
+        let (protocol, ports, destination) = match (
+            value_of(&fields, "-p"),
+            value_of(&fields, "--dport"),
+            value_of(&fields, "--to-destination"),
+        ) {
+            (Some(p), Some(ports), Some(d)) => (p, ports, d),
+            _ => continue,
+        };
+        let (host_port, range) = match ports.split_once(':') {
+            Some((start, end)) => match (start.parse::<u16>(), end.parse::<u16>()) {
+                (Ok(start), Ok(end)) if end >= start => (start, end - start + 1),
+                _ => continue,
+            },
+            None => match ports.parse() {
+                Ok(port) => (port, 1),
+                Err(_) => continue,
+            },
+        };
+        let host_ip = value_of(&fields, "-d")
+            .and_then(|d| d.parse::<IpNet>().ok())
+            .map(|d| d.addr());
+        let owner = host_ports
+            .iter()
+            .find(|(key, _)| key.0 == chain && key.1 == protocol && key.2 == ports)
+            .map(|(_, owner)| owner.clone());
+        forwards.push(FirewallPortForward {
+            network: owner.as_ref().map(|o| o.0.clone()),
+            container_id: owner.map(|o| o.1),
+            chain,
+            protocol,
+            host_ip,
+            host_port,
+            range,
+            destination,
+            ipv6,
+        });
+    }
+    forwards
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_error() {
+        let driver = IPTables {
+            cmd: "iptables",
+            has_check: true,
+            has_wait: true,
+            is_numeric: false,
+        };
+        let err = IptablesError {
+            code: 1,
+            msg: "iptables: No chain/target/match by that name.\n".to_string(),
+        };
+        let args = ["-t", "nat", "-A", "NETAVARK-1", "-j", "ACCEPT"];
+        assert_eq!(
+            command_error(&driver, &args, Box::new(err)).to_string(),
+            "command `iptables -t nat -A NETAVARK-1 -j ACCEPT --wait` failed with exit code 1: \
+             iptables: No chain/target/match by that name."
+        );
+
+        let err = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert_eq!(
+            command_error(&driver, &["-t", "filter", "-S"], Box::new(err)).to_string(),
+            "command `iptables -t filter -S --wait` failed: entity not found"
+        );
+    }
+
+    #[test]
+    fn test_retry_on_lock() {
+        let lock_error = || -> Box<dyn Error> {
+            Box::new(IptablesError {
+                code: 4,
+                msg: "Another app is currently holding the xtables lock. Perhaps you want to use the -w option?\n".to_string(),
+            })
+        };
+
+        let mut calls = 0;
+        let res = retry_on_lock_timeout(Duration::from_secs(5), || {
+            calls += 1;
+            if calls < 3 {
+                return Err(lock_error());
+            }
+            Ok(calls)
+        });
+        assert_eq!(res.unwrap(), 3);
+
+        // other errors are not retried
+        let mut calls = 0;
+        let res: Result<(), _> = retry_on_lock_timeout(Duration::from_secs(5), || {
+            calls += 1;
+            Err(Box::new(IptablesError {
+                code: 1,
+                msg: "iptables: Bad rule.\n".to_string(),
+            }) as Box<dyn Error>)
+        });
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+
+        // the lock error is returned after the timeout
+        let mut calls = 0;
+        let res: Result<(), _> = retry_on_lock_timeout(Duration::ZERO, || {
+            calls += 1;
+            Err(lock_error())
+        });
+        assert!(is_lock_error(res.unwrap_err().as_ref()));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_parse_netavark_chains() {
+        let output = "-P INPUT ACCEPT
+-N NETAVARK-1D8721804F16F
+-N OTHER
+-N NETAVARK-DN-1D8721804F16F
+-A POSTROUTING -j NETAVARK-1D8721804F16F
+-A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT
+-A NETAVARK-1D8721804F16F ! -d 224.0.0.0/4 -j MASQUERADE
+-A OTHER -j ACCEPT
+";
+        assert_eq!(
+            parse_netavark_chains(output),
+            vec![
+                ("NETAVARK-1D8721804F16F".to_string(), 2),
+                ("NETAVARK-DN-1D8721804F16F".to_string(), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_port_forwards() {
+        let output = r#"-N NETAVARK-HOSTPORT-DNAT
+-N NETAVARK-DN-1D8721804F16F
+-A NETAVARK-HOSTPORT-DNAT -p tcp -m tcp --dport 8080 -m comment --comment "dnat name: podman id: 123" -j NETAVARK-DN-1D8721804F16F
+-A NETAVARK-HOSTPORT-DNAT -d 10.89.0.1/32 -p udp -m udp --dport 53 -j DNAT --to-destination 10.89.0.1:1153
+-A NETAVARK-DN-1D8721804F16F -s 10.88.0.0/16 -p tcp -m tcp --dport 8080 -j NETAVARK-HOSTPORT-SETMARK
+-A NETAVARK-DN-1D8721804F16F -p tcp -m tcp --dport 8080 -j DNAT --to-destination 10.88.0.2:80
+-A NETAVARK-DN-1D8721804F16F -d 127.0.0.1/32 -p udp -m udp --dport 9000:9001 -j DNAT --to-destination 10.88.0.3:90-91/9000
+"#;
+        assert_eq!(
+            parse_port_forwards(output, false),
+            vec![
+                FirewallPortForward {
+                    container_id: Some("123".to_string()),
+                    network: Some("podman".to_string()),
+                    chain: "NETAVARK-DN-1D8721804F16F".to_string(),
+                    protocol: "tcp".to_string(),
+                    host_ip: None,
+                    host_port: 8080,
+                    range: 1,
+                    destination: "10.88.0.2:80".to_string(),
+                    ipv6: false,
+                },
+                FirewallPortForward {
+                    container_id: None,
+                    network: None,
+                    chain: "NETAVARK-DN-1D8721804F16F".to_string(),
+                    protocol: "udp".to_string(),
+                    host_ip: Some("127.0.0.1".parse().unwrap()),
+                    host_port: 9000,
+                    range: 2,
+                    destination: "10.88.0.3:90-91/9000".to_string(),
+                    ipv6: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_iptables_version() {
+        assert_eq!(
+            parse_iptables_version("iptables v1.8.8 (nf_tables)\n"),
+            Some((1, 8, 8))
+        );
+        assert_eq!(
+            parse_iptables_version("ip6tables v1.4.21\n"),
+            Some((1, 4, 21))
+        );
+        assert_eq!(parse_iptables_version("iptables version unknown"), None);
+    }
+
+    #[test]
+    fn test_filter_saved_rules() {
+        let saved = "# Generated by iptables-save v1.8.8 on Mon Jan  2 10:00:00 2023
+*filter
+:INPUT ACCEPT [0:0]
+:FORWARD ACCEPT [0:0]
+:NETAVARK_FORWARD - [0:0]
+:OTHER - [0:0]
+-A FORWARD -m comment --comment \"netavark firewall plugin rules\" -j NETAVARK_FORWARD
+-A FORWARD -j OTHER
+-A NETAVARK_FORWARD -s 10.88.0.0/16 -j ACCEPT
+COMMIT
+*mangle
+:PREROUTING ACCEPT [0:0]
+-A PREROUTING -j MARK --set-mark 1
+COMMIT
+";
+        assert_eq!(
+            filter_saved_rules(saved),
+            "*filter
+:NETAVARK_FORWARD - [0:0]
+-A FORWARD -m comment --comment \"netavark firewall plugin rules\" -j NETAVARK_FORWARD
+-A NETAVARK_FORWARD -s 10.88.0.0/16 -j ACCEPT
+COMMIT
+"
+        );
+        assert_eq!(
+            filter_saved_rules("*nat\n:OUTPUT ACCEPT [0:0]\nCOMMIT\n"),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_parse_accounting_rules() {
+        let output = "-N NETAVARK-ACCOUNTING
+-A NETAVARK-ACCOUNTING -s 10.88.0.2/32 -m comment --comment \"accounting name: podman id: 123\" -c 12 3456 -j RETURN
+-A NETAVARK-ACCOUNTING -s fd10:88:a::2/128 -m comment --comment \"accounting name: podman1 id: 456\" -c 0 0 -j RETURN
+-A NETAVARK-ACCOUNTING -s 10.88.0.3/32 -c 1 2 -j RETURN
+";
+        assert_eq!(
+            parse_accounting_rules(output),
+            vec![
+                AccountingCounter {
+                    container_id: "123".to_string(),
+                    network: "podman".to_string(),
+                    address: "10.88.0.2".parse().unwrap(),
+                    packets: 12,
+                    bytes: 3456,
+                },
+                AccountingCounter {
+                    container_id: "456".to_string(),
+                    network: "podman1".to_string(),
+                    address: "fd10:88:a::2".parse().unwrap(),
+                    packets: 0,
+                    bytes: 0,
+                },
+            ]
+        );
+    }
+}
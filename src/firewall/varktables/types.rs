@@ -1,9 +1,15 @@
 use crate::error::{NetavarkError, NetavarkResult};
+use crate::firewall::iptables::HOSTPORT_MARK;
 use crate::firewall::varktables::helpers::{
-    add_chain_unique, append_unique, remove_if_rule_exists,
+    add_chain_unique, append_unique, chain_exists, command_error, remove_if_rule_exists,
+    retry_on_lock, rule_exists,
 };
 use crate::firewall::varktables::types::TeardownPolicy::{Never, OnComplete};
-use crate::network::internal_types::PortForwardConfig;
+use crate::metrics;
+use crate::network::internal_types::{
+    AllowRule, MasqueradePorts, PolicyAction, PortForwardConfig, SetupNetwork,
+};
+use crate::network::script::Script;
 use ipnet::IpNet;
 use iptables::IPTables;
 use log::debug;
@@ -12,13 +18,15 @@ use std::net::IpAddr;
 //  Chain names
 const NAT: &str = "nat";
 const FILTER: &str = "filter";
+const RAW: &str = "raw";
+pub(crate) const MANGLE: &str = "mangle";
 const POSTROUTING: &str = "POSTROUTING";
 const PREROUTING: &str = "PREROUTING";
 const NETAVARK_FORWARD: &str = "NETAVARK_FORWARD";
 const OUTPUT: &str = "OUTPUT";
 const FORWARD: &str = "FORWARD";
 const ACCEPT: &str = "ACCEPT";
-const NETAVARK_HOSTPORT_DNAT: &str = "NETAVARK-HOSTPORT-DNAT";
+pub(crate) const NETAVARK_HOSTPORT_DNAT: &str = "NETAVARK-HOSTPORT-DNAT";
 const NETAVARK_HOSTPORT_SETMARK: &str = "NETAVARK-HOSTPORT-SETMARK";
 const NETAVARK_HOSTPORT_MASK: &str = "NETAVARK-HOSTPORT-MASQ";
 const MASQUERADE: &str = "MASQUERADE";
@@ -27,9 +35,20 @@ const DNAT: &str = "DNAT";
 const NETAVARK_ISOLATION_1: &str = "NETAVARK_ISOLATION_1";
 const NETAVARK_ISOLATION_2: &str = "NETAVARK_ISOLATION_2";
 
-const CONTAINER_DN_CHAIN: &str = "NETAVARK-DN-";
-
-const HEXMARK: &str = "0x2000";
+pub(crate) const CONTAINER_DN_CHAIN: &str = "NETAVARK-DN-";
+const TRUSTED_CHAIN: &str = "NETAVARK-TR-";
+const POLICY_CHAIN: &str = "NETAVARK-PL-";
+const CONNTRACK_ZONE_CHAIN: &str = "NETAVARK-CT-";
+const PACKET_MARK_CHAIN: &str = "NETAVARK-MK-";
+const ICC_CHAIN: &str = "NETAVARK-IC-";
+/// prefix of the hashlimit name of a network, the name must be shorter than 16 bytes
+const RATE_LIMIT_NAME_PREFIX: &str = "NV";
+pub(crate) const NETAVARK_ACCOUNTING: &str = "NETAVARK-ACCOUNTING";
+
+/// all chains created by netavark start with this prefix
+const NETAVARK_CHAIN_PREFIX: &str = "NETAVARK";
+/// built-in chains we add rules to, only jumps into our own chains are allowed there
+const BUILTIN_CHAINS: &[&str] = &[POSTROUTING, PREROUTING, OUTPUT, FORWARD];
 
 const MULTICAST_NET_V4: &str = "224.0.0.0/4";
 const MULTICAST_NET_V6: &str = "ff00::/8";
@@ -103,6 +122,7 @@ impl<'a> VarkChain<'a> {
     // actually add the rules to iptables
     pub fn add_rules(&self) -> NetavarkResult<()> {
         for rule in &self.rules {
+            check_builtin_chain_rule(&self.chain_name, &rule.rule)?;
             // If the rule comes with an optional position, then instead of append
             // we should use insert if it does not already exist
             match rule.position {
@@ -110,20 +130,37 @@ impl<'a> VarkChain<'a> {
                     append_unique(self.driver, &self.table, &self.chain_name, rule.to_str())?;
                 }
                 Some(pos) => {
-                    let exists = match self
-                        .driver
-                        .exists(&self.table, &self.chain_name, &rule.rule)
-                    {
-                        Ok(b) => b,
-                        Err(e) => return Err(NetavarkError::Message(e.to_string())),
-                    };
+                    let exists =
+                        match rule_exists(self.driver, &self.table, &self.chain_name, &rule.rule) {
+                            Ok(b) => b,
+                            Err(e) => {
+                                return Err(command_error(
+                                    self.driver,
+                                    &["-t", &self.table, "-C", &self.chain_name, &rule.rule],
+                                    e,
+                                ))
+                            }
+                        };
                     if !exists {
-                        match self
-                            .driver
-                            .insert(&self.table, &self.chain_name, &rule.rule, pos)
-                        {
-                            Ok(_) => {}
-                            Err(e) => return Err(NetavarkError::Message(e.to_string())),
+                        match retry_on_lock(|| {
+                            self.driver
+                                .insert(&self.table, &self.chain_name, &rule.rule, pos)
+                        }) {
+                            Ok(_) => metrics::rule_programmed(),
+                            Err(e) => {
+                                return Err(command_error(
+                                    self.driver,
+                                    &[
+                                        "-t",
+                                        &self.table,
+                                        "-I",
+                                        &self.chain_name,
+                                        &pos.to_string(),
+                                        &rule.rule,
+                                    ],
+                                    e,
+                                ))
+                            }
                         };
                     }
                 }
@@ -155,24 +192,65 @@ impl<'a> VarkChain<'a> {
     pub fn remove(&self) -> NetavarkResult<()> {
         // this might be a perf hit but we are going to start this
         // way and think of faster AND logical approach.
-        let remaining_rules = match self.driver.list(&self.table, &self.chain_name) {
-            Ok(o) => o,
-            Err(e) => return Err(NetavarkError::Message(e.to_string())),
-        };
+        let remaining_rules =
+            match retry_on_lock(|| self.driver.list(&self.table, &self.chain_name)) {
+                Ok(o) => o,
+                Err(e) => {
+                    return Err(command_error(
+                        self.driver,
+                        &["-t", &self.table, "-S", &self.chain_name],
+                        e,
+                    ))
+                }
+            };
 
         // if for some reason there is a rule left, dont remove the chain and
         // also dont make this a fatal error.  The vec returned by list always
         // reserves [0] for the chain name (-A chain_name), hence the <= 1
         if remaining_rules.len() <= 1 {
-            match self.driver.delete_chain(&self.table, &self.chain_name) {
+            match retry_on_lock(|| self.driver.delete_chain(&self.table, &self.chain_name)) {
                 Ok(_) => {}
-                Err(e) => return Err(NetavarkError::Message(e.to_string())),
+                Err(e) => {
+                    return Err(command_error(
+                        self.driver,
+                        &["-t", &self.table, "-X", &self.chain_name],
+                        e,
+                    ))
+                }
             };
         }
         Result::Ok(())
     }
 }
 
+/// Make sure that rules in built-in chains only jump into netavark chains. This way all
+/// netavark rules are in its own chains and can be removed by flushing them.
+fn check_builtin_chain_rule(chain: &str, rule: &str) -> NetavarkResult<()> {
+    if !BUILTIN_CHAINS.contains(&chain) {
+        return Ok(());
+    }
+    // the rule must end with the only jump of it, e.g. -s 10.88.0.0/16 -j NETAVARK-1D8721804F16F
+    let words: Vec<&str> = rule.split_whitespace().collect();
+    let jumps = words
+        .iter()
+        .filter(|w| ["-j", "--jump", "-g", "--goto"].contains(w))
+        .count();
+    let netavark_jump = match words.as_slice() {
+        [.., "-j", target] => target
+            .strip_prefix(NETAVARK_CHAIN_PREFIX)
+            .and_then(|rest| rest.strip_prefix(['-', '_']))
+            .is_some_and(|name| !name.is_empty()),
+        _ => false,
+    };
+    if jumps != 1 || !netavark_jump {
+        return Err(NetavarkError::Message(format!(
+            "refusing to add rule '{}' to built-in chain {}, only jumps to netavark chains are allowed",
+            rule, chain
+        )));
+    }
+    Ok(())
+}
+
 pub fn create_network_chains(chains: Vec<VarkChain<'_>>) -> NetavarkResult<()> {
     // we have to create first all chains because some might be referenced by other rules
     // and this will fail if they do not exist yet
@@ -188,14 +266,70 @@ pub fn create_network_chains(chains: Vec<VarkChain<'_>>) -> NetavarkResult<()> {
     Ok(())
 }
 
+/// Check if create_network_chains() would not add anything, i.e. all chains
+/// and their rules exist.
+pub fn network_chains_exist(chains: &[VarkChain<'_>]) -> NetavarkResult<bool> {
+    for c in chains {
+        if c.create && !chain_exists(c.driver, &c.table, &c.chain_name)? {
+            return Ok(false);
+        }
+        for rule in &c.rules {
+            let exists =
+                rule_exists(c.driver, &c.table, &c.chain_name, &rule.rule).map_err(|e| {
+                    command_error(
+                        c.driver,
+                        &["-t", &c.table, "-C", &c.chain_name, &rule.rule],
+                        e,
+                    )
+                })?;
+            if !exists {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Add the commands of create_network_chains() to the script, like there the
+/// chains and rules are only added when they do not exist yet.
+pub fn script_network_chains(chains: &[VarkChain<'_>], script: &mut Script) -> NetavarkResult<()> {
+    for c in chains.iter().filter(|c| c.create) {
+        script.raw(format!(
+            "{cmd} -w -t {table} -S {chain} >/dev/null 2>&1 || {cmd} -w -t {table} -N {chain}",
+            cmd = c.driver.cmd,
+            table = c.table,
+            chain = c.chain_name
+        ));
+    }
+    for c in chains {
+        for rule in &c.rules {
+            check_builtin_chain_rule(&c.chain_name, &rule.rule)?;
+            let add = match rule.position {
+                None => format!("-A {}", c.chain_name),
+                Some(pos) => format!("-I {} {}", c.chain_name, pos),
+            };
+            // the rules are split by whitespace, they contain no shell syntax
+            script.raw(format!(
+                "{cmd} -w -t {table} -C {chain} {rule} >/dev/null 2>&1 || {cmd} -w -t {table} {add} {rule}",
+                cmd = c.driver.cmd,
+                table = c.table,
+                chain = c.chain_name,
+                rule = rule.rule,
+                add = add
+            ));
+        }
+    }
+    Ok(())
+}
+
 pub fn get_network_chains<'a>(
     conn: &'a IPTables,
     network: IpNet,
-    network_hash_name: &'a str,
     is_ipv6: bool,
     interface_name: String,
-    isolation: bool,
+    setup: &'a SetupNetwork,
 ) -> Vec<VarkChain<'a>> {
+    let network_hash_name = &setup.network_hash_name;
     let mut chains = Vec::new();
     let prefixed_network_hash_name = format!("{}-{}", "NETAVARK", network_hash_name);
 
@@ -217,8 +351,16 @@ pub fn get_network_chains<'a>(
     if is_ipv6 {
         multicast_dest = MULTICAST_NET_V6;
     }
+    let masquerade_ports = match setup.masquerade_ports {
+        MasqueradePorts::Preserve => "",
+        MasqueradePorts::Random => " --random",
+        MasqueradePorts::RandomFully => " --random-fully",
+    };
     hashed_network_chain.build_rule(VarkRule::new(
-        format!("! -d {} -j {}", multicast_dest, MASQUERADE),
+        format!(
+            "! -d {} -j {}{}",
+            multicast_dest, MASQUERADE, masquerade_ports
+        ),
         Some(TeardownPolicy::OnComplete),
     ));
     chains.push(hashed_network_chain);
@@ -232,13 +374,77 @@ pub fn get_network_chains<'a>(
     ));
     chains.push(postrouting_chain);
 
+    if let Some(zone) = setup.conntrack_zone {
+        // Only the original direction is put into the zone. Replies to
+        // masqueraded connections arrive on the host interface and must
+        // still find their connection which is looked up in the default zone.
+        let zone_chain_name = CONNTRACK_ZONE_CHAIN.to_string() + network_hash_name;
+        let mut zone_chain = VarkChain::new(
+            conn,
+            RAW.to_string(),
+            zone_chain_name.clone(),
+            Some(OnComplete),
+        );
+        zone_chain.create = true;
+        zone_chain.build_rule(VarkRule::new(
+            format!("-j CT --zone-orig {}", zone),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        chains.push(zone_chain);
+
+        let mut raw_prerouting_chain =
+            VarkChain::new(conn, RAW.to_string(), PREROUTING.to_string(), None);
+        raw_prerouting_chain.build_rule(VarkRule::new(
+            format!("-i {} -j {}", interface_name, zone_chain_name),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        chains.push(raw_prerouting_chain);
+    }
+
+    if setup.dscp.is_some() || setup.fwmark.is_some() {
+        // NETAVARK-MK-HASH, before the routing decision so the mark can
+        // select a routing table
+        let mark_chain_name = PACKET_MARK_CHAIN.to_string() + network_hash_name;
+        let mut mark_chain = VarkChain::new(
+            conn,
+            MANGLE.to_string(),
+            mark_chain_name.clone(),
+            Some(OnComplete),
+        );
+        mark_chain.create = true;
+        if let Some(dscp) = setup.dscp {
+            mark_chain.build_rule(VarkRule::new(
+                format!("-j DSCP --set-dscp {}", dscp),
+                Some(TeardownPolicy::OnComplete),
+            ));
+        }
+        if let Some(fwmark) = setup.fwmark {
+            mark_chain.build_rule(VarkRule::new(
+                format!("-j MARK --set-xmark {:#x}/{:#x}", fwmark.mark, fwmark.mask),
+                Some(TeardownPolicy::OnComplete),
+            ));
+        }
+        chains.push(mark_chain);
+
+        let mut mangle_prerouting_chain =
+            VarkChain::new(conn, MANGLE.to_string(), PREROUTING.to_string(), None);
+        mangle_prerouting_chain.build_rule(VarkRule::new(
+            format!(
+                "-i {} -s {} -j {}",
+                interface_name, network, mark_chain_name
+            ),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        chains.push(mangle_prerouting_chain);
+    }
+
     // FORWARD chain
     let mut forward_chain = VarkChain::new(conn, FILTER.to_string(), FORWARD.to_string(), None);
 
     // used to prepend specific rules
     let mut ind = 1;
 
-    if isolation {
+    if setup.isolation {
         debug!("Add extra isolate rules");
         // NETAVARK_ISOLATION_1
         let mut netavark_isolation_chain_1 = VarkChain::new(
@@ -297,6 +503,112 @@ pub fn get_network_chains<'a>(
         position: Some(ind),
         td_policy: Some(TeardownPolicy::Never),
     });
+
+    if setup.trusted {
+        debug!("Add trusted network bypass rules");
+        // NETAVARK-TR-HASH
+        let trusted_chain_name = TRUSTED_CHAIN.to_string() + network_hash_name;
+        let mut trusted_chain = VarkChain::new(
+            conn,
+            FILTER.to_string(),
+            trusted_chain_name.clone(),
+            Some(OnComplete),
+        );
+        trusted_chain.create = true;
+        trusted_chain.build_rule(VarkRule::new(
+            format!("-s {} -j {}", network, ACCEPT),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        trusted_chain.build_rule(VarkRule::new(
+            format!("-d {} -j {}", network, ACCEPT),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        chains.push(trusted_chain);
+
+        // -I FORWARD 1 -j NETAVARK-TR-HASH, this must be inserted last so it ends
+        // up in front of the isolation and netavark forward rules
+        forward_chain.build_rule(VarkRule {
+            rule: format!("-j {}", trusted_chain_name),
+            position: Some(1),
+            td_policy: Some(TeardownPolicy::OnComplete),
+        });
+    }
+
+    if let Some(policy) = &setup.policy {
+        debug!("Add forward policy rules");
+        // NETAVARK-PL-HASH
+        let policy_chain_name = POLICY_CHAIN.to_string() + network_hash_name;
+        let mut policy_chain = VarkChain::new(
+            conn,
+            FILTER.to_string(),
+            policy_chain_name.clone(),
+            Some(OnComplete),
+        );
+        policy_chain.create = true;
+        // replies to allowed connections, in both directions
+        policy_chain.build_rule(VarkRule::new(
+            "-m conntrack --ctstate RELATED,ESTABLISHED -j RETURN".to_string(),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        // allowed traffic returns to the forward chain for the usual processing
+        for allow in &policy.allow {
+            if let Some(rule) = get_allow_rule(allow, is_ipv6) {
+                policy_chain.build_rule(VarkRule::new(rule, Some(TeardownPolicy::OnComplete)));
+            }
+        }
+        let action = match policy.default_action {
+            PolicyAction::Drop => "DROP",
+            PolicyAction::Reject => "REJECT",
+        };
+        policy_chain.build_rule(VarkRule::new(
+            format!("-j {}", action),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        chains.push(policy_chain);
+
+        // -I FORWARD 1 -s network -j NETAVARK-PL-HASH, inserted last so the
+        // policy is applied before the isolation and netavark forward rules
+        forward_chain.build_rule(VarkRule {
+            rule: format!("-s {} -j {}", network, policy_chain_name),
+            position: Some(1),
+            td_policy: Some(TeardownPolicy::OnComplete),
+        });
+    }
+
+    if !setup.icc {
+        debug!("Add inter container communication rules");
+        // NETAVARK-IC-HASH
+        let icc_chain_name = ICC_CHAIN.to_string() + network_hash_name;
+        let mut icc_chain = VarkChain::new(
+            conn,
+            FILTER.to_string(),
+            icc_chain_name.clone(),
+            Some(OnComplete),
+        );
+        icc_chain.create = true;
+        // connections to published ports of another container on the bridge
+        icc_chain.build_rule(VarkRule::new(
+            "-m conntrack --ctstate DNAT -j RETURN".to_string(),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        icc_chain.build_rule(VarkRule::new(
+            format!("-s {} -d {} -j DROP", network, network),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        chains.push(icc_chain);
+
+        // -I FORWARD 1 -i bridge -o bridge -j NETAVARK-IC-HASH, inserted last so
+        // it is in front of all other forward rules, the traffic to the gateway is
+        // not forwarded and thus not affected
+        forward_chain.build_rule(VarkRule {
+            rule: format!(
+                "-i {} -o {} -j {}",
+                interface_name, interface_name, icc_chain_name
+            ),
+            position: Some(1),
+            td_policy: Some(TeardownPolicy::OnComplete),
+        });
+    }
     chains.push(forward_chain);
 
     // NETAVARK_FORWARD
@@ -306,13 +618,15 @@ pub fn get_network_chains<'a>(
 
     // Create incoming traffic rule
     // CNI did this by IP address, this is implemented per subnet
-    netavark_forward_chain.build_rule(VarkRule::new(
-        format!(
-            "-d {} -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT",
-            network
-        ),
-        Some(TeardownPolicy::OnComplete),
-    ));
+    if setup.ctstate_accept {
+        netavark_forward_chain.build_rule(VarkRule::new(
+            format!(
+                "-d {} -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT",
+                network
+            ),
+            Some(TeardownPolicy::OnComplete),
+        ));
+    }
 
     // Create outgoing traffic rule
     // CNI did this by IP address, this is implemented per subnet
@@ -325,6 +639,32 @@ pub fn get_network_chains<'a>(
     chains
 }
 
+/// Build the iptables rule for an allow entry of the forward policy, returns None
+/// when the entry does not apply to the ip family of the chain.
+fn get_allow_rule(allow: &AllowRule, is_ipv6: bool) -> Option<String> {
+    let mut rule = String::new();
+    if let Some(peer) = &allow.peer {
+        if peer.addr().is_ipv6() != is_ipv6 {
+            return None;
+        }
+        rule.push_str(&format!("-d {} ", peer));
+    }
+    match allow.protocol.as_deref() {
+        Some("icmp") if is_ipv6 => return None,
+        Some("icmpv6") if !is_ipv6 => return None,
+        Some("icmpv6") => rule.push_str("-p ipv6-icmp "),
+        Some(protocol) => rule.push_str(&format!("-p {} ", protocol)),
+        None => {}
+    }
+    match allow.ports {
+        Some((start, end)) if start == end => rule.push_str(&format!("--dport {} ", start)),
+        Some((start, end)) => rule.push_str(&format!("--dport {}:{} ", start, end)),
+        None => {}
+    }
+    rule.push_str("-j RETURN");
+    Some(rule)
+}
+
 pub fn get_port_forwarding_chains<'a>(
     conn: &'a IPTables,
     pfwd: &PortForwardConfig,
@@ -370,14 +710,14 @@ pub fn get_port_forwarding_chains<'a>(
     // PREROUTING
     let mut prerouting_chain = VarkChain::new(conn, NAT.to_string(), PREROUTING.to_string(), None);
     prerouting_chain.build_rule(VarkRule::new(
-        format!("-j {} -m addrtype --dst-type LOCAL", NETAVARK_HOSTPORT_DNAT),
+        format!("-m addrtype --dst-type LOCAL -j {}", NETAVARK_HOSTPORT_DNAT),
         Some(TeardownPolicy::Never),
     ));
 
     //  OUTPUT
     let mut output_chain = VarkChain::new(conn, NAT.to_string(), OUTPUT.to_string(), None);
     output_chain.build_rule(VarkRule::new(
-        format!("-j {} -m addrtype --dst-type LOCAL", NETAVARK_HOSTPORT_DNAT),
+        format!("-m addrtype --dst-type LOCAL -j {}", NETAVARK_HOSTPORT_DNAT),
         Some(TeardownPolicy::Never),
     ));
 
@@ -390,7 +730,10 @@ pub fn get_port_forwarding_chains<'a>(
     );
     netavark_hostport_setmark.create = true;
     netavark_hostport_setmark.build_rule(VarkRule::new(
-        format!("-j {}  --set-xmark {}/{}", MARK, HEXMARK, HEXMARK),
+        format!(
+            "-j {}  --set-xmark {:#x}/{:#x}",
+            MARK, HOSTPORT_MARK, HOSTPORT_MARK
+        ),
         Some(TeardownPolicy::Never),
     ));
     chains.push(netavark_hostport_setmark);
@@ -405,8 +748,8 @@ pub fn get_port_forwarding_chains<'a>(
     netavark_hostport_masq_chain.create = true;
     netavark_hostport_masq_chain.build_rule(VarkRule::new(
         format!(
-            "-j {} -m comment --comment 'netavark portfw masq mark' -m mark --mark {}/{}",
-            MASQUERADE, HEXMARK, HEXMARK
+            "-j {} -m comment --comment 'netavark portfw masq mark' -m mark --mark {:#x}/{:#x}",
+            MASQUERADE, HOSTPORT_MARK, HOSTPORT_MARK
         ),
         Some(TeardownPolicy::Never),
     ));
@@ -424,11 +767,44 @@ pub fn get_port_forwarding_chains<'a>(
 
     chains.push(postrouting);
 
+    if pfwd.accounting {
+        // NETAVARK-ACCOUNTING, the counters of the container rule are the traffic it
+        // sent. The mangle POSTROUTING chain only sees packets which passed the filter
+        // and have not been masqueraded yet.
+        let mut accounting_chain = VarkChain::new(
+            conn,
+            MANGLE.to_string(),
+            NETAVARK_ACCOUNTING.to_string(),
+            None,
+        );
+        accounting_chain.create = true;
+        accounting_chain.build_rule(VarkRule::new(
+            format!(
+                "-s {} -m comment --comment 'accounting name: {} id: {}' -j RETURN",
+                container_ip, pfwd.network_name, pfwd.container_id
+            ),
+            None,
+        ));
+        chains.push(accounting_chain);
+
+        let mut mangle_postrouting =
+            VarkChain::new(conn, MANGLE.to_string(), POSTROUTING.to_string(), None);
+        mangle_postrouting.build_rule(VarkRule::new(
+            format!("-j {}", NETAVARK_ACCOUNTING),
+            Some(TeardownPolicy::Never),
+        ));
+        chains.push(mangle_postrouting);
+    }
+
     //  Determine if we need to create chains
     if pfwd.port_mappings.is_some() {
         netavark_hashed_dn_chain.create = true;
     }
 
+    // NETAVARK_FORWARD, excess new connections are dropped before any rule accepts them
+    let mut rate_limit_chain =
+        VarkChain::new(conn, FILTER.to_string(), NETAVARK_FORWARD.to_string(), None);
+
     // Create redirection for aardvark-dns on non-standard port
     if pfwd.dns_port != 53 {
         for dns_ip in pfwd.dns_server_ips {
@@ -453,6 +829,10 @@ pub fn get_port_forwarding_chains<'a>(
     match pfwd.port_mappings {
         Some(ports) => {
             for i in ports {
+                let container_ip = match i.target_ip(&pfwd.container_ips, Some(*container_ip)) {
+                    Some(ip) if ip.is_ipv6() == is_ipv6 => ip,
+                    _ => continue,
+                };
                 let host_ip = if i.host_ip.is_empty() {
                     None
                 } else {
@@ -552,6 +932,30 @@ pub fn get_port_forwarding_chains<'a>(
                     dnat_rule = format!("{} -d {}", dnat_rule, host_ip)
                 }
                 netavark_hashed_dn_chain.build_rule(VarkRule::new(dnat_rule, None));
+
+                if let Some(limit) = &pfwd.rate_limit {
+                    let mut dport = i.container_port.to_string();
+                    if is_range {
+                        dport =
+                            format!("{}:{}", i.container_port, i.container_port + (i.range - 1));
+                    }
+                    rate_limit_chain.build_rule(VarkRule {
+                        rule: format!(
+                            "-d {} -p {} --dport {} -m conntrack --ctstate NEW -m hashlimit --hashlimit-above {} --hashlimit-burst {} --hashlimit-mode srcip,dstip,dstport --hashlimit-name {}{} -m comment --comment 'rate limit name: {} id: {}' -j DROP",
+                            container_ip,
+                            i.protocol,
+                            dport,
+                            limit.rate,
+                            limit.burst,
+                            RATE_LIMIT_NAME_PREFIX,
+                            pfwd.network_hash_name,
+                            pfwd.network_name,
+                            pfwd.container_id
+                        ),
+                        td_policy: None,
+                        position: Some(1),
+                    });
+                }
             }
         }
         None => {}
@@ -562,6 +966,7 @@ pub fn get_port_forwarding_chains<'a>(
     chains.push(netavark_hostport_dn_chain);
     chains.push(prerouting_chain);
     chains.push(output_chain);
+    chains.push(rate_limit_chain);
 
     Ok(chains)
 }
@@ -0,0 +1,115 @@
+//! Commands run after a successful setup or teardown, see the --on-setup and
+//! --on-teardown options.
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    os::fd::AsFd,
+    process::{Command, Stdio},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    network::{core_utils, types::StatusBlock},
+    wrap,
+};
+
+pub const EVENT_SETUP: &str = "setup";
+pub const EVENT_TEARDOWN: &str = "teardown";
+
+/// Information about the event, written as JSON to the stdin of the hook.
+#[derive(Debug, Serialize)]
+pub struct HookInput<'a> {
+    pub event: &'a str,
+    pub container_id: &'a str,
+    pub container_name: &'a str,
+    pub network_namespace_path: &'a str,
+    /// names of the networks which were set up or torn down
+    pub networks: Vec<String>,
+    /// setup response of the networks, only set for setup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<&'a HashMap<String, StatusBlock>>,
+}
+
+impl HookInput<'_> {
+    /// Environment variables for the hook, these contain the same information
+    /// as the JSON input for hooks which do not want to parse it.
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("NETAVARK_HOOK_EVENT", self.event.to_string()),
+            ("NETAVARK_CONTAINER_ID", self.container_id.to_string()),
+            ("NETAVARK_CONTAINER_NAME", self.container_name.to_string()),
+            ("NETAVARK_NETNS", self.network_namespace_path.to_string()),
+            ("NETAVARK_NETWORKS", self.networks.join(",")),
+        ];
+        if let Some(status) = self.status {
+            let mut addresses: Vec<String> = status
+                .values()
+                .flat_map(|s| s.interfaces.iter().flatten())
+                .flat_map(|(_, i)| i.subnets.iter().flatten())
+                .map(|s| s.ipnet.addr().to_string())
+                .collect();
+            addresses.sort();
+            env.push(("NETAVARK_ADDRESSES", addresses.join(",")));
+        }
+        env
+    }
+}
+
+/// Run the hook command with `sh -c`. A failing hook is only logged unless
+/// `fatal` is set, then its error is returned.
+pub fn run(command: &str, input: &HookInput, fatal: bool) -> NetavarkResult<()> {
+    match exec(command, input) {
+        Ok(()) => Ok(()),
+        Err(err) if fatal => Err(err),
+        Err(err) => {
+            warn!("{} hook failed: {}", input.event, err);
+            Ok(())
+        }
+    }
+}
+
+fn exec(command: &str, input: &HookInput) -> NetavarkResult<()> {
+    core_utils::check_read_only(format_args!("run the {} hook", input.event))?;
+    debug!("running {} hook `{}`", input.event, command);
+    // stdout of netavark is reserved for the response, the hook output goes to stderr
+    let stdout = wrap!(
+        io::stderr().as_fd().try_clone_to_owned(),
+        "duplicate stderr for the hook"
+    )?;
+    let mut child = wrap!(
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(input.env())
+            .stdin(Stdio::piped())
+            .stdout(stdout)
+            .stderr(Stdio::inherit())
+            .spawn(),
+        format!("failed to run hook `{}`", command)
+    )?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let result = serde_json::to_writer(&mut stdin, input)
+        .map_err(io::Error::from)
+        .and_then(|_| stdin.write_all(b"\n"));
+    // Close stdin so the hook does not wait for an EOF.
+    drop(stdin);
+
+    let status = wrap!(child.wait(), "wait for hook to exit")?;
+    match result {
+        // the hook does not have to read its input
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            return Err(NetavarkError::wrap("write hook input", e.into()))
+        }
+        _ => {}
+    }
+    if !status.success() {
+        return Err(NetavarkError::Message(format!(
+            "hook `{}` failed with {}",
+            command, status
+        )));
+    }
+    Ok(())
+}
@@ -7,5 +7,8 @@ pub mod dhcp_proxy;
 pub mod dns;
 pub mod error;
 pub mod firewall;
+pub mod hook;
+pub mod logging;
+pub mod metrics;
 pub mod network;
 pub mod plugin;
@@ -0,0 +1,15 @@
+//! Context added to every log line of a command, so that interleaved logs of
+//! concurrent netavark runs can be told apart.
+use std::sync::RwLock;
+
+static NETNS: RwLock<Option<String>> = RwLock::new(None);
+
+/// Set the network namespace path the current command works on.
+pub fn set_netns(netns_path: &str) {
+    *NETNS.write().unwrap_or_else(|e| e.into_inner()) = Some(netns_path.to_string());
+}
+
+/// Get the network namespace path set with set_netns().
+pub fn netns() -> Option<String> {
+    NETNS.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
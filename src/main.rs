@@ -1,10 +1,21 @@
 use clap::{Parser, Subcommand};
+use std::io::Write;
+use std::path::Path;
 
 use netavark::commands::dhcp_proxy;
+use netavark::commands::gc;
+use netavark::commands::inspect;
+use netavark::commands::port_proxy;
+use netavark::commands::reload;
 use netavark::commands::setup;
+use netavark::commands::stats;
 use netavark::commands::teardown;
 use netavark::commands::update;
 use netavark::commands::version;
+use netavark::firewall;
+use netavark::logging;
+use netavark::metrics;
+use netavark::network::core_utils;
 
 #[derive(Parser, Debug)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
@@ -12,7 +23,7 @@ struct Opts {
     /// Instead of reading from STDIN, read the configuration to be applied from the given file.
     #[clap(short, long)]
     file: Option<String>,
-    /// config directory for aardvark, usually path to a tmpfs.
+    /// config directory for aardvark and netavark state such as ipam leases, usually path to a tmpfs.
     #[clap(short, long)]
     config: Option<String>,
     /// Tells if current netavark invocation is for rootless container.
@@ -24,6 +35,15 @@ struct Opts {
     /// Path to netavark plugin directories, can be set multiple times to specify more than one directory.
     #[clap(long, long = "plugin-directory")]
     plugin_directories: Option<Vec<String>>,
+    /// Update the counters in this node-exporter textfile after the command finished.
+    #[clap(long)]
+    metrics_file: Option<String>,
+    /// Write the netavark firewall rules to this directory after they changed, so they can be restored at boot.
+    #[clap(long)]
+    persist_rules_dir: Option<String>,
+    /// Refuse and log every change of the host, the commands only read the current state.
+    #[clap(long)]
+    read_only: bool,
     /// Netavark trig command
     #[clap(subcommand)]
     subcmd: SubCommand,
@@ -37,15 +57,45 @@ enum SubCommand {
     Update(update::Update),
     /// Undo any configuration applied via setup command.
     Teardown(teardown::Teardown),
+    /// Re-apply the firewall rules of an already configured network, e.g. after a firewall reload.
+    Reload(reload::Reload),
+    /// Print the traffic counters of the containers of networks with the accounting option.
+    Stats(stats::Stats),
+    /// Remove the networks of containers whose namespace is gone without a teardown.
+    Gc(gc::Gc),
+    /// Report the containers, bridges, port mappings and firewall chains of netavark on the host without changing anything.
+    Inspect(inspect::Inspect),
     /// Display info about netavark.
     Version(version::Version),
     /// Start dhcp-proxy
     DHCPProxy(dhcp_proxy::Opts),
+    /// Forward ports in userspace, only used internally by setup.
+    #[clap(hide = true)]
+    PortProxy(port_proxy::PortProxy),
 }
 
 fn main() {
-    env_logger::builder().format_timestamp(None).init();
+    env_logger::builder()
+        .format_timestamp(None)
+        .format(|buf, record| {
+            let level = buf.default_styled_level(record.level());
+            match logging::netns() {
+                Some(netns) => writeln!(
+                    buf,
+                    "[{} {} netns={}] {}",
+                    level,
+                    record.target(),
+                    netns,
+                    record.args()
+                ),
+                None => writeln!(buf, "[{} {}] {}", level, record.target(), record.args()),
+            }
+        })
+        .init();
     let opts = Opts::parse();
+    if opts.read_only {
+        core_utils::enable_read_only();
+    }
 
     // aardvark config directory must be supplied by parent or it defaults to /tmp/aardvark
     let config = opts.config.as_deref().unwrap_or("/tmp");
@@ -53,6 +103,20 @@ fn main() {
     let aardvark_bin = opts
         .aardvark_binary
         .unwrap_or_else(|| String::from("/usr/libexec/podman/aardvark-dns"));
+    let command = match &opts.subcmd {
+        SubCommand::Setup(_) => Some("setup"),
+        SubCommand::Teardown(_) => Some("teardown"),
+        SubCommand::Reload(_) => Some("reload"),
+        SubCommand::Update(_) => Some("update"),
+        SubCommand::Gc(_) => Some("gc"),
+        _ => None,
+    };
+    if command.is_some() {
+        if let Err(err) = core_utils::validate_binary_paths() {
+            err.print_json();
+            std::process::exit(err.get_exit_code());
+        }
+    }
     let result = match opts.subcmd {
         SubCommand::Setup(setup) => setup.exec(
             opts.file,
@@ -68,11 +132,38 @@ fn main() {
             opts.plugin_directories,
             rootless,
         ),
+        SubCommand::Reload(reload) => reload.exec(opts.file, config, opts.plugin_directories),
         SubCommand::Update(mut update) => update.exec(config, aardvark_bin, rootless),
+        SubCommand::Stats(stats) => stats.exec(),
+        SubCommand::Gc(gc) => gc.exec(config, aardvark_bin, opts.plugin_directories, rootless),
+        SubCommand::Inspect(inspect) => inspect.exec(config),
         SubCommand::Version(version) => version.exec(),
         SubCommand::DHCPProxy(proxy) => dhcp_proxy::serve(proxy),
+        SubCommand::PortProxy(proxy) => proxy.exec(),
     };
 
+    if let Some(dir) = &opts.persist_rules_dir {
+        // only these commands change the firewall rules, also persist on errors
+        // as a failed command may still have added or removed some rules
+        if matches!(
+            command,
+            Some("setup") | Some("teardown") | Some("reload") | Some("gc")
+        ) {
+            if let Err(err) = firewall::get_supported_firewall_driver()
+                .and_then(|driver| driver.persist_rules(Path::new(dir)))
+            {
+                log::warn!("failed to persist firewall rules: {}", err);
+            }
+        }
+    }
+
+    if let (Some(path), Some(command)) = (&opts.metrics_file, command) {
+        // metrics are best effort, they must never fail the command
+        if let Err(err) = metrics::update(path, command, result.is_err()) {
+            log::warn!("failed to update metrics file: {}", err);
+        }
+    }
+
     match result {
         Ok(_) => {}
         Err(err) => {
@@ -0,0 +1,146 @@
+//! Counters written to a node-exporter textfile, see the --metrics-file option.
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use fs2::FileExt;
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    network::core_utils,
+    wrap,
+};
+
+const RULES_PROGRAMMED: &str = "netavark_rules_programmed";
+
+/// firewall rules added by this process
+static RULES: AtomicU64 = AtomicU64::new(0);
+
+/// Count a firewall rule which was added.
+pub fn rule_programmed() {
+    RULES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Add the run of the given command to the counters in the metrics file.
+/// The file is replaced atomically so the node-exporter never reads a
+/// partially written file, concurrent netavark processes are serialized
+/// with a lock file next to it.
+pub fn update(path: &str, command: &str, failed: bool) -> NetavarkResult<()> {
+    core_utils::check_read_only(format_args!("update the metrics file {}", path))?;
+    let path = Path::new(path);
+    let lock_path = path.with_extension("lock");
+    let lock = wrap!(
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path),
+        format!("open metrics lock file {:?}", lock_path)
+    )?;
+    // the lock is released when the file is closed
+    wrap!(
+        lock.lock_exclusive(),
+        format!("lock metrics lock file {:?}", lock_path)
+    )?;
+
+    let mut metrics = match fs::read_to_string(path) {
+        Ok(content) => parse(&content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => {
+            return Err(NetavarkError::wrap(
+                format!("read metrics file {:?}", path),
+                e.into(),
+            ))
+        }
+    };
+
+    *metrics
+        .entry(format!("netavark_{}_total", command))
+        .or_default() += 1;
+    let errors = metrics
+        .entry(format!("netavark_{}_errors_total", command))
+        .or_default();
+    if failed {
+        *errors += 1;
+    }
+    *metrics.entry(RULES_PROGRAMMED.to_string()).or_default() += RULES.load(Ordering::Relaxed);
+
+    let tmp_path = path.with_extension(format!("tmp.{}", std::process::id()));
+    let write = || -> std::io::Result<()> {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(format(&metrics).as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    };
+    let res = write();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    wrap!(res, format!("write metrics file {:?}", path))
+}
+
+/// Parse the netavark counters of a textfile, everything else is dropped.
+fn parse(content: &str) -> BTreeMap<String, u64> {
+    content
+        .lines()
+        .filter(|line| line.starts_with("netavark_"))
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?;
+            let value = parts.next()?.parse::<f64>().ok()?;
+            Some((name.to_string(), value as u64))
+        })
+        .collect()
+}
+
+fn format(metrics: &BTreeMap<String, u64>) -> String {
+    let mut out = String::new();
+    for (name, value) in metrics {
+        let help = if name == RULES_PROGRAMMED {
+            "Number of firewall rules added by netavark.".to_string()
+        } else if let Some(command) = name
+            .strip_prefix("netavark_")
+            .and_then(|n| n.strip_suffix("_errors_total"))
+        {
+            format!("Number of failed netavark {} runs.", command)
+        } else {
+            let command = name
+                .strip_prefix("netavark_")
+                .and_then(|n| n.strip_suffix("_total"))
+                .unwrap_or(name);
+            format!("Number of netavark {} runs.", command)
+        };
+        out.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} counter\n{} {}\n",
+            name, help, name, name, value
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        let mut metrics = BTreeMap::new();
+        metrics.insert("netavark_setup_total".to_string(), 3);
+        metrics.insert("netavark_setup_errors_total".to_string(), 1);
+        metrics.insert(RULES_PROGRAMMED.to_string(), 12);
+
+        let content = format(&metrics);
+        assert!(content.contains(
+            "# HELP netavark_setup_errors_total Number of failed netavark setup runs.\n\
+             # TYPE netavark_setup_errors_total counter\n\
+             netavark_setup_errors_total 1\n"
+        ));
+        assert!(content.contains("netavark_rules_programmed 12\n"));
+        assert_eq!(parse(&content), metrics);
+        assert_eq!(parse("other_metric 1\nnetavark_setup_total 2e0\n").len(), 1);
+    }
+}
@@ -1,33 +1,62 @@
 use std::{collections::HashMap, net::IpAddr, os::unix::prelude::RawFd, sync::Once};
 
-use ipnet::IpNet;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use log::{debug, error};
 use netlink_packet_route::{
-    nlas::link::{Info, InfoData, InfoKind, Nla, VethInfo},
-    LinkMessage,
+    nlas::link::{Info, InfoBridge, InfoData, InfoKind, InfoVlan, Nla, VethInfo},
+    LinkMessage, BRIDGE_VLAN_INFO_PVID, BRIDGE_VLAN_INFO_UNTAGGED,
 };
+use sysctl::Sysctl;
 
 use crate::{
     dns::aardvark::AardvarkEntry,
-    error::{ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
+    error::{ConfigError, ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
     exec_netns,
-    firewall::iptables::MAX_HASH_SIZE,
-    network::{constants, core_utils::disable_ipv6_autoconf, types},
+    firewall::{
+        self,
+        iptables::{HOSTPORT_MARK, MAX_HASH_SIZE},
+    },
+    network::{
+        conntrack, constants,
+        core_utils::disable_ipv6_autoconf,
+        lease::{self, LeaseMode},
+        port_proxy, types,
+    },
 };
 
 use super::{
-    constants::{NO_CONTAINER_INTERFACE_ERROR, OPTION_ISOLATE, OPTION_METRIC, OPTION_MTU},
-    core_utils::{self, get_ipam_addresses, join_netns, parse_option, CoreUtils},
+    constants::{
+        BRIDGE_CLEANUP_CREATED, BRIDGE_CLEANUP_NEVER, BRIDGE_CLEANUP_UNUSED, BRIDGE_MODE_BRIDGE,
+        BRIDGE_MODE_L2_ONLY, BRIDGE_MODE_ROUTED, DEFAULT_ACTION_ACCEPT, DEFAULT_ACTION_DROP,
+        DEFAULT_ACTION_REJECT, FIREWALL_NETNS_CONTAINER, FIREWALL_NETNS_HOST,
+        MASQUERADE_PORTS_PRESERVE, MASQUERADE_PORTS_RANDOM, MASQUERADE_PORTS_RANDOM_FULLY,
+        NAT64_DEFAULT_INTERFACE, NAT64_DEFAULT_POOL, NAT64_DEFAULT_PREFIX,
+        NO_CONTAINER_INTERFACE_ERROR, OPTION_ACCOUNTING, OPTION_ALLOW, OPTION_BPDU_GUARD,
+        OPTION_BRIDGE_CLEANUP, OPTION_BRING_UP, OPTION_CONNTRACK_CLEANUP, OPTION_CONNTRACK_ZONE,
+        OPTION_CTSTATE_ACCEPT, OPTION_DEFAULT_ACTION, OPTION_DISABLE_IPV6, OPTION_DSCP,
+        OPTION_FIREWALL_NETNS, OPTION_FWMARK, OPTION_GROUP_FWD_MASK, OPTION_HAIRPIN, OPTION_ICC,
+        OPTION_IFALIAS, OPTION_ISOLATE, OPTION_LEARNING, OPTION_MASQUERADE_PORTS, OPTION_MODE,
+        OPTION_MTU, OPTION_NAT64, OPTION_NAT64_INTERFACE, OPTION_NAT64_POOL, OPTION_NAT64_PREFIX,
+        OPTION_POLICY_ROUTES, OPTION_PORT_FORWARD_MODE, OPTION_RATE_LIMIT, OPTION_RATE_LIMIT_BURST,
+        OPTION_RATE_LIMIT_RATE, OPTION_STATIC_NEIGHBORS, OPTION_TRUSTED, OPTION_VLAN,
+        PORT_FORWARD_MODE_DNAT, PORT_FORWARD_MODE_PROXY, RATE_LIMIT_DEFAULT_BURST,
+        RATE_LIMIT_DEFAULT_RATE,
+    },
+    core_utils::{self, join_netns, parse_option, parse_optional_option, CoreUtils},
     driver::{self, DriverInfo},
     internal_types::{
-        IPAMAddresses, PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
+        AllowRule, ForwardPolicy, FwMark, IPAMAddresses, MasqueradePorts, PolicyAction,
+        PortForwardConfig, RateLimit, SetupNetwork, TearDownNetwork, TeardownPortForward,
     },
     netlink,
+    script::{self, Script},
+    state,
     types::StatusBlock,
 };
 
 const NO_BRIDGE_NAME_ERROR: &str = "no bridge interface name given";
 
+#[derive(Clone)]
 struct InternalData {
     /// interface name of the veth pair inside the container netns
     container_interface_name: String,
@@ -35,15 +64,174 @@ struct InternalData {
     bridge_interface_name: String,
     /// static mac address
     mac_address: Option<Vec<u8>>,
+    /// existing host veth which should be used instead of creating a new pair
+    existing_host_veth: Option<String>,
+    /// interface name of the host side veth when we create the pair
+    host_interface_name: String,
     /// ip addresses
     ipam: IPAMAddresses,
     /// mtu for the network interfaces (0 if default)
     mtu: u32,
-    /// if this network should be isolated from others
-    isolate: bool,
+    /// network wide firewall settings
+    firewall: FirewallOptions,
+    /// serve port mappings with the userspace proxy instead of DNAT rules
+    port_proxy: bool,
+    /// apply the firewall rules in the container namespace instead of the host
+    firewall_netns: bool,
     /// Route metric for any default routes added for the network
     metric: Option<u32>,
-    // TODO: add vlan
+    /// settings for the bridge port of the host veth
+    port_options: netlink::BridgePortOptions,
+    /// ifalias to set on the bridge and host veth when they are created
+    aliases: Option<InterfaceAliases>,
+    /// neighbor entries for the container interface
+    static_neighbors: Vec<core_utils::StaticNeighbor>,
+    /// disable ipv6 on the bridge and veths so they get no ipv6 addresses
+    disable_ipv6: bool,
+    /// set the container interface up, otherwise the container has to do it
+    bring_up: bool,
+    /// default routes in other routing tables with the rules selecting them
+    policy_routes: Vec<types::PolicyRoute>,
+    /// container and bridge only get host addresses and are connected by routes
+    routed: bool,
+    /// the bridge is a plain switch without addresses and firewall rules
+    l2_only: bool,
+    /// route the NAT64 prefix to the translator on the host
+    nat64: Option<Nat64>,
+    /// reserved link local multicast groups the bridge forwards when it is created
+    group_fwd_mask: Option<u16>,
+    /// vlan of the network on the vlan filtering bridge
+    vlan: Option<u16>,
+}
+
+impl InternalData {
+    /// interface with the gateway addresses, with a vlan this is the vlan
+    /// interface on top of the bridge
+    fn gateway_interface_name(&self) -> String {
+        match self.vlan {
+            Some(vlan) => vlan_interface_name(&self.bridge_interface_name, vlan),
+            None => self.bridge_interface_name.clone(),
+        }
+    }
+}
+
+/// Network wide firewall settings from the network options.
+#[derive(Clone)]
+struct FirewallOptions {
+    /// if this network should be isolated from others
+    isolate: bool,
+    /// if this network should bypass the forward filtering
+    trusted: bool,
+    /// only forward the allowed traffic from the containers
+    policy: Option<ForwardPolicy>,
+    /// conntrack zone for the traffic of the bridge
+    conntrack_zone: Option<u16>,
+    /// source port handling of the masquerade rule
+    masquerade_ports: MasqueradePorts,
+    /// ipv4 pool of the NAT64 translator
+    nat64_pool: Option<Ipv4Net>,
+    /// add the conntrack state rule for the replies to the containers
+    ctstate_accept: bool,
+    /// allow the traffic between the containers of the bridge
+    icc: bool,
+    /// count the traffic sent by each container
+    accounting: bool,
+    /// limit the new connections to the published ports
+    rate_limit: Option<RateLimit>,
+    /// DSCP value set on the traffic of the containers
+    dscp: Option<u8>,
+    /// firewall mark set on the traffic of the containers
+    fwmark: Option<FwMark>,
+    /// the rules match on the vlan interface instead of the bridge
+    vlan: Option<u16>,
+}
+
+impl FirewallOptions {
+    /// Parse the firewall options of the network, the first invalid option is
+    /// returned as error.
+    fn from_network(network: &types::Network) -> NetavarkResult<Self> {
+        let mut first_error = None;
+        let options = Self::parse(network, &mut |e| {
+            first_error.get_or_insert(e);
+        });
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(options),
+        }
+    }
+
+    /// Parse the firewall options of the network, an invalid option is passed
+    /// to `on_error` and replaced by its default.
+    fn parse(network: &types::Network, on_error: &mut dyn FnMut(NetavarkError)) -> Self {
+        fn or_default<T>(
+            result: NetavarkResult<T>,
+            default: T,
+            on_error: &mut dyn FnMut(NetavarkError),
+        ) -> T {
+            result.unwrap_or_else(|e| {
+                on_error(e);
+                default
+            })
+        }
+        let options = &network.options;
+        FirewallOptions {
+            isolate: or_default(
+                parse_option(options, OPTION_ISOLATE, false),
+                false,
+                on_error,
+            ),
+            trusted: or_default(
+                parse_option(options, OPTION_TRUSTED, false),
+                false,
+                on_error,
+            ),
+            policy: or_default(get_forward_policy(options), None, on_error),
+            conntrack_zone: or_default(get_conntrack_zone_option(options), None, on_error),
+            masquerade_ports: or_default(
+                get_masquerade_ports_option(options),
+                MasqueradePorts::Preserve,
+                on_error,
+            ),
+            nat64_pool: or_default(
+                get_nat64_options(network).map(|nat64| nat64.map(|n| n.pool)),
+                None,
+                on_error,
+            ),
+            ctstate_accept: or_default(
+                parse_option(options, OPTION_CTSTATE_ACCEPT, true),
+                true,
+                on_error,
+            ),
+            icc: or_default(parse_option(options, OPTION_ICC, true), true, on_error),
+            accounting: or_default(
+                parse_option(options, OPTION_ACCOUNTING, false),
+                false,
+                on_error,
+            ),
+            rate_limit: or_default(get_rate_limit_options(options), None, on_error),
+            dscp: or_default(get_dscp_option(options), None, on_error),
+            fwmark: or_default(get_fwmark_option(options), None, on_error),
+            vlan: or_default(get_vlan_option(options), None, on_error),
+        }
+    }
+}
+
+/// NAT64 settings from the network options, the translation itself is done by
+/// a translator like tayga which must be running on the host.
+#[derive(Clone)]
+struct Nat64 {
+    /// prefix with the embedded ipv4 addresses
+    prefix: Ipv6Net,
+    /// the translator uses the addresses of this pool as ipv4 sources
+    pool: Ipv4Net,
+    /// interface of the translator on the host
+    interface: String,
+}
+
+#[derive(Clone)]
+struct InterfaceAliases {
+    bridge: String,
+    host_veth: String,
 }
 
 pub struct Bridge<'a> {
@@ -65,13 +253,275 @@ impl driver::NetworkDriver for Bridge<'_> {
     fn validate(&mut self) -> NetavarkResult<()> {
         let bridge_name = get_interface_name(self.info.network.network_interface.clone())?;
         if self.info.per_network_opts.interface_name.is_empty() {
-            return Err(NetavarkError::msg(NO_CONTAINER_INTERFACE_ERROR));
+            return Err(ConfigError::missing_field(
+                "interface_name",
+                NO_CONTAINER_INTERFACE_ERROR,
+            ));
         }
-        let ipam = get_ipam_addresses(self.info.per_network_opts, self.info.network)?;
-
         let mtu: u32 = parse_option(&self.info.network.options, OPTION_MTU, 0)?;
-        let isolate: bool = parse_option(&self.info.network.options, OPTION_ISOLATE, false)?;
-        let metric: u32 = parse_option(&self.info.network.options, OPTION_METRIC, 100)?;
+        let firewall_options = FirewallOptions::from_network(self.info.network)?;
+        if firewall_options.trusted && firewall_options.policy.is_some() {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_DEFAULT_ACTION),
+                format!(
+                    "{} cannot be used together with {}=true",
+                    OPTION_DEFAULT_ACTION, OPTION_TRUSTED
+                ),
+            ));
+        }
+        let port_proxy = get_port_proxy_option(&self.info.network.options)?;
+        let firewall_netns = get_firewall_netns_option(&self.info.network.options)?;
+        if firewall_netns && self.info.firewall.driver_name() == firewall::FIREWALLD {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_FIREWALL_NETNS),
+                format!(
+                    "{}={} is not supported with the firewalld driver",
+                    OPTION_FIREWALL_NETNS, FIREWALL_NETNS_CONTAINER
+                ),
+            ));
+        }
+        if firewall_options.conntrack_zone.is_some()
+            && self.info.firewall.driver_name() == firewall::FIREWALLD
+        {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_CONNTRACK_ZONE),
+                format!(
+                    "{} is not supported with the firewalld driver",
+                    OPTION_CONNTRACK_ZONE
+                ),
+            ));
+        }
+        if firewall_options.masquerade_ports != MasqueradePorts::Preserve
+            && self.info.firewall.driver_name() == firewall::FIREWALLD
+        {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_MASQUERADE_PORTS),
+                format!(
+                    "{} is not supported with the firewalld driver",
+                    OPTION_MASQUERADE_PORTS
+                ),
+            ));
+        }
+        if !firewall_options.ctstate_accept
+            && self.info.firewall.driver_name() == firewall::FIREWALLD
+        {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_CTSTATE_ACCEPT),
+                format!(
+                    "{}=false is not supported with the firewalld driver",
+                    OPTION_CTSTATE_ACCEPT
+                ),
+            ));
+        }
+        if !firewall_options.icc && firewall_options.trusted {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_ICC),
+                format!(
+                    "{}=false cannot be used together with {}=true",
+                    OPTION_ICC, OPTION_TRUSTED
+                ),
+            ));
+        }
+        if !firewall_options.icc {
+            // the traffic is forwarded by the bridge on the host
+            let conflict = if self.info.firewall.driver_name() == firewall::FIREWALLD {
+                Some("the firewalld driver".to_string())
+            } else if firewall_netns {
+                Some(format!(
+                    "{}={}",
+                    OPTION_FIREWALL_NETNS, FIREWALL_NETNS_CONTAINER
+                ))
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", OPTION_ICC),
+                    format!("{}=false is not supported with {}", OPTION_ICC, conflict),
+                ));
+            }
+            check_bridge_nf_call(self.info.network)?;
+        }
+        if firewall_options.accounting && self.info.firewall.driver_name() == firewall::FIREWALLD {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_ACCOUNTING),
+                format!(
+                    "{} is not supported with the firewalld driver",
+                    OPTION_ACCOUNTING
+                ),
+            ));
+        }
+        if firewall_options.rate_limit.is_some() {
+            // the limit matches forwarded connections, with the proxy or the
+            // rules in the container namespace they are not forwarded
+            let conflict = if self.info.firewall.driver_name() == firewall::FIREWALLD {
+                Some("the firewalld driver".to_string())
+            } else if port_proxy {
+                Some(format!(
+                    "{}={}",
+                    OPTION_PORT_FORWARD_MODE, PORT_FORWARD_MODE_PROXY
+                ))
+            } else if firewall_netns {
+                Some(format!(
+                    "{}={}",
+                    OPTION_FIREWALL_NETNS, FIREWALL_NETNS_CONTAINER
+                ))
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", OPTION_RATE_LIMIT),
+                    format!("{} is not supported with {}", OPTION_RATE_LIMIT, conflict),
+                ));
+            }
+        }
+        for (used, option) in [
+            (firewall_options.dscp.is_some(), OPTION_DSCP),
+            (firewall_options.fwmark.is_some(), OPTION_FWMARK),
+        ] {
+            if !used {
+                continue;
+            }
+            // the rules match the traffic coming from the bridge on the host
+            let conflict = if self.info.firewall.driver_name() == firewall::FIREWALLD {
+                Some("the firewalld driver".to_string())
+            } else if firewall_netns {
+                Some(format!(
+                    "{}={}",
+                    OPTION_FIREWALL_NETNS, FIREWALL_NETNS_CONTAINER
+                ))
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", option),
+                    format!("{} is not supported with {}", option, conflict),
+                ));
+            }
+        }
+        let bring_up: bool = parse_option(&self.info.network.options, OPTION_BRING_UP, true)?;
+        let metric = core_utils::get_route_metric(&self.info.network.options)?;
+        let ifalias: bool = parse_option(&self.info.network.options, OPTION_IFALIAS, false)?;
+        let port_options = get_bridge_port_options(&self.info.network.options)?;
+        let static_neighbors = core_utils::get_static_neighbors(self.info.network)?;
+        let policy_routes = core_utils::get_policy_routes(self.info.network)?;
+        let mode = get_bridge_mode_option(&self.info.network.options)?;
+        get_bridge_cleanup_option(&self.info.network.options)?;
+        let routed = mode == BridgeMode::Routed;
+        let l2_only = mode == BridgeMode::L2Only;
+        let group_fwd_mask = get_group_fwd_mask_option(&self.info.network.options)?;
+        let vlan = firewall_options.vlan;
+        if let Some(vlan) = vlan {
+            if routed {
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", OPTION_VLAN),
+                    format!(
+                        "{} cannot be used with {} mode",
+                        OPTION_VLAN, BRIDGE_MODE_ROUTED
+                    ),
+                ));
+            }
+            let name = vlan_interface_name(&bridge_name, vlan);
+            if name.len() > core_utils::MAX_IFNAME_LEN {
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", OPTION_VLAN),
+                    format!(
+                        "vlan interface name {} is longer than {} characters",
+                        name,
+                        core_utils::MAX_IFNAME_LEN
+                    ),
+                ));
+            }
+        }
+        let nat64 = get_nat64_options(self.info.network)?;
+        if nat64.is_some() && self.info.firewall.driver_name() == firewall::FIREWALLD {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_NAT64),
+                format!(
+                    "{} is not supported with the firewalld driver",
+                    OPTION_NAT64
+                ),
+            ));
+        }
+        if routed && self.info.network.internal {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_MODE),
+                format!(
+                    "{} mode cannot be used with internal networks",
+                    BRIDGE_MODE_ROUTED
+                ),
+            ));
+        }
+        let disable_ipv6: bool =
+            parse_option(&self.info.network.options, OPTION_DISABLE_IPV6, false)?;
+        if disable_ipv6
+            && self
+                .info
+                .network
+                .subnets
+                .iter()
+                .flatten()
+                .any(|s| s.subnet.addr().is_ipv6())
+        {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_DISABLE_IPV6),
+                format!("{} cannot be used with ipv6 subnets", OPTION_DISABLE_IPV6),
+            ));
+        }
+        if l2_only {
+            // these need addresses on the bridge or the container interface
+            let unsupported = [
+                (vlan.is_some(), OPTION_VLAN),
+                (nat64.is_some(), OPTION_NAT64),
+                (!policy_routes.is_empty(), OPTION_POLICY_ROUTES),
+                (port_proxy, OPTION_PORT_FORWARD_MODE),
+                (firewall_netns, OPTION_FIREWALL_NETNS),
+            ];
+            if let Some((_, option)) = unsupported.iter().find(|(used, _)| *used) {
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", option),
+                    format!(
+                        "{} cannot be used with {} mode",
+                        option, BRIDGE_MODE_L2_ONLY
+                    ),
+                ));
+            }
+            if self.info.network.dns_enabled {
+                return Err(ConfigError::invalid_value(
+                    "dns_enabled",
+                    format!(
+                        "dns_enabled cannot be used with {} mode",
+                        BRIDGE_MODE_L2_ONLY
+                    ),
+                ));
+            }
+        }
+        // the lease is only taken by the setup, validating must not change anything
+        let ipam = self.get_ipam(LeaseMode::Preview, routed, l2_only)?;
+        check_port_container_ips(&self.info, &ipam.container_addresses)?;
+        if !bring_up {
+            // the kernel does not accept routes via a link which is down
+            let conflict = if routed {
+                Some(format!("{}={}", OPTION_MODE, BRIDGE_MODE_ROUTED))
+            } else if !policy_routes.is_empty() {
+                Some(OPTION_POLICY_ROUTES.to_string())
+            } else if !self.info.network.internal && !ipam.gateway_addresses.is_empty() {
+                Some("the default routes of a network with a gateway".to_string())
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", OPTION_BRING_UP),
+                    format!(
+                        "{}=false is not supported with {}, routes need an interface which is up",
+                        OPTION_BRING_UP, conflict
+                    ),
+                ));
+            }
+        }
 
         let static_mac = match &self.info.per_network_opts.static_mac {
             Some(mac) => Some(CoreUtils::decode_address_from_hex(mac)?),
@@ -82,10 +532,34 @@ impl driver::NetworkDriver for Bridge<'_> {
             bridge_interface_name: bridge_name,
             container_interface_name: self.info.per_network_opts.interface_name.clone(),
             mac_address: static_mac,
+            existing_host_veth: self.info.per_network_opts.existing_host_veth.clone(),
+            host_interface_name: CoreUtils::create_host_veth_name(
+                self.info.container_id,
+                &self.info.network.name,
+            ),
             ipam,
             mtu,
-            isolate,
+            firewall: firewall_options,
+            port_proxy,
+            firewall_netns,
             metric: Some(metric),
+            port_options,
+            static_neighbors,
+            disable_ipv6,
+            bring_up,
+            policy_routes,
+            routed,
+            l2_only,
+            nat64,
+            group_fwd_mask,
+            vlan,
+            aliases: ifalias.then(|| InterfaceAliases {
+                bridge: format!("netavark network={}", self.info.network.name),
+                host_veth: format!(
+                    "netavark container={} network={}",
+                    self.info.container_id, self.info.network.name
+                ),
+            }),
         });
         Ok(())
     }
@@ -99,6 +573,317 @@ impl driver::NetworkDriver for Bridge<'_> {
             None => return Err(NetavarkError::msg("must call validate() before setup()")),
         };
 
+        // validate() only looked at the lease file, take the lease now
+        let leased = if !data.l2_only && lease::is_lease_ipam(self.info.network) {
+            let ipam = self.get_ipam(LeaseMode::Acquire, data.routed, false)?;
+            Some(InternalData {
+                ipam,
+                ..data.clone()
+            })
+        } else {
+            None
+        };
+        let data = leased.as_ref().unwrap_or(data);
+
+        let result = self.setup_network(data, netlink_sockets);
+        if result.is_err() && leased.is_some() {
+            if let Err(e) = lease::release_for(&self.info) {
+                error!("failed to release lease after setup failed: {}", e);
+            }
+        }
+        result
+    }
+
+    fn reload(&self) -> NetavarkResult<()> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => return Err(NetavarkError::msg("must call validate() before reload()")),
+        };
+        if self.info.network.internal || data.l2_only {
+            return Ok(());
+        }
+        // the rules are only added when missing, the port proxy keeps running
+        self.setup_firewall(data)?;
+        Ok(())
+    }
+
+    fn drain(&self) -> NetavarkResult<()> {
+        self.drain_port_forward()
+    }
+
+    fn cleanup(&self, host: &mut netlink::Socket) -> NetavarkResult<()> {
+        let mut error_list = NetavarkErrorList::new();
+
+        if let Err(err) = self.remove_host_routes(host) {
+            error_list.push(err);
+        }
+
+        // the veth pair is gone with the namespace
+        let complete_teardown = match remove_unused_bridge(host, &self.bridge_removal()?) {
+            Ok(teardown) => teardown,
+            Err(err) => {
+                error_list.push(err);
+                false
+            }
+        };
+
+        if let Err(err) = self.remove_nat64_route(host, None) {
+            error_list.push(err);
+        }
+
+        if !self.info.network.internal && !self.is_l2_only() {
+            // the rules in the container namespace are gone with it
+            if get_firewall_netns_option(&self.info.network.options).unwrap_or(false) {
+                if get_port_proxy_option(&self.info.network.options).unwrap_or(false) {
+                    if let Err(err) =
+                        port_proxy::stop(self.info.config_dir, &self.port_proxy_name())
+                    {
+                        error_list.push(err);
+                    }
+                }
+            } else if let Err(err) = self.teardown_firewall(complete_teardown) {
+                error_list.push(err);
+            }
+        }
+
+        if let Err(err) = lease::release_for(&self.info) {
+            error_list.push(err);
+        }
+
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
+        Ok(())
+    }
+
+    fn emit_script(&self, script: &mut Script) -> NetavarkResult<()> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => {
+                return Err(NetavarkError::msg(
+                    "must call validate() before emit_script()",
+                ))
+            }
+        };
+        // these need netavark or another process at runtime
+        let unsupported = [
+            (data.vlan.is_some(), OPTION_VLAN),
+            (data.routed, OPTION_MODE),
+            (data.nat64.is_some(), OPTION_NAT64),
+            (data.port_proxy, OPTION_PORT_FORWARD_MODE),
+            (data.firewall_netns, OPTION_FIREWALL_NETNS),
+            (!data.static_neighbors.is_empty(), OPTION_STATIC_NEIGHBORS),
+            (!data.policy_routes.is_empty(), OPTION_POLICY_ROUTES),
+        ];
+        if let Some((_, option)) = unsupported.iter().find(|(used, _)| *used) {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", option),
+                format!("{} is not supported with --emit-script", option),
+            ));
+        }
+        if self.info.network.dns_enabled {
+            return Err(ConfigError::invalid_value(
+                "dns_enabled",
+                "dns_enabled is not supported with --emit-script, dns needs aardvark-dns",
+            ));
+        }
+        if data.existing_host_veth.is_some() {
+            return Err(ConfigError::invalid_value(
+                "existing_host_veth",
+                "existing_host_veth is not supported with --emit-script",
+            ));
+        }
+
+        script.comment(format!(
+            "network {}: bridge {}",
+            self.info.network.name, data.bridge_interface_name
+        ));
+        script_bridge(script, data, self.info.config_dir);
+        script.comment(format!(
+            "network {}: container interface {}",
+            self.info.network.name, data.container_interface_name
+        ));
+        script_veth_pair(script, data, self.info.network.internal);
+        if data.l2_only {
+            return Ok(());
+        }
+
+        script.comment(format!("network {}: firewall", self.info.network.name));
+        if self.info.network.internal {
+            script.host(&[
+                "sysctl",
+                "-w",
+                &format!("net.ipv4.conf.{}.forwarding=0", data.bridge_interface_name),
+            ]);
+            if data.ipam.ipv6_enabled {
+                script.host(&[
+                    "sysctl",
+                    "-w",
+                    &format!("net.ipv6.conf.{}.forwarding=0", data.bridge_interface_name),
+                ]);
+            }
+            return Ok(());
+        }
+        let (sn, spf) = self.get_firewall_conf(
+            &data.ipam.container_addresses,
+            &data.ipam.nameservers,
+            data.firewall.clone(),
+        )?;
+        if spf.port_mappings.is_some() {
+            script.host(&[
+                "sysctl",
+                "-w",
+                &format!(
+                    "net.ipv4.conf.{}.route_localnet=1",
+                    data.bridge_interface_name
+                ),
+            ]);
+        }
+        self.info.firewall.emit_script(&sn, &spf, script)
+    }
+
+    fn teardown(
+        &self,
+        netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<()> {
+        let mut error_list = NetavarkErrorList::new();
+
+        if let Err(err) = self.teardown_network(netlink_sockets) {
+            error_list.push(err);
+        }
+
+        // release the lease only after the firewall rules for its addresses are gone
+        if let Err(err) = lease::release_for(&self.info) {
+            error_list.push(err);
+        }
+
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
+
+        Ok(())
+    }
+}
+
+fn get_interface_name(name: Option<String>) -> NetavarkResult<String> {
+    let name = match name {
+        None => {
+            return Err(ConfigError::missing_field(
+                "network_interface",
+                NO_BRIDGE_NAME_ERROR,
+            ))
+        }
+        Some(n) => {
+            if n.is_empty() {
+                return Err(ConfigError::missing_field(
+                    "network_interface",
+                    NO_BRIDGE_NAME_ERROR,
+                ));
+            }
+            n
+        }
+    };
+    Ok(name)
+}
+
+impl<'a> Bridge<'a> {
+    /// Everything of the teardown but the release of the lease, which the caller
+    /// of a failed setup releases.
+    fn teardown_network(
+        &self,
+        netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<()> {
+        let (host_sock, netns_sock) = netlink_sockets;
+
+        let mut error_list = NetavarkErrorList::new();
+
+        // the routes are removed with the interface but the rules stay
+        match core_utils::get_policy_routes(self.info.network) {
+            Ok(routes) => {
+                if let Err(err) = core_utils::remove_policy_rules(netns_sock, &routes) {
+                    error_list.push(err);
+                }
+            }
+            Err(err) => error!("failed to parse {} option: {}", OPTION_POLICY_ROUTES, err),
+        }
+
+        // the host routes point to the bridge and are not removed with the veth
+        if let Err(err) = self.remove_host_routes(host_sock) {
+            error_list.push(err);
+        }
+
+        let complete_teardown = match remove_link(
+            host_sock,
+            netns_sock,
+            &self.bridge_removal()?,
+            &self.info.per_network_opts.interface_name,
+            self.info.per_network_opts.existing_host_veth.as_deref(),
+        ) {
+            Ok(teardown) => teardown,
+            Err(err) => {
+                error_list.push(err);
+                false
+            }
+        };
+
+        if let Err(err) = self.remove_nat64_route(host_sock, Some(netns_sock)) {
+            error_list.push(err);
+        }
+
+        if !self.info.network.internal && !self.is_l2_only() {
+            match self.teardown_firewall(complete_teardown) {
+                Ok(_) => {}
+                Err(err) => {
+                    error_list.push(err);
+                }
+            };
+        }
+
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
+
+        Ok(())
+    }
+
+    /// The addresses of the container, with `routed` the bridge and the
+    /// container only get host addresses.
+    fn get_ipam(
+        &self,
+        mode: LeaseMode,
+        routed: bool,
+        l2_only: bool,
+    ) -> NetavarkResult<IPAMAddresses> {
+        if l2_only {
+            // the container gets its addresses some other way
+            return Ok(IPAMAddresses {
+                container_addresses: vec![],
+                dhcp_enabled: false,
+                gateway_addresses: vec![],
+                net_addresses: vec![],
+                nameservers: vec![],
+                ipv6_enabled: false,
+            });
+        }
+        let mut ipam = lease::get_ipam_addresses(&self.info, mode)?;
+        if routed {
+            // there is no on-link subnet, the firewall rules still use the
+            // subnets of the container_addresses
+            for addr in ipam.gateway_addresses.iter_mut() {
+                *addr = IpNet::from(addr.addr());
+            }
+            for net in ipam.net_addresses.iter_mut() {
+                net.ipnet = IpNet::from(net.ipnet.addr());
+            }
+        }
+        Ok(ipam)
+    }
+
+    fn setup_network(
+        &self,
+        data: &InternalData,
+        netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
         debug!("Setup network {}", self.info.network.name);
         debug!(
             "Container interface name: {} with IP addresses {:?}",
@@ -109,27 +894,110 @@ impl driver::NetworkDriver for Bridge<'_> {
             data.bridge_interface_name, data.ipam.gateway_addresses
         );
 
-        setup_ipv4_fw_sysctl()?;
+        // the bridge only switches with l2_only, nothing is forwarded
+        if !data.l2_only {
+            setup_ipv4_fw_sysctl()?;
+        }
         if data.ipam.ipv6_enabled {
             setup_ipv6_fw_sysctl()?;
         }
 
         let (host_sock, netns_sock) = netlink_sockets;
 
-        let container_veth_mac = create_interfaces(
+        let nat64_added = match &data.nat64 {
+            Some(nat64) => add_nat64_route(host_sock, nat64)?,
+            None => false,
+        };
+
+        let (bridge, bridge_created) = match setup_bridge(host_sock, data, self.info.config_dir) {
+            Ok(bridge) => bridge,
+            Err(err) => {
+                self.undo_setup(host_sock, data, false, nat64_added);
+                return Err(err);
+            }
+        };
+
+        let turn = self
+            .info
+            .link_order
+            .map(|order| order.wait(&self.info.network.name));
+        let container_veth_mac = match create_veth_pair(
             host_sock,
             netns_sock,
             data,
+            bridge.header.index,
             self.info.network.internal,
             self.info.netns_host,
             self.info.netns_container,
-        )?;
+        ) {
+            Ok(mac) => mac,
+            Err(err) => {
+                self.undo_setup(host_sock, data, bridge_created, nat64_added);
+                return Err(err);
+            }
+        };
+        drop(turn);
+
+        match self.setup_container(data, container_veth_mac) {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                // do not leave a half configured interface behind, the container
+                // must only keep the networks which were set up successfully
+                // setup() releases the lease
+                if let Err(e) = self.teardown_network((host_sock, netns_sock)) {
+                    error!(
+                        "failed to cleanup network {} after setup failed: {}",
+                        self.info.network.name, e
+                    );
+                }
+                self.undo_setup(host_sock, data, bridge_created, nat64_added);
+                Err(err)
+            }
+        }
+    }
+
+    /// Remove the bridge and the nat64 route when setup_network() created
+    /// them and fails afterwards, the teardown keeps both in some modes.
+    fn undo_setup(
+        &self,
+        host: &mut netlink::Socket,
+        data: &InternalData,
+        bridge_created: bool,
+        nat64_added: bool,
+    ) {
+        if bridge_created {
+            if let Err(e) =
+                remove_created_bridge(host, self.info.config_dir, &data.bridge_interface_name)
+            {
+                error!("failed to remove bridge after setup failed: {}", e);
+            }
+        }
+        if let Some(nat64) = data.nat64.as_ref().filter(|_| nat64_added) {
+            if let Err(e) = del_nat64_route(host, nat64) {
+                error!("failed to remove nat64 route after setup failed: {}", e);
+            }
+        }
+    }
 
+    /// Setup everything for the container after its interface was created,
+    /// the network wide firewall rules are only added when they are missing.
+    fn setup_container(
+        &self,
+        data: &InternalData,
+        container_veth_mac: String,
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
         //  StatusBlock response
         let mut response = types::StatusBlock {
             dns_server_ips: Some(Vec::<IpAddr>::new()),
             dns_search_domains: Some(Vec::<String>::new()),
             interfaces: Some(HashMap::new()),
+            port_proxies: None,
+            default_gateways: None,
+            policy_routes: None,
+            host_routes: None,
+            nat64_prefix: None,
+            interface_index: None,
+            bridge: None,
         };
         // interfaces map, but we only ever expect one, for response
         let mut interfaces: HashMap<String, types::NetInterface> = HashMap::new();
@@ -137,10 +1005,39 @@ impl driver::NetworkDriver for Bridge<'_> {
         let interface = types::NetInterface {
             mac_address: container_veth_mac,
             subnets: Option::from(data.ipam.net_addresses.clone()),
+            admin_state: Some(
+                if data.bring_up {
+                    constants::ADMIN_STATE_UP
+                } else {
+                    constants::ADMIN_STATE_DOWN
+                }
+                .to_string(),
+            ),
         };
         // Add interface to interfaces (part of StatusBlock)
         interfaces.insert(data.container_interface_name.clone(), interface);
         let _ = response.interfaces.insert(interfaces);
+        if !data.policy_routes.is_empty() {
+            response.policy_routes = Some(data.policy_routes.clone());
+        }
+        if let Some(nat64) = &data.nat64 {
+            response.nat64_prefix = Some(nat64.prefix.into());
+        }
+        if data.routed {
+            response.host_routes = Some(
+                data.ipam
+                    .container_addresses
+                    .iter()
+                    .map(|addr| types::HostRoute {
+                        destination: IpNet::from(addr.addr()),
+                        interface: data.bridge_interface_name.clone(),
+                    })
+                    .collect(),
+            );
+        }
+        if data.l2_only {
+            response.bridge = Some(data.bridge_interface_name.clone());
+        }
         let aardvark_entry = if self.info.network.dns_enabled {
             let _ = response
                 .dns_server_ips
@@ -199,104 +1096,90 @@ impl driver::NetworkDriver for Bridge<'_> {
             None
         };
 
+        // a plain switch, nothing is routed or filtered
+        if data.l2_only {
+            return Ok((response, aardvark_entry));
+        }
+
         // if the network is internal block routing and do not setup firewall rules
         if self.info.network.internal {
             CoreUtils::apply_sysctl_value(
                 format!(
                     "/proc/sys/net/ipv4/conf/{}/forwarding",
-                    data.bridge_interface_name
+                    data.gateway_interface_name()
                 ),
-                "0",
-            )?;
-            if data.ipam.ipv6_enabled {
-                CoreUtils::apply_sysctl_value(
-                    format!(
-                        "/proc/sys/net/ipv6/conf/{}/forwarding",
-                        data.bridge_interface_name
-                    ),
-                    "0",
-                )?;
-            }
-            // return here to skip setting up firewall rules
-            return Ok((response, aardvark_entry));
-        }
-
-        self.setup_firewall(data)?;
-
-        Ok((response, aardvark_entry))
-    }
-
-    fn teardown(
-        &self,
-        netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
-    ) -> NetavarkResult<()> {
-        let (host_sock, netns_sock) = netlink_sockets;
-
-        let mut error_list = NetavarkErrorList::new();
-
-        let complete_teardown = match remove_link(
-            host_sock,
-            netns_sock,
-            &get_interface_name(self.info.network.network_interface.clone())?,
-            &self.info.per_network_opts.interface_name,
-        ) {
-            Ok(teardown) => teardown,
-            Err(err) => {
-                error_list.push(err);
-                false
-            }
-        };
-
-        if self.info.network.internal {
-            if !error_list.is_empty() {
-                return Err(NetavarkError::List(error_list));
+                "0",
+            )?;
+            if data.ipam.ipv6_enabled {
+                CoreUtils::apply_sysctl_value(
+                    format!(
+                        "/proc/sys/net/ipv6/conf/{}/forwarding",
+                        data.gateway_interface_name()
+                    ),
+                    "0",
+                )?;
             }
-            return Ok(());
+            // return here to skip setting up firewall rules
+            return Ok((response, aardvark_entry));
         }
 
-        match self.teardown_firewall(complete_teardown) {
-            Ok(_) => {}
-            Err(err) => {
-                error_list.push(err);
-            }
-        };
+        let proxy_forwards = self.setup_firewall(data)?;
+        port_proxy::start(
+            self.info.config_dir,
+            &self.port_proxy_name(),
+            &proxy_forwards,
+        )?;
 
-        if !error_list.is_empty() {
-            return Err(NetavarkError::List(error_list));
+        if data.port_proxy {
+            response.port_proxies = self.info.port_mappings.clone();
         }
 
-        Ok(())
+        Ok((response, aardvark_entry))
     }
-}
-
-fn get_interface_name(name: Option<String>) -> NetavarkResult<String> {
-    let name = match name {
-        None => return Err(NetavarkError::msg(NO_BRIDGE_NAME_ERROR)),
-        Some(n) => {
-            if n.is_empty() {
-                return Err(NetavarkError::msg(NO_BRIDGE_NAME_ERROR));
-            }
-            n
-        }
-    };
-    Ok(name)
-}
 
-impl<'a> Bridge<'a> {
     fn get_firewall_conf(
         &'a self,
         container_addresses: &Vec<IpNet>,
         nameservers: &'a Vec<IpAddr>,
-        isolate: bool,
+        options: FirewallOptions,
     ) -> NetavarkResult<(SetupNetwork, PortForwardConfig)> {
         let id_network_hash =
             CoreUtils::create_network_hash(&self.info.network.name, MAX_HASH_SIZE);
+        let accounting = options.accounting;
+        let rate_limit = options.rate_limit.clone();
+        let mut net = self.info.network.clone();
+        if let (Some(vlan), Some(bridge)) = (options.vlan, &net.network_interface) {
+            // the routed traffic of the containers goes through the vlan interface
+            net.network_interface = Some(vlan_interface_name(bridge, vlan));
+        }
         let sn = SetupNetwork {
-            net: self.info.network.clone(),
-            network_hash_name: id_network_hash.clone(),
-            isolation: isolate,
+            net,
+            network_hash_name: id_network_hash,
+            isolation: options.isolate,
+            trusted: options.trusted,
+            policy: options.policy,
+            conntrack_zone: options.conntrack_zone,
+            masquerade_ports: options.masquerade_ports,
+            nat64_pool: options.nat64_pool,
+            ctstate_accept: options.ctstate_accept,
+            icc: options.icc,
+            dscp: options.dscp,
+            fwmark: options.fwmark,
         };
+        let spf =
+            self.get_port_forward_conf(container_addresses, nameservers, accounting, rate_limit)?;
+        Ok((sn, spf))
+    }
 
+    fn get_port_forward_conf(
+        &'a self,
+        container_addresses: &Vec<IpNet>,
+        nameservers: &'a Vec<IpAddr>,
+        accounting: bool,
+        rate_limit: Option<RateLimit>,
+    ) -> NetavarkResult<PortForwardConfig<'a>> {
+        let id_network_hash =
+            CoreUtils::create_network_hash(&self.info.network.name, MAX_HASH_SIZE);
         let mut has_ipv4 = false;
         let mut has_ipv6 = false;
         let mut addr_v4: Option<IpAddr> = None;
@@ -333,59 +1216,232 @@ impl<'a> Bridge<'a> {
             subnet_v4: net_v4,
             container_ip_v6: addr_v6,
             subnet_v6: net_v6,
+            container_ips: container_addresses.iter().map(|a| a.addr()).collect(),
             dns_port: self.info.dns_port,
             dns_server_ips: nameservers,
+            accounting,
+            rate_limit,
         };
-        Ok((sn, spf))
+        Ok(spf)
     }
 
-    fn setup_firewall(&self, data: &InternalData) -> NetavarkResult<()> {
-        let (sn, spf) = self.get_firewall_conf(
+    /// Add the firewall rules for the container and with `network` also the
+    /// rules of the network, returns the port forwards which must be served
+    /// by the port proxy.
+    fn setup_firewall(&self, data: &InternalData) -> NetavarkResult<Vec<port_proxy::ProxyForward>> {
+        let (sn, mut spf) = self.get_firewall_conf(
             &data.ipam.container_addresses,
             &data.ipam.nameservers,
-            data.isolate,
+            data.firewall.clone(),
         )?;
 
-        self.info.firewall.setup_network(sn)?;
+        // the proxy serves the port mappings, the firewall only handles dns
+        let mut proxy_forwards = Vec::new();
+        if data.port_proxy {
+            if let Some(port_mappings) = spf.port_mappings {
+                proxy_forwards = port_proxy::get_proxy_forwards(
+                    port_mappings,
+                    spf.container_ip_v4,
+                    spf.container_ip_v6,
+                    &spf.container_ips,
+                )?;
+            }
+            spf.port_mappings = &None;
+        }
+
+        if data.firewall_netns {
+            // iptables is executed as child process which inherits the namespace of this thread
+            exec_netns!(self.info.netns_host, self.info.netns_container, res, {
+                self.info
+                    .firewall
+                    .setup_network(sn)
+                    .and_then(|_| self.info.firewall.setup_port_forward(spf))
+            });
+            res.wrap("setup firewall in container namespace")?;
+        } else {
+            // the rules of the network are only added by the first container,
+            // unless they were removed since then, e.g. by a firewall reload
+            if self.info.firewall.network_is_set_up(&sn)? {
+                debug!(
+                    "Firewall rules of network {} are already set up",
+                    self.info.network.name
+                );
+            } else {
+                self.info.firewall.setup_network(sn)?;
+            }
 
-        if spf.port_mappings.is_some() {
-            // Need to enable sysctl localnet so that traffic can pass
-            // through localhost to containers
+            if spf.port_mappings.is_some() {
+                // Need to enable sysctl localnet so that traffic can pass
+                // through localhost to containers
 
-            CoreUtils::apply_sysctl_value(
-                format!(
-                    "net.ipv4.conf.{}.route_localnet",
-                    data.bridge_interface_name
-                ),
-                "1",
-            )?;
+                CoreUtils::apply_sysctl_value(
+                    format!(
+                        "net.ipv4.conf.{}.route_localnet",
+                        data.gateway_interface_name()
+                    ),
+                    "1",
+                )?;
+            }
+
+            self.info.firewall.setup_port_forward(spf)?;
         }
 
-        self.info.firewall.setup_port_forward(spf)?;
+        Ok(proxy_forwards)
+    }
+
+    /// The bridge of the network for its removal, the options are parsed again
+    /// because the teardown may run without validate()
+    fn bridge_removal(&self) -> NetavarkResult<BridgeRemoval<'_>> {
+        let vlan = get_vlan_option(&self.info.network.options).unwrap_or_else(|e| {
+            error!("failed to parse {} option: {}", OPTION_VLAN, e);
+            None
+        });
+        let cleanup = get_bridge_cleanup_option(&self.info.network.options).unwrap_or_else(|e| {
+            error!("failed to parse {} option: {}", OPTION_BRIDGE_CLEANUP, e);
+            BridgeCleanup::Created
+        });
+        Ok(BridgeRemoval {
+            name: get_interface_name(self.info.network.network_interface.clone())?,
+            vlan,
+            cleanup,
+            config_dir: self.info.config_dir,
+        })
+    }
+
+    /// l2_only mode from the parsed data, the teardown may run without validate()
+    fn is_l2_only(&self) -> bool {
+        match &self.data {
+            Some(d) => d.l2_only,
+            None => match get_bridge_mode_option(&self.info.network.options) {
+                Ok(mode) => mode == BridgeMode::L2Only,
+                Err(e) => {
+                    error!("failed to parse {} option: {}", OPTION_MODE, e);
+                    false
+                }
+            },
+        }
+    }
+
+    /// name of the port proxy for this container and network
+    fn port_proxy_name(&self) -> String {
+        format!(
+            "{}-{}",
+            self.info.container_id,
+            CoreUtils::create_network_hash(&self.info.network.name, MAX_HASH_SIZE)
+        )
+    }
+
+    fn remove_host_routes(&self, host: &mut netlink::Socket) -> NetavarkResult<()> {
+        let addresses = match &self.data {
+            Some(d) if !d.routed => return Ok(()),
+            Some(d) => d.ipam.container_addresses.clone(),
+            None => {
+                match get_bridge_mode_option(&self.info.network.options) {
+                    Ok(BridgeMode::Routed) => {}
+                    Ok(_) => return Ok(()),
+                    Err(e) => {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_MODE, e);
+                        return Ok(());
+                    }
+                }
+                lease::get_ipam_addresses(&self.info, LeaseMode::Lookup)?.container_addresses
+            }
+        };
+        let bridge_name = get_interface_name(self.info.network.network_interface.clone())?;
+        let bridge = match host.get_link(netlink::LinkID::Name(bridge_name)) {
+            Ok(bridge) => bridge,
+            // the routes are gone with the bridge
+            Err(NetavarkError::Netlink(e)) if -e.code == libc::ENODEV => return Ok(()),
+            Err(err) => return Err(err).wrap("get bridge interface"),
+        };
+        for addr in addresses {
+            let dest = IpNet::from(addr.addr());
+            match host.del_link_route(bridge.header.index, &dest) {
+                Ok(_) => {}
+                Err(NetavarkError::Netlink(ref e)) if -e.code == libc::ESRCH => {}
+                Err(err) => return Err(err).wrap(format!("remove host route to {}", dest)),
+            }
+        }
         Ok(())
     }
 
+    /// Remove the route of the NAT64 prefix when no other network of a set up
+    /// container uses the translator anymore. The other networks of this
+    /// container are only in use while their interface is still in the
+    /// namespace, without a namespace they are all gone.
+    fn remove_nat64_route(
+        &self,
+        host: &mut netlink::Socket,
+        mut netns: Option<&mut netlink::Socket>,
+    ) -> NetavarkResult<()> {
+        let nat64 = match &self.data {
+            Some(d) => d.nat64.clone(),
+            None => match get_nat64_options(self.info.network) {
+                Ok(nat64) => nat64,
+                Err(e) => {
+                    // just log we still try to do as much as possible for cleanup
+                    error!("failed to parse nat64 options: {}", e);
+                    None
+                }
+            },
+        };
+        let nat64 = match nat64 {
+            Some(nat64) => nat64,
+            None => return Ok(()),
+        };
+
+        for container in state::list(self.info.config_dir)? {
+            let own = container.options.container_id == *self.info.container_id;
+            for (name, network) in &container.options.network_info {
+                if own && *name == self.info.network.name {
+                    continue;
+                }
+                let uses_translator = get_nat64_options(network)
+                    .ok()
+                    .flatten()
+                    .is_some_and(|n| n.interface == nat64.interface && n.prefix == nat64.prefix);
+                if !uses_translator {
+                    continue;
+                }
+                if !own {
+                    return Ok(());
+                }
+                let interface = match container.options.networks.get(name) {
+                    Some(opts) => opts.interface_name.clone(),
+                    None => continue,
+                };
+                if let Some(netns) = netns.as_deref_mut() {
+                    if netns.get_link(netlink::LinkID::Name(interface)).is_ok() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        del_nat64_route(host, &nat64)
+    }
+
     fn teardown_firewall(&self, complete_teardown: bool) -> NetavarkResult<()> {
         // we have to allocate the vecoros here in the top level to avoid
         // "borrow later used" problems
         let (container_addresses, nameservers);
 
-        let (container_addresses_ref, nameservers_ref, isolate) = match &self.data {
-            Some(d) => (&d.ipam.container_addresses, &d.ipam.nameservers, d.isolate),
+        let (container_addresses_ref, nameservers_ref, firewall_options) = match &self.data {
+            Some(d) => (
+                &d.ipam.container_addresses,
+                &d.ipam.nameservers,
+                d.firewall.clone(),
+            ),
             None => {
                 // options are not yet parsed
-                let isolate = match parse_option(&self.info.network.options, OPTION_ISOLATE, false)
-                {
-                    Ok(i) => i,
-                    Err(e) => {
-                        // just log we still try to do as much as possible for cleanup
-                        error!("failed to parse {} option: {}", OPTION_ISOLATE, e);
-                        false
-                    }
-                };
+                let firewall_options = FirewallOptions::parse(self.info.network, &mut |e| {
+                    // just log we still try to do as much as possible for cleanup
+                    error!("failed to parse firewall options: {}", e);
+                });
 
                 (container_addresses, nameservers) =
-                    match get_ipam_addresses(self.info.per_network_opts, self.info.network) {
+                    match lease::get_ipam_addresses(&self.info, LeaseMode::Lookup) {
                         Ok(i) => (i.container_addresses, i.nameservers),
                         Err(e) => {
                             // just log we still try to do as much as possible for cleanup
@@ -393,30 +1449,134 @@ impl<'a> Bridge<'a> {
                             (Vec::new(), Vec::new())
                         }
                     };
-                (&container_addresses, &nameservers, isolate)
+                (&container_addresses, &nameservers, firewall_options)
             }
         };
 
-        let (sn, spf) =
-            self.get_firewall_conf(container_addresses_ref, nameservers_ref, isolate)?;
+        let port_proxy = match &self.data {
+            Some(d) => d.port_proxy,
+            None => get_port_proxy_option(&self.info.network.options).unwrap_or_else(|e| {
+                error!("failed to parse {} option: {}", OPTION_PORT_FORWARD_MODE, e);
+                false
+            }),
+        };
+
+        let firewall_netns = match &self.data {
+            Some(d) => d.firewall_netns,
+            None => get_firewall_netns_option(&self.info.network.options).unwrap_or_else(|e| {
+                error!("failed to parse {} option: {}", OPTION_FIREWALL_NETNS, e);
+                false
+            }),
+        };
+        // the rules in the container namespace are never shared with other containers
+        let complete_teardown = complete_teardown || firewall_netns;
+
+        let (sn, mut spf) =
+            self.get_firewall_conf(container_addresses_ref, nameservers_ref, firewall_options)?;
+        if port_proxy {
+            spf.port_mappings = &None;
+        }
+
+        let container_ips = spf.container_ips.clone();
 
         let tn = TearDownNetwork {
             config: sn,
             complete_teardown,
         };
+        let tpf = TeardownPortForward {
+            config: spf,
+            complete_teardown,
+        };
 
-        if complete_teardown {
-            // FIXME store error and continue
-            self.info.firewall.teardown_network(tn)?;
+        if firewall_netns {
+            exec_netns!(self.info.netns_host, self.info.netns_container, res, {
+                self.teardown_firewall_rules(tn, tpf)
+            });
+            res.wrap("teardown firewall in container namespace")?;
+        } else {
+            self.teardown_firewall_rules(tn, tpf)?;
+        }
+
+        if port_proxy {
+            port_proxy::stop(self.info.config_dir, &self.port_proxy_name())?;
+            return Ok(());
         }
 
+        if let Some(port_mappings) = self.info.port_mappings {
+            let cleanup =
+                match parse_option(&self.info.network.options, OPTION_CONNTRACK_CLEANUP, true) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        error!("failed to parse {} option: {}", OPTION_CONNTRACK_CLEANUP, e);
+                        true
+                    }
+                };
+            if cleanup {
+                if firewall_netns {
+                    exec_netns!(self.info.netns_host, self.info.netns_container, _res, {
+                        conntrack::flush_port_mappings(port_mappings, &container_ips)
+                    });
+                } else {
+                    conntrack::flush_port_mappings(port_mappings, &container_ips);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove only the port forwarding rules of the container, conntrack keeps
+    /// translating the established connections.
+    fn drain_port_forward(&self) -> NetavarkResult<()> {
+        if self.info.network.internal
+            || self.is_l2_only()
+            || self
+                .info
+                .port_mappings
+                .as_ref()
+                .map(|m| m.is_empty())
+                .unwrap_or(true)
+        {
+            return Ok(());
+        }
+        if get_port_proxy_option(&self.info.network.options).unwrap_or(false) {
+            // the connections go through the proxy and end with it
+            debug!(
+                "port mappings of network {} are served by the port proxy, not draining them",
+                self.info.network.name
+            );
+            return Ok(());
+        }
+        let firewall_netns = get_firewall_netns_option(&self.info.network.options).unwrap_or(false);
+
+        let ipam = lease::get_ipam_addresses(&self.info, LeaseMode::Lookup)?;
+        // keep the accounting rule so its counters are still there until the teardown,
+        // the rate limit rules only match connections to the container and stay too
+        let spf =
+            self.get_port_forward_conf(&ipam.container_addresses, &ipam.nameservers, false, None)?;
         let tpf = TeardownPortForward {
             config: spf,
-            complete_teardown,
+            complete_teardown: false,
         };
+        if firewall_netns {
+            exec_netns!(self.info.netns_host, self.info.netns_container, res, {
+                self.info.firewall.teardown_port_forward(tpf)
+            });
+            res.wrap("remove port forwarding in container namespace")
+        } else {
+            self.info.firewall.teardown_port_forward(tpf)
+        }
+    }
 
-        self.info.firewall.teardown_port_forward(tpf)?;
-        Ok(())
+    fn teardown_firewall_rules(
+        &self,
+        tn: TearDownNetwork,
+        tpf: TeardownPortForward,
+    ) -> NetavarkResult<()> {
+        if tn.complete_teardown {
+            // FIXME store error and continue
+            self.info.firewall.teardown_network(tn)?;
+        }
+        self.info.firewall.teardown_port_forward(tpf)
     }
 }
 
@@ -456,19 +1616,30 @@ fn setup_ipv6_fw_sysctl() -> NetavarkResult<()> {
     Ok(())
 }
 
-/// returns the container veth mac address
-fn create_interfaces(
+/// Create the bridge if it does not exist yet, an existing one only gets the
+/// missing gateway addresses. Returns whether the bridge was created.
+fn setup_bridge(
     host: &mut netlink::Socket,
-    netns: &mut netlink::Socket,
     data: &InternalData,
-    internal: bool,
-    hostns_fd: RawFd,
-    netns_fd: RawFd,
-) -> NetavarkResult<String> {
-    let bridge = match host.get_link(netlink::LinkID::Name(
+    config_dir: &str,
+) -> NetavarkResult<(LinkMessage, bool)> {
+    // a parallel setup must not create the same bridge or its addresses
+    let _lock = state::lock_bridge(config_dir, &data.bridge_interface_name)?;
+    match host.get_link(netlink::LinkID::Name(
         data.bridge_interface_name.to_string(),
     )) {
-        Ok(bridge) => check_link_is_bridge(bridge, &data.bridge_interface_name)?,
+        Ok(bridge) => {
+            let bridge = check_link_is_bridge(bridge, &data.bridge_interface_name)?;
+            if let Some(vlan) = data.vlan {
+                // the bridge may have been created without a vlan or by someone else
+                host.set_bridge_vlan_filtering(bridge.header.index)
+                    .wrap("enable vlan filtering on bridge")?;
+                setup_vlan_interface(host, &bridge, data, vlan)?;
+                return Ok((bridge, false));
+            }
+            ensure_bridge_addresses(host, bridge.header.index, &data.bridge_interface_name, data)?;
+            Ok((bridge, false))
+        }
         Err(err) => match err.unwrap() {
             NetavarkError::Netlink(e) => {
                 if -e.code != libc::ENODEV {
@@ -481,53 +1652,477 @@ fn create_interfaces(
                     InfoKind::Bridge,
                 );
                 create_link_opts.mtu = data.mtu;
+                let mut bridge_info = Vec::new();
+                if let Some(mask) = data.group_fwd_mask {
+                    bridge_info.push(InfoBridge::GroupFwdMask(mask));
+                }
+                if data.vlan.is_some() {
+                    bridge_info.push(InfoBridge::VlanFiltering(1));
+                }
+                if !bridge_info.is_empty() {
+                    create_link_opts.info_data = Some(InfoData::Bridge(bridge_info));
+                }
                 host.create_link(create_link_opts).wrap("create bridge")?;
 
-                if data.ipam.ipv6_enabled {
-                    // Disable duplicate address detection if ipv6 enabled
-                    // Do not accept Router Advertisements if ipv6 is enabled
-                    let br_accept_dad = format!(
-                        "/proc/sys/net/ipv6/conf/{}/accept_dad",
-                        &data.bridge_interface_name
-                    );
-                    let br_accept_ra =
-                        format!("net/ipv6/conf/{}/accept_ra", &data.bridge_interface_name);
-                    CoreUtils::apply_sysctl_value(br_accept_dad, "0")?;
-                    CoreUtils::apply_sysctl_value(br_accept_ra, "0")?;
-                }
+                match configure_new_bridge(host, data, config_dir) {
+                    Ok(link) => Ok((link, true)),
+                    Err(err) => {
+                        // do not leave a half configured bridge behind
+                        if let Err(e) =
+                            remove_created_bridge(host, config_dir, &data.bridge_interface_name)
+                        {
+                            error!("failed to remove bridge after setup failed: {}", e);
+                        }
+                        Err(err)
+                    }
+                }
+            }
+            _ => Err(err),
+        },
+    }
+}
+
+/// Record and configure the bridge which setup_bridge() just created.
+fn configure_new_bridge(
+    host: &mut netlink::Socket,
+    data: &InternalData,
+    config_dir: &str,
+) -> NetavarkResult<LinkMessage> {
+    let link = host
+        .get_link(netlink::LinkID::Name(
+            data.bridge_interface_name.to_string(),
+        ))
+        .wrap("get bridge interface")?;
+    // the teardown only removes the bridges which we created
+    state::add_bridge(config_dir, &data.bridge_interface_name, link.header.index)?;
+
+    if let Some(vlan) = data.vlan {
+        host.set_up(netlink::LinkID::ID(link.header.index))
+            .wrap("set bridge up")?;
+        setup_vlan_interface(host, &link, data, vlan)?;
+        return Ok(link);
+    }
+    configure_gateway_interface(host, link.header.index, &data.bridge_interface_name, data)?;
+    Ok(link)
+}
+
+/// Remove the bridge which a failed setup created together with its state,
+/// unless another network attached an interface to it meanwhile.
+fn remove_created_bridge(
+    host: &mut netlink::Socket,
+    config_dir: &str,
+    name: &str,
+) -> NetavarkResult<()> {
+    let br = match host.get_link(netlink::LinkID::Name(name.to_string())) {
+        Ok(br) => br,
+        Err(NetavarkError::Netlink(e)) if -e.code == libc::ENODEV => {
+            return state::remove_bridge(config_dir, name)
+        }
+        Err(err) => return Err(err).wrap("failed to get bridge interface"),
+    };
+    let links = host
+        .dump_links(&mut vec![Nla::Master(br.header.index)])
+        .wrap("failed to get connected bridge interfaces")?;
+    if !links.is_empty() {
+        return Ok(());
+    }
+    log::info!("removing bridge {}", name);
+    host.del_link(netlink::LinkID::ID(br.header.index))
+        .wrap(format!("failed to delete bridge {}", name))?;
+    state::remove_bridge(config_dir, name)
+}
+
+/// Add the commands of setup_bridge() to the script, the bridge is only
+/// created when it does not exist yet.
+fn script_bridge(script: &mut Script, data: &InternalData, config_dir: &str) {
+    let bridge = data.bridge_interface_name.as_str();
+    if !data.l2_only {
+        script.host(&["sysctl", "-w", &format!("{}=1", IPV4_FORWARD)]);
+    }
+    if data.ipam.ipv6_enabled {
+        script.host(&["sysctl", "-w", &format!("{}=1", IPV6_FORWARD)]);
+    }
+
+    let mtu = data.mtu.to_string();
+    let mask = data.group_fwd_mask.map(|mask| mask.to_string());
+    let mut create = vec!["ip", "link", "add", bridge];
+    if data.mtu != 0 {
+        create.extend(["mtu", &mtu]);
+    }
+    create.extend(["type", "bridge"]);
+    if let Some(mask) = &mask {
+        create.extend(["group_fwd_mask", mask]);
+    }
+    // record the bridge like setup_bridge() so the teardown removes it
+    let marker = state::bridge_path(config_dir, bridge);
+    script.raw(format!(
+        "if ! ip link show {} >/dev/null 2>&1; then",
+        script::quote(bridge)
+    ));
+    script.host(&create);
+    if let Some(dir) = marker.parent() {
+        script.host(&["mkdir", "-p", &dir.to_string_lossy()]);
+    }
+    script.raw(format!(
+        "echo \"$(stat -L -c %i /proc/self/ns/net) $(ip -o link show {} | cut -d: -f1)\" >{}",
+        script::quote(bridge),
+        script::quote(&marker.to_string_lossy())
+    ));
+    script.raw("fi".to_string());
+
+    if data.ipam.ipv6_enabled {
+        script.host(&[
+            "sysctl",
+            "-w",
+            &format!("net.ipv6.conf.{}.accept_dad=0", bridge),
+        ]);
+        script.host(&[
+            "sysctl",
+            "-w",
+            &format!("net.ipv6.conf.{}.accept_ra=0", bridge),
+        ]);
+    }
+    for addr in &data.ipam.gateway_addresses {
+        script.host(&["ip", "addr", "replace", &addr.to_string(), "dev", bridge]);
+    }
+    if data.disable_ipv6 {
+        script.host(&[
+            "sysctl",
+            "-w",
+            &format!("net.ipv6.conf.{}.disable_ipv6=1", bridge),
+        ]);
+    }
+    if let Some(aliases) = &data.aliases {
+        script.host(&["ip", "link", "set", bridge, "alias", &aliases.bridge]);
+    }
+    script.host(&["ip", "link", "set", bridge, "up"]);
+}
+
+/// Add the commands of create_veth_pair() to the script.
+fn script_veth_pair(script: &mut Script, data: &InternalData, internal: bool) {
+    let host_veth = data.host_interface_name.as_str();
+    let container_veth = data.container_interface_name.as_str();
+    let mtu = data.mtu.to_string();
+    let mac = data
+        .mac_address
+        .as_ref()
+        .map(|mac| CoreUtils::encode_address_to_hex(mac));
+
+    let mut create = vec!["ip", "link", "add", host_veth];
+    if data.mtu != 0 {
+        create.extend(["mtu", &mtu]);
+    }
+    create.extend(["type", "veth", "peer", "name", container_veth]);
+    if let Some(mac) = &mac {
+        create.extend(["address", mac]);
+    }
+    if data.mtu != 0 {
+        create.extend(["mtu", &mtu]);
+    }
+    create.extend(["netns", script::NETNS]);
+    script.host(&create);
+
+    // -e: without ipv6 support in the kernel the sysctls do not exist
+    if data.disable_ipv6 {
+        script.container(&[
+            "sysctl",
+            "-e",
+            "-w",
+            &format!("net.ipv6.conf.{}.disable_ipv6=1", container_veth),
+        ]);
+    }
+    script.container(&[
+        "sysctl",
+        "-e",
+        "-w",
+        &format!("net.ipv6.conf.{}.autoconf=0", container_veth),
+    ]);
+    if data.ipam.ipv6_enabled {
+        script.container(&[
+            "sysctl",
+            "-w",
+            &format!("net.ipv6.conf.{}.accept_dad=0", container_veth),
+        ]);
+    }
+
+    script.host(&[
+        "ip",
+        "link",
+        "set",
+        host_veth,
+        "master",
+        &data.bridge_interface_name,
+    ]);
+    if let Some(aliases) = &data.aliases {
+        script.host(&["ip", "link", "set", host_veth, "alias", &aliases.host_veth]);
+    }
+    let mut port_options = vec!["ip", "link", "set", host_veth, "type", "bridge_slave"];
+    let on_off = |value: bool| if value { "on" } else { "off" };
+    for (option, value) in [
+        ("hairpin", data.port_options.hairpin),
+        ("guard", data.port_options.bpdu_guard),
+        ("learning", data.port_options.learning),
+    ] {
+        if let Some(value) = value {
+            port_options.extend([option, on_off(value)]);
+        }
+    }
+    if !data.port_options.is_empty() {
+        script.host(&port_options);
+    }
+    if data.ipam.ipv6_enabled {
+        script.host(&[
+            "sysctl",
+            "-w",
+            &format!("net.ipv6.conf.{}.accept_dad=0", host_veth),
+        ]);
+    }
+    if data.disable_ipv6 {
+        script.host(&[
+            "sysctl",
+            "-w",
+            &format!("net.ipv6.conf.{}.disable_ipv6=1", host_veth),
+        ]);
+    }
+    script.host(&["ip", "link", "set", host_veth, "up"]);
+
+    for addr in &data.ipam.container_addresses {
+        script.container(&[
+            "ip",
+            "addr",
+            "add",
+            &addr.to_string(),
+            "dev",
+            container_veth,
+        ]);
+    }
+    if data.bring_up {
+        script.container(&["ip", "link", "set", container_veth, "up"]);
+    }
+    if internal {
+        return;
+    }
+    let metric = data.metric.map(|m| m.to_string());
+    let mut ipv4 = false;
+    let mut ipv6 = false;
+    for gw in &data.ipam.gateway_addresses {
+        // only the first gateway of each ip family gets the default route
+        let first = match gw {
+            IpNet::V4(_) => !std::mem::replace(&mut ipv4, true),
+            IpNet::V6(_) => !std::mem::replace(&mut ipv6, true),
+        };
+        if !first {
+            continue;
+        }
+        let gateway = gw.addr().to_string();
+        let mut route = vec!["ip", "route", "add", "default", "via", &gateway];
+        if let Some(metric) = &metric {
+            route.extend(["metric", metric]);
+        }
+        script.container(&route);
+    }
+}
+
+/// Configure the new interface with the gateway addresses, this is the bridge
+/// or with a vlan the vlan interface on top of it.
+fn configure_gateway_interface(
+    host: &mut netlink::Socket,
+    index: u32,
+    name: &str,
+    data: &InternalData,
+) -> NetavarkResult<()> {
+    if data.ipam.ipv6_enabled {
+        // Disable duplicate address detection if ipv6 enabled
+        // Do not accept Router Advertisements if ipv6 is enabled
+        let br_accept_dad = format!("/proc/sys/net/ipv6/conf/{}/accept_dad", name);
+        let br_accept_ra = format!("net/ipv6/conf/{}/accept_ra", name);
+        CoreUtils::apply_sysctl_value(br_accept_dad, "0")?;
+        CoreUtils::apply_sysctl_value(br_accept_ra, "0")?;
+    }
+
+    for addr in &data.ipam.gateway_addresses {
+        host.add_addr(index, addr).wrap("add ip addr to bridge")?;
+    }
+
+    if data.disable_ipv6 {
+        core_utils::disable_ipv6(name)?;
+    }
+
+    if let Some(aliases) = &data.aliases {
+        host.set_link_alias(index, aliases.bridge.clone())
+            .wrap("set bridge alias")?;
+    }
+
+    host.set_up(netlink::LinkID::ID(index))
+        .wrap("set bridge up")?;
+    Ok(())
+}
+
+/// name of the vlan interface with the gateway addresses of the network
+fn vlan_interface_name(bridge_name: &str, vlan: u16) -> String {
+    format!("{}.{}", bridge_name, vlan)
+}
+
+/// Setup the vlan interface on top of the bridge.
+fn setup_vlan_interface(
+    host: &mut netlink::Socket,
+    bridge: &LinkMessage,
+    data: &InternalData,
+    vlan: u16,
+) -> NetavarkResult<()> {
+    let name = vlan_interface_name(&data.bridge_interface_name, vlan);
+    // the bridge itself must be a member to pass the frames to the vlan interface
+    host.add_bridge_vlan(bridge.header.index, vlan, 0, true)
+        .wrap("add vlan to bridge")?;
+    match host.get_link(netlink::LinkID::Name(name.clone())) {
+        Ok(link) => ensure_bridge_addresses(host, link.header.index, &name, data),
+        Err(NetavarkError::Netlink(e)) if -e.code == libc::ENODEV => {
+            let mut opts = netlink::CreateLinkOptions::new(name.clone(), InfoKind::Vlan);
+            opts.mtu = data.mtu;
+            opts.link = bridge.header.index;
+            opts.info_data = Some(InfoData::Vlan(vec![InfoVlan::Id(vlan)]));
+            host.create_link(opts).wrap("create vlan interface")?;
+            let link = host
+                .get_link(netlink::LinkID::Name(name.clone()))
+                .wrap("get vlan interface")?;
+            configure_gateway_interface(host, link.header.index, &name, data)
+        }
+        Err(err) => Err(err).wrap("get vlan interface"),
+    }
+}
+
+/// Make the bridge port an untagged member of only the network vlan.
+fn set_port_vlan(
+    host: &mut netlink::Socket,
+    port: u32,
+    bridge: u32,
+    vlan: u16,
+) -> NetavarkResult<()> {
+    host.add_bridge_vlan(
+        port,
+        vlan,
+        BRIDGE_VLAN_INFO_PVID | BRIDGE_VLAN_INFO_UNTAGGED,
+        false,
+    )
+    .wrap("add vlan to bridge port")?;
+    // new ports are also members of the default vlan of the bridge
+    let vlans = host
+        .dump_bridge_vlans(bridge)
+        .wrap("get bridge port vlans")?;
+    for vid in vlans.get(&port).into_iter().flatten() {
+        if *vid != vlan {
+            host.del_bridge_vlan(port, *vid, false)
+                .wrap(format!("remove vlan {} from bridge port", vid))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check if a container on the bridge is in the vlan, other ports like an
+/// uplink carrying the vlan do not count.
+fn vlan_in_use(host: &mut netlink::Socket, bridge: u32, vlan: u16) -> NetavarkResult<bool> {
+    let vlans = host
+        .dump_bridge_vlans(bridge)
+        .wrap("get bridge port vlans")?;
+    Ok(host
+        .dump_links(&mut vec![Nla::Master(bridge)])
+        .wrap("get connected bridge interfaces")?
+        .iter()
+        .filter(|link| matches!(get_link_kind(link), Some(InfoKind::Veth)))
+        .any(|link| {
+            vlans
+                .get(&link.header.index)
+                .is_some_and(|vids| vids.contains(&vlan))
+        }))
+}
 
-                let link = host
-                    .get_link(netlink::LinkID::Name(
-                        data.bridge_interface_name.to_string(),
-                    ))
-                    .wrap("get bridge interface")?;
+/// return the container veth mac address
+fn create_veth_pair(
+    host: &mut netlink::Socket,
+    netns: &mut netlink::Socket,
+    data: &InternalData,
+    primary_index: u32,
+    internal: bool,
+    hostns_fd: RawFd,
+    netns_fd: RawFd,
+) -> NetavarkResult<String> {
+    match &data.existing_host_veth {
+        Some(host_veth_name) => {
+            adopt_veth_pair(host, netns, data, host_veth_name, primary_index, netns_fd)?
+        }
+        None => {
+            let mut peer_opts = netlink::CreateLinkOptions::new(
+                data.container_interface_name.to_string(),
+                InfoKind::Veth,
+            );
+            peer_opts.mac = data.mac_address.clone().unwrap_or_default();
+            peer_opts.mtu = data.mtu;
+            peer_opts.netns = netns_fd;
 
-                for addr in &data.ipam.gateway_addresses {
-                    host.add_addr(link.header.index, addr)
-                        .wrap("add ip addr to bridge")?;
-                }
+            let mut peer = LinkMessage::default();
+            netlink::parse_create_link_options(&mut peer, peer_opts);
 
-                host.set_up(netlink::LinkID::ID(link.header.index))
-                    .wrap("set bridge up")?;
-                link
+            let mut host_veth = netlink::CreateLinkOptions::new(
+                data.host_interface_name.to_string(),
+                InfoKind::Veth,
+            );
+            host_veth.mtu = data.mtu;
+            host_veth.primary_index = primary_index;
+            host_veth.info_data = Some(InfoData::Veth(VethInfo::Peer(peer)));
+
+            if let Err(err) = host.create_link(host_veth) {
+                return Err(match err {
+                    NetavarkError::Netlink(ref e) if -e.code == libc::EEXIST => {
+                        // the host veth name is derived from the container, a stale
+                        // one is left behind by an earlier setup of the container
+                        let exists_on_host = host
+                            .get_link(netlink::LinkID::Name(data.host_interface_name.clone()))
+                            .is_ok();
+                        let msg = if exists_on_host {
+                            format!(
+                                "create veth pair: interface {} already exists on the host",
+                                data.host_interface_name
+                            )
+                        } else {
+                            format!(
+                                "create veth pair: interface {} already exists on container namespace",
+                                data.container_interface_name
+                            )
+                        };
+                        NetavarkError::wrap(msg, err)
+                    }
+                    _ => NetavarkError::wrap("create veth pair", err),
+                });
             }
-            _ => return Err(err),
-        },
-    };
+        }
+    }
 
-    create_veth_pair(
+    configure_veth_pair(
         host,
         netns,
         data,
-        bridge.header.index,
+        primary_index,
         internal,
         hostns_fd,
         netns_fd,
     )
+    .inspect_err(|_| {
+        // the pair exists now, remove it again so no orphan interface is left,
+        // the caller takes care of the bridge
+        if let Err(e) = remove_veth(
+            host,
+            netns,
+            &data.container_interface_name,
+            data.existing_host_veth.as_deref(),
+        ) {
+            error!("failed to remove veth pair after setup failed: {}", e);
+        }
+    })
 }
 
-/// return the container veth mac address
-fn create_veth_pair(
+/// configure the veth pair after it was created,
+/// returns the container veth mac address
+fn configure_veth_pair(
     host: &mut netlink::Socket,
     netns: &mut netlink::Socket,
     data: &InternalData,
@@ -536,31 +2131,6 @@ fn create_veth_pair(
     hostns_fd: RawFd,
     netns_fd: RawFd,
 ) -> NetavarkResult<String> {
-    let mut peer_opts =
-        netlink::CreateLinkOptions::new(data.container_interface_name.to_string(), InfoKind::Veth);
-    peer_opts.mac = data.mac_address.clone().unwrap_or_default();
-    peer_opts.mtu = data.mtu;
-    peer_opts.netns = netns_fd;
-
-    let mut peer = LinkMessage::default();
-    netlink::parse_create_link_options(&mut peer, peer_opts);
-
-    let mut host_veth = netlink::CreateLinkOptions::new(String::from(""), InfoKind::Veth);
-    host_veth.mtu = data.mtu;
-    host_veth.primary_index = primary_index;
-    host_veth.info_data = Some(InfoData::Veth(VethInfo::Peer(peer)));
-
-    host.create_link(host_veth).map_err(|err| match err {
-        NetavarkError::Netlink(ref e) if -e.code == libc::EEXIST => NetavarkError::wrap(
-            format!(
-                "create veth pair: interface {} already exists on container namespace",
-                data.container_interface_name
-            ),
-            err,
-        ),
-        _ => NetavarkError::wrap("create veth pair", err),
-    })?;
-
     let veth = netns
         .get_link(netlink::LinkID::Name(
             data.container_interface_name.to_string(),
@@ -586,6 +2156,9 @@ fn create_veth_pair(
     }
 
     exec_netns!(hostns_fd, netns_fd, res, {
+        if data.disable_ipv6 {
+            core_utils::disable_ipv6(&data.container_interface_name)?;
+        }
         disable_ipv6_autoconf(&data.container_interface_name)?;
         if data.ipam.ipv6_enabled {
             //  Disable dad inside the container too
@@ -600,6 +2173,20 @@ fn create_veth_pair(
     // check the result and return error
     res?;
 
+    if let (Some(aliases), None) = (&data.aliases, &data.existing_host_veth) {
+        host.set_link_alias(host_link, aliases.host_veth.clone())
+            .wrap("set host veth alias")?;
+    }
+
+    if !data.port_options.is_empty() {
+        host.set_bridge_port_options(host_link, &data.port_options)
+            .wrap("set bridge port options")?;
+    }
+
+    if let Some(vlan) = data.vlan {
+        set_port_vlan(host, host_link, primary_index, vlan)?;
+    }
+
     if data.ipam.ipv6_enabled {
         let host_veth = host.get_link(netlink::LinkID::ID(host_link))?;
 
@@ -613,26 +2200,740 @@ fn create_veth_pair(
         }
     }
 
+    if data.disable_ipv6 {
+        core_utils::disable_ipv6(
+            data.existing_host_veth
+                .as_deref()
+                .unwrap_or(&data.host_interface_name),
+        )?;
+    }
+
     host.set_up(netlink::LinkID::ID(host_link))
         .wrap("failed to set host veth up")?;
 
     for addr in &data.ipam.container_addresses {
+        let addr = if data.routed {
+            IpNet::from(addr.addr())
+        } else {
+            *addr
+        };
         netns
-            .add_addr(veth.header.index, addr)
+            .add_addr(veth.header.index, &addr)
             .wrap("add ip addr to container veth")?;
+        if data.routed {
+            match host.add_link_route(primary_index, &IpNet::from(addr.addr())) {
+                Ok(_) => {}
+                // left over from a container which was not torn down
+                Err(NetavarkError::Netlink(ref e)) if -e.code == libc::EEXIST => {}
+                Err(err) => return Err(err).wrap("add host route to the container address"),
+            }
+        }
     }
 
-    netns
-        .set_up(netlink::LinkID::ID(veth.header.index))
-        .wrap("set container veth up")?;
+    // validate() rejects the routes below without it
+    if data.bring_up {
+        netns
+            .set_up(netlink::LinkID::ID(veth.header.index))
+            .wrap("set container veth up")?;
+    } else {
+        debug!(
+            "leave container veth {} down",
+            data.container_interface_name
+        );
+    }
+
+    core_utils::add_static_neighbors(netns, veth.header.index, &data.static_neighbors)?;
+
+    if data.routed {
+        // the gateway is not in an on-link subnet of the container
+        for gw in &data.ipam.gateway_addresses {
+            netns
+                .add_link_route(veth.header.index, gw)
+                .wrap("add route to the gateway")?;
+        }
+    }
 
     if !internal {
         core_utils::add_default_routes(netns, &data.ipam.gateway_addresses, data.metric)?;
     }
+    core_utils::add_policy_routes(netns, &data.policy_routes)?;
 
     Ok(mac)
 }
 
+fn get_firewall_netns_option(options: &Option<HashMap<String, String>>) -> NetavarkResult<bool> {
+    let netns: String = parse_option(
+        options,
+        OPTION_FIREWALL_NETNS,
+        FIREWALL_NETNS_HOST.to_string(),
+    )?;
+    match netns.as_str() {
+        FIREWALL_NETNS_HOST => Ok(false),
+        FIREWALL_NETNS_CONTAINER => Ok(true),
+        n => Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_FIREWALL_NETNS),
+            format!(
+                "unknown {} \"{}\", must be {} or {}",
+                OPTION_FIREWALL_NETNS, n, FIREWALL_NETNS_HOST, FIREWALL_NETNS_CONTAINER
+            ),
+        )),
+    }
+}
+
+/// Parse the conntrack_zone option, zone 0 is the default zone of the kernel
+/// and cannot be selected.
+fn get_conntrack_zone_option(
+    options: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<u16>> {
+    let zone: Option<u32> = parse_optional_option(options, OPTION_CONNTRACK_ZONE)?;
+    match zone {
+        Some(z) if !(1..=u16::MAX as u32).contains(&z) => Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_CONNTRACK_ZONE),
+            format!(
+                "invalid {} {}: must be between 1 and {}",
+                OPTION_CONNTRACK_ZONE,
+                z,
+                u16::MAX
+            ),
+        )),
+        z => Ok(z.map(|z| z as u16)),
+    }
+}
+
+/// A port mapping may only select an address the container has on the
+/// network. Addresses outside of its subnets belong to another network.
+fn check_port_container_ips(
+    info: &DriverInfo,
+    container_addresses: &[IpNet],
+) -> NetavarkResult<()> {
+    for mapping in info.port_mappings.iter().flatten() {
+        let ip = match mapping.container_ip {
+            Some(ip) => ip,
+            None => continue,
+        };
+        if container_addresses.iter().any(|addr| addr.addr() == ip) {
+            continue;
+        }
+        if info
+            .network
+            .subnets
+            .iter()
+            .flatten()
+            .any(|s| s.subnet.contains(&ip))
+        {
+            return Err(ConfigError::invalid_value(
+                "port_mappings",
+                format!(
+                    "container_ip {} of port {} is not assigned to the container on network {}",
+                    ip, mapping.host_port, info.network.name
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The mask selects the groups 01:80:c2:00:00:0X which the bridge forwards,
+/// e.g. 0x4000 for LLDP. The kernel never forwards STP, pause and LACP frames
+/// (bits 0-2).
+fn get_group_fwd_mask_option(
+    options: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<u16>> {
+    let value = match options
+        .as_ref()
+        .and_then(|map| map.get(OPTION_GROUP_FWD_MASK))
+    {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let invalid = |msg: String| {
+        Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_GROUP_FWD_MASK),
+            format!("invalid {} {}: {}", OPTION_GROUP_FWD_MASK, value, msg),
+        ))
+    };
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse::<u16>(),
+    };
+    match parsed {
+        Ok(mask) if mask & 0x7 != 0 => {
+            invalid("the bits 0-2 (STP, pause and LACP) cannot be forwarded".to_string())
+        }
+        Ok(mask) => Ok(Some(mask)),
+        Err(e) => invalid(format!("must be a 16 bit mask: {}", e)),
+    }
+}
+
+/// Parse the dscp option, the 6 bit code point of the ip header.
+fn get_dscp_option(options: &Option<HashMap<String, String>>) -> NetavarkResult<Option<u8>> {
+    let dscp: Option<u32> = parse_optional_option(options, OPTION_DSCP)?;
+    match dscp {
+        Some(d) if d > 63 => Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_DSCP),
+            format!("invalid {} {}: must be between 0 and 63", OPTION_DSCP, d),
+        )),
+        d => Ok(d.map(|d| d as u8)),
+    }
+}
+
+/// Parse the fwmark option, a mark with an optional mask like 0x10/0xff.
+fn get_fwmark_option(options: &Option<HashMap<String, String>>) -> NetavarkResult<Option<FwMark>> {
+    let value = match options.as_ref().and_then(|map| map.get(OPTION_FWMARK)) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let invalid = |msg: String| {
+        Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_FWMARK),
+            format!("invalid {} {}: {}", OPTION_FWMARK, value, msg),
+        ))
+    };
+    let parse = |v: &str| match v.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => v.parse::<u32>(),
+    };
+    let (mark, mask) = match value.split_once('/') {
+        Some((mark, mask)) => (parse(mark), parse(mask)),
+        // all bits but the one of the port forwarding
+        None => (parse(value), Ok(!HOSTPORT_MARK)),
+    };
+    match (mark, mask) {
+        (Ok(_), Ok(0)) => invalid("the mask must not be 0".to_string()),
+        (Ok(mark), Ok(mask)) if (mark | mask) & HOSTPORT_MARK != 0 => invalid(format!(
+            "the bit {:#x} is used for the port forwarding",
+            HOSTPORT_MARK
+        )),
+        (Ok(mark), Ok(mask)) if mark & !mask != 0 => {
+            invalid("the mark has bits outside of the mask".to_string())
+        }
+        (Ok(mark), Ok(mask)) => Ok(Some(FwMark { mark, mask })),
+        (Err(e), _) | (_, Err(e)) => invalid(format!("must be a 32 bit mark: {}", e)),
+    }
+}
+
+fn get_vlan_option(options: &Option<HashMap<String, String>>) -> NetavarkResult<Option<u16>> {
+    match parse_optional_option::<u16>(options, OPTION_VLAN)? {
+        Some(vlan) if vlan == 0 || vlan > 4094 => Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_VLAN),
+            format!(
+                "invalid {} {}: must be between 1 and 4094",
+                OPTION_VLAN, vlan
+            ),
+        )),
+        vlan => Ok(vlan),
+    }
+}
+
+fn get_masquerade_ports_option(
+    options: &Option<HashMap<String, String>>,
+) -> NetavarkResult<MasqueradePorts> {
+    let ports: String = parse_option(
+        options,
+        OPTION_MASQUERADE_PORTS,
+        MASQUERADE_PORTS_PRESERVE.to_string(),
+    )?;
+    match ports.as_str() {
+        MASQUERADE_PORTS_PRESERVE => Ok(MasqueradePorts::Preserve),
+        MASQUERADE_PORTS_RANDOM => Ok(MasqueradePorts::Random),
+        MASQUERADE_PORTS_RANDOM_FULLY => Ok(MasqueradePorts::RandomFully),
+        p => Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_MASQUERADE_PORTS),
+            format!(
+                "unknown {} \"{}\", must be {}, {} or {}",
+                OPTION_MASQUERADE_PORTS,
+                p,
+                MASQUERADE_PORTS_PRESERVE,
+                MASQUERADE_PORTS_RANDOM,
+                MASQUERADE_PORTS_RANDOM_FULLY
+            ),
+        )),
+    }
+}
+
+/// The icc rules only see the traffic between the ports of the bridge when
+/// br_netfilter passes it to iptables, without it they would silently allow
+/// everything.
+fn check_bridge_nf_call(network: &types::Network) -> NetavarkResult<()> {
+    let subnets = network.subnets.iter().flatten();
+    let mut sysctls = Vec::new();
+    if subnets.clone().any(|s| s.subnet.addr().is_ipv4()) {
+        sysctls.push("net.bridge.bridge-nf-call-iptables");
+    }
+    if subnets.clone().any(|s| s.subnet.addr().is_ipv6()) {
+        sysctls.push("net.bridge.bridge-nf-call-ip6tables");
+    }
+    for name in sysctls {
+        let path = format!("/proc/sys/{}", name.replace('.', "/"));
+        let enabled = match sysctl::Ctl::new(&path).and_then(|ctl| ctl.value_string()) {
+            Ok(value) => value.trim() == "1",
+            // the sysctl only exists with the br_netfilter module loaded
+            Err(sysctl::SysctlError::NotFound(_)) => false,
+            Err(e) => return Err(NetavarkError::wrap(format!("read {}", name), e.into())),
+        };
+        if !enabled {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_ICC),
+                format!(
+                    "{}=false requires {}=1, load the br_netfilter kernel module and enable the sysctl",
+                    OPTION_ICC, name
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn get_nat64_options(network: &types::Network) -> NetavarkResult<Option<Nat64>> {
+    let nat64: bool = parse_option(&network.options, OPTION_NAT64, false)?;
+    if !nat64 {
+        return Ok(None);
+    }
+    let invalid =
+        |option: &str, msg: String| ConfigError::invalid_value(format!("options.{}", option), msg);
+    let subnets: Vec<&IpNet> = network
+        .subnets
+        .iter()
+        .flatten()
+        .map(|s| &s.subnet)
+        .collect();
+    if network.internal || subnets.is_empty() || subnets.iter().any(|s| s.addr().is_ipv4()) {
+        return Err(invalid(
+            OPTION_NAT64,
+            format!(
+                "{} requires a non internal network with only ipv6 subnets",
+                OPTION_NAT64
+            ),
+        ));
+    }
+
+    let prefix: String = parse_option(
+        &network.options,
+        OPTION_NAT64_PREFIX,
+        NAT64_DEFAULT_PREFIX.to_string(),
+    )?;
+    let prefix: Ipv6Net = prefix.parse().map_err(|e| {
+        invalid(
+            OPTION_NAT64_PREFIX,
+            format!("invalid {} \"{}\": {}", OPTION_NAT64_PREFIX, prefix, e),
+        )
+    })?;
+    // prefix lengths of RFC 6052
+    if ![32, 40, 48, 56, 64, 96].contains(&prefix.prefix_len()) {
+        return Err(invalid(
+            OPTION_NAT64_PREFIX,
+            format!(
+                "invalid {} {}: the prefix length must be 32, 40, 48, 56, 64 or 96",
+                OPTION_NAT64_PREFIX, prefix
+            ),
+        ));
+    }
+
+    let pool: String = parse_option(
+        &network.options,
+        OPTION_NAT64_POOL,
+        NAT64_DEFAULT_POOL.to_string(),
+    )?;
+    let pool: Ipv4Net = pool.parse().map_err(|e| {
+        invalid(
+            OPTION_NAT64_POOL,
+            format!("invalid {} \"{}\": {}", OPTION_NAT64_POOL, pool, e),
+        )
+    })?;
+
+    let interface: String = parse_option(
+        &network.options,
+        OPTION_NAT64_INTERFACE,
+        NAT64_DEFAULT_INTERFACE.to_string(),
+    )?;
+    Ok(Some(Nat64 {
+        prefix: prefix.trunc(),
+        pool: pool.trunc(),
+        interface,
+    }))
+}
+
+/// Parse the rate limit options, the rate and burst have defaults so only
+/// the rate_limit option must be set.
+fn get_rate_limit_options(
+    options: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<RateLimit>> {
+    let rate_limit: bool = parse_option(options, OPTION_RATE_LIMIT, false)?;
+    if !rate_limit {
+        return Ok(None);
+    }
+    let rate: String = parse_option(
+        options,
+        OPTION_RATE_LIMIT_RATE,
+        RATE_LIMIT_DEFAULT_RATE.to_string(),
+    )?;
+    let valid = match rate.split_once('/') {
+        Some((count, unit)) => {
+            count.parse::<u32>().is_ok_and(|c| c > 0)
+                && ["second", "minute", "hour", "day"].contains(&unit)
+        }
+        None => false,
+    };
+    if !valid {
+        return Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_RATE_LIMIT_RATE),
+            format!(
+                "invalid {} \"{}\": must be a number followed by /second, /minute, /hour or /day",
+                OPTION_RATE_LIMIT_RATE, rate
+            ),
+        ));
+    }
+    let burst: u32 = parse_option(options, OPTION_RATE_LIMIT_BURST, RATE_LIMIT_DEFAULT_BURST)?;
+    if burst == 0 {
+        return Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_RATE_LIMIT_BURST),
+            format!("{} must be at least 1", OPTION_RATE_LIMIT_BURST),
+        ));
+    }
+    Ok(Some(RateLimit { rate, burst }))
+}
+
+/// Route the NAT64 prefix to the translator. The route is shared by all
+/// networks using the translator, see Bridge::remove_nat64_route(). Returns
+/// whether the route was added, it may exist already.
+fn add_nat64_route(host: &mut netlink::Socket, nat64: &Nat64) -> NetavarkResult<bool> {
+    let link = match host.get_link(netlink::LinkID::Name(nat64.interface.clone())) {
+        Ok(link) => link,
+        Err(NetavarkError::Netlink(e)) if -e.code == libc::ENODEV => {
+            return Err(NetavarkError::Message(format!(
+                "nat64 interface {} does not exist, the NAT64 translator (e.g. tayga) must be running",
+                nat64.interface
+            )))
+        }
+        Err(err) => return Err(err).wrap("get nat64 interface"),
+    };
+    match host.add_link_route(link.header.index, &nat64.prefix.into()) {
+        Ok(_) => Ok(true),
+        Err(NetavarkError::Netlink(ref e)) if -e.code == libc::EEXIST => Ok(false),
+        Err(err) => Err(err).wrap("add nat64 prefix route"),
+    }
+}
+
+fn del_nat64_route(host: &mut netlink::Socket, nat64: &Nat64) -> NetavarkResult<()> {
+    let link = match host.get_link(netlink::LinkID::Name(nat64.interface.clone())) {
+        Ok(link) => link,
+        // the route is gone with the translator
+        Err(NetavarkError::Netlink(e)) if -e.code == libc::ENODEV => return Ok(()),
+        Err(err) => return Err(err).wrap("get nat64 interface"),
+    };
+    match host.del_link_route(link.header.index, &nat64.prefix.into()) {
+        Ok(_) => Ok(()),
+        Err(NetavarkError::Netlink(ref e)) if -e.code == libc::ESRCH => Ok(()),
+        Err(err) => Err(err).wrap("remove nat64 prefix route"),
+    }
+}
+
+/// Which unused bridges the teardown removes
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BridgeCleanup {
+    /// only the ones netavark created or which have no record at all
+    Created,
+    /// also the ones created by someone else
+    Unused,
+    Never,
+}
+
+/// The bridge of a network as needed for its removal
+struct BridgeRemoval<'a> {
+    name: String,
+    vlan: Option<u16>,
+    cleanup: BridgeCleanup,
+    /// directory with the state of the bridges created by netavark
+    config_dir: &'a str,
+}
+
+fn get_bridge_cleanup_option(
+    options: &Option<HashMap<String, String>>,
+) -> NetavarkResult<BridgeCleanup> {
+    let cleanup: String = parse_option(
+        options,
+        OPTION_BRIDGE_CLEANUP,
+        BRIDGE_CLEANUP_CREATED.to_string(),
+    )?;
+    match cleanup.as_str() {
+        BRIDGE_CLEANUP_CREATED => Ok(BridgeCleanup::Created),
+        BRIDGE_CLEANUP_UNUSED => Ok(BridgeCleanup::Unused),
+        BRIDGE_CLEANUP_NEVER => Ok(BridgeCleanup::Never),
+        c => Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_BRIDGE_CLEANUP),
+            format!(
+                "unknown {} \"{}\", must be {}, {} or {}",
+                OPTION_BRIDGE_CLEANUP,
+                c,
+                BRIDGE_CLEANUP_CREATED,
+                BRIDGE_CLEANUP_UNUSED,
+                BRIDGE_CLEANUP_NEVER
+            ),
+        )),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BridgeMode {
+    Bridge,
+    Routed,
+    L2Only,
+}
+
+fn get_bridge_mode_option(options: &Option<HashMap<String, String>>) -> NetavarkResult<BridgeMode> {
+    let mode: String = parse_option(options, OPTION_MODE, BRIDGE_MODE_BRIDGE.to_string())?;
+    match mode.as_str() {
+        BRIDGE_MODE_BRIDGE => Ok(BridgeMode::Bridge),
+        BRIDGE_MODE_ROUTED => Ok(BridgeMode::Routed),
+        BRIDGE_MODE_L2_ONLY => Ok(BridgeMode::L2Only),
+        m => Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_MODE),
+            format!(
+                "unknown {} \"{}\", must be {}, {} or {}",
+                OPTION_MODE, m, BRIDGE_MODE_BRIDGE, BRIDGE_MODE_ROUTED, BRIDGE_MODE_L2_ONLY
+            ),
+        )),
+    }
+}
+
+fn get_port_proxy_option(options: &Option<HashMap<String, String>>) -> NetavarkResult<bool> {
+    let mode: String = parse_option(
+        options,
+        OPTION_PORT_FORWARD_MODE,
+        PORT_FORWARD_MODE_DNAT.to_string(),
+    )?;
+    match mode.as_str() {
+        PORT_FORWARD_MODE_DNAT => Ok(false),
+        PORT_FORWARD_MODE_PROXY => Ok(true),
+        m => Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_PORT_FORWARD_MODE),
+            format!(
+                "unknown {} \"{}\", must be {} or {}",
+                OPTION_PORT_FORWARD_MODE, m, PORT_FORWARD_MODE_DNAT, PORT_FORWARD_MODE_PROXY
+            ),
+        )),
+    }
+}
+
+/// Parse the default_action and allow options into the forward policy,
+/// None when all traffic is allowed.
+fn get_forward_policy(
+    options: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<ForwardPolicy>> {
+    let default_action: String = parse_option(
+        options,
+        OPTION_DEFAULT_ACTION,
+        DEFAULT_ACTION_ACCEPT.to_string(),
+    )?;
+    let allow: String = parse_option(options, OPTION_ALLOW, String::new())?;
+
+    let default_action = match default_action.as_str() {
+        DEFAULT_ACTION_ACCEPT => {
+            if !allow.is_empty() {
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", OPTION_ALLOW),
+                    format!(
+                        "{} requires {}={} or {}",
+                        OPTION_ALLOW,
+                        OPTION_DEFAULT_ACTION,
+                        DEFAULT_ACTION_DROP,
+                        DEFAULT_ACTION_REJECT
+                    ),
+                ));
+            }
+            return Ok(None);
+        }
+        DEFAULT_ACTION_DROP => PolicyAction::Drop,
+        DEFAULT_ACTION_REJECT => PolicyAction::Reject,
+        other => {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_DEFAULT_ACTION),
+                format!("invalid {} \"{}\"", OPTION_DEFAULT_ACTION, other),
+            ))
+        }
+    };
+
+    let allow = allow
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry.parse::<AllowRule>().map_err(|e| {
+                ConfigError::invalid_value(
+                    format!("options.{}", OPTION_ALLOW),
+                    format!("invalid {} entry \"{}\": {}", OPTION_ALLOW, entry, e),
+                )
+            })
+        })
+        .collect::<NetavarkResult<Vec<_>>>()?;
+
+    Ok(Some(ForwardPolicy {
+        default_action,
+        allow,
+    }))
+}
+
+fn get_bridge_port_options(
+    options: &Option<HashMap<String, String>>,
+) -> NetavarkResult<netlink::BridgePortOptions> {
+    let port_options = netlink::BridgePortOptions {
+        hairpin: parse_optional_option(options, OPTION_HAIRPIN)?,
+        bpdu_guard: parse_optional_option(options, OPTION_BPDU_GUARD)?,
+        learning: parse_optional_option(options, OPTION_LEARNING)?,
+    };
+    // without learning unknown unicast is flooded to all ports, with hairpin
+    // the container would then receive its own frames
+    if port_options.hairpin == Some(true) && port_options.learning == Some(false) {
+        return Err(ConfigError::invalid_value(
+            format!("options.{}", OPTION_HAIRPIN),
+            format!(
+                "{}=true cannot be used together with {}=false",
+                OPTION_HAIRPIN, OPTION_LEARNING
+            ),
+        ));
+    }
+    Ok(port_options)
+}
+
+/// Make sure an already existing bridge has the gateway addresses. Addresses which
+/// are already there are left alone, however the same ip with a different prefix
+/// length is an error as we cannot know which one is correct.
+fn ensure_bridge_addresses(
+    host: &mut netlink::Socket,
+    index: u32,
+    name: &str,
+    data: &InternalData,
+) -> NetavarkResult<()> {
+    let existing: Vec<IpNet> = host
+        .dump_addresses()
+        .wrap("dump bridge addresses")?
+        .iter()
+        .filter(|msg| msg.header.index == index)
+        .filter_map(core_utils::parse_address_message)
+        .collect();
+
+    for addr in &data.ipam.gateway_addresses {
+        match existing.iter().find(|e| e.addr() == addr.addr()) {
+            Some(e) if e.prefix_len() == addr.prefix_len() => {
+                debug!("bridge {} already has gateway address {}", name, addr);
+            }
+            Some(e) => {
+                return Err(NetavarkError::Message(format!(
+                    "bridge {} already has address {} which conflicts with gateway address {}",
+                    name, e, addr
+                )));
+            }
+            None => {
+                host.add_addr(index, addr).wrap("add ip addr to bridge")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Use an existing veth pair created by someone else. The host side must be a veth
+/// whose peer is still unused on the host, the peer is then moved into the container
+/// namespace and renamed to the container interface name.
+fn adopt_veth_pair(
+    host: &mut netlink::Socket,
+    netns: &mut netlink::Socket,
+    data: &InternalData,
+    host_veth_name: &str,
+    primary_index: u32,
+    netns_fd: RawFd,
+) -> NetavarkResult<()> {
+    let host_veth = host
+        .get_link(netlink::LinkID::Name(host_veth_name.to_string()))
+        .wrap(format!("get existing host veth {}", host_veth_name))?;
+    if get_link_kind(&host_veth) != Some(&InfoKind::Veth) {
+        return Err(NetavarkError::Message(format!(
+            "existing host interface {} is not a veth",
+            host_veth_name
+        )));
+    }
+
+    let mut peer_index = 0;
+    for nla in host_veth.nlas.iter() {
+        match nla {
+            Nla::Link(link) => peer_index = *link,
+            Nla::Master(_) => {
+                return Err(NetavarkError::Message(format!(
+                    "existing host veth {} is already attached to a master interface",
+                    host_veth_name
+                )))
+            }
+            Nla::NetnsId(_) => {
+                return Err(NetavarkError::Message(format!(
+                    "peer of existing host veth {} is already used in another namespace",
+                    host_veth_name
+                )))
+            }
+            _ => {}
+        }
+    }
+    if peer_index == 0 {
+        return Err(NetavarkError::Message(format!(
+            "failed to get the peer of existing host veth {}",
+            host_veth_name
+        )));
+    }
+
+    let peer = host
+        .get_link(netlink::LinkID::ID(peer_index))
+        .wrap("get peer of existing host veth")?;
+    let mut peer_name = String::new();
+    for nla in peer.nlas.into_iter() {
+        match nla {
+            Nla::IfName(name) => peer_name = name,
+            Nla::Master(_) => {
+                return Err(NetavarkError::Message(format!(
+                    "peer of existing host veth {} is already attached to a master interface",
+                    host_veth_name
+                )))
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(mac) = &data.mac_address {
+        host.set_link_address(peer_index, mac.clone())
+            .wrap("set mac address on existing veth peer")?;
+    }
+    if data.mtu != 0 {
+        host.set_link_mtu(host_veth.header.index, data.mtu)
+            .wrap("set mtu on existing host veth")?;
+        host.set_link_mtu(peer_index, data.mtu)
+            .wrap("set mtu on existing veth peer")?;
+    }
+
+    host.set_link_ns(peer_index, netns_fd)
+        .wrap("move existing veth peer into container namespace")?;
+    // the index can change when moving it into another namespace, use the name
+    let peer = netns
+        .get_link(netlink::LinkID::Name(peer_name))
+        .wrap("get existing veth peer in container namespace")?;
+    netns
+        .set_link_name(peer.header.index, data.container_interface_name.to_string())
+        .wrap("rename existing veth peer")?;
+
+    host.set_link_master(host_veth.header.index, primary_index)
+        .wrap("attach existing host veth to bridge")?;
+    Ok(())
+}
+
+fn get_link_kind(msg: &LinkMessage) -> Option<&InfoKind> {
+    for nla in msg.nlas.iter() {
+        if let Nla::Info(info) = nla {
+            for inf in info.iter() {
+                if let Info::Kind(kind) = inf {
+                    return Some(kind);
+                }
+            }
+        }
+    }
+    None
+}
+
 /// make sure the LinkMessage has the kind bridge
 fn check_link_is_bridge(msg: LinkMessage, br_name: &str) -> NetavarkResult<LinkMessage> {
     for nla in msg.nlas.iter() {
@@ -660,16 +2961,57 @@ fn check_link_is_bridge(msg: LinkMessage, br_name: &str) -> NetavarkResult<LinkM
 fn remove_link(
     host: &mut netlink::Socket,
     netns: &mut netlink::Socket,
-    br_name: &str,
+    bridge: &BridgeRemoval,
     container_veth_name: &str,
+    existing_host_veth: Option<&str>,
 ) -> NetavarkResult<bool> {
-    netns
-        .del_link(netlink::LinkID::Name(container_veth_name.to_string()))
-        .wrap(format!(
-            "failed to delete container veth {}",
-            container_veth_name
-        ))?;
+    remove_veth(host, netns, container_veth_name, existing_host_veth)?;
+    remove_unused_bridge(host, bridge)
+}
+
+/// Remove the veth pair of the container or detach the existing host veth.
+fn remove_veth(
+    host: &mut netlink::Socket,
+    netns: &mut netlink::Socket,
+    container_veth_name: &str,
+    existing_host_veth: Option<&str>,
+) -> NetavarkResult<()> {
+    match existing_host_veth {
+        // we do not own the veth pair, only detach it from the bridge
+        Some(host_veth_name) => {
+            let host_veth = host
+                .get_link(netlink::LinkID::Name(host_veth_name.to_string()))
+                .wrap(format!(
+                    "failed to get existing host veth {}",
+                    host_veth_name
+                ))?;
+            host.set_link_master(host_veth.header.index, 0)
+                .wrap(format!(
+                    "failed to detach existing host veth {}",
+                    host_veth_name
+                ))?;
+        }
+        None => {
+            netns
+                .del_link(netlink::LinkID::Name(container_veth_name.to_string()))
+                .wrap(format!(
+                    "failed to delete container veth {}",
+                    container_veth_name
+                ))?;
+        }
+    }
+    Ok(())
+}
 
+/// Remove the bridge once no interface is connected to it anymore and its
+/// cleanup mode allows it, with a vlan only its vlan interface is removed when
+/// the bridge is still used by others. Returns true when the network is not
+/// used by other containers anymore.
+fn remove_unused_bridge(
+    host: &mut netlink::Socket,
+    bridge: &BridgeRemoval,
+) -> NetavarkResult<bool> {
+    let br_name = bridge.name.as_str();
     let br = host
         .get_link(netlink::LinkID::Name(br_name.to_string()))
         .wrap("failed to get bridge interface")?;
@@ -679,10 +3021,47 @@ fn remove_link(
         .wrap("failed to get connected bridge interfaces")?;
     // no connected interfaces on that bridge we can remove it
     if links.is_empty() {
-        log::info!("removing bridge {}", br_name);
-        host.del_link(netlink::LinkID::ID(br.header.index))
-            .wrap(format!("failed to delete bridge {}", container_veth_name))?;
-        return Ok(true);
+        let remove = match bridge.cleanup {
+            // without any record the bridge is from a version before the
+            // records, it was removed in that case as well
+            BridgeCleanup::Created => {
+                !state::bridge_path(bridge.config_dir, br_name).exists()
+                    || state::is_own_bridge(bridge.config_dir, br_name, br.header.index)
+            }
+            BridgeCleanup::Unused => true,
+            BridgeCleanup::Never => false,
+        };
+        if remove {
+            log::info!("removing bridge {}", br_name);
+            host.del_link(netlink::LinkID::ID(br.header.index))
+                .wrap(format!("failed to delete bridge {}", br_name))?;
+            state::remove_bridge(bridge.config_dir, br_name)?;
+            return Ok(true);
+        }
+        debug!("keeping unused bridge {}", br_name);
+        if bridge.vlan.is_none() {
+            return Ok(true);
+        }
+    }
+    // the bridge is shared with other vlans, only remove the one of this network
+    if let Some(vlan) = bridge.vlan {
+        if !vlan_in_use(host, br.header.index, vlan)? {
+            let name = vlan_interface_name(br_name, vlan);
+            log::info!("removing vlan interface {}", name);
+            match host.del_link(netlink::LinkID::Name(name.clone())) {
+                Ok(_) => {}
+                Err(NetavarkError::Netlink(ref e)) if -e.code == libc::ENODEV => {}
+                Err(err) => {
+                    return Err(err).wrap(format!("failed to delete vlan interface {}", name))
+                }
+            }
+            host.del_bridge_vlan(br.header.index, vlan, true)
+                .wrap(format!(
+                    "failed to remove vlan {} from bridge {}",
+                    vlan, br_name
+                ))?;
+            return Ok(true);
+        }
     }
     Ok(false)
 }
@@ -0,0 +1,212 @@
+//! Post setup connectivity check, see the --verify-connectivity option.
+//! The gateways are pinged with ICMP echo requests from a raw socket which
+//! is created in the container namespace.
+use std::{
+    io,
+    net::{IpAddr, SocketAddrV4, SocketAddrV6},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use nix::sys::{
+    socket::{self, sockopt, MsgFlags, SockaddrIn, SockaddrIn6, SockaddrStorage},
+    time::{TimeVal, TimeValLike},
+};
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    exec_netns,
+    network::core_utils::join_netns,
+    wrap,
+};
+
+/// Exit code of the setup when a gateway did not answer, so callers can tell
+/// a configured network without connectivity apart from a failed setup.
+pub const EXIT_NO_CONNECTIVITY: i32 = libc::EHOSTUNREACH;
+
+/// Time to wait for the reply to a single echo request before sending the next one.
+const PING_INTERVAL: Duration = Duration::from_millis(200);
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Ping all gateways from the container namespace, every gateway must answer
+/// within `timeout`. The error lists the gateways which did not answer.
+pub fn ping_gateways(
+    hostns_fd: RawFd,
+    netns_fd: RawFd,
+    gateways: &[IpAddr],
+    timeout: Duration,
+) -> NetavarkResult<()> {
+    let mut unreachable = Vec::new();
+    for gateway in gateways {
+        // the socket stays in the namespace it was created in
+        exec_netns!(hostns_fd, netns_fd, sock, open_icmp_socket(gateway));
+        let sock = wrap!(sock, format!("open icmp socket to ping {}", gateway))?;
+        match ping(&sock, gateway, timeout) {
+            Ok(true) => debug!("gateway {} answered", gateway),
+            Ok(false) => unreachable.push(gateway.to_string()),
+            Err(e) => return Err(NetavarkError::wrap(format!("ping {}", gateway), e)),
+        }
+    }
+    if !unreachable.is_empty() {
+        return Err(NetavarkError::ExitCode(
+            format!(
+                "no connectivity: gateway {} did not answer within {:?}",
+                unreachable.join(", "),
+                timeout
+            ),
+            EXIT_NO_CONNECTIVITY,
+        ));
+    }
+    Ok(())
+}
+
+fn open_icmp_socket(gateway: &IpAddr) -> io::Result<OwnedFd> {
+    let (domain, protocol) = match gateway {
+        IpAddr::V4(_) => (libc::AF_INET, libc::IPPROTO_ICMP),
+        IpAddr::V6(_) => (libc::AF_INET6, libc::IPPROTO_ICMPV6),
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_RAW | libc::SOCK_CLOEXEC, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Send echo requests until the gateway answers, returns false on timeout.
+fn ping(sock: &OwnedFd, gateway: &IpAddr, timeout: Duration) -> NetavarkResult<bool> {
+    let fd = sock.as_raw_fd();
+    wrap!(
+        socket::setsockopt(
+            fd,
+            sockopt::ReceiveTimeout,
+            &TimeVal::milliseconds(PING_INTERVAL.as_millis() as i64)
+        )
+        .map_err(io::Error::from),
+        "set receive timeout"
+    )?;
+
+    let id = std::process::id() as u16;
+    let deadline = Instant::now() + timeout;
+    let mut seq: u16 = 0;
+    while Instant::now() < deadline {
+        seq = seq.wrapping_add(1);
+        let request = echo_request(gateway, id, seq);
+        let sent = match gateway {
+            IpAddr::V4(ip) => socket::sendto(
+                fd,
+                &request,
+                &SockaddrIn::from(SocketAddrV4::new(*ip, 0)),
+                MsgFlags::empty(),
+            ),
+            IpAddr::V6(ip) => socket::sendto(
+                fd,
+                &request,
+                &SockaddrIn6::from(SocketAddrV6::new(*ip, 0, 0, 0)),
+                MsgFlags::empty(),
+            ),
+        };
+        match sent {
+            Ok(_) => {}
+            // no route or neighbor (yet), try again with the next request
+            Err(nix::errno::Errno::EHOSTUNREACH) | Err(nix::errno::Errno::ENETUNREACH) => {
+                std::thread::sleep(PING_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                return Err(NetavarkError::wrap(
+                    "send echo request",
+                    NetavarkError::Io(e.into()),
+                ))
+            }
+        }
+
+        let wait_until = (Instant::now() + PING_INTERVAL).min(deadline);
+        let mut buf = [0u8; 1500];
+        while Instant::now() < wait_until {
+            match socket::recvfrom::<SockaddrStorage>(fd, &mut buf) {
+                Ok((len, from)) => {
+                    if from_gateway(from, gateway) && is_echo_reply(&buf[..len], gateway, id) {
+                        return Ok(true);
+                    }
+                }
+                Err(nix::errno::Errno::EAGAIN) | Err(nix::errno::Errno::EINTR) => {}
+                Err(e) => {
+                    return Err(NetavarkError::wrap(
+                        "receive echo reply",
+                        NetavarkError::Io(e.into()),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn echo_request(gateway: &IpAddr, id: u16, seq: u16) -> Vec<u8> {
+    let kind = match gateway {
+        IpAddr::V4(_) => ICMP_ECHO_REQUEST,
+        IpAddr::V6(_) => ICMPV6_ECHO_REQUEST,
+    };
+    let mut packet = vec![kind, 0, 0, 0];
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(b"netavark");
+    // the kernel fills in the checksum for icmpv6
+    if gateway.is_ipv4() {
+        let sum = checksum(&packet);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+    packet
+}
+
+fn from_gateway(from: Option<SockaddrStorage>, gateway: &IpAddr) -> bool {
+    let from = match from {
+        Some(from) => from,
+        None => return false,
+    };
+    match gateway {
+        IpAddr::V4(ip) => from
+            .as_sockaddr_in()
+            .map(|addr| SocketAddrV4::from(*addr).ip() == ip)
+            .unwrap_or(false),
+        IpAddr::V6(ip) => from
+            .as_sockaddr_in6()
+            .map(|addr| SocketAddrV6::from(*addr).ip() == ip)
+            .unwrap_or(false),
+    }
+}
+
+/// Check that the packet is the echo reply to our request, the packets of
+/// raw ipv4 sockets start with the ip header.
+fn is_echo_reply(packet: &[u8], gateway: &IpAddr, id: u16) -> bool {
+    let (icmp, reply) = match gateway {
+        IpAddr::V4(_) => {
+            let header_len = match packet.first() {
+                Some(b) => ((b & 0x0f) as usize) * 4,
+                None => return false,
+            };
+            (packet.get(header_len..), ICMP_ECHO_REPLY)
+        }
+        IpAddr::V6(_) => (Some(packet), ICMPV6_ECHO_REPLY),
+    };
+    match icmp {
+        Some(icmp) if icmp.len() >= 8 => icmp[0] == reply && icmp[4..6] == id.to_be_bytes(),
+        _ => false,
+    }
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
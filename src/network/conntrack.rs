@@ -0,0 +1,63 @@
+use std::{io::ErrorKind, net::IpAddr, process::Command};
+
+use log::{debug, info, warn};
+
+use super::{constants::CONNTRACK_PATH_ENV, core_utils, types::PortMapping};
+
+const CONNTRACK_BIN: &str = "conntrack";
+
+/// Delete the conntrack entries of the given port mappings, otherwise
+/// connections to a reused host port might still be sent to the old container.
+/// This is best effort, errors are only logged.
+pub fn flush_port_mappings(port_mappings: &[PortMapping], container_ips: &[IpAddr]) {
+    if port_mappings.is_empty()
+        || core_utils::check_read_only("flush the conntrack entries of the port mappings").is_err()
+    {
+        return;
+    }
+    let conntrack = match core_utils::get_binary_path(CONNTRACK_PATH_ENV) {
+        Ok(path) => path.unwrap_or_else(|| CONNTRACK_BIN.to_string()),
+        Err(e) => {
+            warn!("skipping conntrack cleanup: {}", e);
+            return;
+        }
+    };
+    for port in port_mappings {
+        for ip in container_ips {
+            for i in 0..port.range.max(1) {
+                let host_port = (port.host_port + i).to_string();
+                let args = [
+                    "-D",
+                    "-p",
+                    &port.protocol,
+                    "--orig-port-dst",
+                    &host_port,
+                    // after the dnat the container ip is the reply source
+                    "--reply-src",
+                    &ip.to_string(),
+                ];
+                debug!("flush conntrack entries: {} {:?}", conntrack, args);
+                match Command::new(&conntrack).args(args).output() {
+                    Ok(output) => {
+                        // conntrack exits with 1 when there were no entries to delete
+                        if !output.status.success() {
+                            debug!(
+                                "conntrack did not delete any entries for port {}: {}",
+                                host_port,
+                                String::from_utf8_lossy(&output.stderr).trim()
+                            );
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::NotFound => {
+                        info!("{} binary not found, skipping conntrack cleanup", conntrack);
+                        return;
+                    }
+                    Err(e) => {
+                        warn!("failed to run {}: {}", conntrack, e);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
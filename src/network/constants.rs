@@ -7,15 +7,114 @@ pub static PODMAN_DEFAULT_SEARCH_DOMAIN: &str = "dns.podman";
 pub const IPAM_HOSTLOCAL: &str = "host-local";
 pub const IPAM_DHCP: &str = "dhcp";
 pub const IPAM_NONE: &str = "none";
+pub const IPAM_LEASE: &str = "lease";
+/// ipam option with the id of an address pool shared by several lease networks
+pub const IPAM_OPTION_POOL: &str = "pool";
 
 pub const DRIVER_BRIDGE: &str = "bridge";
 pub const DRIVER_IPVLAN: &str = "ipvlan";
 pub const DRIVER_MACVLAN: &str = "macvlan";
 
+/// prefix of the env vars with host wide defaults for the network options
+pub const DEFAULT_OPTION_ENV_PREFIX: &str = "NETAVARK_DEFAULT_";
+/// network options which set the same value under different names
+pub const OPTION_ALIASES: &[&[&str]] = &[&[OPTION_ROUTE_METRIC, OPTION_METRIC]];
+
+/// env vars with the path of a binary which is used instead of the one in PATH
+pub const IPTABLES_PATH_ENV: &str = "NETAVARK_IPTABLES_PATH";
+pub const IP6TABLES_PATH_ENV: &str = "NETAVARK_IP6TABLES_PATH";
+pub const CONNTRACK_PATH_ENV: &str = "NETAVARK_CONNTRACK_PATH";
+pub const BINARY_PATH_ENVS: [&str; 3] = [IPTABLES_PATH_ENV, IP6TABLES_PATH_ENV, CONNTRACK_PATH_ENV];
+
 pub const OPTION_ISOLATE: &str = "isolate";
 pub const OPTION_MTU: &str = "mtu";
 pub const OPTION_MODE: &str = "mode";
 pub const OPTION_METRIC: &str = "metric";
+pub const OPTION_ROUTE_METRIC: &str = "route_metric";
+pub const OPTION_GATEWAY_POSITION: &str = "gateway_position";
+pub const OPTION_DISABLE_IPV6: &str = "disable_ipv6";
+pub const OPTION_CONNTRACK_ZONE: &str = "conntrack_zone";
+pub const OPTION_HOST_ROUTES: &str = "host_routes";
+pub const OPTION_BRING_UP: &str = "bring_up";
+pub const OPTION_MASQUERADE_PORTS: &str = "masquerade_ports";
+pub const OPTION_CTSTATE_ACCEPT: &str = "ctstate_accept";
+pub const OPTION_ICC: &str = "icc";
+pub const OPTION_GROUP_FWD_MASK: &str = "group_fwd_mask";
+pub const OPTION_ACCOUNTING: &str = "accounting";
+pub const OPTION_VLAN: &str = "vlan";
+
+pub const MASQUERADE_PORTS_PRESERVE: &str = "preserve";
+pub const MASQUERADE_PORTS_RANDOM: &str = "random";
+pub const MASQUERADE_PORTS_RANDOM_FULLY: &str = "random-fully";
+
+pub const ADMIN_STATE_UP: &str = "up";
+pub const ADMIN_STATE_DOWN: &str = "down";
+pub const OPTION_IPV6_MODE: &str = "ipv6_mode";
+pub const OPTION_CONNTRACK_CLEANUP: &str = "conntrack_cleanup";
+pub const OPTION_IFALIAS: &str = "ifalias";
+pub const OPTION_TRUSTED: &str = "trusted";
+pub const OPTION_HAIRPIN: &str = "hairpin";
+pub const OPTION_BPDU_GUARD: &str = "bpdu_guard";
+pub const OPTION_LEARNING: &str = "learning";
+pub const OPTION_PORT_FORWARD_MODE: &str = "port_forward_mode";
+pub const OPTION_DEFAULT_ACTION: &str = "default_action";
+pub const OPTION_ALLOW: &str = "allow";
+pub const OPTION_STATIC_NEIGHBORS: &str = "static_neighbors";
+pub const OPTION_GATEWAYS: &str = "gateways";
+pub const OPTION_POLICY_ROUTES: &str = "policy_routes";
+pub const OPTION_FIREWALL_NETNS: &str = "firewall_netns";
+pub const OPTION_NAT64: &str = "nat64";
+pub const OPTION_NAT64_PREFIX: &str = "nat64_prefix";
+pub const OPTION_NAT64_POOL: &str = "nat64_pool";
+pub const OPTION_NAT64_INTERFACE: &str = "nat64_interface";
+
+// defaults of the nat64 options, the well-known prefix of RFC 6052 and
+// the dynamic pool and interface name of the tayga example config
+pub const NAT64_DEFAULT_PREFIX: &str = "64:ff9b::/96";
+pub const NAT64_DEFAULT_POOL: &str = "192.168.255.0/24";
+pub const NAT64_DEFAULT_INTERFACE: &str = "nat64";
+pub const OPTION_RATE_LIMIT: &str = "rate_limit";
+pub const OPTION_RATE_LIMIT_RATE: &str = "rate_limit_rate";
+pub const OPTION_RATE_LIMIT_BURST: &str = "rate_limit_burst";
+pub const OPTION_DSCP: &str = "dscp";
+pub const OPTION_FWMARK: &str = "fwmark";
+
+// defaults of the rate limit options, new connections per source address
+// and published port
+pub const RATE_LIMIT_DEFAULT_RATE: &str = "10/second";
+pub const RATE_LIMIT_DEFAULT_BURST: u32 = 20;
+
+// bridge modes
+pub const BRIDGE_MODE_BRIDGE: &str = "bridge";
+pub const BRIDGE_MODE_ROUTED: &str = "routed";
+pub const BRIDGE_MODE_L2_ONLY: &str = "l2_only";
+
+// port forward modes
+pub const PORT_FORWARD_MODE_DNAT: &str = "dnat";
+pub const PORT_FORWARD_MODE_PROXY: &str = "proxy";
+
+// which unused bridges the teardown removes
+pub const OPTION_BRIDGE_CLEANUP: &str = "bridge_cleanup";
+pub const BRIDGE_CLEANUP_CREATED: &str = "created";
+pub const BRIDGE_CLEANUP_UNUSED: &str = "unused";
+pub const BRIDGE_CLEANUP_NEVER: &str = "never";
+
+// network namespaces the firewall rules can be applied in
+pub const FIREWALL_NETNS_HOST: &str = "host";
+pub const FIREWALL_NETNS_CONTAINER: &str = "container";
+
+// forward policy default actions
+pub const DEFAULT_ACTION_ACCEPT: &str = "accept";
+pub const DEFAULT_ACTION_DROP: &str = "drop";
+pub const DEFAULT_ACTION_REJECT: &str = "reject";
+
+// positions of a computed gateway in the subnet
+pub const GATEWAY_POSITION_FIRST: &str = "first";
+pub const GATEWAY_POSITION_LAST: &str = "last";
+
+// ipv6 modes
+pub const IPV6_MODE_STATIC: &str = "static";
+pub const IPV6_MODE_SLAAC: &str = "slaac";
 
 /// 100 is the default metric for most Linux networking tools.
 pub const DEFAULT_METRIC: u32 = 100;
@@ -1,14 +1,25 @@
-use crate::error::{ErrorWrap, NetavarkError, NetavarkResult};
+use crate::error::{ConfigError, ErrorWrap, NetavarkError, NetavarkResult};
 use crate::network::{constants, internal_types, types};
 use crate::wrap;
-use log::debug;
+use log::{debug, info, warn};
+use netlink_packet_route::{
+    address::Nla as AddressNla, route::Nla as RouteNla, AddressMessage, AF_INET, AF_INET6,
+    IFA_F_DADFAILED, IFA_F_TENTATIVE, RT_SCOPE_UNIVERSE, RT_TABLE_COMPAT, RT_TABLE_LOCAL,
+    RT_TABLE_UNSPEC,
+};
+use netlink_packet_route::{
+    link::nlas::{Info, InfoKind, Nla as LinkNla},
+    neighbour::Nla as NeighbourNla,
+    AF_BRIDGE, AF_UNSPEC, NUD_DELAY, NUD_PERMANENT, NUD_PROBE, NUD_REACHABLE,
+};
 use netlink_packet_route::{
     MACVLAN_MODE_BRIDGE, MACVLAN_MODE_PASSTHRU, MACVLAN_MODE_PRIVATE, MACVLAN_MODE_SOURCE,
     MACVLAN_MODE_VEPA,
 };
 use nix::sched;
 use sha2::{Digest, Sha512};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::env;
 use std::fmt::Display;
 use std::fs::File;
@@ -18,6 +29,8 @@ use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
 use std::os::unix::prelude::*;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use sysctl::{Sysctl, SysctlError};
 
 use super::netlink;
@@ -26,6 +39,43 @@ pub const IPVLAN_MODE_L2: u16 = 0;
 pub const IPVLAN_MODE_L3: u16 = 1;
 pub const IPVLAN_MODE_L3S: u16 = 2;
 
+/// IFNAMSIZ without the trailing null byte
+pub const MAX_IFNAME_LEN: usize = 15;
+const HOST_VETH_PREFIX: &str = "veth";
+
+/// set by --skip-sysctl, when true apply_sysctl_value() does not touch the system
+static SKIP_SYSCTL: AtomicBool = AtomicBool::new(false);
+
+/// Make all following sysctl writes a no-op for this process.
+pub fn skip_sysctl_writes() {
+    SKIP_SYSCTL.store(true, Ordering::Relaxed);
+}
+
+/// set by --read-only, when true check_read_only() refuses every change of the host
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Refuse all following changes of the host for this process.
+pub fn enable_read_only() {
+    READ_ONLY.store(true, Ordering::Relaxed);
+}
+
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+/// Called before each change of the host, fails and logs the change in
+/// read-only mode.
+pub fn check_read_only(change: impl Display) -> NetavarkResult<()> {
+    if is_read_only() {
+        warn!("read-only mode: refused to {}", change);
+        return Err(NetavarkError::Message(format!(
+            "read-only mode: refused to {}",
+            change
+        )));
+    }
+    Ok(())
+}
+
 pub struct CoreUtils {
     pub networkns: String,
 }
@@ -43,6 +93,35 @@ pub fn get_netavark_dns_port() -> Result<u16, NetavarkError> {
     }
 }
 
+/// Path of the binary set with the env var, None when it is not set and the
+/// binary is searched in PATH.
+pub fn get_binary_path(env_var: &str) -> NetavarkResult<Option<String>> {
+    let path = match env::var(env_var) {
+        Ok(path) if !path.is_empty() => path,
+        _ => return Ok(None),
+    };
+    match std::fs::metadata(&path) {
+        Ok(meta) if meta.is_file() && meta.permissions().mode() & 0o111 != 0 => Ok(Some(path)),
+        Ok(_) => Err(NetavarkError::Message(format!(
+            "invalid {} {}: not an executable file",
+            env_var, path
+        ))),
+        Err(e) => Err(NetavarkError::Message(format!(
+            "invalid {} {}: {}",
+            env_var, path, e
+        ))),
+    }
+}
+
+/// Check all binary paths set in the environment, so a wrong one fails the
+/// command before anything is changed.
+pub fn validate_binary_paths() -> NetavarkResult<()> {
+    for env_var in constants::BINARY_PATH_ENVS {
+        get_binary_path(env_var)?;
+    }
+    Ok(())
+}
+
 pub fn parse_option<T>(
     opts: &Option<HashMap<String, String>>,
     name: &str,
@@ -57,10 +136,10 @@ where
         Some(val) => match val.parse::<T>() {
             Ok(mtu) => mtu,
             Err(err) => {
-                return Err(NetavarkError::Message(format!(
-                    "unable to parse \"{}\": {}",
-                    name, err
-                )));
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", name),
+                    format!("unable to parse \"{}\": {}", name, err),
+                ));
             }
         },
         // if no option is set return the default value
@@ -69,18 +148,40 @@ where
     Ok(val)
 }
 
+/// Like parse_option() but returns None when the option is not set.
+pub fn parse_optional_option<T>(
+    opts: &Option<HashMap<String, String>>,
+    name: &str,
+) -> NetavarkResult<Option<T>>
+where
+    T: FromStr,
+    <T as FromStr>::Err: Display,
+{
+    match opts.as_ref().and_then(|map| map.get(name)) {
+        Some(val) => match val.parse::<T>() {
+            Ok(v) => Ok(Some(v)),
+            Err(err) => Err(ConfigError::invalid_value(
+                format!("options.{}", name),
+                format!("unable to parse \"{}\": {}", name, err),
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
 pub fn get_ipam_addresses<'a>(
     per_network_opts: &'a types::PerNetworkOptions,
     network: &'a types::Network,
-) -> Result<internal_types::IPAMAddresses, std::io::Error> {
+) -> NetavarkResult<internal_types::IPAMAddresses> {
     let addresses = match network
         .ipam_options
         .as_ref()
         .and_then(|map| map.get("driver").cloned())
         .as_deref()
     {
-        // when option is none default to host local
-        Some(constants::IPAM_HOSTLOCAL) | None => {
+        // when option is none default to host local, the lease driver
+        // passes the leased addresses as static ips
+        Some(constants::IPAM_HOSTLOCAL) | Some(constants::IPAM_LEASE) | None => {
             // static ip vector
             let mut container_addresses = Vec::new();
             // gateway ip vector
@@ -93,30 +194,25 @@ pub fn get_ipam_addresses<'a>(
             // nameservers which can be configured for this container
             let mut nameservers: Vec<IpAddr> = Vec::new();
 
+            let gateways = get_subnet_gateways(network)?;
+
             let static_ips = match per_network_opts.static_ips.as_ref() {
-                None => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "no static ips provided",
-                    ))
-                }
+                None => return Err(std::io::Error::other("no static ips provided").into()),
                 Some(i) => i,
             };
 
             // prepare a vector of static aps with appropriate cidr
             for (idx, subnet) in network.subnets.iter().flatten().enumerate() {
                 let subnet_mask_cidr = subnet.subnet.prefix_len();
-                if let Some(gw) = subnet.gateway {
+                if let Some(gw) = gateways[idx] {
                     let gw_net = match ipnet::IpNet::new(gw, subnet_mask_cidr) {
                         Ok(dest) => dest,
                         Err(err) => {
-                            return Err(std::io::Error::new(
-                                std::io::ErrorKind::Other,
-                                format!(
-                                    "failed to parse address {}/{}: {}",
-                                    gw, subnet_mask_cidr, err
-                                ),
+                            return Err(std::io::Error::other(format!(
+                                "failed to parse address {}/{}: {}",
+                                gw, subnet_mask_cidr, err
                             ))
+                            .into())
                         }
                     };
                     gateway_addresses.push(gw_net);
@@ -133,13 +229,13 @@ pub fn get_ipam_addresses<'a>(
                     match format!("{}/{}", static_ips[idx], subnet_mask_cidr).parse() {
                         Ok(i) => i,
                         Err(e) => {
-                            return Err(Error::new(std::io::ErrorKind::Other, e));
+                            return Err(Error::other(e).into());
                         }
                     };
                 // Add the IP to the address_vector
                 container_addresses.push(container_address);
                 net_addresses.push(types::NetAddress {
-                    gateway: subnet.gateway,
+                    gateway: gateways[idx],
                     ipnet: container_address,
                 });
             }
@@ -172,16 +268,100 @@ pub fn get_ipam_addresses<'a>(
             nameservers: vec![],
         },
         Some(driver) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("unsupported ipam driver {}", driver),
-            ));
+            return Err(
+                std::io::Error::other(format!("unsupported ipam driver {}", driver)).into(),
+            );
         }
     };
 
     Ok(addresses)
 }
 
+/// Get the gateway of every subnet of the network. A gateway set in the subnet
+/// is used verbatim, otherwise one is only computed when the gateway_position
+/// option is set. Every gateway must be a usable host address of its subnet.
+pub fn get_subnet_gateways(network: &types::Network) -> NetavarkResult<Vec<Option<IpAddr>>> {
+    let position: Option<String> =
+        parse_optional_option(&network.options, constants::OPTION_GATEWAY_POSITION)?;
+    let first = match position.as_deref() {
+        None => None,
+        Some(constants::GATEWAY_POSITION_FIRST) => Some(true),
+        Some(constants::GATEWAY_POSITION_LAST) => Some(false),
+        Some(position) => {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", constants::OPTION_GATEWAY_POSITION),
+                format!(
+                    "invalid gateway position \"{}\", must be {} or {}",
+                    position,
+                    constants::GATEWAY_POSITION_FIRST,
+                    constants::GATEWAY_POSITION_LAST
+                ),
+            ))
+        }
+    };
+
+    network
+        .subnets
+        .iter()
+        .flatten()
+        .map(|subnet| {
+            let (host_first, host_last) = subnet_host_range(&subnet.subnet);
+            let gateway = match (subnet.gateway, first) {
+                (Some(gw), _) => gw,
+                (None, Some(true)) => host_first,
+                (None, Some(false)) => host_last,
+                (None, None) => return Ok(None),
+            };
+            if !subnet.subnet.contains(&gateway) || gateway < host_first || gateway > host_last {
+                return Err(ConfigError::invalid_value(
+                    "subnets.gateway",
+                    format!(
+                        "gateway {} is not a usable address of subnet {}",
+                        gateway, subnet.subnet
+                    ),
+                ));
+            }
+            Ok(Some(gateway))
+        })
+        .collect()
+}
+
+/// First and last address of the subnet which can be assigned to a host, this
+/// excludes the network and broadcast address for ipv4 and the subnet-router
+/// anycast address for ipv6.
+pub fn subnet_host_range(subnet: &ipnet::IpNet) -> (IpAddr, IpAddr) {
+    match subnet.trunc() {
+        ipnet::IpNet::V4(net) => {
+            let first = u32::from(net.network());
+            let last = u32::from(net.broadcast());
+            if net.prefix_len() < 31 {
+                (
+                    IpAddr::V4(Ipv4Addr::from(first + 1)),
+                    IpAddr::V4(Ipv4Addr::from(last - 1)),
+                )
+            } else {
+                (
+                    IpAddr::V4(Ipv4Addr::from(first)),
+                    IpAddr::V4(Ipv4Addr::from(last)),
+                )
+            }
+        }
+        ipnet::IpNet::V6(net) => {
+            let first = u128::from(net.network());
+            let last = u128::from(net.broadcast());
+            let first = if net.prefix_len() < 128 {
+                first + 1
+            } else {
+                first
+            };
+            (
+                IpAddr::V6(Ipv6Addr::from(first)),
+                IpAddr::V6(Ipv6Addr::from(last)),
+            )
+        }
+    }
+}
+
 impl CoreUtils {
     pub fn encode_address_to_hex(bytes: &[u8]) -> String {
         let address: String = bytes
@@ -258,6 +438,17 @@ impl CoreUtils {
         response.to_string()
     }
 
+    /// Create a deterministic name for the host side veth of the given container
+    /// and network, the result always fits into IFNAMSIZ.
+    pub fn create_host_veth_name(container_id: &str, network_name: &str) -> String {
+        // network names cannot contain a slash so this is unambiguous
+        let hash = Self::create_network_hash(
+            &format!("{}/{}", container_id, network_name),
+            MAX_IFNAME_LEN - HOST_VETH_PREFIX.len(),
+        );
+        format!("{}{}", HOST_VETH_PREFIX, hash.to_lowercase())
+    }
+
     /// Set a sysctl value by value's namespace.
     pub fn apply_sysctl_value(
         ns_value: impl AsRef<str>,
@@ -265,6 +456,10 @@ impl CoreUtils {
     ) -> Result<String, SysctlError> {
         let ns_value = ns_value.as_ref();
         let val = val.as_ref();
+        if SKIP_SYSCTL.load(Ordering::Relaxed) {
+            debug!("Skipping sysctl value for {} (wanted {})", ns_value, val);
+            return Ok(val.to_string());
+        }
         debug!("Setting sysctl value for {} to {}", ns_value, val);
         let ctl = sysctl::Ctl::new(ns_value)?;
         match ctl.value_string() {
@@ -275,6 +470,12 @@ impl CoreUtils {
             }
             Err(e) => return Err(e),
         }
+        if let Err(e) = check_read_only(format_args!("set sysctl {} to {}", ns_value, val)) {
+            return Err(SysctlError::IoError(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                e.to_string(),
+            )));
+        }
         ctl.set_value_string(val)
     }
 }
@@ -346,6 +547,535 @@ fn open_netlink_socket(netns_path: &str) -> NetavarkResult<(File, RawFd)> {
     Ok((ns, ns_fd))
 }
 
+/// Neighbor entry which is added to the container interface
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaticNeighbor {
+    pub ip: IpAddr,
+    pub mac: Vec<u8>,
+}
+
+/// Parse the static_neighbors option, a comma separated list of `ip=mac` entries.
+/// The ips must be on-link, i.e. part of one of the network subnets.
+pub fn get_static_neighbors(network: &types::Network) -> NetavarkResult<Vec<StaticNeighbor>> {
+    let value: String = parse_option(
+        &network.options,
+        constants::OPTION_STATIC_NEIGHBORS,
+        String::new(),
+    )?;
+    let field = format!("options.{}", constants::OPTION_STATIC_NEIGHBORS);
+
+    let mut neighbors = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let invalid = |msg: String| {
+            ConfigError::invalid_value(
+                field.clone(),
+                format!("invalid static neighbor \"{}\": {}", entry, msg),
+            )
+        };
+        let (ip, mac) = entry
+            .split_once('=')
+            .ok_or_else(|| invalid("expected ip=mac".to_string()))?;
+        let ip: IpAddr = ip.parse().map_err(|e| invalid(format!("{}", e)))?;
+        let mac = CoreUtils::decode_address_from_hex(mac).map_err(|e| invalid(e.to_string()))?;
+        if !network
+            .subnets
+            .iter()
+            .flatten()
+            .any(|subnet| subnet.subnet.contains(&ip))
+        {
+            return Err(invalid("ip is not part of a network subnet".to_string()));
+        }
+        neighbors.push(StaticNeighbor { ip, mac });
+    }
+    Ok(neighbors)
+}
+
+/// Add the neighbor entries to the interface, the kernel removes them
+/// again together with the interface on teardown.
+pub fn add_static_neighbors(
+    sock: &mut netlink::Socket,
+    link_id: u32,
+    neighbors: &[StaticNeighbor],
+) -> NetavarkResult<()> {
+    for neighbor in neighbors {
+        sock.add_neighbor(link_id, &neighbor.ip, &neighbor.mac)
+            .wrap(format!("add static neighbor {}", neighbor.ip))?;
+    }
+    Ok(())
+}
+
+/// Parse the gateways and policy_routes options. gateways is a comma separated
+/// list of `name=ip` entries, the ips must be on-link. policy_routes is a comma
+/// separated list of `gateway:table[:from=cidr|:fwmark=mark[/mask]]` entries.
+pub fn get_policy_routes(network: &types::Network) -> NetavarkResult<Vec<types::PolicyRoute>> {
+    let value: String = parse_option(&network.options, constants::OPTION_GATEWAYS, String::new())?;
+    let field = format!("options.{}", constants::OPTION_GATEWAYS);
+
+    let mut gateways: HashMap<&str, IpAddr> = HashMap::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let invalid = |msg: &str| {
+            ConfigError::invalid_value(
+                field.clone(),
+                format!("invalid gateway \"{}\": {}", entry, msg),
+            )
+        };
+        let (name, ip) = entry
+            .split_once('=')
+            .ok_or_else(|| invalid("expected name=ip"))?;
+        if name.is_empty() {
+            return Err(invalid("empty gateway name"));
+        }
+        let ip: IpAddr = ip.parse().map_err(|e| invalid(&format!("{}", e)))?;
+        // we add the routes without a gateway in front of it
+        if !network
+            .subnets
+            .iter()
+            .flatten()
+            .any(|subnet| subnet.subnet.contains(&ip))
+        {
+            return Err(invalid("ip is not part of a network subnet"));
+        }
+        if gateways.insert(name, ip).is_some() {
+            return Err(invalid("duplicate gateway name"));
+        }
+    }
+
+    let value: String = parse_option(
+        &network.options,
+        constants::OPTION_POLICY_ROUTES,
+        String::new(),
+    )?;
+    let field = format!("options.{}", constants::OPTION_POLICY_ROUTES);
+
+    let mut routes: Vec<types::PolicyRoute> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let invalid = |msg: String| {
+            ConfigError::invalid_value(
+                field.clone(),
+                format!("invalid policy route \"{}\": {}", entry, msg),
+            )
+        };
+        let mut parts = entry.splitn(3, ':');
+        let name = parts.next().unwrap_or_default();
+        let gateway = *gateways
+            .get(name)
+            .ok_or_else(|| invalid(format!("unknown gateway \"{}\"", name)))?;
+        let table: u32 = parts
+            .next()
+            .ok_or_else(|| invalid("expected gateway:table".to_string()))?
+            .parse()
+            .map_err(|e| invalid(format!("invalid table: {}", e)))?;
+        if table == RT_TABLE_UNSPEC as u32
+            || (RT_TABLE_COMPAT as u32..=RT_TABLE_LOCAL as u32).contains(&table)
+        {
+            return Err(invalid(format!("table {} is reserved", table)));
+        }
+
+        let mut route = types::PolicyRoute {
+            gateway_name: name.to_string(),
+            gateway,
+            table,
+            source: None,
+            fwmark: None,
+            fwmask: None,
+        };
+        match parts.next().map(|selector| selector.split_once('=')) {
+            None => {}
+            Some(Some(("from", source))) => {
+                let source: ipnet::IpNet = source
+                    .parse()
+                    .map_err(|e| invalid(format!("invalid source: {}", e)))?;
+                if source.addr().is_ipv6() != gateway.is_ipv6() {
+                    return Err(invalid(
+                        "source and gateway must be of the same ip family".to_string(),
+                    ));
+                }
+                route.source = Some(source.trunc());
+            }
+            Some(Some(("fwmark", mark))) => {
+                let (mark, mask) = match mark.split_once('/') {
+                    Some((mark, mask)) => (mark, Some(mask)),
+                    None => (mark, None),
+                };
+                let parse = |v: &str| {
+                    match v.strip_prefix("0x") {
+                        Some(hex) => u32::from_str_radix(hex, 16),
+                        None => v.parse(),
+                    }
+                    .map_err(|e| invalid(format!("invalid fwmark \"{}\": {}", v, e)))
+                };
+                route.fwmark = Some(parse(mark)?);
+                route.fwmask = mask.map(parse).transpose()?;
+            }
+            Some(_) => {
+                return Err(invalid(
+                    "expected from=cidr or fwmark=mark[/mask] as selector".to_string(),
+                ))
+            }
+        }
+        // every table can only have one default route per ip family
+        if let Some(other) = routes.iter().find(|r| {
+            r.table == table && r.gateway.is_ipv6() == gateway.is_ipv6() && r.gateway != gateway
+        }) {
+            return Err(invalid(format!(
+                "table {} already has a default route via {}",
+                table, other.gateway
+            )));
+        }
+        routes.push(route);
+    }
+    Ok(routes)
+}
+
+/// Add the default routes into the routing tables of the policy routes and the
+/// rules which select them. The routes are removed by the kernel together with
+/// the interface, the rules must be removed with remove_policy_rules().
+pub fn add_policy_routes(
+    sock: &mut netlink::Socket,
+    routes: &[types::PolicyRoute],
+) -> NetavarkResult<()> {
+    for (i, policy_route) in routes.iter().enumerate() {
+        // several rules can use the same table
+        if !routes[..i].iter().any(|r| {
+            r.table == policy_route.table && r.gateway.is_ipv6() == policy_route.gateway.is_ipv6()
+        }) {
+            let route = match policy_route.gateway {
+                IpAddr::V4(gw) => netlink::Route::Ipv4 {
+                    dest: ipnet::Ipv4Net::new(Ipv4Addr::new(0, 0, 0, 0), 0)?,
+                    gw,
+                    metric: None,
+                },
+                IpAddr::V6(gw) => netlink::Route::Ipv6 {
+                    dest: ipnet::Ipv6Net::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 0)?,
+                    gw,
+                    metric: None,
+                },
+            };
+            sock.add_route_to_table(&route, policy_route.table)
+                .wrap(format!(
+                    "add default route {} to table {}",
+                    &route, policy_route.table
+                ))?;
+        }
+        if let Some(rule) = get_policy_rule(policy_route) {
+            sock.add_rule(&rule)
+                .wrap(format!("add rule for table {}", policy_route.table))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the rules added by add_policy_routes(), rules which are already gone are ignored.
+pub fn remove_policy_rules(
+    sock: &mut netlink::Socket,
+    routes: &[types::PolicyRoute],
+) -> NetavarkResult<()> {
+    for policy_route in routes {
+        if let Some(rule) = get_policy_rule(policy_route) {
+            match sock.del_rule(&rule) {
+                Ok(_) => {}
+                Err(NetavarkError::Netlink(ref e)) if -e.code == libc::ENOENT => {}
+                Err(e) => {
+                    return Err(e).wrap(format!("remove rule for table {}", policy_route.table))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_policy_rule(route: &types::PolicyRoute) -> Option<netlink::Rule> {
+    if route.source.is_none() && route.fwmark.is_none() {
+        return None;
+    }
+    Some(netlink::Rule {
+        table: route.table,
+        ipv6: route.gateway.is_ipv6(),
+        source: route.source,
+        fwmark: route
+            .fwmark
+            .map(|mark| (mark, route.fwmask.unwrap_or(u32::MAX))),
+    })
+}
+
+/// Get the metric for the default routes of the network, set with the
+/// route_metric option or its older name metric.
+pub fn get_route_metric(options: &Option<HashMap<String, String>>) -> NetavarkResult<u32> {
+    let route_metric: Option<String> =
+        parse_optional_option(options, constants::OPTION_ROUTE_METRIC)?;
+    let metric: Option<String> = parse_optional_option(options, constants::OPTION_METRIC)?;
+    let (name, value) = match (route_metric, metric) {
+        (Some(_), Some(_)) => {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", constants::OPTION_ROUTE_METRIC),
+                format!(
+                    "{} and {} cannot be set at the same time",
+                    constants::OPTION_ROUTE_METRIC,
+                    constants::OPTION_METRIC
+                ),
+            ))
+        }
+        (Some(value), None) => (constants::OPTION_ROUTE_METRIC, value),
+        (None, Some(value)) => (constants::OPTION_METRIC, value),
+        (None, None) => return Ok(constants::DEFAULT_METRIC),
+    };
+    match value.parse::<i64>() {
+        Ok(metric) => u32::try_from(metric).map_err(|_| {
+            ConfigError::invalid_value(
+                format!("options.{}", name),
+                format!(
+                    "invalid {} {}: must be between 0 and {}",
+                    name,
+                    metric,
+                    u32::MAX
+                ),
+            )
+        }),
+        Err(err) => Err(ConfigError::invalid_value(
+            format!("options.{}", name),
+            format!("unable to parse \"{}\": {}", name, err),
+        )),
+    }
+}
+
+fn link_name(link: &netlink_packet_route::LinkMessage) -> Option<String> {
+    link.nlas.iter().find_map(|nla| match nla {
+        netlink_packet_route::link::nlas::Nla::IfName(name) => Some(name.clone()),
+        _ => None,
+    })
+}
+
+/// Give the networks whose interface name already exists in the namespace of
+/// the socket the next free name with the same prefix, e.g. eth1 for eth0.
+/// The names requested by the other networks are never picked.
+pub fn rename_conflicting_interfaces(
+    sock: &mut netlink::Socket,
+    networks: &mut HashMap<String, types::PerNetworkOptions>,
+) -> NetavarkResult<()> {
+    let existing: HashSet<String> = sock
+        .dump_links(&mut vec![])
+        .wrap("dump container interfaces")?
+        .iter()
+        .filter_map(link_name)
+        .collect();
+    let mut taken: HashSet<String> = existing.clone();
+    taken.extend(networks.values().map(|opts| opts.interface_name.clone()));
+
+    let mut names: Vec<&String> = networks.keys().collect();
+    names.sort();
+    let names: Vec<String> = names.into_iter().cloned().collect();
+    for network in names {
+        let opts = networks.get_mut(&network).unwrap();
+        if !existing.contains(&opts.interface_name) {
+            continue;
+        }
+        let name = next_free_interface_name(&opts.interface_name, &taken)?;
+        info!(
+            "interface {} already exists in the container namespace, using {} for network {}",
+            opts.interface_name, name, network
+        );
+        taken.insert(name.clone());
+        opts.interface_name = name;
+    }
+    Ok(())
+}
+
+fn next_free_interface_name(name: &str, taken: &HashSet<String>) -> NetavarkResult<String> {
+    let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let start: u32 = name[prefix.len()..].parse().unwrap_or(0);
+    let no_free_name =
+        || NetavarkError::Message(format!("no free interface name left for {}", name));
+    let next = start.checked_add(1).ok_or_else(no_free_name)?;
+    (next..=u32::MAX)
+        .map(|i| format!("{}{}", prefix, i))
+        // IFNAMSIZ includes the nul byte
+        .take_while(|n| n.len() < 16)
+        .find(|n| !taken.contains(n))
+        .ok_or_else(no_free_name)
+}
+
+/// Find the user requested static ips which are already in use on the host,
+/// either assigned to a host interface or answering on a link according to the
+/// neighbor table. Returns a description of each conflict.
+pub fn find_static_ip_conflicts(
+    sock: &mut netlink::Socket,
+    networks: &HashMap<String, types::PerNetworkOptions>,
+) -> NetavarkResult<Vec<String>> {
+    let mut requested = Vec::new();
+    for (network, opts) in networks {
+        let mac = match &opts.static_mac {
+            Some(mac) => Some(CoreUtils::decode_address_from_hex(mac)?),
+            None => None,
+        };
+        for ip in opts.static_ips.iter().flatten() {
+            requested.push((network, ip, mac.clone()));
+        }
+    }
+    if requested.is_empty() {
+        return Ok(Vec::new());
+    }
+    requested.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    let links = sock.dump_links(&mut vec![]).wrap("dump host interfaces")?;
+    let names: HashMap<u32, String> = links
+        .iter()
+        .filter_map(|link| link_name(link).map(|name| (link.header.index, name)))
+        .collect();
+    let name = |index: u32| {
+        names
+            .get(&index)
+            .cloned()
+            .unwrap_or_else(|| index.to_string())
+    };
+    let bridges: HashSet<u32> = links
+        .iter()
+        .filter(|link| {
+            link.nlas.iter().any(|nla| match nla {
+                LinkNla::Info(info) => info.contains(&Info::Kind(InfoKind::Bridge)),
+                _ => false,
+            })
+        })
+        .map(|link| link.header.index)
+        .collect();
+
+    let addresses: Vec<(u32, IpAddr)> = sock
+        .dump_addresses()
+        .wrap("dump host addresses")?
+        .iter()
+        .filter_map(|msg| parse_address_message(msg).map(|net| (msg.header.index, net.addr())))
+        .collect();
+
+    // stale and failed entries may be left over from removed containers
+    let neighbors: Vec<(u32, IpAddr, Vec<u8>)> = sock
+        .dump_neighbors(AF_UNSPEC as u8)
+        .wrap("dump host neighbors")?
+        .iter()
+        .filter(|msg| {
+            msg.header.state & (NUD_REACHABLE | NUD_DELAY | NUD_PROBE | NUD_PERMANENT) != 0
+        })
+        .filter_map(|msg| {
+            let ip = msg.nlas.iter().find_map(|nla| match nla {
+                NeighbourNla::Destination(b) => bytes_to_ip(b),
+                _ => None,
+            })?;
+            let mac = msg.nlas.iter().find_map(|nla| match nla {
+                NeighbourNla::LinkLocalAddress(b) => Some(b.clone()),
+                _ => None,
+            })?;
+            Some((msg.header.ifindex, ip, mac))
+        })
+        .collect();
+
+    // a neighbor entry on a bridge outlives the container interface it was
+    // learned from, so the mac must also be known on one of the bridge ports
+    let mut bridge_macs: HashSet<(u32, Vec<u8>)> = HashSet::new();
+    if neighbors.iter().any(|n| bridges.contains(&n.0)) {
+        for msg in sock
+            .dump_neighbors(AF_BRIDGE as u8)
+            .wrap("dump bridge forwarding database")?
+        {
+            let master = msg.nlas.iter().find_map(|nla| match nla {
+                NeighbourNla::Master(b) => <[u8; 4]>::try_from(b.as_slice())
+                    .ok()
+                    .map(u32::from_ne_bytes),
+                _ => None,
+            });
+            let mac = msg.nlas.iter().find_map(|nla| match nla {
+                NeighbourNla::LinkLocalAddress(b) => Some(b.clone()),
+                _ => None,
+            });
+            if let (Some(master), Some(mac)) = (master, mac) {
+                bridge_macs.insert((master, mac));
+            }
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for (network, ip, static_mac) in requested {
+        for (index, _) in addresses.iter().filter(|(_, addr)| addr == ip) {
+            conflicts.push(format!(
+                "static ip {} of network {} is already assigned to interface {} on the host",
+                ip,
+                network,
+                name(*index)
+            ));
+        }
+        for (index, _, mac) in neighbors.iter().filter(|(_, addr, _)| addr == ip) {
+            // the container itself from an earlier setup
+            if static_mac.as_ref() == Some(mac) {
+                continue;
+            }
+            if bridges.contains(index) && !bridge_macs.contains(&(*index, mac.clone())) {
+                continue;
+            }
+            conflicts.push(format!(
+                "static ip {} of network {} is already used by {} on interface {}",
+                ip,
+                network,
+                CoreUtils::encode_address_to_hex(mac),
+                name(*index)
+            ));
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Get the default gateways used in the namespace of the socket, for each ip
+/// family the default route with the lowest metric wins. Returns the name of
+/// the output interface together with the gateway.
+pub fn get_default_gateways(sock: &mut netlink::Socket) -> NetavarkResult<Vec<(String, IpAddr)>> {
+    // ordered by address family, so ipv4 is always reported before ipv6
+    let mut best: BTreeMap<u8, (u32, u32, IpAddr)> = BTreeMap::new();
+    for route in sock.dump_routes().wrap("dump routes")? {
+        if route.header.destination_prefix_length != 0
+            || route.header.table != netlink_packet_route::RT_TABLE_MAIN
+        {
+            continue;
+        }
+        let mut gateway = None;
+        let mut oif = 0;
+        let mut metric = 0;
+        for nla in &route.nlas {
+            match nla {
+                RouteNla::Gateway(gw) => gateway = bytes_to_ip(gw),
+                RouteNla::Oif(i) => oif = *i,
+                RouteNla::Priority(p) => metric = *p,
+                _ => {}
+            }
+        }
+        let gateway = match gateway {
+            Some(gw) if oif > 0 => gw,
+            _ => continue,
+        };
+        let family = route.header.address_family;
+        // routes are dumped in the order the kernel prefers them for equal metrics
+        if best
+            .get(&family)
+            .map(|(m, _, _)| metric < *m)
+            .unwrap_or(true)
+        {
+            best.insert(family, (metric, oif, gateway));
+        }
+    }
+
+    let mut gateways = Vec::with_capacity(best.len());
+    for (_, oif, gateway) in best.into_values() {
+        let link = sock.get_link(netlink::LinkID::ID(oif))?;
+        if let Some(name) = link_name(&link) {
+            gateways.push((name, gateway));
+        }
+    }
+    Ok(gateways)
+}
+
+fn bytes_to_ip(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
 pub fn add_default_routes(
     sock: &mut netlink::Socket,
     gws: &[ipnet::IpNet],
@@ -410,3 +1140,93 @@ pub fn disable_ipv6_autoconf(if_name: &str) -> NetavarkResult<()> {
     };
     Ok(())
 }
+
+/// Disable ipv6 on the interface, it then does not get any ipv6 address, not
+/// even a link-local one. Must be called before the interface is set up.
+pub fn disable_ipv6(if_name: &str) -> NetavarkResult<()> {
+    match CoreUtils::apply_sysctl_value(
+        format!("/proc/sys/net/ipv6/conf/{}/disable_ipv6", if_name),
+        "1",
+    ) {
+        Ok(_) => Ok(()),
+        // without ipv6 support in the kernel there is nothing to disable
+        Err(SysctlError::NotFound(_)) => Ok(()),
+        Err(err) => Err(NetavarkError::wrap(
+            format!("failed to disable ipv6 on {}", if_name),
+            NetavarkError::Sysctl(err),
+        )),
+    }
+}
+
+/// Turn on ipv6 autoconf and accept router advertisements, accept_ra=2 makes
+/// sure they are accepted even when forwarding is enabled.
+pub fn enable_ipv6_slaac(if_name: &str) -> NetavarkResult<()> {
+    CoreUtils::apply_sysctl_value(
+        format!("/proc/sys/net/ipv6/conf/{}/accept_ra", if_name),
+        "2",
+    )?;
+    CoreUtils::apply_sysctl_value(format!("/proc/sys/net/ipv6/conf/{}/autoconf", if_name), "1")?;
+    Ok(())
+}
+
+/// Get the address of the interface from a netlink address message.
+pub fn parse_address_message(msg: &AddressMessage) -> Option<ipnet::IpNet> {
+    let bytes = msg.nlas.iter().find_map(|nla| match nla {
+        // for ipv4 local is the address of the interface, for ipv6 address
+        AddressNla::Local(b) if msg.header.family == AF_INET as u8 => Some(b),
+        AddressNla::Address(b) if msg.header.family == AF_INET6 as u8 => Some(b),
+        _ => None,
+    })?;
+    let ip = match bytes.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(bytes.as_slice()).ok()?),
+        16 => IpAddr::from(<[u8; 16]>::try_from(bytes.as_slice()).ok()?),
+        _ => return None,
+    };
+    ipnet::IpNet::new(ip, msg.header.prefix_len).ok()
+}
+
+/// Wait until the kernel autoconfigured a global ipv6 address on the given link
+/// and return it. Tentative addresses, i.e. dad is not finished, are not returned.
+pub fn wait_for_slaac_address(
+    sock: &mut netlink::Socket,
+    link_index: u32,
+    timeout: Duration,
+) -> NetavarkResult<ipnet::IpNet> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        for addr in sock.dump_addresses()? {
+            if addr.header.index != link_index
+                || addr.header.family != AF_INET6 as u8
+                || addr.header.scope != RT_SCOPE_UNIVERSE
+            {
+                continue;
+            }
+            let mut flags = addr.header.flags as u32;
+            let mut ip = None;
+            for nla in addr.nlas {
+                match nla {
+                    AddressNla::Flags(f) => flags = f,
+                    AddressNla::Address(a) => ip = Some(a),
+                    _ => {}
+                }
+            }
+            if flags & (IFA_F_TENTATIVE | IFA_F_DADFAILED) != 0 {
+                continue;
+            }
+            if let Some(Ok(octets)) = ip.map(<[u8; 16]>::try_from) {
+                return Ok(ipnet::IpNet::new(
+                    IpAddr::V6(Ipv6Addr::from(octets)),
+                    addr.header.prefix_len,
+                )?);
+            }
+        }
+
+        if Instant::now() >= deadline {
+            return Err(NetavarkError::Message(format!(
+                "no ipv6 address was autoconfigured via SLAAC within {}s",
+                timeout.as_secs()
+            )));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -1,15 +1,20 @@
 use crate::{
     dns::aardvark::AardvarkEntry,
-    error::{NetavarkError, NetavarkResult},
+    error::{ConfigError, NetavarkError, NetavarkResult},
     firewall::FirewallDriver,
 };
 
-use std::{net::IpAddr, path::Path};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex},
+};
 
 use super::{
     bridge::Bridge,
     constants, netlink,
     plugin::PluginDriver,
+    script::Script,
     types::{Network, PerNetworkOptions, PortMapping, StatusBlock},
     vlan::Vlan,
 };
@@ -28,9 +33,81 @@ pub struct DriverInfo<'a> {
     pub per_network_opts: &'a PerNetworkOptions,
     pub port_mappings: &'a Option<Vec<PortMapping>>,
     pub dns_port: u16,
+    /// netavark config directory, usually a tmpfs
+    pub config_dir: &'a str,
+    /// set when the networks are set up in parallel
+    pub link_order: Option<&'a LinkOrder>,
+}
+
+/// Lets the networks which are set up in parallel create their container
+/// interfaces one after another in network_order(), so the interface order
+/// in the container does not depend on the timing of the threads.
+pub struct LinkOrder {
+    networks: Vec<String>,
+    /// done flag of each network and the index of the first one not done
+    state: Mutex<(Vec<bool>, usize)>,
+    turn: Condvar,
+}
+
+/// Turn of a network to create its container interface, it ends when dropped.
+pub struct LinkTurn<'a> {
+    order: &'a LinkOrder,
+    index: Option<usize>,
 }
 
-pub trait NetworkDriver {
+impl LinkOrder {
+    pub fn new(networks: Vec<String>) -> Self {
+        LinkOrder {
+            state: Mutex::new((vec![false; networks.len()], 0)),
+            networks,
+            turn: Condvar::new(),
+        }
+    }
+
+    /// Wait until all networks before this one created their interface.
+    pub fn wait(&self, network: &str) -> LinkTurn<'_> {
+        let index = self.networks.iter().position(|n| n == network);
+        if let Some(index) = index {
+            let mut state = self.lock();
+            while state.1 < index {
+                state = self.turn.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        }
+        LinkTurn { order: self, index }
+    }
+
+    /// Mark the network as done, also when its setup failed before it
+    /// created the interface, otherwise the networks after it wait forever.
+    pub fn done(&self, network: &str) {
+        if let Some(index) = self.networks.iter().position(|n| n == network) {
+            self.finish(index);
+        }
+    }
+
+    fn finish(&self, index: usize) {
+        let mut state = self.lock();
+        state.0[index] = true;
+        while state.1 < state.0.len() && state.0[state.1] {
+            state.1 += 1;
+        }
+        self.turn.notify_all();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, (Vec<bool>, usize)> {
+        // a poisoned lock only means another setup panicked, the flags are still fine
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for LinkTurn<'_> {
+    fn drop(&mut self) {
+        if let Some(index) = self.index {
+            self.order.finish(index);
+        }
+    }
+}
+
+pub trait NetworkDriver: Sync {
     /// validate the driver options
     fn validate(&mut self) -> NetavarkResult<()>;
     /// setup the network interfaces/firewall rules for this driver
@@ -43,6 +120,29 @@ pub trait NetworkDriver {
         &self,
         netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
     ) -> NetavarkResult<()>;
+    /// add the firewall rules of an already set up network again,
+    /// drivers without firewall rules have nothing to do
+    fn reload(&self) -> NetavarkResult<()> {
+        Ok(())
+    }
+    /// stop forwarding new connections to the published ports before the
+    /// teardown, established connections keep working until then
+    fn drain(&self) -> NetavarkResult<()> {
+        Ok(())
+    }
+    /// remove what is left on the host after the container namespace is gone
+    /// without a teardown, the interfaces in it are gone with the namespace
+    fn cleanup(&self, _host: &mut netlink::Socket) -> NetavarkResult<()> {
+        Err(NetavarkError::msg(
+            "the driver does not support the cleanup of orphaned containers",
+        ))
+    }
+    /// add the commands of the setup to the script instead of running them
+    fn emit_script(&self, _script: &mut Script) -> NetavarkResult<()> {
+        Err(NetavarkError::msg(
+            "the driver does not support --emit-script",
+        ))
+    }
 
     /// return the network name
     fn network_name(&self) -> String;
@@ -56,22 +156,61 @@ pub fn get_network_driver<'a>(
         constants::DRIVER_BRIDGE => Ok(Box::new(Bridge::new(info))),
         constants::DRIVER_IPVLAN | constants::DRIVER_MACVLAN => Ok(Box::new(Vlan::new(info))),
 
-        name => {
-            if let Some(dirs) = plugins_directories {
-                for path in dirs.iter() {
-                    let path = Path::new(path).join(name);
-                    if let Ok(meta) = path.metadata() {
-                        if meta.is_file() && meta.permissions().mode() & 0o111 != 0 {
-                            return Ok(Box::new(PluginDriver::new(path, info)));
-                        }
-                    }
-                }
+        name => match find_plugin(name, plugins_directories) {
+            Some(path) => Ok(Box::new(PluginDriver::new(path, info))),
+            None => Err(unknown_driver_error(info.network)),
+        },
+    }
+}
+
+/// Make sure every network uses a driver we support before any network is
+/// touched, all networks with an unknown driver are reported at once.
+pub fn check_network_drivers<'a>(
+    networks: impl Iterator<Item = &'a Network>,
+    plugins_directories: &Option<Vec<String>>,
+) -> NetavarkResult<()> {
+    let mut unknown: Vec<&Network> = networks
+        .filter(|network| match network.driver.as_str() {
+            constants::DRIVER_BRIDGE | constants::DRIVER_IPVLAN | constants::DRIVER_MACVLAN => {
+                false
             }
+            name => find_plugin(name, plugins_directories).is_none(),
+        })
+        .collect();
+    unknown.sort_by(|a, b| a.name.cmp(&b.name));
 
-            Err(NetavarkError::Message(format!(
-                "unknown network driver \"{}\"",
-                info.network.driver
-            )))
-        }
+    match unknown.as_slice() {
+        [] => Ok(()),
+        [network] => Err(unknown_driver_error(network)),
+        networks => Err(ConfigError::invalid_value(
+            "driver",
+            format!(
+                "unknown network drivers {}",
+                networks
+                    .iter()
+                    .map(|n| format!("\"{}\" (network {})", n.driver, n.name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )),
     }
 }
+
+fn unknown_driver_error(network: &Network) -> NetavarkError {
+    ConfigError::invalid_value(
+        "driver",
+        format!("unknown network driver \"{}\"", network.driver),
+    )
+    .with_network(&network.name)
+}
+
+/// Find the executable plugin with the given name in the plugin directories.
+fn find_plugin(name: &str, plugins_directories: &Option<Vec<String>>) -> Option<PathBuf> {
+    plugins_directories.iter().flatten().find_map(|dir| {
+        let path = Path::new(dir).join(name);
+        match path.metadata() {
+            Ok(meta) if meta.is_file() && meta.permissions().mode() & 0o111 != 0 => Some(path),
+            _ => None,
+        }
+    })
+}
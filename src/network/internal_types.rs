@@ -1,5 +1,6 @@
 use crate::network::types;
 use std::net::IpAddr;
+use std::str::FromStr;
 
 /// Teardown contains options for tearing down behind a container
 #[derive(Debug)]
@@ -18,6 +19,140 @@ pub struct SetupNetwork {
     pub network_hash_name: String,
     /// isolation determines whether the network can communicate with others outside of its interface
     pub isolation: bool,
+    /// trusted networks bypass all netavark forward filtering
+    pub trusted: bool,
+    /// filter the traffic forwarded from the containers, None allows everything
+    pub policy: Option<ForwardPolicy>,
+    /// conntrack zone for the connections of the containers, None uses the default zone
+    pub conntrack_zone: Option<u16>,
+    /// source port handling of the masquerade rule
+    pub masquerade_ports: MasqueradePorts,
+    /// ipv4 pool of the NAT64 translator, its traffic is masqueraded and forwarded like the subnets
+    pub nat64_pool: Option<ipnet::Ipv4Net>,
+    /// accept the replies to the containers with a conntrack state rule, false when
+    /// the host firewall already has a global one
+    pub ctstate_accept: bool,
+    /// allow the traffic between the containers of the bridge
+    pub icc: bool,
+    /// DSCP value set on the traffic of the containers
+    pub dscp: Option<u8>,
+    /// firewall mark set on the traffic of the containers
+    pub fwmark: Option<FwMark>,
+}
+
+impl SetupNetwork {
+    /// Subnets which get the network firewall rules, the NAT64 pool is
+    /// handled like an ipv4 subnet of the network.
+    pub fn firewall_subnets(&self) -> Vec<types::Subnet> {
+        let mut subnets = self.net.subnets.clone().unwrap_or_default();
+        if let Some(pool) = self.nat64_pool {
+            subnets.push(types::Subnet {
+                gateway: None,
+                lease_range: None,
+                subnet: pool.into(),
+            });
+        }
+        subnets
+    }
+}
+
+/// Firewall mark, only the bits of the mask are changed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FwMark {
+    pub mark: u32,
+    pub mask: u32,
+}
+
+/// Source port handling when the traffic of the containers is masqueraded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MasqueradePorts {
+    /// keep the source port unless it clashes, the kernel default
+    #[default]
+    Preserve,
+    /// randomize the source port
+    Random,
+    /// randomize the source port without reusing recently used ones
+    RandomFully,
+}
+
+/// ForwardPolicy only allows the listed traffic from the containers of a network,
+/// everything else is handled by the default action.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForwardPolicy {
+    pub default_action: PolicyAction,
+    pub allow: Vec<AllowRule>,
+}
+
+/// Action for traffic which is not explicitly allowed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolicyAction {
+    Drop,
+    Reject,
+}
+
+/// AllowRule matches traffic by protocol, destination port and destination peer.
+/// The format is `protocol[/port[-port]][@cidr]`, e.g. `tcp/443@10.0.0.0/8`.
+/// The protocol `all` matches every protocol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AllowRule {
+    /// None matches all protocols
+    pub protocol: Option<String>,
+    /// inclusive destination port range
+    pub ports: Option<(u16, u16)>,
+    pub peer: Option<ipnet::IpNet>,
+}
+
+impl FromStr for AllowRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (proto_port, peer) = match s.split_once('@') {
+            Some((proto_port, peer)) => {
+                let peer: ipnet::IpNet = peer
+                    .parse()
+                    .map_err(|e| format!("invalid peer \"{}\": {}", peer, e))?;
+                (proto_port, Some(peer.trunc()))
+            }
+            None => (s, None),
+        };
+        let (protocol, ports) = match proto_port.split_once('/') {
+            Some((protocol, ports)) => (protocol, Some(ports)),
+            None => (proto_port, None),
+        };
+
+        let protocol = match protocol {
+            "all" => None,
+            "tcp" | "udp" | "sctp" | "icmp" | "icmpv6" => Some(protocol.to_string()),
+            _ => return Err(format!("unsupported protocol \"{}\"", protocol)),
+        };
+
+        let ports = match ports {
+            Some(ports) => {
+                if !matches!(protocol.as_deref(), Some("tcp" | "udp" | "sctp")) {
+                    return Err("ports can only be used with tcp, udp or sctp".to_string());
+                }
+                let parse = |port: &str| {
+                    port.parse::<u16>()
+                        .map_err(|e| format!("invalid port \"{}\": {}", port, e))
+                };
+                let (start, end) = match ports.split_once('-') {
+                    Some((start, end)) => (parse(start)?, parse(end)?),
+                    None => (parse(ports)?, parse(ports)?),
+                };
+                if start > end {
+                    return Err(format!("invalid port range \"{}\"", ports));
+                }
+                Some((start, end))
+            }
+            None => None,
+        };
+
+        Ok(AllowRule {
+            protocol,
+            ports,
+            peer,
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -52,14 +187,31 @@ pub struct PortForwardConfig<'a> {
     /// subnet associated with the ipv6 address.
     /// Must be set if the v6 address is set.
     pub subnet_v6: Option<ipnet::IpNet>,
+    /// all addresses of the container on the network, the port mappings with
+    /// a container_ip are only forwarded to one of these
+    pub container_ips: Vec<IpAddr>,
     /// port used by DNS that should create forwarding rules
     /// forwarding is not setup if this is 53.
     pub dns_port: u16,
     /// dns servers IPs where forwarding rule to port 53 from dns_port are necessary
     pub dns_server_ips: &'a Vec<IpAddr>,
+    /// count the traffic sent by the container in the accounting chain
+    pub accounting: bool,
+    /// drop the new connections to the published ports above this rate
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Limit of the new connections from one source address to a published port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    /// connections per time unit as understood by iptables, e.g. 10/second
+    pub rate: String,
+    /// connections above the rate which are accepted at once
+    pub burst: u32,
 }
 
 /// IPAMAddresses is used to pass ipam information around
+#[derive(Clone)]
 pub struct IPAMAddresses {
     // ip addresses for netlink
     pub container_addresses: Vec<ipnet::IpNet>,
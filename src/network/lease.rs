@@ -0,0 +1,452 @@
+//! Lease file based ipam, remembers the addresses assigned to a container
+//! so that it gets the same ones on the next setup for the same network.
+//! Networks with the same pool share one lease file, so an address is only
+//! assigned once across all of them.
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use fs2::FileExt;
+use log::debug;
+
+use crate::{
+    error::{ConfigError, NetavarkError, NetavarkResult},
+    network::core_utils,
+    wrap,
+};
+
+use super::{
+    constants,
+    core_utils::{
+        get_ipam_addresses as get_static_ipam_addresses, get_subnet_gateways, subnet_host_range,
+    },
+    driver::DriverInfo,
+    internal_types::IPAMAddresses,
+    types,
+};
+
+const LEASE_DIR: &str = "ipam-lease";
+const POOL_DIR: &str = "pools";
+
+/// container id -> lease
+type Leases = BTreeMap<String, Lease>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Lease {
+    /// leased addresses, one per subnet of the network
+    ips: Vec<IpAddr>,
+    /// released leases are kept so the container gets the same addresses
+    /// again as long as nobody else took them in the meantime
+    #[serde(default)]
+    released: bool,
+}
+
+/// How the addresses of the lease ipam driver are looked up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseMode {
+    /// allocate a new lease if the container has none and record it
+    Acquire,
+    /// the addresses an acquire would return now, the lease file is not changed
+    Preview,
+    /// only the addresses of the active lease, a missing lease results in no addresses
+    Lookup,
+}
+
+/// Get the ipam addresses for the driver, for the lease ipam driver the
+/// addresses are taken from the lease file as selected by the mode.
+pub fn get_ipam_addresses(info: &DriverInfo, mode: LeaseMode) -> NetavarkResult<IPAMAddresses> {
+    if !is_lease_ipam(info.network) {
+        if get_pool(info.network)?.is_some() {
+            return Err(ConfigError::invalid_value(
+                format!("ipam_options.{}", constants::IPAM_OPTION_POOL),
+                format!(
+                    "an address pool requires the {} ipam driver",
+                    constants::IPAM_LEASE
+                ),
+            ));
+        }
+        return get_static_ipam_addresses(info.per_network_opts, info.network);
+    }
+
+    let static_ips = info.per_network_opts.static_ips.as_ref();
+    let ips = match mode {
+        LeaseMode::Acquire => Some(acquire(
+            info.config_dir,
+            info.network,
+            info.container_id,
+            static_ips,
+        )?),
+        LeaseMode::Preview => Some(preview(
+            info.config_dir,
+            info.network,
+            info.container_id,
+            static_ips,
+        )?),
+        LeaseMode::Lookup => {
+            let key = LeaseKey::for_network(info.config_dir, info.network, info.container_id)?;
+            lookup_key(&key)?
+        }
+    };
+
+    match ips {
+        Some(ips) => {
+            let mut opts = info.per_network_opts.clone();
+            opts.static_ips = Some(ips);
+            get_static_ipam_addresses(&opts, info.network)
+        }
+        None => {
+            debug!(
+                "no lease for container {} in network {}",
+                info.container_id, info.network.name
+            );
+            Ok(IPAMAddresses {
+                container_addresses: vec![],
+                dhcp_enabled: false,
+                gateway_addresses: vec![],
+                net_addresses: vec![],
+                nameservers: vec![],
+                ipv6_enabled: false,
+            })
+        }
+    }
+}
+
+/// Release the lease of the container if the network uses the lease ipam driver.
+pub fn release_for(info: &DriverInfo) -> NetavarkResult<()> {
+    if !is_lease_ipam(info.network) {
+        return Ok(());
+    }
+    release_key(&LeaseKey::for_network(
+        info.config_dir,
+        info.network,
+        info.container_id,
+    )?)
+}
+
+pub fn is_lease_ipam(network: &types::Network) -> bool {
+    network
+        .ipam_options
+        .as_ref()
+        .and_then(|map| map.get("driver"))
+        .map(|driver| driver == constants::IPAM_LEASE)
+        .unwrap_or(false)
+}
+
+/// Id of the address pool of the network, it is used as file name.
+fn get_pool(network: &types::Network) -> NetavarkResult<Option<&str>> {
+    let pool = match network
+        .ipam_options
+        .as_ref()
+        .and_then(|map| map.get(constants::IPAM_OPTION_POOL))
+    {
+        Some(pool) => pool,
+        None => return Ok(None),
+    };
+    let valid = !pool.is_empty()
+        && !pool.starts_with('.')
+        && pool
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if !valid {
+        return Err(ConfigError::invalid_value(
+            format!("ipam_options.{}", constants::IPAM_OPTION_POOL),
+            format!(
+                "invalid pool \"{}\": only letters, digits, '-', '_' and '.' are allowed",
+                pool
+            ),
+        ));
+    }
+    Ok(Some(pool))
+}
+
+/// Where the lease of a container in a network is kept. The networks of a
+/// pool share the lease file, their leases are keyed by network as well.
+struct LeaseKey {
+    path: PathBuf,
+    key: String,
+}
+
+impl LeaseKey {
+    fn network(config_dir: &str, network_name: &str, container_id: &str) -> Self {
+        LeaseKey {
+            path: lease_path(config_dir, network_name),
+            key: container_id.to_string(),
+        }
+    }
+
+    fn for_network(
+        config_dir: &str,
+        network: &types::Network,
+        container_id: &str,
+    ) -> NetavarkResult<Self> {
+        Ok(match get_pool(network)? {
+            Some(pool) => LeaseKey {
+                path: Path::new(config_dir)
+                    .join(LEASE_DIR)
+                    .join(POOL_DIR)
+                    .join(format!("{}.json", pool)),
+                key: format!("{}/{}", network.name, container_id),
+            },
+            None => Self::network(config_dir, &network.name, container_id),
+        })
+    }
+}
+
+/// Assign one address per subnet to the container and record it in the lease file.
+/// Static ips are recorded as given, otherwise the previous lease of the container
+/// is reused when it is still free. New addresses are picked from the subnet,
+/// preferring addresses which are not remembered for released leases of other containers.
+pub fn acquire(
+    config_dir: &str,
+    network: &types::Network,
+    container_id: &str,
+    static_ips: Option<&Vec<IpAddr>>,
+) -> NetavarkResult<Vec<IpAddr>> {
+    core_utils::check_read_only(format_args!(
+        "lease an address of network {} for container {}",
+        network.name, container_id
+    ))?;
+    let key = LeaseKey::for_network(config_dir, network, container_id)?;
+    // the lock is held until the new lease is written, so concurrent setups
+    // of all networks of the pool never hand out the same address
+    let mut file = LeaseFile::open(key.path.clone())?;
+    let mut leases = file.read()?;
+    let ips = assign(&mut leases, key.key, network, container_id, static_ips)?;
+    file.write(&leases)?;
+    Ok(ips)
+}
+
+/// Get the addresses an acquire would assign to the container now. Neither
+/// the lease file nor its directory are created.
+fn preview(
+    config_dir: &str,
+    network: &types::Network,
+    container_id: &str,
+    static_ips: Option<&Vec<IpAddr>>,
+) -> NetavarkResult<Vec<IpAddr>> {
+    let key = LeaseKey::for_network(config_dir, network, container_id)?;
+    let mut leases = if key.path.exists() {
+        LeaseFile::open(key.path.clone())?.read()?
+    } else {
+        Leases::new()
+    };
+    assign(&mut leases, key.key, network, container_id, static_ips)
+}
+
+/// Pick the addresses of the container and update its lease in `leases`.
+fn assign(
+    leases: &mut Leases,
+    key: String,
+    network: &types::Network,
+    container_id: &str,
+    static_ips: Option<&Vec<IpAddr>>,
+) -> NetavarkResult<Vec<IpAddr>> {
+    let previous = leases
+        .remove(&key)
+        .map(|lease| lease.ips)
+        .unwrap_or_default();
+    let used: HashSet<IpAddr> = leases
+        .values()
+        .filter(|lease| !lease.released)
+        .flat_map(|lease| lease.ips.iter().copied())
+        .collect();
+    let remembered: HashSet<IpAddr> = leases
+        .values()
+        .flat_map(|lease| lease.ips.iter().copied())
+        .collect();
+
+    let ips = match static_ips {
+        Some(ips) => ips.clone(),
+        None => {
+            let gateways = get_subnet_gateways(network)?;
+            let mut ips = Vec::new();
+            for (subnet, gateway) in network.subnets.iter().flatten().zip(gateways) {
+                let ip = match previous
+                    .iter()
+                    .find(|ip| subnet.subnet.contains(*ip) && !used.contains(ip))
+                {
+                    Some(ip) => *ip,
+                    None => next_free_ip(subnet, gateway, &remembered)
+                        .or_else(|| next_free_ip(subnet, gateway, &used))
+                        .ok_or_else(|| {
+                            NetavarkError::Message(format!(
+                                "no free ip address left in subnet {} of network {}",
+                                subnet.subnet, network.name
+                            ))
+                        })?,
+                };
+                ips.push(ip);
+            }
+            ips
+        }
+    };
+
+    // forget released leases which lost their addresses to this container
+    leases.retain(|_, lease| !lease.released || !lease.ips.iter().any(|ip| ips.contains(ip)));
+
+    debug!(
+        "lease {:?} for container {} in network {}",
+        ips, container_id, network.name
+    );
+    leases.insert(
+        key,
+        Lease {
+            ips: ips.clone(),
+            released: false,
+        },
+    );
+    Ok(ips)
+}
+
+/// Get the addresses of the active lease of the container without changing the lease file.
+pub fn lookup(
+    config_dir: &str,
+    network: &types::Network,
+    container_id: &str,
+) -> NetavarkResult<Option<Vec<IpAddr>>> {
+    lookup_key(&LeaseKey::for_network(config_dir, network, container_id)?)
+}
+
+fn lookup_key(key: &LeaseKey) -> NetavarkResult<Option<Vec<IpAddr>>> {
+    if !key.path.exists() {
+        return Ok(None);
+    }
+    let mut file = LeaseFile::open(key.path.clone())?;
+    Ok(file
+        .read()?
+        .remove(&key.key)
+        .filter(|lease| !lease.released)
+        .map(|lease| lease.ips))
+}
+
+/// Release the lease of the container so its addresses can be reassigned.
+pub fn release(
+    config_dir: &str,
+    network: &types::Network,
+    container_id: &str,
+) -> NetavarkResult<()> {
+    release_key(&LeaseKey::for_network(config_dir, network, container_id)?)
+}
+
+fn release_key(key: &LeaseKey) -> NetavarkResult<()> {
+    if !key.path.exists() {
+        return Ok(());
+    }
+    core_utils::check_read_only(format_args!(
+        "release the lease {} in {:?}",
+        key.key, key.path
+    ))?;
+    let mut file = LeaseFile::open(key.path.clone())?;
+    let mut leases = file.read()?;
+    match leases.get_mut(&key.key) {
+        Some(lease) if !lease.released => {
+            debug!("release lease {} in {:?}", key.key, key.path);
+            lease.released = true;
+            file.write(&leases)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn lease_path(config_dir: &str, network_name: &str) -> PathBuf {
+    Path::new(config_dir)
+        .join(LEASE_DIR)
+        .join(format!("{}.json", network_name))
+}
+
+/// Lease file which is exclusively locked as long as it is open.
+struct LeaseFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl LeaseFile {
+    fn open(path: PathBuf) -> NetavarkResult<Self> {
+        if let Some(dir) = path.parent() {
+            wrap!(
+                fs::create_dir_all(dir),
+                format!("create lease directory {:?}", dir)
+            )?;
+        }
+        let file = wrap!(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path),
+            format!("open lease file {:?}", path)
+        )?;
+        // the lock is released when the file is closed
+        wrap!(file.lock_exclusive(), format!("lock lease file {:?}", path))?;
+        Ok(LeaseFile { path, file })
+    }
+
+    fn read(&mut self) -> NetavarkResult<Leases> {
+        let mut content = String::new();
+        wrap!(
+            self.file.read_to_string(&mut content),
+            format!("read lease file {:?}", self.path)
+        )?;
+        if content.trim().is_empty() {
+            return Ok(Leases::new());
+        }
+        serde_json::from_str(&content).map_err(|e| {
+            NetavarkError::Message(format!("failed to parse lease file {:?}: {}", self.path, e))
+        })
+    }
+
+    fn write(&mut self, leases: &Leases) -> NetavarkResult<()> {
+        let content = serde_json::to_string(leases)
+            .map_err(|e| NetavarkError::Message(format!("failed to encode leases: {}", e)))?;
+        wrap!(
+            self.file
+                .set_len(0)
+                .and_then(|_| self.file.seek(SeekFrom::Start(0)))
+                .and_then(|_| self.file.write_all(content.as_bytes())),
+            format!("write lease file {:?}", self.path)
+        )
+    }
+}
+
+/// First address of the subnet or its lease range which is neither
+/// the gateway nor used by another container.
+fn next_free_ip(
+    subnet: &types::Subnet,
+    gateway: Option<IpAddr>,
+    used: &HashSet<IpAddr>,
+) -> Option<IpAddr> {
+    let net = subnet.subnet.trunc();
+    let (first, last) = subnet_host_range(&net);
+    let (mut start, mut end) = (ip_to_u128(first), ip_to_u128(last));
+
+    if let Some(range) = &subnet.lease_range {
+        let parse = |ip: &Option<String>| {
+            ip.as_ref()
+                .and_then(|ip| IpAddr::from_str(ip).ok())
+                .filter(|ip| net.contains(ip))
+                .map(ip_to_u128)
+        };
+        start = parse(&range.start_ip).unwrap_or(start).max(start);
+        end = parse(&range.end_ip).unwrap_or(end).min(end);
+    }
+
+    (start..=end)
+        .map(|i| match net {
+            ipnet::IpNet::V4(_) => IpAddr::V4(Ipv4Addr::from(i as u32)),
+            ipnet::IpNet::V6(_) => IpAddr::V6(Ipv6Addr::from(i)),
+        })
+        .find(|ip| Some(*ip) != gateway && !used.contains(ip))
+}
+
+fn ip_to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip) as u128,
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::{NetavarkError, NetavarkResult};
-use crate::network::types::NetAddress;
+use crate::network::{core_utils, types::NetAddress};
 use ipnet::IpNet;
 use std::net::IpAddr;
 use std::str::FromStr;
@@ -31,6 +31,10 @@ pub fn get_dhcp_lease(
     ns_path: &str,
     container_macvlan_mac: &str,
 ) -> NetavarkResult<Vec<NetAddress>> {
+    core_utils::check_read_only(format_args!(
+        "request a dhcp lease for {}",
+        container_network_interface
+    ))?;
     let nvp_config = NetworkConfig {
         host_iface: host_network_interface.to_string(),
         // TODO add in domain name support
@@ -99,6 +103,10 @@ pub fn release_dhcp_lease(
     ns_path: &str,
     container_macvlan_mac: &str,
 ) -> NetavarkResult<()> {
+    core_utils::check_read_only(format_args!(
+        "release the dhcp lease of {}",
+        container_network_interface
+    ))?;
     let nvp_config = NetworkConfig {
         host_iface: host_network_interface.to_string(),
         // TODO add in domain name support
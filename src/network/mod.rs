@@ -1,34 +1,336 @@
 pub mod types;
 pub mod validation;
 use std::{
+    collections::{BTreeMap, HashMap},
+    env,
     fs::File,
-    io::{self, BufReader},
+    io::{self, BufReader, Read},
+    path::Path,
 };
 
-use crate::{
-    error::{NetavarkError, NetavarkResult},
-    wrap,
-};
+use log::debug;
+
+use crate::error::{ConfigError, ErrorWrap, NetavarkError, NetavarkResult};
 pub mod bridge;
+pub mod connectivity;
+pub mod conntrack;
 pub mod constants;
 pub mod core_utils;
 pub mod driver;
 pub mod internal_types;
+pub mod lease;
 mod macvlan_dhcp;
 pub mod netlink;
 pub mod plugin;
+pub mod port_proxy;
+pub mod response;
+pub mod script;
+pub mod state;
 pub mod vlan;
 
+/// How lists are combined when an override config is merged into a base config.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListMerge {
+    /// the items of the override are added after the ones of the base
+    Append,
+    /// the list of the override replaces the one of the base
+    Replace,
+}
+
 impl types::NetworkOptions {
     pub fn load(path: Option<String>) -> NetavarkResult<types::NetworkOptions> {
-        wrap!(Self::load_inner(path), "failed to load network options")
+        let mut opts = Self::load_inner(path).wrap("failed to load network options")?;
+        opts.apply_default_options(env::vars());
+        Ok(opts)
+    }
+
+    /// Load a base config and deep merge an override config into it, see
+    /// load_merged_with(). Lists of the override replace the ones of the base.
+    pub fn load_merged(base: &str, override_path: &str) -> NetavarkResult<types::NetworkOptions> {
+        Self::load_merged_with(base, override_path, ListMerge::Replace)
+    }
+
+    /// Load a base config and deep merge an override config into it. Maps are
+    /// merged key by key, every other value of the override wins and a null
+    /// removes the value of the base. Neither file has to be complete on its own,
+    /// only the merged config is decoded and validated.
+    pub fn load_merged_with(
+        base: &str,
+        override_path: &str,
+        lists: ListMerge,
+    ) -> NetavarkResult<types::NetworkOptions> {
+        let mut merged =
+            read_value(base).wrap(format!("failed to load network options from {}", base))?;
+        let layer = read_value(override_path).wrap(format!(
+            "failed to load network options from {}",
+            override_path
+        ))?;
+        merge_value(&mut merged, layer, lists);
+        let mut opts: types::NetworkOptions = serde_json::from_value(merged)
+            .map_err(|e| {
+                NetavarkError::Config(ConfigError::Decode {
+                    message: e.to_string(),
+                    line: None,
+                    column: None,
+                })
+            })
+            .wrap("failed to load merged network options")?;
+        opts.validate_merged()?;
+        opts.apply_default_options(env::vars());
+        Ok(opts)
+    }
+
+    /// The base and the override may each name a network the other one
+    /// configures, so the merged config must have both parts of every network.
+    fn validate_merged(&self) -> NetavarkResult<()> {
+        for name in self.network_info.keys() {
+            if !self.networks.contains_key(name) {
+                return Err(ConfigError::missing_field(
+                    format!("networks.{}", name),
+                    format!("network {} has no per network options", name),
+                ));
+            }
+        }
+        for name in self.networks.keys() {
+            if !self.network_info.contains_key(name) {
+                return Err(ConfigError::missing_field(
+                    format!("network_info.{}", name),
+                    format!("network {} has no network info", name),
+                ));
+            }
+        }
+        self.validate_interface_indexes()?;
+        self.validate_port_container_ips()
+    }
+
+    /// The container_ip of a port mapping must be in a subnet of one of the
+    /// networks, the driver of that network checks that it is assigned.
+    pub fn validate_port_container_ips(&self) -> NetavarkResult<()> {
+        for mapping in self.port_mappings.iter().flatten() {
+            let ip = match mapping.container_ip {
+                Some(ip) => ip,
+                None => continue,
+            };
+            let in_subnet = self
+                .network_info
+                .values()
+                .flat_map(|network| network.subnets.iter().flatten())
+                .any(|s| s.subnet.contains(&ip));
+            if !in_subnet {
+                return Err(ConfigError::invalid_value(
+                    "port_mappings",
+                    format!(
+                        "container_ip {} of port {} is not in a subnet of the container networks",
+                        ip, mapping.host_port
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in the network options which are not set in the config from the
+    /// NETAVARK_DEFAULT_<OPTION> and NETAVARK_DEFAULT_<DRIVER>_<OPTION> variables,
+    /// the driver specific variable wins over the generic one. An option is
+    /// also set when one of its aliases is, plugin networks get no defaults.
+    pub fn apply_default_options(&mut self, vars: impl IntoIterator<Item = (String, String)>) {
+        let drivers = [
+            constants::DRIVER_BRIDGE,
+            constants::DRIVER_MACVLAN,
+            constants::DRIVER_IPVLAN,
+        ];
+        // sorted so that a default and its alias always resolve the same way
+        let mut generic: BTreeMap<String, String> = BTreeMap::new();
+        let mut per_driver: HashMap<&str, BTreeMap<String, String>> = HashMap::new();
+        for (key, value) in vars {
+            let name = match key.strip_prefix(constants::DEFAULT_OPTION_ENV_PREFIX) {
+                Some(name) if !name.is_empty() && !value.is_empty() => name.to_lowercase(),
+                _ => continue,
+            };
+            match drivers
+                .iter()
+                .find(|driver| name.starts_with(&format!("{}_", driver)))
+            {
+                Some(driver) => {
+                    per_driver
+                        .entry(driver)
+                        .or_default()
+                        .insert(name[driver.len() + 1..].to_string(), value);
+                }
+                None => {
+                    generic.insert(name, value);
+                }
+            }
+        }
+        if generic.is_empty() && per_driver.is_empty() {
+            return;
+        }
+
+        for network in self.network_info.values_mut() {
+            if !drivers.contains(&network.driver.as_str()) {
+                continue;
+            }
+            let defaults: Vec<(&String, &String)> = per_driver
+                .get(network.driver.as_str())
+                .into_iter()
+                .flatten()
+                .chain(generic.iter())
+                .collect();
+            if defaults.is_empty() {
+                continue;
+            }
+            let options = network.options.get_or_insert_with(HashMap::new);
+            for (name, value) in defaults {
+                if !is_option_set(options, name) {
+                    debug!(
+                        "using default {}={} for network {}",
+                        name, value, network.name
+                    );
+                    options.insert(name.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    /// Names of the networks in the order in which their interfaces are set up,
+    /// by interface_index first and then by name. The maps have no stable order
+    /// so this keeps the interfaces of the same config in the same order.
+    pub fn network_order(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = self.network_info.keys().collect();
+        names.sort_by_key(|name| {
+            let index = self
+                .networks
+                .get(*name)
+                .and_then(|opts| opts.interface_index);
+            // networks without an index go last
+            (index.is_none(), index, *name)
+        });
+        names
+    }
+
+    /// Every interface_index may only be used by one network.
+    pub fn validate_interface_indexes(&self) -> NetavarkResult<()> {
+        let mut used: HashMap<u32, &String> = HashMap::new();
+        for name in self.network_order() {
+            let index = match self.networks.get(name).and_then(|o| o.interface_index) {
+                Some(index) => index,
+                None => continue,
+            };
+            if let Some(other) = used.insert(index, name) {
+                return Err(ConfigError::invalid_value(
+                    format!("networks.{}.interface_index", name),
+                    format!(
+                        "interface_index {} is used by the networks {} and {}",
+                        index, other, name
+                    ),
+                ));
+            }
+        }
+        Ok(())
     }
 
-    fn load_inner(path: Option<String>) -> Result<types::NetworkOptions, io::Error> {
+    fn load_inner(path: Option<String>) -> NetavarkResult<types::NetworkOptions> {
         let opts = match path {
-            Some(path) => serde_json::from_reader(BufReader::new(File::open(path)?)),
-            None => serde_json::from_reader(io::stdin()),
-        }?;
+            Some(path) => {
+                let reader = BufReader::new(File::open(&path)?);
+                if is_yaml_path(&path) {
+                    serde_yaml::from_reader(reader).map_err(yaml_error)?
+                } else {
+                    serde_json::from_reader(reader).map_err(json_error)?
+                }
+            }
+            None => {
+                let mut content = String::new();
+                io::stdin().read_to_string(&mut content)?;
+                parse_stdin_config(&content)?
+            }
+        };
         Ok(opts)
     }
 }
+
+/// Whether the option or one of its aliases is set.
+fn is_option_set(options: &HashMap<String, String>, name: &str) -> bool {
+    constants::OPTION_ALIASES
+        .iter()
+        .find(|aliases| aliases.contains(&name))
+        .map_or(options.contains_key(name), |aliases| {
+            aliases.iter().any(|alias| options.contains_key(*alias))
+        })
+}
+
+/// Parse the config given on stdin. Json is the wire format, so content which
+/// looks like json or is empty reports json errors, yaml is only tried when
+/// other content is not valid json.
+fn parse_stdin_config(content: &str) -> NetavarkResult<types::NetworkOptions> {
+    let trimmed = content.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('{') || trimmed.starts_with('[') {
+        return serde_json::from_str(content).map_err(json_error);
+    }
+    match serde_json::from_str(content) {
+        Ok(opts) => Ok(opts),
+        Err(_) => serde_yaml::from_str(content).map_err(yaml_error),
+    }
+}
+
+fn is_yaml_path(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|ext| ext.to_str()),
+        Some("yaml") | Some("yml")
+    )
+}
+
+/// Read a config file into a generic value, yaml is used for .yaml and .yml files.
+fn read_value(path: &str) -> NetavarkResult<serde_json::Value> {
+    let reader = BufReader::new(File::open(path)?);
+    if is_yaml_path(path) {
+        serde_yaml::from_reader(reader).map_err(yaml_error)
+    } else {
+        serde_json::from_reader(reader).map_err(json_error)
+    }
+}
+
+fn merge_value(base: &mut serde_json::Value, layer: serde_json::Value, lists: ListMerge) {
+    use serde_json::Value;
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                if value.is_null() {
+                    base.remove(&key);
+                    continue;
+                }
+                match base.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value, lists),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(layer)) if lists == ListMerge::Append => {
+            base.extend(layer);
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+fn json_error(err: serde_json::Error) -> NetavarkError {
+    // io errors while reading are not a problem with the config itself
+    if err.is_io() {
+        return NetavarkError::Io(err.into());
+    }
+    NetavarkError::Config(ConfigError::Decode {
+        message: err.to_string(),
+        line: Some(err.line()),
+        column: Some(err.column()),
+    })
+}
+
+fn yaml_error(err: serde_yaml::Error) -> NetavarkError {
+    let location = err.location();
+    NetavarkError::Config(ConfigError::Decode {
+        message: format!("YAML decoding error: {}", err),
+        line: location.as_ref().map(|l| l.line()),
+        column: location.as_ref().map(|l| l.column()),
+    })
+}
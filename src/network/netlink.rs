@@ -1,22 +1,30 @@
 use std::{
-    net::{Ipv4Addr, Ipv6Addr},
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     os::unix::prelude::RawFd,
 };
 
 use crate::{
     error::{ErrorWrap, NetavarkError, NetavarkResult},
-    network::constants,
+    network::{constants, core_utils},
     wrap,
 };
 use log::{info, trace};
 use netlink_packet_core::{
     NetlinkHeader, NetlinkMessage, NetlinkPayload, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL,
-    NLM_F_REQUEST,
+    NLM_F_REPLACE, NLM_F_REQUEST,
 };
 use netlink_packet_route::{
-    nlas::link::{Info, InfoData, InfoKind, Nla},
-    AddressMessage, LinkMessage, RouteMessage, RtnlMessage, AF_INET, AF_INET6, IFF_UP, RTN_UNICAST,
-    RTPROT_STATIC, RTPROT_UNSPEC, RT_SCOPE_UNIVERSE, RT_TABLE_MAIN,
+    nlas::link::{AfSpecBridge, BridgeVlanInfo, Info, InfoBridge, InfoData, InfoKind, Nla},
+    rule::Nla as RuleNla,
+    AddressMessage, LinkMessage, NeighbourMessage, RouteMessage, RtnlMessage, RuleMessage,
+    AF_BRIDGE, AF_INET, AF_INET6, BRIDGE_FLAGS_SELF, FR_ACT_TO_TBL, IFF_UP, IFLA_PROTINFO,
+    NUD_PERMANENT, RTEXT_FILTER_BRVLAN, RTN_UNICAST, RTPROT_STATIC, RTPROT_UNSPEC, RT_SCOPE_LINK,
+    RT_SCOPE_UNIVERSE, RT_TABLE_MAIN, RT_TABLE_UNSPEC,
+};
+use netlink_packet_utils::{
+    nla::{DefaultNla, NLA_F_NESTED},
+    Emitable,
 };
 use netlink_sys::{protocols::NETLINK_ROUTE, SocketAddr};
 
@@ -39,6 +47,28 @@ pub struct CreateLinkOptions {
     pub netns: RawFd,
 }
 
+// not exported by netlink_packet_route, see include/uapi/linux/if_link.h
+const IFLA_BRPORT_MODE: u16 = 4;
+const IFLA_BRPORT_GUARD: u16 = 5;
+const IFLA_BRPORT_LEARNING: u16 = 8;
+
+/// Settings for a port of a bridge, None keeps the kernel default.
+#[derive(Clone, Debug, Default)]
+pub struct BridgePortOptions {
+    /// allow frames to be sent back out of the port they were received on
+    pub hairpin: Option<bool>,
+    /// disable the port when it receives a STP BPDU
+    pub bpdu_guard: Option<bool>,
+    /// learn source mac addresses of frames received on the port
+    pub learning: Option<bool>,
+}
+
+impl BridgePortOptions {
+    pub fn is_empty(&self) -> bool {
+        self.hairpin.is_none() && self.bpdu_guard.is_none() && self.learning.is_none()
+    }
+}
+
 pub enum LinkID {
     ID(u32),
     Name(String),
@@ -57,6 +87,16 @@ pub enum Route {
     },
 }
 
+/// Rule which selects the routing table for the matching traffic
+pub struct Rule {
+    pub table: u32,
+    pub ipv6: bool,
+    /// match the source address
+    pub source: Option<ipnet::IpNet>,
+    /// match the firewall mark with the given mask
+    pub fwmark: Option<(u32, u32)>,
+}
+
 impl std::fmt::Display for Route {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let (dest, gw, metric) = match self {
@@ -161,6 +201,164 @@ impl Socket {
         Ok(())
     }
 
+    /// attach the link to the given master, a master index of 0 detaches it
+    pub fn set_link_master(&mut self, id: u32, master_index: u32) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+        msg.header.index = id;
+        msg.nlas.push(Nla::Master(master_index));
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    pub fn set_link_address(&mut self, id: u32, mac: Vec<u8>) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+        msg.header.index = id;
+        msg.nlas.push(Nla::Address(mac));
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    pub fn set_link_mtu(&mut self, id: u32, mtu: u32) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+        msg.header.index = id;
+        msg.nlas.push(Nla::Mtu(mtu));
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    /// set the IFLA_BRPORT_* attributes on a link which is attached to a bridge
+    pub fn set_bridge_port_options(
+        &mut self,
+        id: u32,
+        options: &BridgePortOptions,
+    ) -> NetavarkResult<()> {
+        let attrs: Vec<DefaultNla> = [
+            (IFLA_BRPORT_MODE, options.hairpin),
+            (IFLA_BRPORT_GUARD, options.bpdu_guard),
+            (IFLA_BRPORT_LEARNING, options.learning),
+        ]
+        .iter()
+        .filter_map(|(kind, val)| val.map(|v| DefaultNla::new(*kind, vec![v as u8])))
+        .collect();
+        let mut protinfo = vec![0; attrs.as_slice().buffer_len()];
+        attrs.as_slice().emit(&mut protinfo);
+
+        let mut msg = LinkMessage::default();
+        msg.header.interface_family = AF_BRIDGE as u8;
+        msg.header.index = id;
+        // the bridge only parses the port attributes when the nested flag is set
+        msg.nlas.push(Nla::Other(DefaultNla::new(
+            IFLA_PROTINFO | NLA_F_NESTED,
+            protinfo,
+        )));
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    /// enable vlan filtering on the bridge, the ports then only forward the
+    /// vlans they are a member of
+    pub fn set_bridge_vlan_filtering(&mut self, id: u32) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+        msg.header.index = id;
+        msg.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::Bridge),
+            Info::Data(InfoData::Bridge(vec![InfoBridge::VlanFiltering(1)])),
+        ]));
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    /// add the vlan to a bridge port (`bridge vlan add`), with `bridge_self`
+    /// the link is the bridge itself
+    pub fn add_bridge_vlan(
+        &mut self,
+        id: u32,
+        vid: u16,
+        flags: u16,
+        bridge_self: bool,
+    ) -> NetavarkResult<()> {
+        let msg = bridge_vlan_msg(id, vid, flags, bridge_self);
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    /// remove the vlan from a bridge port (`bridge vlan del`)
+    pub fn del_bridge_vlan(&mut self, id: u32, vid: u16, bridge_self: bool) -> NetavarkResult<()> {
+        // a RTM_DELLINK of the bridge family only removes the vlan, not the link
+        let msg = bridge_vlan_msg(id, vid, 0, bridge_self);
+        let result = self.make_netlink_request(RtnlMessage::DelLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    /// get the vlans of all ports of the bridge, mapped by the port index
+    pub fn dump_bridge_vlans(&mut self, bridge_id: u32) -> NetavarkResult<HashMap<u32, Vec<u16>>> {
+        let mut msg = LinkMessage::default();
+        msg.header.interface_family = AF_BRIDGE as u8;
+        msg.nlas.push(Nla::ExtMask(RTEXT_FILTER_BRVLAN));
+
+        let results =
+            self.make_netlink_request(RtnlMessage::GetLink(msg), NLM_F_DUMP | NLM_F_ACK)?;
+
+        let mut ports = HashMap::new();
+        for res in results {
+            let link = match res {
+                RtnlMessage::NewLink(m) => m,
+                m => {
+                    return Err(NetavarkError::Message(format!(
+                        "unexpected netlink message type: {}",
+                        m.message_type()
+                    )))
+                }
+            };
+            if !link
+                .nlas
+                .iter()
+                .any(|nla| matches!(nla, Nla::Master(m) if *m == bridge_id))
+            {
+                continue;
+            }
+            let vids = link
+                .nlas
+                .iter()
+                .filter_map(|nla| match nla {
+                    Nla::AfSpecBridge(spec) => Some(spec),
+                    _ => None,
+                })
+                .flatten()
+                .filter_map(|spec| match spec {
+                    AfSpecBridge::VlanInfo(info) => Some(info.vid),
+                    _ => None,
+                })
+                .collect();
+            ports.insert(link.header.index, vids);
+        }
+        Ok(ports)
+    }
+
+    pub fn set_link_alias(&mut self, id: u32, alias: String) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+        msg.header.index = id;
+        msg.nlas.push(Nla::IfAlias(alias));
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
     pub fn del_link(&mut self, id: LinkID) -> NetavarkResult<()> {
         let mut msg = LinkMessage::default();
 
@@ -234,6 +432,39 @@ impl Socket {
         Ok(())
     }
 
+    /// Add a permanent neighbor entry, an existing entry for the ip is replaced.
+    pub fn add_neighbor(&mut self, link_id: u32, ip: &IpAddr, mac: &[u8]) -> NetavarkResult<()> {
+        let mut msg = NeighbourMessage::default();
+        msg.header.ifindex = link_id;
+        msg.header.state = NUD_PERMANENT;
+        let destination = match ip {
+            IpAddr::V4(ip) => {
+                msg.header.family = AF_INET as u8;
+                ip.octets().to_vec()
+            }
+            IpAddr::V6(ip) => {
+                msg.header.family = AF_INET6 as u8;
+                ip.octets().to_vec()
+            }
+        };
+        msg.nlas
+            .push(netlink_packet_route::neighbour::Nla::Destination(
+                destination,
+            ));
+        msg.nlas
+            .push(netlink_packet_route::neighbour::Nla::LinkLocalAddress(
+                mac.to_vec(),
+            ));
+
+        let result = self.make_netlink_request(
+            RtnlMessage::NewNeighbour(msg),
+            NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE,
+        )?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
     pub fn del_addr(&mut self, link_id: u32, addr: &ipnet::IpNet) -> NetavarkResult<()> {
         let msg = Self::create_addr_msg(link_id, addr);
         let result = self.make_netlink_request(RtnlMessage::DelAddress(msg), NLM_F_ACK)?;
@@ -242,10 +473,16 @@ impl Socket {
         Ok(())
     }
 
-    fn create_route_msg(route: &Route) -> RouteMessage {
+    fn create_route_msg(route: &Route, table: u32) -> RouteMessage {
         let mut msg = RouteMessage::default();
 
         msg.header.table = RT_TABLE_MAIN;
+        if table != RT_TABLE_MAIN as u32 {
+            // the header only fits table ids up to 255, the nla overrides it
+            msg.header.table = RT_TABLE_UNSPEC;
+            msg.nlas
+                .push(netlink_packet_route::route::Nla::Table(table));
+        }
         msg.header.protocol = RTPROT_STATIC;
         msg.header.scope = RT_SCOPE_UNIVERSE;
         msg.header.kind = RTN_UNICAST;
@@ -284,7 +521,11 @@ impl Socket {
     }
 
     pub fn add_route(&mut self, route: &Route) -> NetavarkResult<()> {
-        let msg = Self::create_route_msg(route);
+        self.add_route_to_table(route, RT_TABLE_MAIN as u32)
+    }
+
+    pub fn add_route_to_table(&mut self, route: &Route, table: u32) -> NetavarkResult<()> {
+        let msg = Self::create_route_msg(route, table);
 
         let result =
             self.make_netlink_request(RtnlMessage::NewRoute(msg), NLM_F_ACK | NLM_F_CREATE)?;
@@ -294,7 +535,7 @@ impl Socket {
     }
 
     pub fn del_route(&mut self, route: &Route) -> NetavarkResult<()> {
-        let msg = Self::create_route_msg(route);
+        let msg = Self::create_route_msg(route, RT_TABLE_MAIN as u32);
 
         let result = self.make_netlink_request(RtnlMessage::DelRoute(msg), NLM_F_ACK)?;
         expect_netlink_result!(result, 0);
@@ -302,6 +543,96 @@ impl Socket {
         Ok(())
     }
 
+    fn create_link_route_msg(link_id: u32, dest: &ipnet::IpNet) -> RouteMessage {
+        let mut msg = RouteMessage::default();
+
+        msg.header.table = RT_TABLE_MAIN;
+        msg.header.protocol = RTPROT_STATIC;
+        msg.header.scope = RT_SCOPE_LINK;
+        msg.header.kind = RTN_UNICAST;
+        msg.header.destination_prefix_length = dest.prefix_len();
+
+        let dest_vec = match dest.addr() {
+            IpAddr::V4(ip) => {
+                msg.header.address_family = AF_INET as u8;
+                ip.octets().to_vec()
+            }
+            IpAddr::V6(ip) => {
+                msg.header.address_family = AF_INET6 as u8;
+                ip.octets().to_vec()
+            }
+        };
+        msg.nlas
+            .push(netlink_packet_route::route::Nla::Destination(dest_vec));
+        msg.nlas
+            .push(netlink_packet_route::route::Nla::Oif(link_id));
+        msg
+    }
+
+    /// Add a route to `dest` which is directly reachable via the link, without a gateway.
+    pub fn add_link_route(&mut self, link_id: u32, dest: &ipnet::IpNet) -> NetavarkResult<()> {
+        let msg = Self::create_link_route_msg(link_id, dest);
+
+        info!("Adding route (dest: {}, dev: {})", dest, link_id);
+        let result =
+            self.make_netlink_request(RtnlMessage::NewRoute(msg), NLM_F_ACK | NLM_F_CREATE)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    pub fn del_link_route(&mut self, link_id: u32, dest: &ipnet::IpNet) -> NetavarkResult<()> {
+        let msg = Self::create_link_route_msg(link_id, dest);
+
+        let result = self.make_netlink_request(RtnlMessage::DelRoute(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    fn create_rule_msg(rule: &Rule) -> RuleMessage {
+        let mut msg = RuleMessage::default();
+
+        msg.header.family = if rule.ipv6 { AF_INET6 } else { AF_INET } as u8;
+        msg.header.table = RT_TABLE_UNSPEC;
+        msg.header.action = FR_ACT_TO_TBL;
+        msg.nlas.push(RuleNla::Table(rule.table));
+        if let Some(source) = &rule.source {
+            msg.header.src_len = source.prefix_len();
+            msg.nlas.push(RuleNla::Source(match source.addr() {
+                IpAddr::V4(ip) => ip.octets().to_vec(),
+                IpAddr::V6(ip) => ip.octets().to_vec(),
+            }));
+        }
+        if let Some((mark, mask)) = rule.fwmark {
+            msg.nlas.push(RuleNla::FwMark(mark));
+            msg.nlas.push(RuleNla::FwMask(mask));
+        }
+        msg
+    }
+
+    pub fn add_rule(&mut self, rule: &Rule) -> NetavarkResult<()> {
+        let msg = Self::create_rule_msg(rule);
+
+        info!("Adding rule to table {}", rule.table);
+        let result = self.make_netlink_request(
+            RtnlMessage::NewRule(msg),
+            NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL,
+        )?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    pub fn del_rule(&mut self, rule: &Rule) -> NetavarkResult<()> {
+        let msg = Self::create_rule_msg(rule);
+
+        let result = self.make_netlink_request(RtnlMessage::DelRule(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
     pub fn dump_routes(&mut self) -> NetavarkResult<Vec<RouteMessage>> {
         let mut msg = RouteMessage::default();
 
@@ -374,6 +705,31 @@ impl Socket {
         Ok(addresses)
     }
 
+    /// dump the neighbour entries of the given family, AF_UNSPEC returns the
+    /// arp and ndisc entries and AF_BRIDGE the forwarding database
+    pub fn dump_neighbors(&mut self, family: u8) -> NetavarkResult<Vec<NeighbourMessage>> {
+        let mut msg = NeighbourMessage::default();
+        msg.header.family = family;
+
+        let results =
+            self.make_netlink_request(RtnlMessage::GetNeighbour(msg), NLM_F_DUMP | NLM_F_ACK)?;
+
+        let mut neighbors = Vec::with_capacity(results.len());
+
+        for res in results {
+            match res {
+                RtnlMessage::NewNeighbour(m) => neighbors.push(m),
+                m => {
+                    return Err(NetavarkError::Message(format!(
+                        "unexpected netlink message type: {}",
+                        m.message_type()
+                    )))
+                }
+            };
+        }
+        Ok(neighbors)
+    }
+
     pub fn set_up(&mut self, id: LinkID) -> NetavarkResult<()> {
         let mut msg = LinkMessage::default();
 
@@ -399,6 +755,9 @@ impl Socket {
         msg: RtnlMessage,
         flags: u16,
     ) -> NetavarkResult<Vec<RtnlMessage>> {
+        if let Some(change) = message_change(&msg) {
+            core_utils::check_read_only(change)?;
+        }
         self.send(msg, flags).wrap("send to netlink")?;
         self.recv(flags & NLM_F_DUMP == NLM_F_DUMP)
     }
@@ -485,6 +844,28 @@ impl Socket {
     }
 }
 
+/// What the message changes on the host, None for the requests which only read.
+fn message_change(msg: &RtnlMessage) -> Option<&'static str> {
+    match msg {
+        RtnlMessage::GetLink(_)
+        | RtnlMessage::GetAddress(_)
+        | RtnlMessage::GetNeighbour(_)
+        | RtnlMessage::GetRoute(_)
+        | RtnlMessage::GetRule(_) => None,
+        RtnlMessage::NewLink(_) => Some("create a link"),
+        RtnlMessage::SetLink(_) => Some("change a link"),
+        RtnlMessage::DelLink(_) => Some("delete a link"),
+        RtnlMessage::NewAddress(_) => Some("add an address"),
+        RtnlMessage::DelAddress(_) => Some("delete an address"),
+        RtnlMessage::NewRoute(_) => Some("add a route"),
+        RtnlMessage::DelRoute(_) => Some("delete a route"),
+        RtnlMessage::NewNeighbour(_) => Some("add a neighbour"),
+        RtnlMessage::NewRule(_) => Some("add a routing rule"),
+        RtnlMessage::DelRule(_) => Some("delete a routing rule"),
+        _ => Some("send a netlink request"),
+    }
+}
+
 impl CreateLinkOptions {
     pub fn new(name: String, kind: InfoKind) -> Self {
         CreateLinkOptions {
@@ -501,6 +882,22 @@ impl CreateLinkOptions {
     }
 }
 
+fn bridge_vlan_msg(id: u32, vid: u16, flags: u16, bridge_self: bool) -> LinkMessage {
+    let mut info = BridgeVlanInfo::default();
+    info.vid = vid;
+    info.flags = flags;
+    let mut spec = vec![AfSpecBridge::VlanInfo(info)];
+    if bridge_self {
+        spec.insert(0, AfSpecBridge::Flags(BRIDGE_FLAGS_SELF));
+    }
+
+    let mut msg = LinkMessage::default();
+    msg.header.interface_family = AF_BRIDGE as u8;
+    msg.header.index = id;
+    msg.nlas.push(Nla::AfSpecBridge(spec));
+    msg
+}
+
 pub fn parse_create_link_options(msg: &mut LinkMessage, options: CreateLinkOptions) {
     // add link specific data
     let mut link_info_nlas = vec![Info::Kind(options.kind)];
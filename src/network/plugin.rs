@@ -11,6 +11,7 @@ use crate::{
 };
 
 use super::{
+    core_utils,
     driver::{DriverInfo, NetworkDriver},
     types,
 };
@@ -38,6 +39,11 @@ impl NetworkDriver for PluginDriver<'_> {
         &self,
         _netlink_sockets: (&mut super::netlink::Socket, &mut super::netlink::Socket),
     ) -> NetavarkResult<(types::StatusBlock, Option<AardvarkEntry>)> {
+        // the plugin may create its interface at any time
+        let _turn = self
+            .info
+            .link_order
+            .map(|order| order.wait(&self.info.network.name));
         let result = self.exec_plugin(true, self.info.netns_path).wrap(format!(
             "plugin \"{}\" failed",
             &self
@@ -75,6 +81,11 @@ impl NetworkDriver for PluginDriver<'_> {
 
 impl PluginDriver<'_> {
     fn exec_plugin(&self, setup: bool, netns: &str) -> NetavarkResult<Option<types::StatusBlock>> {
+        core_utils::check_read_only(format_args!(
+            "run the {} of plugin {:?}",
+            if setup { "setup" } else { "teardown" },
+            self.path
+        ))?;
         // problem we always need to clone since you can only deserialize owned data,
         // it is not a problem here but for the plugin it is required.
         // If performance becomes a concern we could use two types for it but the
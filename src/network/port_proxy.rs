@@ -0,0 +1,477 @@
+//! Userspace port forwarding for port mappings which cannot use DNAT rules.
+//!
+//! The proxy runs as a separate `netavark port-proxy` process which binds the
+//! host ports and copies the traffic to the container address. Its pid is stored
+//! in the config directory so that teardown can stop it again.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info};
+use nix::{
+    sys::{
+        signal::{self, Signal},
+        socket::{
+            self, listen, setsockopt, socket, sockopt, AddressFamily, SockFlag, SockType,
+            SockaddrStorage,
+        },
+    },
+    unistd::Pid,
+};
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    wrap,
+};
+
+use super::{core_utils, types::PortMapping};
+
+const PORT_PROXY_DIR: &str = "port-proxy";
+/// printed by the proxy process once all ports are bound
+const READY_MESSAGE: &str = "ready";
+/// time after which an idle udp "connection" is dropped
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    Tcp,
+    Udp,
+}
+
+/// A single forwarded port, formatted as `proto,listen_addr,target_addr`
+/// when passed to the proxy process.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyForward {
+    pub protocol: ProxyProtocol,
+    pub listen: SocketAddr,
+    pub target: SocketAddr,
+}
+
+impl fmt::Display for ProxyForward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let proto = match self.protocol {
+            ProxyProtocol::Tcp => "tcp",
+            ProxyProtocol::Udp => "udp",
+        };
+        write!(f, "{},{},{}", proto, self.listen, self.target)
+    }
+}
+
+impl FromStr for ProxyForward {
+    type Err = NetavarkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').collect();
+        if parts.len() != 3 {
+            return Err(NetavarkError::Message(format!(
+                "invalid port forward \"{}\", expected proto,listen_addr,target_addr",
+                s
+            )));
+        }
+        let protocol = match parts[0] {
+            "tcp" => ProxyProtocol::Tcp,
+            "udp" => ProxyProtocol::Udp,
+            p => {
+                return Err(NetavarkError::Message(format!(
+                    "protocol {} is not supported by the port proxy",
+                    p
+                )))
+            }
+        };
+        let parse_addr = |addr: &str| {
+            addr.parse::<SocketAddr>().map_err(|e| {
+                NetavarkError::Message(format!("invalid socket address \"{}\": {}", addr, e))
+            })
+        };
+        Ok(ProxyForward {
+            protocol,
+            listen: parse_addr(parts[1])?,
+            target: parse_addr(parts[2])?,
+        })
+    }
+}
+
+/// Convert the port mappings into the single forwards the proxy has to serve.
+pub fn get_proxy_forwards(
+    port_mappings: &[PortMapping],
+    container_ip_v4: Option<IpAddr>,
+    container_ip_v6: Option<IpAddr>,
+    container_ips: &[IpAddr],
+) -> NetavarkResult<Vec<ProxyForward>> {
+    let mut forwards = Vec::new();
+    for mapping in port_mappings {
+        let (listen_ip, target_ip) = if mapping.container_ip.is_some() {
+            let target = match mapping.target_ip(container_ips, None) {
+                Some(ip) => ip,
+                None => continue,
+            };
+            let listen_ip = if mapping.host_ip.is_empty() {
+                match target {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                }
+            } else {
+                mapping.host_ip.parse().map_err(|_| {
+                    NetavarkError::Message(format!(
+                        "invalid host ip \"{}\" provided for port {}",
+                        mapping.host_ip, mapping.host_port
+                    ))
+                })?
+            };
+            if listen_ip.is_ipv4() != target.is_ipv4() {
+                return Err(NetavarkError::Message(format!(
+                    "host ip {} and container ip {} of port {} are not of the same family",
+                    listen_ip, target, mapping.host_port
+                )));
+            }
+            (listen_ip, target)
+        } else if mapping.host_ip.is_empty() {
+            // like the DNAT rules, both families are forwarded on a dual stack network
+            if let Some(ip) = container_ip_v4 {
+                push_forwards(
+                    &mut forwards,
+                    mapping,
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    ip,
+                )?;
+            }
+            if let Some(ip) = container_ip_v6 {
+                push_forwards(
+                    &mut forwards,
+                    mapping,
+                    IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                    ip,
+                )?;
+            }
+            continue;
+        } else {
+            let host_ip: IpAddr = mapping.host_ip.parse().map_err(|_| {
+                NetavarkError::Message(format!(
+                    "invalid host ip \"{}\" provided for port {}",
+                    mapping.host_ip, mapping.host_port
+                ))
+            })?;
+            let target = if host_ip.is_ipv4() {
+                container_ip_v4
+            } else {
+                container_ip_v6
+            };
+            match target {
+                Some(ip) => (host_ip, ip),
+                None => {
+                    return Err(NetavarkError::Message(format!(
+                    "host ip {} has no container address of the same family to forward port {} to",
+                    host_ip, mapping.host_port
+                )))
+                }
+            }
+        };
+
+        push_forwards(&mut forwards, mapping, listen_ip, target_ip)?;
+    }
+    Ok(forwards)
+}
+
+/// Add the forwards of all protocols and ports of the mapping.
+fn push_forwards(
+    forwards: &mut Vec<ProxyForward>,
+    mapping: &PortMapping,
+    listen_ip: IpAddr,
+    target_ip: IpAddr,
+) -> NetavarkResult<()> {
+    for proto in mapping.protocol.split(',') {
+        let protocol = match proto {
+            "tcp" => ProxyProtocol::Tcp,
+            "udp" => ProxyProtocol::Udp,
+            p => {
+                return Err(NetavarkError::Message(format!(
+                    "protocol {} is not supported by the port proxy",
+                    p
+                )))
+            }
+        };
+        for i in 0..mapping.range.max(1) {
+            forwards.push(ProxyForward {
+                protocol,
+                listen: SocketAddr::new(listen_ip, mapping.host_port + i),
+                target: SocketAddr::new(target_ip, mapping.container_port + i),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn pid_file(config_dir: &str, name: &str) -> PathBuf {
+    Path::new(config_dir)
+        .join(PORT_PROXY_DIR)
+        .join(format!("{}.pid", name))
+}
+
+/// The proxy logs to this file, it must not keep the stderr of our caller open.
+fn log_file(config_dir: &str, name: &str) -> PathBuf {
+    Path::new(config_dir)
+        .join(PORT_PROXY_DIR)
+        .join(format!("{}.log", name))
+}
+
+/// Spawn the proxy process and wait until it has bound all ports.
+pub fn start(config_dir: &str, name: &str, forwards: &[ProxyForward]) -> NetavarkResult<()> {
+    if forwards.is_empty() {
+        return Ok(());
+    }
+    core_utils::check_read_only(format_args!("start the port proxy {}", name))?;
+    let dir = Path::new(config_dir).join(PORT_PROXY_DIR);
+    wrap!(fs::create_dir_all(&dir), "create port proxy directory")?;
+
+    let exe = wrap!(std::env::current_exe(), "get netavark executable")?;
+    let mut cmd = Command::new(exe);
+    cmd.arg("port-proxy");
+    for forward in forwards {
+        cmd.arg("--forward").arg(forward.to_string());
+    }
+    debug!("start port proxy: {:?}", cmd);
+
+    // stdout is only used to signal readiness, the proxy must not keep
+    // the stdout/stderr of our caller open
+    let log = wrap!(
+        File::create(log_file(config_dir, name)),
+        "create port proxy log file"
+    )?;
+    let mut child = wrap!(
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(log)
+            .spawn(),
+        "start port proxy"
+    )?;
+
+    let mut line = String::new();
+    if let Some(stdout) = child.stdout.take() {
+        if let Err(e) = BufReader::new(stdout).read_line(&mut line) {
+            kill_child(&mut child);
+            return Err(NetavarkError::wrap("read port proxy status", e.into()));
+        }
+    }
+    if line.trim() != READY_MESSAGE {
+        let _ = child.wait();
+        return Err(NetavarkError::Message(format!(
+            "port proxy failed to start: {}",
+            line.trim()
+        )));
+    }
+
+    // without the pid file nothing could stop the proxy
+    if let Err(e) = fs::write(pid_file(config_dir, name), child.id().to_string()) {
+        kill_child(&mut child);
+        return Err(NetavarkError::wrap("write port proxy pid file", e.into()));
+    }
+    Ok(())
+}
+
+fn kill_child(child: &mut Child) {
+    if let Err(e) = child.kill() {
+        debug!("failed to kill port proxy {}: {}", child.id(), e);
+    }
+    let _ = child.wait();
+}
+
+/// Stop the proxy process started for the given name, if any.
+pub fn stop(config_dir: &str, name: &str) -> NetavarkResult<()> {
+    let path = pid_file(config_dir, name);
+    let pid = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(NetavarkError::wrap("read port proxy pid file", e.into())),
+    };
+    core_utils::check_read_only(format_args!("stop the port proxy {}", name))?;
+    match pid.trim().parse::<i32>() {
+        Ok(pid) => match signal::kill(Pid::from_raw(pid), Signal::SIGTERM) {
+            Ok(_) => {}
+            // process is already gone
+            Err(nix::errno::Errno::ESRCH) => {}
+            Err(e) => {
+                return Err(NetavarkError::wrap(
+                    format!("stop port proxy {}", pid),
+                    NetavarkError::Io(io::Error::from(e)),
+                ))
+            }
+        },
+        Err(e) => info!("ignoring invalid port proxy pid file {:?}: {}", path, e),
+    }
+    wrap!(fs::remove_file(&path), "remove port proxy pid file")?;
+    match fs::remove_file(log_file(config_dir, name)) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(NetavarkError::wrap("remove port proxy log file", e.into())),
+    }
+    Ok(())
+}
+
+/// Entry point of the proxy process, binds all ports and serves them forever.
+pub fn run(forwards: Vec<ProxyForward>) -> NetavarkResult<()> {
+    let mut handles = Vec::with_capacity(forwards.len());
+    let mut servers: Vec<Box<dyn FnOnce() + Send>> = Vec::with_capacity(forwards.len());
+    for forward in forwards {
+        let target = forward.target;
+        match forward.protocol {
+            ProxyProtocol::Tcp => {
+                let listener: TcpListener = wrap!(
+                    bind(forward.listen, SockType::Stream).map(Into::into),
+                    format!("bind tcp port {}", forward.listen)
+                )?;
+                servers.push(Box::new(move || serve_tcp(listener, target)));
+            }
+            ProxyProtocol::Udp => {
+                let socket: UdpSocket = wrap!(
+                    bind(forward.listen, SockType::Datagram).map(Into::into),
+                    format!("bind udp port {}", forward.listen)
+                )?;
+                servers.push(Box::new(move || serve_udp(socket, target)));
+            }
+        }
+    }
+
+    for server in servers {
+        handles.push(thread::spawn(server));
+    }
+
+    let mut stdout = io::stdout();
+    writeln!(stdout, "{}", READY_MESSAGE)?;
+    stdout.flush()?;
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+    Ok(())
+}
+
+/// Bind the socket of a forward, ipv6 sockets only get ipv6 traffic so that
+/// the ipv4 forward of the same port can be bound next to it.
+fn bind(addr: SocketAddr, ty: SockType) -> io::Result<OwnedFd> {
+    let family = match addr {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    };
+    let fd = socket(family, ty, SockFlag::SOCK_CLOEXEC, None)?;
+    // SAFETY: the fd was just created and is owned by nothing else
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // like the std listeners, allow a restart while old connections linger
+    setsockopt(fd.as_raw_fd(), sockopt::ReuseAddr, &true)?;
+    if addr.is_ipv6() {
+        setsockopt(fd.as_raw_fd(), sockopt::Ipv6V6Only, &true)?;
+    }
+    socket::bind(fd.as_raw_fd(), &SockaddrStorage::from(addr))?;
+    if ty == SockType::Stream {
+        listen(fd.as_raw_fd(), 128)?;
+    }
+    Ok(fd)
+}
+
+fn serve_tcp(listener: TcpListener, target: SocketAddr) {
+    for conn in listener.incoming() {
+        let client = match conn {
+            Ok(c) => c,
+            Err(e) => {
+                debug!("failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            if let Err(e) = proxy_tcp_connection(client, target) {
+                debug!("tcp proxy connection to {} failed: {}", target, e);
+            }
+        });
+    }
+}
+
+fn proxy_tcp_connection(client: TcpStream, target: SocketAddr) -> io::Result<()> {
+    let upstream = TcpStream::connect_timeout(&target, TCP_CONNECT_TIMEOUT)?;
+    let (mut client_read, mut upstream_write) = (client.try_clone()?, upstream.try_clone()?);
+    let forward = thread::spawn(move || {
+        let _ = io::copy(&mut client_read, &mut upstream_write);
+        let _ = upstream_write.shutdown(Shutdown::Write);
+    });
+    let (mut upstream_read, mut client_write) = (upstream, client);
+    let _ = io::copy(&mut upstream_read, &mut client_write);
+    let _ = client_write.shutdown(Shutdown::Write);
+    let _ = forward.join();
+    Ok(())
+}
+
+fn serve_udp(socket: UdpSocket, target: SocketAddr) {
+    let socket = Arc::new(socket);
+    let clients: Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+    let mut buf = [0u8; 65535];
+    loop {
+        let (len, client) = match socket.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(e) => {
+                debug!("failed to receive udp packet: {}", e);
+                continue;
+            }
+        };
+        let upstream = match get_udp_upstream(&socket, &clients, client, target) {
+            Ok(u) => u,
+            Err(e) => {
+                debug!("failed to create udp socket for {}: {}", target, e);
+                continue;
+            }
+        };
+        if let Err(e) = upstream.send(&buf[..len]) {
+            debug!("failed to send udp packet to {}: {}", target, e);
+        }
+    }
+}
+
+/// get the socket connected to the target for this client, each client gets its
+/// own socket so replies can be sent back to the right address
+fn get_udp_upstream(
+    socket: &Arc<UdpSocket>,
+    clients: &Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>>,
+    client: SocketAddr,
+    target: SocketAddr,
+) -> io::Result<Arc<UdpSocket>> {
+    let mut map = clients.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(upstream) = map.get(&client) {
+        return Ok(upstream.clone());
+    }
+
+    let bind_addr: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let upstream = Arc::new(UdpSocket::bind(bind_addr)?);
+    upstream.connect(target)?;
+    upstream.set_read_timeout(Some(UDP_IDLE_TIMEOUT))?;
+    map.insert(client, upstream.clone());
+
+    let (socket, clients, reply) = (socket.clone(), clients.clone(), upstream.clone());
+    thread::spawn(move || {
+        let mut buf = [0u8; 65535];
+        // an error is most likely the idle timeout, in any case forget the client
+        while let Ok(len) = reply.recv(&mut buf) {
+            if let Err(e) = socket.send_to(&buf[..len], client) {
+                debug!("failed to send udp reply to {}: {}", client, e);
+            }
+        }
+        clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&client);
+    });
+    Ok(upstream)
+}
@@ -0,0 +1,98 @@
+//! Serialization of the setup response for a given response format version.
+//!
+//! New fields in the StatusBlock can break older callers, the caller can
+//! therefore ask for a specific response version and we drop every field
+//! which was added after that version.
+
+use std::collections::HashMap;
+
+use crate::error::{NetavarkError, NetavarkResult};
+
+use super::types::{PerNetworkOptions, StatusBlock};
+
+/// The latest response format version, used if the caller does not request one.
+pub const RESPONSE_VERSION_LATEST: u32 = 9;
+
+/// Fields of the StatusBlock together with the response version they were added in.
+/// The path contains the json keys below a single StatusBlock, "*" matches any key
+/// of a map (e.g. the interface name). Fields not listed here are part of version 1.
+const VERSIONED_FIELDS: &[(&[&str], u32)] = &[
+    (&["port_proxies"], 2),
+    (&["default_gateways"], 3),
+    (&["interfaces", "*", "admin_state"], 4),
+    (&["policy_routes"], 5),
+    (&["host_routes"], 6),
+    (&["nat64_prefix"], 7),
+    (&["interface_index"], 8),
+    (&["bridge"], 9),
+];
+
+/// make sure the requested response version is one we know how to produce
+pub fn validate_response_version(version: u32) -> NetavarkResult<()> {
+    if version == 0 || version > RESPONSE_VERSION_LATEST {
+        return Err(NetavarkError::Message(format!(
+            "unsupported response version {}, supported versions are 1 to {}",
+            version, RESPONSE_VERSION_LATEST
+        )));
+    }
+    Ok(())
+}
+
+/// Serialize the setup response into json, only including fields known to the given version.
+pub fn serialize_response(
+    response: &HashMap<String, StatusBlock>,
+    version: u32,
+) -> NetavarkResult<String> {
+    validate_response_version(version)?;
+
+    let mut value = serde_json::to_value(response)?;
+    if let Some(networks) = value.as_object_mut() {
+        for status in networks.values_mut() {
+            for (path, added_in) in VERSIONED_FIELDS {
+                if *added_in > version {
+                    remove_path(status, path);
+                }
+            }
+        }
+    }
+    Ok(serde_json::to_string(&value)?)
+}
+
+/// Key the response by the container interface name instead of the network name.
+/// `networks` are the per network options of the setup with the interface names
+/// which were actually used.
+pub fn key_by_interface(
+    response: HashMap<String, StatusBlock>,
+    networks: &HashMap<String, PerNetworkOptions>,
+) -> HashMap<String, StatusBlock> {
+    response
+        .into_iter()
+        .map(|(network, status)| match networks.get(&network) {
+            Some(opts) => (opts.interface_name.clone(), status),
+            None => (network, status),
+        })
+        .collect()
+}
+
+fn remove_path(value: &mut serde_json::Value, path: &[&str]) {
+    let obj = match value.as_object_mut() {
+        Some(obj) => obj,
+        None => return,
+    };
+    match path {
+        [] => {}
+        [key] => {
+            obj.remove(*key);
+        }
+        ["*", rest @ ..] => {
+            for v in obj.values_mut() {
+                remove_path(v, rest);
+            }
+        }
+        [key, rest @ ..] => {
+            if let Some(v) = obj.get_mut(*key) {
+                remove_path(v, rest);
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Shell script with the commands of a setup, printed by `setup --emit-script`
+//! for hosts where netavark itself cannot be run.
+use std::fmt;
+
+/// Argument which expands to the container namespace path in the script,
+/// it is the only one which is not quoted.
+pub const NETNS: &str = "\"$NETNS\"";
+
+pub struct Script {
+    lines: Vec<String>,
+}
+
+impl Script {
+    pub fn new(container_id: &str, netns_path: &str) -> Self {
+        Script {
+            lines: vec![
+                "#!/bin/sh".to_string(),
+                format!("# netavark setup of container {}", container_id),
+                "set -e".to_string(),
+                format!("NETNS={}", quote(netns_path)),
+            ],
+        }
+    }
+
+    /// Add a comment, the commands which follow belong to it.
+    pub fn comment(&mut self, comment: impl fmt::Display) {
+        self.lines.push(String::new());
+        self.lines.push(format!("# {}", comment));
+    }
+
+    /// Add a command which runs on the host.
+    pub fn host(&mut self, args: &[&str]) {
+        self.lines.push(join(args));
+    }
+
+    /// Add a command which only runs when `check` fails on the host, e.g.
+    /// to create an interface which may already exist.
+    pub fn host_unless(&mut self, check: &[&str], args: &[&str]) {
+        self.lines
+            .push(format!("{} >/dev/null 2>&1 || {}", join(check), join(args)));
+    }
+
+    /// Add a command which runs in the container namespace.
+    pub fn container(&mut self, args: &[&str]) {
+        self.lines
+            .push(format!("nsenter --net={} {}", NETNS, join(args)));
+    }
+
+    /// Add a line as it is, the caller must quote it.
+    pub fn raw(&mut self, line: String) {
+        self.lines.push(line);
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn join(args: &[&str]) -> String {
+    args.iter()
+        .map(|arg| quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quote the argument for the shell when needed.
+pub fn quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+    if safe || arg == NETNS {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
@@ -0,0 +1,373 @@
+//! State files of the set up containers, the gc command uses them to find the
+//! containers whose network namespace is gone without a teardown. The bridges
+//! created by netavark are recorded as well, the teardown only removes these.
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use fs2::FileExt;
+use log::{debug, warn};
+
+use crate::{
+    error::{NetavarkError, NetavarkResult},
+    network::core_utils,
+    wrap,
+};
+
+use super::types::NetworkOptions;
+
+const STATE_DIR: &str = "containers";
+const BRIDGE_DIR: &str = "bridges";
+const LOCK_DIR: &str = "locks";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContainerState {
+    /// namespace path given to the setup
+    pub netns_path: String,
+    /// inode of the namespace, the path may be reused by another namespace
+    pub netns_inode: u64,
+    /// setup input of all networks the container is connected to
+    pub options: NetworkOptions,
+}
+
+/// Record the networks which were set up for the container, the networks of
+/// an earlier setup of the same container are kept.
+pub fn add(config_dir: &str, netns_path: &str, options: &NetworkOptions) -> NetavarkResult<()> {
+    core_utils::check_read_only(format_args!("record container {}", options.container_id))?;
+    let netns_inode = wrap!(
+        fs::metadata(netns_path),
+        format!("stat network namespace {}", netns_path)
+    )?
+    .ino();
+    let mut file = StateFile::open(config_dir, &options.container_id)?;
+    let state = match file.read()? {
+        Some(mut state) if state.netns_inode == netns_inode => {
+            state.options.container_name = options.container_name.clone();
+            state.options.port_mappings = options.port_mappings.clone();
+            state.options.dns_servers = options.dns_servers.clone();
+            state.options.networks.extend(options.networks.clone());
+            state
+                .options
+                .network_info
+                .extend(options.network_info.clone());
+            state
+        }
+        // a new namespace, the old one is gone with all of its networks
+        _ => ContainerState {
+            netns_path: netns_path.to_string(),
+            netns_inode,
+            options: options.clone(),
+        },
+    };
+    file.write(&state)
+}
+
+/// Forget the networks of the container after their teardown, the state file
+/// is removed with the last network.
+pub fn remove(config_dir: &str, container_id: &str, networks: &[String]) -> NetavarkResult<()> {
+    if !state_path(config_dir, container_id).exists() {
+        return Ok(());
+    }
+    core_utils::check_read_only(format_args!("forget container {}", container_id))?;
+    let mut file = StateFile::open(config_dir, container_id)?;
+    let mut state = match file.read()? {
+        Some(state) => state,
+        None => return file.remove(),
+    };
+    for network in networks {
+        state.options.networks.remove(network);
+        state.options.network_info.remove(network);
+    }
+    if state.options.network_info.is_empty() {
+        return file.remove();
+    }
+    file.write(&state)
+}
+
+/// Use the interface names of the recorded setup of the container in the same
+/// namespace, with --interface-conflict=rename the setup picks other names
+/// than the ones of the config.
+pub fn use_recorded_interface_names(
+    config_dir: &str,
+    netns_path: &str,
+    options: &mut NetworkOptions,
+) -> NetavarkResult<()> {
+    if !state_path(config_dir, &options.container_id).exists() {
+        return Ok(());
+    }
+    let state = match StateFile::open(config_dir, &options.container_id)?.read()? {
+        Some(state) => state,
+        None => return Ok(()),
+    };
+    // a state of an earlier namespace does not tell anything about this one
+    if !fs::metadata(netns_path).is_ok_and(|m| m.ino() == state.netns_inode) {
+        return Ok(());
+    }
+    for (network, opts) in options.networks.iter_mut() {
+        if let Some(recorded) = state.options.networks.get(network) {
+            if recorded.interface_name != opts.interface_name {
+                debug!(
+                    "network {} was set up with interface {} instead of {}",
+                    network, recorded.interface_name, opts.interface_name
+                );
+                opts.interface_name = recorded.interface_name.clone();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Get the state of all set up containers, unreadable state files are skipped.
+pub fn list(config_dir: &str) -> NetavarkResult<Vec<ContainerState>> {
+    let dir = Path::new(config_dir).join(STATE_DIR);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(NetavarkError::wrap(format!("read {:?}", dir), e.into())),
+    };
+    let mut states = Vec::new();
+    for entry in entries {
+        let path = wrap!(entry, format!("read {:?}", dir))?.path();
+        let container_id = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(id) if path.extension().is_some_and(|e| e == "json") => id.to_string(),
+            _ => continue,
+        };
+        match StateFile::open(config_dir, &container_id).and_then(|mut f| f.read()) {
+            Ok(Some(state)) => states.push(state),
+            Ok(None) => {}
+            Err(e) => warn!("skipping state of container {}: {}", container_id, e),
+        }
+    }
+    Ok(states)
+}
+
+/// Check if the namespace of the container still exists. It is kept alive by
+/// a bind mount, a process in it or an open file descriptor, so besides the
+/// path all processes which can be inspected are searched for it.
+pub fn namespace_exists(state: &ContainerState) -> bool {
+    if fs::metadata(&state.netns_path).is_ok_and(|m| m.ino() == state.netns_inode) {
+        return true;
+    }
+    let target = format!("net:[{}]", state.netns_inode);
+    let procs = match fs::read_dir("/proc") {
+        Ok(procs) => procs,
+        // without /proc nothing can be proven
+        Err(_) => return true,
+    };
+    for proc in procs.flatten() {
+        let path = proc.path();
+        if !proc
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            continue;
+        }
+        // the process may exit at any time and the ones of other users
+        // are not readable, both are skipped
+        let is_target =
+            |p: PathBuf| fs::read_link(p).is_ok_and(|l| l.as_os_str() == target.as_str());
+        if is_target(path.join("ns/net")) {
+            return true;
+        }
+        if let Ok(fds) = fs::read_dir(path.join("fd")) {
+            if fds.flatten().any(|fd| is_target(fd.path())) {
+                return true;
+            }
+        }
+        // nsfs bind mounts show the namespace as root of the mount
+        if fs::read_to_string(path.join("mountinfo")).is_ok_and(|m| {
+            m.lines()
+                .any(|line| line.split(' ').nth(3) == Some(target.as_str()))
+        }) {
+            return true;
+        }
+    }
+    debug!(
+        "network namespace {} ({}) no longer exists",
+        state.netns_path, target
+    );
+    false
+}
+
+/// Path of the file which records that netavark created the bridge.
+pub fn bridge_path(config_dir: &str, name: &str) -> PathBuf {
+    Path::new(config_dir).join(BRIDGE_DIR).join(name)
+}
+
+/// Record that netavark created the bridge. The host namespace and the index
+/// of the bridge are part of it, so a bridge with the same name which was
+/// created by someone else later is not taken for ours.
+pub fn add_bridge(config_dir: &str, name: &str, index: u32) -> NetavarkResult<()> {
+    core_utils::check_read_only(format_args!("record bridge {}", name))?;
+    let path = bridge_path(config_dir, name);
+    if let Some(dir) = path.parent() {
+        wrap!(
+            fs::create_dir_all(dir),
+            format!("create state directory {:?}", dir)
+        )?;
+    }
+    let netns_inode = host_netns_inode()?;
+    wrap!(
+        fs::write(&path, format!("{} {}\n", netns_inode, index)),
+        format!("write bridge state {:?}", path)
+    )
+}
+
+/// Check if the bridge with the given index was created by netavark.
+pub fn is_own_bridge(config_dir: &str, name: &str, index: u32) -> bool {
+    let content = match fs::read_to_string(bridge_path(config_dir, name)) {
+        Ok(content) => content,
+        Err(_) => return false,
+    };
+    match host_netns_inode() {
+        Ok(netns_inode) => content.trim() == format!("{} {}", netns_inode, index),
+        Err(e) => {
+            warn!("cannot check the owner of bridge {}: {}", name, e);
+            false
+        }
+    }
+}
+
+/// Names of the bridges recorded as created by netavark.
+pub fn list_bridges(config_dir: &str) -> NetavarkResult<Vec<String>> {
+    let dir = Path::new(config_dir).join(BRIDGE_DIR);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(NetavarkError::wrap(format!("read {:?}", dir), e.into())),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = wrap!(entry, format!("read {:?}", dir))?;
+        if let Some(name) = entry.file_name().to_str() {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Lock the setup of the bridge against parallel setups of other networks
+/// which use the same bridge, the lock is released when the file is closed.
+/// Nothing is locked in read-only mode, the setup does not change anything.
+pub fn lock_bridge(config_dir: &str, name: &str) -> NetavarkResult<Option<File>> {
+    if core_utils::is_read_only() {
+        return Ok(None);
+    }
+    let dir = Path::new(config_dir).join(LOCK_DIR);
+    wrap!(
+        fs::create_dir_all(&dir),
+        format!("create lock directory {:?}", dir)
+    )?;
+    let path = dir.join(format!("bridge-{}", name));
+    let file = wrap!(
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path),
+        format!("open lock file {:?}", path)
+    )?;
+    wrap!(file.lock_exclusive(), format!("lock bridge {}", name))?;
+    Ok(Some(file))
+}
+
+/// Forget the bridge after it was removed.
+pub fn remove_bridge(config_dir: &str, name: &str) -> NetavarkResult<()> {
+    let path = bridge_path(config_dir, name);
+    if !path.exists() {
+        return Ok(());
+    }
+    core_utils::check_read_only(format_args!("forget bridge {}", name))?;
+    match fs::remove_file(&path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(NetavarkError::wrap(
+            format!("remove bridge state {:?}", path),
+            e.into(),
+        )),
+    }
+}
+
+fn host_netns_inode() -> NetavarkResult<u64> {
+    // the namespace of this thread, others may be in a container namespace
+    Ok(wrap!(
+        fs::metadata("/proc/thread-self/ns/net"),
+        "stat host network namespace"
+    )?
+    .ino())
+}
+
+fn state_path(config_dir: &str, container_id: &str) -> PathBuf {
+    Path::new(config_dir)
+        .join(STATE_DIR)
+        .join(format!("{}.json", container_id))
+}
+
+/// State file which is exclusively locked as long as it is open.
+struct StateFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl StateFile {
+    fn open(config_dir: &str, container_id: &str) -> NetavarkResult<Self> {
+        let path = state_path(config_dir, container_id);
+        if let Some(dir) = path.parent() {
+            wrap!(
+                fs::create_dir_all(dir),
+                format!("create state directory {:?}", dir)
+            )?;
+        }
+        let file = wrap!(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path),
+            format!("open state file {:?}", path)
+        )?;
+        // the lock is released when the file is closed
+        wrap!(file.lock_exclusive(), format!("lock state file {:?}", path))?;
+        Ok(StateFile { path, file })
+    }
+
+    fn read(&mut self) -> NetavarkResult<Option<ContainerState>> {
+        let mut content = String::new();
+        wrap!(
+            self.file.read_to_string(&mut content),
+            format!("read state file {:?}", self.path)
+        )?;
+        if content.trim().is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_str(&content).map(Some).map_err(|e| {
+            NetavarkError::Message(format!("failed to parse state file {:?}: {}", self.path, e))
+        })
+    }
+
+    fn write(&mut self, state: &ContainerState) -> NetavarkResult<()> {
+        let content = serde_json::to_string(state)
+            .map_err(|e| NetavarkError::Message(format!("failed to encode state: {}", e)))?;
+        wrap!(
+            self.file
+                .set_len(0)
+                .and_then(|_| self.file.seek(SeekFrom::Start(0)))
+                .and_then(|_| self.file.write_all(content.as_bytes())),
+            format!("write state file {:?}", self.path)
+        )
+    }
+
+    fn remove(self) -> NetavarkResult<()> {
+        wrap!(
+            fs::remove_file(&self.path),
+            format!("remove state file {:?}", self.path)
+        )
+    }
+}
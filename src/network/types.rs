@@ -108,6 +108,18 @@ pub struct PerNetworkOptions {
     /// MAC address for the container interface.
     #[serde(rename = "static_mac")]
     pub static_mac: Option<String>,
+
+    /// Name of an existing veth on the host which should be used instead of
+    /// creating a new veth pair. Its peer is moved into the container namespace.
+    /// Only supported by the bridge driver.
+    #[serde(rename = "existing_host_veth")]
+    pub existing_host_veth: Option<String>,
+
+    /// Position of the interface in the order in which the interfaces of the
+    /// container are created, lower indexes first. Networks without an index
+    /// are set up after them sorted by network name.
+    #[serde(rename = "interface_index")]
+    pub interface_index: Option<u32>,
 }
 
 /// PortMapping is one or more ports that will be mapped into the container.
@@ -145,6 +157,30 @@ pub struct PortMapping {
     /// 65536.
     #[serde(rename = "range")]
     pub range: u16,
+
+    /// ContainerIP selects the address of the container the port is
+    /// forwarded to when it has several. It must be assigned to the container
+    /// and the port is only forwarded on the network with this address.
+    /// If unset, the first address of each network is used.
+    #[serde(
+        rename = "container_ip",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub container_ip: Option<IpAddr>,
+}
+
+impl PortMapping {
+    /// Address the port is forwarded to on a network where the container has
+    /// `container_ips`, None when the mapping selects an address of another
+    /// network.
+    pub fn target_ip(&self, container_ips: &[IpAddr], default: Option<IpAddr>) -> Option<IpAddr> {
+        match self.container_ip {
+            Some(ip) if container_ips.contains(&ip) => Some(ip),
+            Some(_) => None,
+            None => default,
+        }
+    }
 }
 
 /// StatusBlock contains the network information about a container
@@ -166,6 +202,105 @@ pub struct StatusBlock {
     /// The map key is the interface name.
     #[serde(rename = "interfaces")]
     pub interfaces: Option<HashMap<String, NetInterface>>,
+
+    /// Port mappings which are served by the userspace port proxy
+    /// instead of DNAT rules.
+    #[serde(
+        rename = "port_proxies",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub port_proxies: Option<Vec<PortMapping>>,
+
+    /// Gateways of the default routes via this network which are used by
+    /// the container, i.e. have the lowest metric of all its default routes.
+    #[serde(
+        rename = "default_gateways",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub default_gateways: Option<Vec<IpAddr>>,
+
+    /// Default routes in separate routing tables which were added for
+    /// policy routing, see the gateways and policy_routes options.
+    #[serde(
+        rename = "policy_routes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub policy_routes: Option<Vec<PolicyRoute>>,
+
+    /// Routes to the container addresses which were added on the host,
+    /// only set for bridge networks in routed mode.
+    #[serde(
+        rename = "host_routes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub host_routes: Option<Vec<HostRoute>>,
+
+    /// Prefix which the container can use to reach ipv4 addresses via the
+    /// NAT64 translator on the host, see the nat64 option.
+    #[serde(
+        rename = "nat64_prefix",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub nat64_prefix: Option<IpNet>,
+
+    /// Position of the network in the order in which the interfaces of the
+    /// container were created, starting at 0.
+    #[serde(
+        rename = "interface_index",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub interface_index: Option<u32>,
+
+    /// Bridge the container interface is attached to, only set for bridge
+    /// networks in l2_only mode which have no addresses to report.
+    #[serde(rename = "bridge", default, skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<String>,
+}
+
+/// HostRoute is a route on the host to a single container address.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostRoute {
+    #[serde(rename = "destination")]
+    pub destination: IpNet,
+
+    /// host interface the route points to
+    #[serde(rename = "interface")]
+    pub interface: String,
+}
+
+/// PolicyRoute is a default route via a named gateway in its own routing
+/// table, the table is used for the traffic matching the source or fwmark.
+/// Without either no rule is added and the container selects the table itself.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyRoute {
+    /// name of the gateway in the gateways option
+    #[serde(rename = "gateway_name")]
+    pub gateway_name: String,
+
+    #[serde(rename = "gateway")]
+    pub gateway: IpAddr,
+
+    /// routing table id
+    #[serde(rename = "table")]
+    pub table: u32,
+
+    /// use the table for traffic from this subnet
+    #[serde(rename = "source", default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpNet>,
+
+    /// use the table for traffic with this firewall mark
+    #[serde(rename = "fwmark", default, skip_serializing_if = "Option::is_none")]
+    pub fwmark: Option<u32>,
+
+    /// mask applied to the firewall mark before it is compared
+    #[serde(rename = "fwmask", default, skip_serializing_if = "Option::is_none")]
+    pub fwmask: Option<u32>,
 }
 
 /// NetInterface contains the settings for a given network interface.
@@ -178,6 +313,14 @@ pub struct NetInterface {
     /// Subnets list of assigned subnets with their gateway.
     #[serde(rename = "subnets")]
     pub subnets: Option<Vec<NetAddress>>,
+
+    /// Administrative state of the interface after setup, "up" or "down".
+    #[serde(
+        rename = "admin_state",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub admin_state: Option<String>,
 }
 
 /// NetAddress contains the ip address, subnet and gateway.
@@ -1,6 +1,11 @@
-use crate::error::NetavarkResult;
+use crate::error::{NetavarkError, NetavarkResult};
 use log::debug;
 use std::fs::File;
+use std::io;
+use std::os::fd::RawFd;
+
+/// ioctl to get the CLONE_NEW* type of a namespace fd
+const NS_GET_NSTYPE: u64 = 0xb703;
 
 pub fn ns_checks(file: &str) -> NetavarkResult<()> {
     debug!("{:?}", "Validating network namespace...");
@@ -8,3 +13,34 @@ pub fn ns_checks(file: &str) -> NetavarkResult<()> {
     let _ = File::open(file)?.metadata()?;
     Ok(())
 }
+
+/// Get the namespace path from the namespace path argument or --netns-fd.
+pub fn netns_path(path: &Option<String>, fd: Option<RawFd>) -> NetavarkResult<String> {
+    match (path, fd) {
+        (_, Some(fd)) => netns_fd_path(fd),
+        (Some(path), None) => Ok(path.clone()),
+        (None, None) => Err(NetavarkError::msg(
+            "network namespace path or --netns-fd is required",
+        )),
+    }
+}
+
+/// The fd must refer to a network namespace. Its /proc path refers to the same
+/// namespace as long as the fd is open, unlike a namespace path it cannot be
+/// replaced in the meantime.
+fn netns_fd_path(fd: RawFd) -> NetavarkResult<String> {
+    let nstype = unsafe { libc::ioctl(fd, NS_GET_NSTYPE as _) };
+    if nstype < 0 {
+        return Err(NetavarkError::wrap(
+            format!("invalid --netns-fd {}", fd),
+            NetavarkError::Io(io::Error::last_os_error()),
+        ));
+    }
+    if nstype != libc::CLONE_NEWNET {
+        return Err(NetavarkError::Message(format!(
+            "--netns-fd {} is not a network namespace",
+            fd
+        )));
+    }
+    Ok(format!("/proc/{}/fd/{}", std::process::id(), fd))
+}
@@ -1,26 +1,39 @@
 use log::{debug, error};
-use std::{collections::HashMap, net::IpAddr, os::unix::prelude::RawFd};
+use std::{collections::HashMap, net::IpAddr, os::unix::prelude::RawFd, time::Duration};
 
-use netlink_packet_route::nlas::link::{InfoData, InfoIpVlan, InfoKind, InfoMacVlan, Nla};
+use ipnet::IpNet;
+use netlink_packet_route::{
+    nlas::link::{InfoData, InfoIpVlan, InfoKind, InfoMacVlan, Nla},
+    IFF_UP, RT_SCOPE_UNIVERSE,
+};
 use rand::distributions::{Alphanumeric, DistString};
 
 use crate::network::macvlan_dhcp::{get_dhcp_lease, release_dhcp_lease};
 use crate::{
     dns::aardvark::AardvarkEntry,
-    error::{ErrorWrap, NetavarkError, NetavarkResult},
+    error::{ConfigError, ErrorWrap, NetavarkError, NetavarkResult},
     exec_netns,
-    network::core_utils::{disable_ipv6_autoconf, join_netns},
+    network::core_utils::{disable_ipv6_autoconf, enable_ipv6_slaac, join_netns},
 };
 
 use super::{
-    constants::{NO_CONTAINER_INTERFACE_ERROR, OPTION_METRIC, OPTION_MODE, OPTION_MTU},
-    core_utils::{self, get_ipam_addresses, parse_option, CoreUtils},
+    constants::{
+        ADMIN_STATE_DOWN, ADMIN_STATE_UP, IPV6_MODE_SLAAC, IPV6_MODE_STATIC,
+        NO_CONTAINER_INTERFACE_ERROR, OPTION_HOST_ROUTES, OPTION_IPV6_MODE, OPTION_MODE,
+        OPTION_MTU, OPTION_POLICY_ROUTES,
+    },
+    core_utils::{self, parse_option, CoreUtils},
     driver::{self, DriverInfo},
     internal_types::IPAMAddresses,
+    lease::{self, LeaseMode},
     netlink::{self, CreateLinkOptions},
-    types::{NetInterface, StatusBlock},
+    types::{NetAddress, NetInterface, PolicyRoute, StatusBlock},
 };
 
+/// how long to wait for the router to hand out an address in slaac mode
+const SLAAC_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
 enum KindData {
     MacVlan {
         /// static mac address
@@ -43,6 +56,7 @@ impl core::fmt::Display for KindData {
     }
 }
 
+#[derive(Clone)]
 struct InternalData {
     /// interface name inside the container
     container_interface_name: String,
@@ -54,6 +68,14 @@ struct InternalData {
     mtu: u32,
     /// Route metric for default routes added to the network
     metric: Option<u32>,
+    /// get the ipv6 address via router advertisements from the upstream router
+    slaac: bool,
+    /// neighbor entries for the container interface
+    static_neighbors: Vec<core_utils::StaticNeighbor>,
+    /// route the container addresses via the parent interface on the host
+    host_routes: bool,
+    /// default routes in other routing tables with the rules selecting them
+    policy_routes: Vec<PolicyRoute>,
     /// kind-specific data
     kind: KindData,
     // TODO: add vlan
@@ -71,6 +93,162 @@ impl<'a> Vlan<'a> {
             data: None::<InternalData>,
         }
     }
+
+    /// The addresses of the container, internal networks have no gateway.
+    fn get_ipam(&self, mode: LeaseMode) -> NetavarkResult<IPAMAddresses> {
+        let mut ipam = lease::get_ipam_addresses(&self.info, mode)?;
+
+        // Remove gateways when marked as internal network
+        if self.info.network.internal {
+            ipam.gateway_addresses = Vec::new();
+        }
+        Ok(ipam)
+    }
+
+    fn setup_network(
+        &self,
+        data: &InternalData,
+        netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
+        debug!("Setup network {}", self.info.network.name);
+        debug!(
+            "Container interface name: {} with IP addresses {:?}",
+            self.info.per_network_opts.interface_name, data.ipam.container_addresses
+        );
+
+        let (host_sock, netns_sock) = netlink_sockets;
+
+        let turn = self
+            .info
+            .link_order
+            .map(|order| order.wait(&self.info.network.name));
+        let container_vlan_mac = setup(
+            host_sock,
+            netns_sock,
+            &self.info.per_network_opts.interface_name,
+            data,
+            self.info.netns_host,
+            self.info.netns_container,
+            &data.kind,
+        )?;
+        drop(turn);
+
+        match self.setup_container(data, (host_sock, netns_sock), container_vlan_mac) {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                // do not leave a half configured interface behind, the container
+                // must only keep the networks which were set up successfully
+                if let Err(e) = netns_sock
+                    .del_link(netlink::LinkID::Name(data.container_interface_name.clone()))
+                {
+                    error!(
+                        "failed to remove {} interface after setup failed: {}",
+                        data.kind, e
+                    );
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Setup everything for the container after its interface was created, the
+    /// host routes which were added are removed again when a later step fails.
+    fn setup_container(
+        &self,
+        data: &InternalData,
+        netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+        container_vlan_mac: String,
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry<'_>>)> {
+        let (host_sock, netns_sock) = netlink_sockets;
+
+        //  StatusBlock response is what we return at the end
+        // of all of this
+        let mut response = StatusBlock {
+            dns_server_ips: Some(Vec::<IpAddr>::new()),
+            dns_search_domains: Some(Vec::<String>::new()),
+            interfaces: Some(HashMap::new()),
+            port_proxies: None,
+            default_gateways: None,
+            policy_routes: None,
+            host_routes: None,
+            nat64_prefix: None,
+            interface_index: None,
+            bridge: None,
+        };
+
+        // interfaces map, but we only ever expect one, for response
+        let mut interfaces: HashMap<String, NetInterface> = HashMap::new();
+
+        // if dhcp is enabled, we need to call the dhcp proxy to perform
+        // a dhcp lease.  it will also perform the IP address assignment
+        // to the macvlan interface.
+        let mut subnets = if data.ipam.dhcp_enabled {
+            get_dhcp_lease(
+                &data.host_interface_name,
+                &data.container_interface_name,
+                self.info.netns_path,
+                &container_vlan_mac,
+            )?
+        } else {
+            data.ipam.net_addresses.clone()
+        };
+
+        // the router is responsible for the ipv6 address, wait for it and report it back
+        if data.slaac {
+            let dev = netns_sock
+                .get_link(netlink::LinkID::Name(data.container_interface_name.clone()))
+                .wrap(format!("get {} interface", data.kind))?;
+            let ipnet =
+                core_utils::wait_for_slaac_address(netns_sock, dev.header.index, SLAAC_TIMEOUT)?;
+            subnets.push(NetAddress {
+                gateway: None,
+                ipnet,
+            });
+        }
+
+        let addresses: Vec<IpNet> = subnets.iter().map(|s| s.ipnet).collect();
+        if data.host_routes {
+            add_host_routes(host_sock, &data.host_interface_name, &addresses)?;
+        }
+
+        // report the state of the link, the dhcp proxy may have changed it
+        let dev = match netns_sock
+            .get_link(netlink::LinkID::Name(data.container_interface_name.clone()))
+            .wrap(format!("get {} interface", data.kind))
+        {
+            Ok(dev) => dev,
+            Err(err) => {
+                // the routes point to the host interface and stay without a teardown
+                if data.host_routes {
+                    if let Err(e) =
+                        del_host_routes(host_sock, &data.host_interface_name, &addresses)
+                    {
+                        error!("failed to remove host routes after setup failed: {}", e);
+                    }
+                }
+                return Err(err);
+            }
+        };
+        let admin_state = if dev.header.flags & IFF_UP != 0 {
+            ADMIN_STATE_UP
+        } else {
+            ADMIN_STATE_DOWN
+        };
+
+        let interface = NetInterface {
+            mac_address: container_vlan_mac,
+            subnets: Option::from(subnets),
+            admin_state: Some(admin_state.to_string()),
+        };
+
+        // Add interface to interfaces (part of StatusBlock)
+        interfaces.insert(self.info.per_network_opts.interface_name.clone(), interface);
+        let _ = response.interfaces.insert(interfaces);
+        if !data.policy_routes.is_empty() {
+            response.policy_routes = Some(data.policy_routes.clone());
+        }
+        Ok((response, None))
+    }
 }
 
 impl driver::NetworkDriver for Vlan<'_> {
@@ -80,21 +258,52 @@ impl driver::NetworkDriver for Vlan<'_> {
 
     fn validate(&mut self) -> NetavarkResult<()> {
         if self.info.per_network_opts.interface_name.is_empty() {
-            return Err(NetavarkError::msg(NO_CONTAINER_INTERFACE_ERROR));
+            return Err(ConfigError::missing_field(
+                "interface_name",
+                NO_CONTAINER_INTERFACE_ERROR,
+            ));
         }
 
         let mode = parse_option(&self.info.network.options, OPTION_MODE, String::default())?;
 
-        let mut ipam = get_ipam_addresses(self.info.per_network_opts, self.info.network)?;
-
         let mtu = parse_option(&self.info.network.options, OPTION_MTU, 0)?;
-        let metric = parse_option(&self.info.network.options, OPTION_METRIC, 100)?;
+        let metric = core_utils::get_route_metric(&self.info.network.options)?;
 
-        // Remove gateways when marked as internal network
-        if self.info.network.internal {
-            ipam.gateway_addresses = Vec::new();
+        let ipv6_mode = parse_option(
+            &self.info.network.options,
+            OPTION_IPV6_MODE,
+            String::default(),
+        )?;
+        let slaac = match ipv6_mode.as_str() {
+            "" | IPV6_MODE_STATIC => false,
+            IPV6_MODE_SLAAC => true,
+            other => {
+                return Err(ConfigError::invalid_value(
+                    format!("options.{}", OPTION_IPV6_MODE),
+                    format!("invalid {} \"{}\"", OPTION_IPV6_MODE, other),
+                ))
+            }
+        };
+
+        let static_neighbors = core_utils::get_static_neighbors(self.info.network)?;
+
+        let policy_routes = core_utils::get_policy_routes(self.info.network)?;
+
+        let host_routes: bool =
+            parse_option(&self.info.network.options, OPTION_HOST_ROUTES, false)?;
+        if host_routes && self.info.network.driver != super::constants::DRIVER_MACVLAN {
+            return Err(ConfigError::invalid_value(
+                format!("options.{}", OPTION_HOST_ROUTES),
+                format!(
+                    "{} is only supported for macvlan networks",
+                    OPTION_HOST_ROUTES
+                ),
+            ));
         }
 
+        // the lease is only taken by the setup, validating must not change anything
+        let ipam = self.get_ipam(LeaseMode::Preview)?;
+
         self.data = Some(InternalData {
             container_interface_name: self.info.per_network_opts.interface_name.clone(),
             host_interface_name: self
@@ -106,6 +315,10 @@ impl driver::NetworkDriver for Vlan<'_> {
             ipam,
             mtu,
             metric: Some(metric),
+            slaac,
+            static_neighbors,
+            host_routes,
+            policy_routes,
             kind: match self.info.network.driver.as_str() {
                 super::constants::DRIVER_IPVLAN => KindData::IpVlan {
                     mode: CoreUtils::get_ipvlan_mode_from_string(&mode)?,
@@ -137,65 +350,32 @@ impl driver::NetworkDriver for Vlan<'_> {
             None => return Err(NetavarkError::msg("must call validate() before setup()")),
         };
 
-        debug!("Setup network {}", self.info.network.name);
-        debug!(
-            "Container interface name: {} with IP addresses {:?}",
-            self.info.per_network_opts.interface_name, data.ipam.container_addresses
-        );
-
-        let (host_sock, netns_sock) = netlink_sockets;
-
-        let container_vlan_mac = setup(
-            host_sock,
-            netns_sock,
-            &self.info.per_network_opts.interface_name,
-            data,
-            self.info.netns_host,
-            self.info.netns_container,
-            &data.kind,
-        )?;
-
-        //  StatusBlock response is what we return at the end
-        // of all of this
-        let mut response = StatusBlock {
-            dns_server_ips: Some(Vec::<IpAddr>::new()),
-            dns_search_domains: Some(Vec::<String>::new()),
-            interfaces: Some(HashMap::new()),
-        };
-
-        // interfaces map, but we only ever expect one, for response
-        let mut interfaces: HashMap<String, NetInterface> = HashMap::new();
-
-        // if dhcp is enabled, we need to call the dhcp proxy to perform
-        // a dhcp lease.  it will also perform the IP address assignment
-        // to the macvlan interface.
-        let subnets = if data.ipam.dhcp_enabled {
-            get_dhcp_lease(
-                &data.host_interface_name,
-                &data.container_interface_name,
-                self.info.netns_path,
-                &container_vlan_mac,
-            )?
+        // validate() only looked at the lease file, take the lease now
+        let leased = if lease::is_lease_ipam(self.info.network) {
+            let ipam = self.get_ipam(LeaseMode::Acquire)?;
+            Some(InternalData {
+                ipam,
+                ..data.clone()
+            })
         } else {
-            data.ipam.net_addresses.clone()
+            None
         };
+        let data = leased.as_ref().unwrap_or(data);
 
-        let interface = NetInterface {
-            mac_address: container_vlan_mac,
-            subnets: Option::from(subnets),
-        };
-
-        // Add interface to interfaces (part of StatusBlock)
-        interfaces.insert(self.info.per_network_opts.interface_name.clone(), interface);
-        let _ = response.interfaces.insert(interfaces);
-        Ok((response, None))
+        let result = self.setup_network(data, netlink_sockets);
+        if result.is_err() && leased.is_some() {
+            if let Err(e) = lease::release_for(&self.info) {
+                error!("failed to release lease after setup failed: {}", e);
+            }
+        }
+        result
     }
 
     fn teardown(
         &self,
         netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
     ) -> NetavarkResult<()> {
-        let ipam = get_ipam_addresses(self.info.per_network_opts, self.info.network)?;
+        let ipam = lease::get_ipam_addresses(&self.info, LeaseMode::Lookup)?;
         let if_name = self.info.per_network_opts.interface_name.clone();
 
         // If we are using DHCP macvlan, we need to at least call to the proxy so that
@@ -223,10 +403,73 @@ impl driver::NetworkDriver for Vlan<'_> {
             )?
         }
 
+        let host_routes = parse_option(&self.info.network.options, OPTION_HOST_ROUTES, false)
+            .unwrap_or_else(|e| {
+                // just log we still try to do as much as possible for cleanup
+                error!("failed to parse {} option: {}", OPTION_HOST_ROUTES, e);
+                false
+            });
+        if host_routes {
+            if let Err(e) = remove_host_routes(
+                netlink_sockets.0,
+                netlink_sockets.1,
+                &self
+                    .info
+                    .network
+                    .network_interface
+                    .clone()
+                    .unwrap_or_default(),
+                &self.info.per_network_opts.interface_name,
+            ) {
+                error!("failed to remove host routes: {}", e);
+            }
+        }
+
+        // the routes are removed with the interface but the rules stay
+        match core_utils::get_policy_routes(self.info.network) {
+            Ok(routes) => {
+                if let Err(e) = core_utils::remove_policy_rules(netlink_sockets.1, &routes) {
+                    error!("failed to remove policy rules: {}", e);
+                }
+            }
+            Err(e) => error!("failed to parse {} option: {}", OPTION_POLICY_ROUTES, e),
+        }
+
         netlink_sockets.1.del_link(netlink::LinkID::Name(
             self.info.per_network_opts.interface_name.to_string(),
         ))?;
-        Ok(())
+        lease::release_for(&self.info)
+    }
+
+    fn cleanup(&self, host: &mut netlink::Socket) -> NetavarkResult<()> {
+        let ipam = lease::get_ipam_addresses(&self.info, LeaseMode::Lookup)?;
+        if ipam.dhcp_enabled {
+            // the proxy needs the mac address of the interface to release the lease
+            debug!(
+                "dhcp lease of network {} is not released, it expires",
+                self.info.network.name
+            );
+        }
+        let host_routes = parse_option(&self.info.network.options, OPTION_HOST_ROUTES, false)
+            .unwrap_or_else(|e| {
+                error!("failed to parse {} option: {}", OPTION_HOST_ROUTES, e);
+                false
+            });
+        if host_routes {
+            if let Err(e) = del_host_routes(
+                host,
+                &self
+                    .info
+                    .network
+                    .network_interface
+                    .clone()
+                    .unwrap_or_default(),
+                &ipam.container_addresses,
+            ) {
+                error!("failed to remove host routes: {}", e);
+            }
+        }
+        lease::release_for(&self.info)
     }
 }
 
@@ -239,10 +482,7 @@ fn setup(
     netns_fd: RawFd,
     kind_data: &KindData,
 ) -> NetavarkResult<String> {
-    let primary_ifname = match data.host_interface_name.as_ref() {
-        "" => get_default_route_interface(host)?,
-        host_name => host_name.to_string(),
-    };
+    let primary_ifname = get_parent_interface(host, &data.host_interface_name)?;
 
     let link = host.get_link(netlink::LinkID::Name(primary_ifname))?;
 
@@ -321,7 +561,13 @@ fn setup(
         }
     }
 
-    exec_netns!(hostns_fd, netns_fd, res, { disable_ipv6_autoconf(if_name) });
+    exec_netns!(hostns_fd, netns_fd, res, {
+        if data.slaac {
+            enable_ipv6_slaac(if_name)
+        } else {
+            disable_ipv6_autoconf(if_name)
+        }
+    });
     res?; // return autoconf sysctl error
 
     let dev = netns
@@ -338,11 +584,81 @@ fn setup(
         .set_up(netlink::LinkID::ID(dev.header.index))
         .wrap(format!("set {} up", kind_data))?;
 
+    core_utils::add_static_neighbors(netns, dev.header.index, &data.static_neighbors)?;
+
     core_utils::add_default_routes(netns, &data.ipam.gateway_addresses, data.metric)?;
+    core_utils::add_policy_routes(netns, &data.policy_routes)?;
 
     get_mac_address(dev.nlas)
 }
 
+/// The configured parent interface or the one of the default route when none is set.
+fn get_parent_interface(host: &mut netlink::Socket, name: &str) -> NetavarkResult<String> {
+    match name {
+        "" => get_default_route_interface(host),
+        name => Ok(name.to_string()),
+    }
+}
+
+/// Remove the host routes to the addresses of the container interface, this must
+/// be called before the interface is removed as the addresses are read from it.
+fn remove_host_routes(
+    host: &mut netlink::Socket,
+    netns: &mut netlink::Socket,
+    parent: &str,
+    if_name: &str,
+) -> NetavarkResult<()> {
+    let dev = netns
+        .get_link(netlink::LinkID::Name(if_name.to_string()))
+        .wrap("get container interface")?;
+    let addresses: Vec<IpNet> = netns
+        .dump_addresses()
+        .wrap("dump container addresses")?
+        .iter()
+        .filter(|msg| msg.header.index == dev.header.index && msg.header.scope == RT_SCOPE_UNIVERSE)
+        .filter_map(core_utils::parse_address_message)
+        .collect();
+    del_host_routes(host, parent, &addresses)
+}
+
+/// Add the host routes to the container addresses, the routes which were
+/// already added are removed again when one of them fails.
+fn add_host_routes(
+    host: &mut netlink::Socket,
+    parent: &str,
+    addresses: &[IpNet],
+) -> NetavarkResult<()> {
+    let parent_name = get_parent_interface(host, parent)?;
+    let link = host.get_link(netlink::LinkID::Name(parent_name))?;
+    for (i, addr) in addresses.iter().enumerate() {
+        if let Err(err) = host.add_link_route(link.header.index, &IpNet::from(addr.addr())) {
+            if let Err(e) = del_host_routes(host, parent, &addresses[..i]) {
+                error!("failed to remove host routes after setup failed: {}", e);
+            }
+            return Err(err).wrap("add host route to the container address");
+        }
+    }
+    Ok(())
+}
+
+fn del_host_routes(
+    host: &mut netlink::Socket,
+    parent: &str,
+    addresses: &[IpNet],
+) -> NetavarkResult<()> {
+    let parent = get_parent_interface(host, parent)?;
+    let link = host.get_link(netlink::LinkID::Name(parent))?;
+    for addr in addresses {
+        match host.del_link_route(link.header.index, &IpNet::from(addr.addr())) {
+            Ok(_) => {}
+            // the route is already gone
+            Err(NetavarkError::Netlink(ref e)) if -e.code == libc::ESRCH => {}
+            Err(e) => return Err(e).wrap(format!("remove host route to {}", addr.addr())),
+        }
+    }
+    Ok(())
+}
+
 fn get_mac_address(v: Vec<Nla>) -> NetavarkResult<String> {
     for nla in v.into_iter() {
         if let Nla::Address(ref addr) = nla {
@@ -43,9 +43,698 @@ mod tests {
         }
     }
 
+    // Test that yaml and json configs are loaded into the same options
+    #[test]
+    fn test_setup_opts_load_yaml() {
+        let json = network::types::NetworkOptions::load(Some(
+            "src/test/config/setupopts.test.json".to_owned(),
+        ))
+        .expect("load json config");
+        let yaml = network::types::NetworkOptions::load(Some(
+            "src/test/config/setupopts.test.yaml".to_owned(),
+        ))
+        .expect("load yaml config");
+        assert_eq!(
+            serde_json::to_value(json).unwrap(),
+            serde_json::to_value(yaml).unwrap()
+        );
+    }
+
     // Test commands::setup::ns_checks works correctly
     #[test]
     fn test_ns_checks() {
         assert!(network::validation::ns_checks("src/test/config/setupopts.test.json").is_ok());
     }
+
+    // Test that only known response versions are accepted
+    #[test]
+    fn test_response_version() {
+        use netavark::network::response::{serialize_response, RESPONSE_VERSION_LATEST};
+        use std::collections::HashMap;
+
+        let response = HashMap::new();
+        assert!(serialize_response(&response, RESPONSE_VERSION_LATEST).is_ok());
+        assert!(serialize_response(&response, 0).is_err());
+        assert!(serialize_response(&response, RESPONSE_VERSION_LATEST + 1).is_err());
+    }
+
+    // Test that host veth names are unique and fit into IFNAMSIZ
+    #[test]
+    fn test_create_host_veth_name() {
+        use netavark::network::core_utils::{CoreUtils, MAX_IFNAME_LEN};
+        use std::collections::HashSet;
+
+        let name = CoreUtils::create_host_veth_name("6ce776ea58b5", "podman");
+        assert_eq!(
+            name,
+            CoreUtils::create_host_veth_name("6ce776ea58b5", "podman")
+        );
+        assert!(name.starts_with("veth"));
+
+        let mut names = HashSet::new();
+        for i in 0..10000 {
+            let container_id = format!("6ce776ea58b5{:04x}", i);
+            for network in ["podman", "podman1"] {
+                let name = CoreUtils::create_host_veth_name(&container_id, network);
+                assert!(name.len() <= MAX_IFNAME_LEN, "{} is too long", name);
+                assert!(names.insert(name), "collision for {}", container_id);
+            }
+        }
+    }
+
+    // Test that port mappings are converted into single proxy forwards
+    #[test]
+    fn test_port_proxy_forwards() {
+        use netavark::network::port_proxy::{get_proxy_forwards, ProxyForward};
+        use netavark::network::types::PortMapping;
+
+        let mappings = vec![PortMapping {
+            container_port: 80,
+            host_ip: "".to_string(),
+            host_port: 8080,
+            protocol: "tcp,udp".to_string(),
+            range: 2,
+            container_ip: None,
+        }];
+        let forwards =
+            get_proxy_forwards(&mappings, Some("10.88.0.2".parse().unwrap()), None, &[]).unwrap();
+        assert_eq!(forwards.len(), 4);
+        assert_eq!(forwards[1].to_string(), "tcp,0.0.0.0:8081,10.88.0.2:81");
+        assert_eq!(forwards[3].to_string(), "udp,0.0.0.0:8081,10.88.0.2:81");
+        for forward in forwards {
+            assert_eq!(
+                forward.to_string().parse::<ProxyForward>().unwrap(),
+                forward
+            );
+        }
+
+        // both families on a dual stack network
+        let forwards = get_proxy_forwards(
+            &mappings,
+            Some("10.88.0.2".parse().unwrap()),
+            Some("fd00::2".parse().unwrap()),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(forwards.len(), 8);
+        assert_eq!(forwards[0].to_string(), "tcp,0.0.0.0:8080,10.88.0.2:80");
+        assert_eq!(forwards[4].to_string(), "tcp,[::]:8080,[fd00::2]:80");
+
+        let mut sctp = mappings.clone();
+        sctp[0].protocol = "sctp".to_string();
+        assert!(get_proxy_forwards(&sctp, Some("10.88.0.2".parse().unwrap()), None, &[]).is_err());
+
+        // no ipv6 container address for an ipv6 host ip
+        let mut v6 = mappings.clone();
+        v6[0].host_ip = "::1".to_string();
+        assert!(get_proxy_forwards(&v6, Some("10.88.0.2".parse().unwrap()), None, &[]).is_err());
+
+        // the container_ip selects the address, other networks have the rest
+        let mut pinned = mappings;
+        pinned[0].container_ip = Some("10.88.0.3".parse().unwrap());
+        let ips = ["10.88.0.2".parse().unwrap(), "10.88.0.3".parse().unwrap()];
+        let forwards =
+            get_proxy_forwards(&pinned, Some("10.88.0.2".parse().unwrap()), None, &ips).unwrap();
+        assert_eq!(forwards[0].to_string(), "tcp,0.0.0.0:8080,10.88.0.3:80");
+        let forwards =
+            get_proxy_forwards(&pinned, Some("10.89.0.2".parse().unwrap()), None, &ips[..0])
+                .unwrap();
+        assert!(forwards.is_empty());
+    }
+
+    // Test that config errors keep their structure in the json output
+    #[test]
+    fn test_config_error() {
+        use netavark::error::{ConfigError, NetavarkError};
+
+        let err = NetavarkError::wrap(
+            "validate",
+            ConfigError::invalid_value("options.mtu", "unable to parse \"mtu\""),
+        )
+        .with_network("podman");
+        assert_eq!(err.to_string(), "validate: unable to parse \"mtu\"");
+        match err.unwrap() {
+            NetavarkError::Config(config) => {
+                let json = serde_json::to_value(config).unwrap();
+                assert_eq!(json["kind"], "invalid_value");
+                assert_eq!(json["network"], "podman");
+                assert_eq!(json["field"], "options.mtu");
+            }
+            e => panic!("expected config error, got {:?}", e),
+        }
+
+        let path = std::env::temp_dir().join("netavark-invalid-config.json");
+        std::fs::write(&path, "{\n  \"container_id\": }").unwrap();
+        let result =
+            netavark::network::types::NetworkOptions::load(Some(path.display().to_string()));
+        std::fs::remove_file(&path).unwrap();
+        match result {
+            Err(err) => match err.unwrap() {
+                NetavarkError::Config(ConfigError::Decode { line, .. }) => {
+                    assert_eq!(*line, Some(2))
+                }
+                e => panic!("expected decode error, got {:?}", e),
+            },
+            Ok(_) => panic!("invalid config must not load"),
+        }
+    }
+
+    // Test that the lease ipam reuses addresses per container id
+    #[test]
+    fn test_lease_ipam() {
+        use netavark::network::lease;
+        use netavark::network::types::Network;
+        use std::net::IpAddr;
+
+        let network: Network = serde_json::from_str(
+            r#"{"dns_enabled": false, "driver": "bridge", "id": "1", "internal": false,
+                "ipv6_enabled": true, "name": "lease", "network_interface": "podman1",
+                "ipam_options": {"driver": "lease"},
+                "subnets": [{"gateway": "10.89.0.1", "subnet": "10.89.0.0/29"},
+                            {"subnet": "fd10::/64", "lease_range": {"start_ip": "fd10::10"}}]}"#,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("netavark-lease-{}", std::process::id()));
+        let config_dir = dir.to_str().unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let first = lease::acquire(config_dir, &network, "first", None).unwrap();
+        assert_eq!(first, vec![ip("10.89.0.2"), ip("fd10::10")]);
+        let second = lease::acquire(config_dir, &network, "second", None).unwrap();
+        assert_eq!(second, vec![ip("10.89.0.3"), ip("fd10::11")]);
+        // the same container gets the same addresses again
+        let again = lease::acquire(config_dir, &network, "first", None).unwrap();
+        assert_eq!(again, first);
+        assert_eq!(
+            lease::lookup(config_dir, &network, "first").unwrap(),
+            Some(first.clone())
+        );
+
+        lease::release(config_dir, &network, "first").unwrap();
+        assert_eq!(lease::lookup(config_dir, &network, "first").unwrap(), None);
+        // released addresses are kept for their old container while others are free
+        let third = lease::acquire(config_dir, &network, "third", None).unwrap();
+        assert_eq!(third, vec![ip("10.89.0.4"), ip("fd10::12")]);
+        let again = lease::acquire(config_dir, &network, "first", None).unwrap();
+        assert_eq!(again, first);
+
+        // once the range is exhausted released addresses are reassigned
+        lease::release(config_dir, &network, "first").unwrap();
+        let fourth = lease::acquire(config_dir, &network, "fourth", None).unwrap();
+        assert_eq!(fourth[0], ip("10.89.0.5"));
+        let fifth = lease::acquire(config_dir, &network, "fifth", None).unwrap();
+        assert_eq!(fifth[0], ip("10.89.0.6"));
+        let sixth = lease::acquire(config_dir, &network, "sixth", None).unwrap();
+        assert_eq!(sixth[0], ip("10.89.0.2"));
+        assert!(lease::acquire(config_dir, &network, "first", None).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Test that the networks of a pool never lease the same address
+    #[test]
+    fn test_lease_ipam_pool() {
+        use netavark::network::lease;
+        use netavark::network::types::Network;
+        use std::net::IpAddr;
+
+        let network = |name: &str, gateway: &str| -> Network {
+            serde_json::from_str(&format!(
+                r#"{{"dns_enabled": false, "driver": "bridge", "id": "1", "internal": false,
+                    "ipv6_enabled": false, "name": "{}", "network_interface": "{}",
+                    "ipam_options": {{"driver": "lease", "pool": "flat"}},
+                    "subnets": [{{"gateway": "{}", "subnet": "10.90.0.0/29"}}]}}"#,
+                name, name, gateway
+            ))
+            .unwrap()
+        };
+        let a = network("a", "10.90.0.1");
+        let b = network("b", "10.90.0.1");
+        let dir = std::env::temp_dir().join(format!("netavark-pool-{}", std::process::id()));
+        let config_dir = dir.to_str().unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let first = lease::acquire(config_dir, &a, "first", None).unwrap();
+        assert_eq!(first, vec![ip("10.90.0.2")]);
+        let second = lease::acquire(config_dir, &b, "second", None).unwrap();
+        assert_eq!(second, vec![ip("10.90.0.3")]);
+        // the same container has a lease per network
+        let both = lease::acquire(config_dir, &b, "first", None).unwrap();
+        assert_eq!(both, vec![ip("10.90.0.4")]);
+        let again = lease::acquire(config_dir, &a, "first", None).unwrap();
+        assert_eq!(again, first);
+
+        // networks without the pool have their own addresses
+        let mut c = network("c", "10.90.0.1");
+        c.ipam_options.as_mut().unwrap().remove("pool");
+        let other = lease::acquire(config_dir, &c, "other", None).unwrap();
+        assert_eq!(other, vec![ip("10.90.0.2")]);
+
+        c.ipam_options
+            .as_mut()
+            .unwrap()
+            .insert("pool".to_string(), "../flat".to_string());
+        assert!(lease::acquire(config_dir, &c, "other", None).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Test parsing of the forward policy allow entries
+    #[test]
+    fn test_allow_rule_parse() {
+        use netavark::network::internal_types::AllowRule;
+
+        let rule: AllowRule = "tcp/8000-8100@10.88.1.5/16".parse().unwrap();
+        assert_eq!(rule.protocol.as_deref(), Some("tcp"));
+        assert_eq!(rule.ports, Some((8000, 8100)));
+        assert_eq!(rule.peer, Some("10.88.0.0/16".parse().unwrap()));
+
+        let rule: AllowRule = "all@fd00::/64".parse().unwrap();
+        assert_eq!(rule.protocol, None);
+        assert_eq!(rule.ports, None);
+
+        let rule: AllowRule = "udp/53".parse().unwrap();
+        assert_eq!(rule.ports, Some((53, 53)));
+        assert_eq!(rule.peer, None);
+
+        assert!("icmp/8".parse::<AllowRule>().is_err());
+        assert!("tcp/100-10".parse::<AllowRule>().is_err());
+        assert!("gre".parse::<AllowRule>().is_err());
+        assert!("tcp@10.0.0.0".parse::<AllowRule>().is_err());
+    }
+
+    // Test parsing of the static_neighbors option
+    #[test]
+    fn test_static_neighbors() {
+        use netavark::network::core_utils::get_static_neighbors;
+        use netavark::network::types::Network;
+
+        let network = |neighbors: &str| -> Network {
+            serde_json::from_value(serde_json::json!({
+                "dns_enabled": false, "driver": "bridge", "id": "1", "internal": false,
+                "ipv6_enabled": true, "name": "podman", "network_interface": "podman0",
+                "options": {"static_neighbors": neighbors},
+                "subnets": [{"subnet": "10.88.0.0/16"}, {"subnet": "fd00::/64"}]
+            }))
+            .unwrap()
+        };
+
+        let neighbors = get_static_neighbors(&network(
+            "10.88.0.5=aa:bb:cc:dd:ee:ff, fd00::5=02-00-00-00-00-01",
+        ))
+        .unwrap();
+        assert_eq!(neighbors.len(), 2);
+        assert_eq!(
+            neighbors[0].ip,
+            "10.88.0.5".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(neighbors[0].mac, vec![0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        assert_eq!(neighbors[1].mac, vec![2, 0, 0, 0, 0, 1]);
+
+        assert!(get_static_neighbors(&network("")).unwrap().is_empty());
+        // not on-link
+        assert!(get_static_neighbors(&network("10.89.0.5=aa:bb:cc:dd:ee:ff")).is_err());
+        // bad mac
+        assert!(get_static_neighbors(&network("10.88.0.5=aa:bb:cc:dd:ee")).is_err());
+        // bad ip
+        assert!(get_static_neighbors(&network("10.88.0=aa:bb:cc:dd:ee:ff")).is_err());
+        assert!(get_static_neighbors(&network("10.88.0.5")).is_err());
+    }
+
+    // Test parsing of the default route metric options
+    #[test]
+    fn test_route_metric() {
+        use netavark::network::core_utils::get_route_metric;
+        use std::collections::HashMap;
+
+        let opts = |pairs: &[(&str, &str)]| -> Option<HashMap<String, String>> {
+            Some(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            )
+        };
+
+        assert_eq!(get_route_metric(&None).unwrap(), 100);
+        assert_eq!(
+            get_route_metric(&opts(&[("route_metric", "0")])).unwrap(),
+            0
+        );
+        assert_eq!(get_route_metric(&opts(&[("metric", "250")])).unwrap(), 250);
+        assert!(get_route_metric(&opts(&[("route_metric", "-1")])).is_err());
+        assert!(get_route_metric(&opts(&[("route_metric", "4294967296")])).is_err());
+        assert!(get_route_metric(&opts(&[("route_metric", "low")])).is_err());
+        assert!(get_route_metric(&opts(&[("route_metric", "1"), ("metric", "1")])).is_err());
+    }
+
+    // Test that gateways are taken from the subnet or computed with gateway_position
+    #[test]
+    fn test_subnet_gateways() {
+        use netavark::network::core_utils::get_subnet_gateways;
+        use netavark::network::types::Network;
+        use std::net::IpAddr;
+
+        let network = |subnets: serde_json::Value, position: Option<&str>| -> Network {
+            let mut network = serde_json::json!({
+                "dns_enabled": false, "driver": "bridge", "id": "1", "internal": false,
+                "ipv6_enabled": true, "name": "podman", "network_interface": "podman0",
+                "subnets": subnets
+            });
+            if let Some(position) = position {
+                network["options"] = serde_json::json!({ "gateway_position": position });
+            }
+            serde_json::from_value(network).unwrap()
+        };
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        let subnets = serde_json::json!([{"subnet": "10.88.0.0/24"}, {"subnet": "fd00::/64"}]);
+
+        assert_eq!(
+            get_subnet_gateways(&network(subnets.clone(), None)).unwrap(),
+            vec![None, None]
+        );
+        assert_eq!(
+            get_subnet_gateways(&network(subnets.clone(), Some("first"))).unwrap(),
+            vec![ip("10.88.0.1"), ip("fd00::1")]
+        );
+        assert_eq!(
+            get_subnet_gateways(&network(subnets.clone(), Some("last"))).unwrap(),
+            vec![ip("10.88.0.254"), ip("fd00::ffff:ffff:ffff:ffff")]
+        );
+        assert!(get_subnet_gateways(&network(subnets, Some("middle"))).is_err());
+
+        // a configured gateway is used verbatim
+        let configured = serde_json::json!([{"subnet": "10.88.0.0/24", "gateway": "10.88.0.254"}]);
+        assert_eq!(
+            get_subnet_gateways(&network(configured, Some("first"))).unwrap(),
+            vec![ip("10.88.0.254")]
+        );
+
+        for gateway in ["10.88.0.0", "10.88.0.255", "10.89.0.1"] {
+            let subnets = serde_json::json!([{"subnet": "10.88.0.0/24", "gateway": gateway}]);
+            assert!(
+                get_subnet_gateways(&network(subnets, None)).is_err(),
+                "{} must be rejected",
+                gateway
+            );
+        }
+    }
+
+    // Test that the interface admin state is only part of response version 4
+    #[test]
+    fn test_response_admin_state() {
+        use netavark::network::response::serialize_response;
+        use netavark::network::types::{NetInterface, StatusBlock};
+        use std::collections::HashMap;
+
+        let interface = NetInterface {
+            mac_address: "aa:bb:cc:dd:ee:ff".to_string(),
+            subnets: None,
+            admin_state: Some("down".to_string()),
+        };
+        let status = StatusBlock {
+            dns_search_domains: None,
+            dns_server_ips: None,
+            interfaces: Some(HashMap::from([("eth0".to_string(), interface)])),
+            port_proxies: None,
+            default_gateways: None,
+            policy_routes: None,
+            host_routes: None,
+            nat64_prefix: None,
+            interface_index: None,
+            bridge: None,
+        };
+        let response = HashMap::from([("podman".to_string(), status)]);
+
+        let latest = serialize_response(&response, 4).unwrap();
+        assert!(latest.contains(r#""admin_state":"down""#), "{}", latest);
+        let old = serialize_response(&response, 3).unwrap();
+        assert!(!old.contains("admin_state"), "{}", old);
+        assert!(
+            old.contains(r#""mac_address":"aa:bb:cc:dd:ee:ff""#),
+            "{}",
+            old
+        );
+    }
+
+    // Test parsing of the gateways and policy_routes options
+    #[test]
+    fn test_policy_routes() {
+        use netavark::network::core_utils::get_policy_routes;
+        use netavark::network::types::Network;
+
+        let network = |gateways: &str, routes: &str| -> Network {
+            serde_json::from_value(serde_json::json!({
+                "dns_enabled": false, "driver": "bridge", "id": "1", "internal": false,
+                "ipv6_enabled": true, "name": "podman", "network_interface": "podman0",
+                "options": {"gateways": gateways, "policy_routes": routes},
+                "subnets": [{"subnet": "10.88.0.0/16"}, {"subnet": "fd00::/64"}]
+            }))
+            .unwrap()
+        };
+
+        let routes = get_policy_routes(&network(
+            "mgmt=10.88.0.254, mgmt6=fd00::fe",
+            "mgmt:100:from=10.88.1.0/24, mgmt:100:fwmark=0x10/0xff, mgmt6:100, mgmt:200",
+        ))
+        .unwrap();
+        assert_eq!(routes.len(), 4);
+        assert_eq!(routes[0].gateway_name, "mgmt");
+        assert_eq!(routes[0].table, 100);
+        assert_eq!(routes[0].source, Some("10.88.1.0/24".parse().unwrap()));
+        assert_eq!(routes[1].fwmark, Some(0x10));
+        assert_eq!(routes[1].fwmask, Some(0xff));
+        assert_eq!(
+            routes[2].gateway,
+            "fd00::fe".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!((routes[3].source, routes[3].fwmark), (None, None));
+
+        assert!(get_policy_routes(&network("", "")).unwrap().is_empty());
+        // not on-link
+        assert!(get_policy_routes(&network("mgmt=10.89.0.1", "")).is_err());
+        // duplicate name
+        assert!(get_policy_routes(&network("a=10.88.0.1,a=10.88.0.2", "")).is_err());
+        // unknown gateway
+        assert!(get_policy_routes(&network("mgmt=10.88.0.254", "other:100")).is_err());
+        // reserved tables
+        for table in ["0", "252", "253", "254", "255", "x"] {
+            assert!(
+                get_policy_routes(&network("mgmt=10.88.0.254", &format!("mgmt:{}", table)))
+                    .is_err(),
+                "table {} must be rejected",
+                table
+            );
+        }
+        // family mismatch
+        assert!(
+            get_policy_routes(&network("mgmt=10.88.0.254", "mgmt:100:from=fd00::/64")).is_err()
+        );
+        // different default routes in one table
+        assert!(get_policy_routes(&network(
+            "a=10.88.0.1,b=10.88.0.2",
+            "a:100:from=10.88.1.0/24,b:100:from=10.88.2.0/24"
+        ))
+        .is_err());
+        // bad selector
+        assert!(get_policy_routes(&network("mgmt=10.88.0.254", "mgmt:100:to=10.0.0.0/8")).is_err());
+    }
+
+    // Test that the host routes are only part of response version 6
+    #[test]
+    fn test_response_host_routes() {
+        use netavark::network::response::serialize_response;
+        use netavark::network::types::{HostRoute, StatusBlock};
+        use std::collections::HashMap;
+
+        let status = StatusBlock {
+            dns_search_domains: None,
+            dns_server_ips: None,
+            interfaces: None,
+            port_proxies: None,
+            default_gateways: None,
+            policy_routes: None,
+            host_routes: Some(vec![HostRoute {
+                destination: "10.88.0.2/32".parse().unwrap(),
+                interface: "podman0".to_string(),
+            }]),
+            nat64_prefix: None,
+            interface_index: None,
+            bridge: None,
+        };
+        let response = HashMap::from([("podman".to_string(), status)]);
+
+        let latest = serialize_response(&response, 6).unwrap();
+        assert!(
+            latest.contains(
+                r#""host_routes":[{"destination":"10.88.0.2/32","interface":"podman0"}]"#
+            ),
+            "{}",
+            latest
+        );
+        let old = serialize_response(&response, 5).unwrap();
+        assert!(!old.contains("host_routes"), "{}", old);
+    }
+
+    #[test]
+    fn test_default_options() {
+        let mut opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/twoNetworks.json".to_owned(),
+        ))
+        .unwrap();
+        opts.network_info.get_mut("podman1").unwrap().options = Some(
+            vec![("mtu".to_string(), "1500".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let vars = [
+            ("NETAVARK_DEFAULT_MTU", "1400"),
+            ("NETAVARK_DEFAULT_ISOLATE", "false"),
+            ("NETAVARK_DEFAULT_BRIDGE_ISOLATE", "true"),
+            ("NETAVARK_DEFAULT_MACVLAN_MODE", "passthru"),
+            ("NETAVARK_DEFAULT_METRIC", ""),
+            ("NETAVARK_FW", "none"),
+        ];
+        opts.apply_default_options(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+
+        let options = |name: &str| opts.network_info[name].options.clone().unwrap();
+        // the network config wins over the defaults
+        assert_eq!(options("podman1")["mtu"], "1500");
+        assert_eq!(options("podman2")["mtu"], "1400");
+        // the driver specific default wins over the generic one
+        assert_eq!(options("podman1")["isolate"], "true");
+        assert_eq!(options("podman2").len(), 2);
+
+        // an alias in the config or in a default counts as set
+        opts.network_info.get_mut("podman1").unwrap().options = Some(
+            vec![("route_metric".to_string(), "200".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        opts.network_info.get_mut("podman2").unwrap().options = None;
+        opts.network_info.get_mut("podman2").unwrap().driver = "my-plugin".to_string();
+        let vars = [
+            ("NETAVARK_DEFAULT_METRIC", "100"),
+            ("NETAVARK_DEFAULT_BRIDGE_ROUTE_METRIC", "150"),
+        ];
+        opts.apply_default_options(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        let options = opts.network_info["podman1"].options.clone().unwrap();
+        assert_eq!(options.len(), 1);
+        assert_eq!(options["route_metric"], "200");
+        // plugin networks get no defaults
+        assert_eq!(opts.network_info["podman2"].options, None);
+    }
+
+    #[test]
+    fn test_response_key_by_interface() {
+        use netavark::network::response::key_by_interface;
+        use netavark::network::types::StatusBlock;
+        use std::collections::HashMap;
+
+        let opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/twoNetworks.json".to_owned(),
+        ))
+        .unwrap();
+        let response: HashMap<String, StatusBlock> = serde_json::from_str(
+            r#"{"podman1":{"dns_server_ips":["10.89.0.1"]},"podman2":{},"other":{}}"#,
+        )
+        .unwrap();
+
+        let response = key_by_interface(response, &opts.networks);
+        let mut keys: Vec<&String> = response.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["eth0", "eth1", "other"]);
+        assert_eq!(
+            response["eth0"].dns_server_ips,
+            Some(vec!["10.89.0.1".parse().unwrap()])
+        );
+    }
+
+    #[test]
+    fn test_script_quoting() {
+        use netavark::network::script::{self, Script};
+
+        assert_eq!(script::quote("10.88.0.1/16"), "10.88.0.1/16");
+        assert_eq!(script::quote("a b"), "'a b'");
+        assert_eq!(script::quote("it's"), "'it'\\''s'");
+        assert_eq!(script::quote(""), "''");
+        assert_eq!(script::quote(script::NETNS), "\"$NETNS\"");
+
+        let mut s = Script::new("abc", "/run/netns/my ns");
+        s.comment("network podman");
+        s.host_unless(
+            &["ip", "link", "show", "podman0"],
+            &["ip", "link", "add", "podman0"],
+        );
+        s.container(&["ip", "link", "set", "lo", "up"]);
+        assert_eq!(
+            s.to_string(),
+            "#!/bin/sh\n# netavark setup of container abc\nset -e\n\
+             NETNS='/run/netns/my ns'\n\n# network podman\n\
+             ip link show podman0 >/dev/null 2>&1 || ip link add podman0\n\
+             nsenter --net=\"$NETNS\" ip link set lo up\n"
+        );
+    }
+
+    #[test]
+    fn test_network_order() {
+        let mut opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/twoNetworks.json".to_owned(),
+        ))
+        .unwrap();
+        let mut third = opts.network_info["podman2"].clone();
+        third.name = "podman0".to_string();
+        opts.network_info.insert("podman0".to_string(), third);
+        let mut third_opts = opts.networks["podman2"].clone();
+        third_opts.interface_name = "eth2".to_string();
+        opts.networks.insert("podman0".to_string(), third_opts);
+
+        // sorted by name without an index
+        assert_eq!(opts.network_order(), vec!["podman0", "podman1", "podman2"]);
+
+        // the networks with an index come first
+        opts.networks.get_mut("podman2").unwrap().interface_index = Some(1);
+        opts.networks.get_mut("podman1").unwrap().interface_index = Some(0);
+        assert_eq!(opts.network_order(), vec!["podman1", "podman2", "podman0"]);
+        assert!(opts.validate_interface_indexes().is_ok());
+
+        opts.networks.get_mut("podman0").unwrap().interface_index = Some(1);
+        let err = opts.validate_interface_indexes().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "interface_index 1 is used by the networks podman0 and podman2"
+        );
+    }
+
+    // Test that an override config is deep merged into the base config
+    #[test]
+    fn test_setup_opts_load_merged() {
+        let base = "src/test/config/setupopts.test.json";
+        let layer = "src/test/config/merge-override.yaml";
+        let opts =
+            network::types::NetworkOptions::load_merged(base, layer).expect("load merged config");
+        assert_eq!(opts.container_name, "overridden");
+        assert_eq!(opts.container_id, "6ce776ea58b5");
+        let ports = opts.port_mappings.expect("port mappings");
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0].host_port, 8080);
+        let network = &opts.networks["defaultNetwork"];
+        assert_eq!(network.interface_name, "eth0");
+        assert_eq!(network.aliases, Some(vec!["web".to_string()]));
+        let info = &opts.network_info["defaultNetwork"];
+        assert!(!info.dns_enabled);
+        assert_eq!(info.network_interface.as_deref(), Some("podman0"));
+        assert_eq!(info.options.as_ref().unwrap()["mtu"], "1400");
+
+        let opts = network::types::NetworkOptions::load_merged_with(
+            base,
+            layer,
+            network::ListMerge::Append,
+        )
+        .expect("load merged config");
+        let ports = opts.port_mappings.expect("port mappings");
+        assert_eq!(ports.len(), 2);
+        assert_eq!(ports[0].host_port, 5001);
+        assert_eq!(ports[1].host_port, 8080);
+    }
 }
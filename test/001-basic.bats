@@ -26,3 +26,86 @@ load helpers
     expected_rc=1 run_netavark -f /test/1 setup $(get_container_netns_path)
     assert_json ".error" "failed to load network options: IO error: No such file or directory (os error 2)" "Config file does not exists"
 }
+
+@test "netavark error - structured config errors" {
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(sed 's/"network_interface": "podman0",/"network_interface": "podman0", "options": {"mtu": "abc"},/' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "unable to parse \"mtu\": invalid digit found in string" "error message"
+    assert_json ".kind" "invalid_value" "error kind"
+    assert_json ".network" "podman" "error network"
+    assert_json ".field" "options.mtu" "error field"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"{"
+    assert_json ".kind" "decode" "decode error kind"
+    assert_json ".line" "1" "decode error line"
+
+    # malformed json is not mistaken for yaml
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<'  {"container_id":'
+    assert "$output" !~ "YAML" "json error for json after whitespace"
+    expected_rc=1 run_netavark setup $(get_container_netns_path) </dev/null
+    assert_json ".message" "EOF while parsing a value at line 1 column 0" "json error for empty input"
+}
+
+@test "netavark metrics file" {
+    metrics=$NETAVARK_TMPDIR/netavark.prom
+    run_netavark --metrics-file $metrics --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_netavark --metrics-file $metrics --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    expected_rc=1 run_netavark --metrics-file $metrics --file ${TESTSDIR}/testfiles/invalid-port.json setup $(get_container_netns_path)
+
+    run cat $metrics
+    assert "$output" =~ "# TYPE netavark_setup_total counter" "metric type"
+    assert "$output" =~ "netavark_setup_total 2" "setup runs"
+    assert "$output" =~ "netavark_setup_errors_total 1" "failed setup runs"
+    assert "$output" =~ "netavark_teardown_total 1" "teardown runs"
+    assert "$output" =~ "netavark_teardown_errors_total 0" "failed teardown runs"
+    assert "$output" =~ "netavark_rules_programmed [0-9]+" "programmed rules"
+}
+
+@test "netavark log lines contain the netns path" {
+    RUST_LOG=info run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert "$output" =~ "\[INFO netavark::network::netlink netns=$(get_container_netns_path)\] Adding route" "log line with netns"
+
+    RUST_LOG=info run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    assert "$output" =~ "netns=$(get_container_netns_path)\] removing bridge podman0" "teardown log line with netns"
+}
+
+@test "netavark setup with --container-id" {
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --container-id aed1ab81e3a5 $(get_container_netns_path)
+    assert_json ".field" "container_id" "error field"
+    assert_json ".error" "container id 6ce776ea58b5 does not match --container-id aed1ab81e3a5" "mismatched container id"
+    expected_rc=1 run_in_host_netns ip link show podman0
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --container-id 6ce776ea58b5 $(get_container_netns_path)
+    assert_json "$output" ".podman.interfaces.eth0.subnets[0].ipnet" == "10.88.0.2/16" "setup with matching container id"
+}
+
+@test "netavark setup and teardown hooks" {
+    hook_out=$NETAVARK_TMPDIR/hook
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --on-setup "cat > $hook_out.json; env > $hook_out.env; echo hook output" $(get_container_netns_path)
+    assert "$output" =~ "hook output" "hook output goes to stderr"
+    # the response must still be valid json
+    assert_json "$(sed -n '/^{/p' <<<"$output")" ".podman.interfaces.eth0.subnets[0].ipnet" == "10.88.0.2/16" "setup response"
+
+    run cat $hook_out.env
+    assert "$output" =~ "NETAVARK_HOOK_EVENT=setup" "event env"
+    assert "$output" =~ "NETAVARK_CONTAINER_ID=6ce776ea58b5" "container id env"
+    assert "$output" =~ "NETAVARK_NETWORKS=podman" "networks env"
+    assert "$output" =~ "NETAVARK_ADDRESSES=10.88.0.2" "addresses env"
+    run jq -r '.status.podman.interfaces.eth0.subnets[0].ipnet' $hook_out.json
+    assert "$output" == "10.88.0.2/16" "status in the hook input"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown --on-teardown "exit 1" $(get_container_netns_path)
+
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --on-setup "exit 3" --hook-errors-fatal $(get_container_netns_path)
+    assert_json ".error" "hook \`exit 3\` failed with exit status: 3" "fatal hook error"
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "netavark setup reports all unknown drivers" {
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.driver="brigde"' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "unknown network driver \"brigde\"" "single unknown driver"
+    assert_json ".network" "podman" "network of the unknown driver"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.t1.driver="foo" | .network_info.t2.driver="bar"' ${TESTSDIR}/testfiles/two-networks.json)"
+    assert_json ".error" "unknown network drivers \"foo\" (network t1), \"bar\" (network t2)" "all unknown drivers are listed"
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
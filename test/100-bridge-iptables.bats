@@ -111,6 +111,31 @@ fw_driver=iptables
     expected_rc=1 run_in_host_netns ip addr show podman0
 }
 
+@test "$fw_driver - setup hook runs after the dns entries are committed" {
+    dns_port=$((RANDOM+10000))
+    rootless=false
+    if [[ ! -e "/run/dbus/system_bus_socket" ]]; then
+        rootless=true
+    fi
+    config="$NETAVARK_TMPDIR/config"
+    mkdir -p "$config"
+
+    NETAVARK_DNS_PORT="$dns_port" run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge-network-container-dns-server.json \
+        --rootless "$rootless" --config "$config" \
+        setup --on-setup "cat $config/aardvark-dns/podman1 > $NETAVARK_TMPDIR/hook-dns" $(get_container_netns_path)
+    run_helper cat "$NETAVARK_TMPDIR/hook-dns"
+    assert "${lines[1]}" =~ "10.89.3.2 fd10:88:a::2 somename" "the hook sees the dns entry"
+    NETAVARK_DNS_PORT="$dns_port" run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge-network-container-dns-server.json \
+        --rootless "$rootless" --config "$config" teardown $(get_container_netns_path)
+
+    # a fatal hook error removes the dns entry again
+    NETAVARK_DNS_PORT="$dns_port" expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge-network-container-dns-server.json \
+        --rootless "$rootless" --config "$config" \
+        setup --on-setup "exit 1" --hook-errors-fatal $(get_container_netns_path)
+    expected_rc=1 run_helper cat "$config/aardvark-dns/podman1"
+    expected_rc=2 run_helper ls "$config/containers/f031bf33eecba75d0d84952337b1ceef6a239eb8e94b48aee0993d0791345325.json"
+}
+
 @test "$fw_driver - bridge driver must generate config for aardvark with multiple custom dns server with network dns servers and perform update" {
     # get a random port directly to avoid low ports e.g. 53 would not create iptables
     dns_port=$((RANDOM+10000))
@@ -377,6 +402,19 @@ fw_driver=iptables
     assert_json ".error" "create veth pair: interface eth0 already exists on container namespace: Netlink error: File exists (os error 17)" "interface exists on netns"
 }
 
+@test "$fw_driver - check error message for an existing host veth" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns ip -j link show master podman0
+    host_veth=$(jq -r '.[0].ifname' <<<"$output")
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    # stale host interface with the name of the container veth
+    run_in_host_netns ip link add $host_veth type veth peer name stale0
+
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".error" "create veth pair: interface $host_veth already exists on the host: Netlink error: File exists (os error 17)" "interface exists on the host"
+}
+
 @test "$fw_driver - port forwarding ipv4 - tcp" {
     test_port_fw
 }
@@ -763,7 +801,6 @@ EOF
     assert_json "$result" 'has("t1")' == "true" "t1 object key exists"
     assert_json "$result" 'has("t2")' == "true" "t2 object key exists"
 
-    run_in_container_netns ip link del eth0
     run_in_container_netns ip link del eth1
 
     run_in_host_netns iptables -S -t nat
@@ -821,4 +858,1079 @@ EOF
 @test "netavark error - invalid host_ip in port mappings" {
     expected_rc=1 run_netavark -f ${TESTSDIR}/testfiles/invalid-port.json setup $(get_container_netns_path)
     assert_json ".error" "invalid host ip \"abcd\" provided for port 8080" "host ip error"
+
+    # nothing is left behind by the failed setup
+    expected_rc=1 run_in_container_netns ip link show eth0
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "$fw_driver - existing host veth" {
+    run_in_host_netns ip link add veth-host0 type veth peer name veth-peer0
+
+    run_netavark --file ${TESTSDIR}/testfiles/existing-veth.json setup $(get_container_netns_path)
+
+    run_in_container_netns ip -j --details link show eth0
+    assert_json "$output" ".[].linkinfo.info_kind" "==" "veth" "Container interface is the existing veth peer"
+
+    run_in_host_netns ip -j link show veth-host0
+    assert_json "$output" ".[].master" "==" "podman0" "Existing host veth is attached to the bridge"
+
+    run_netavark --file ${TESTSDIR}/testfiles/existing-veth.json teardown $(get_container_netns_path)
+
+    run_in_host_netns ip -j link show veth-host0
+    assert_json "$output" ".[].master" "==" "null" "Existing host veth is detached but not deleted"
+
+    run_in_host_netns ip link add dummy1 type dummy
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(sed 's/veth-host0/dummy1/' ${TESTSDIR}/testfiles/existing-veth.json)"
+    assert_json ".error" "existing host interface dummy1 is not a veth" "non veth interface is rejected"
+}
+
+@test "$fw_driver - existing bridge with addresses" {
+    run_in_host_netns ip link add podman0 type bridge
+    run_in_host_netns ip addr add 10.88.0.1/16 dev podman0
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    run_in_host_netns ip -j addr show podman0
+    assert_json "$output" '[.[].addr_info[] | select(.local == "10.88.0.1")] | length' "==" "1" "gateway address is not duplicated"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    run_in_host_netns ip link show podman0
+    run_in_host_netns ip link del podman0
+
+    run_in_host_netns ip link add podman0 type bridge
+    run_in_host_netns ip addr add 10.88.0.1/24 dev podman0
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".error" "bridge podman0 already has address 10.88.0.1/24 which conflicts with gateway address 10.88.0.1/16" "conflicting address is rejected"
+}
+
+@test "$fw_driver - interface alias" {
+    run_netavark --file ${TESTSDIR}/testfiles/ifalias.json setup $(get_container_netns_path)
+
+    run_in_host_netns ip -j link show podman0
+    assert_json "$output" ".[].ifalias" "==" "netavark network=podman" "bridge alias is set"
+
+    run_in_host_netns ip -j link show master podman0
+    assert_json "$output" ".[].ifalias" "==" "netavark container=6ce776ea58b5 network=podman" "host veth alias is set"
+}
+
+@test "$fw_driver - trusted network" {
+    run_netavark --file ${TESTSDIR}/testfiles/trusted.json setup $(get_container_netns_path)
+
+    run_in_host_netns iptables -S FORWARD
+    assert "${lines[1]}" == "-A FORWARD -j NETAVARK-TR-1D8721804F16F" "trusted FORWARD rule is first"
+    assert "${lines[2]}" == "-A FORWARD -m comment --comment \"netavark firewall plugin rules\" -j NETAVARK_FORWARD" "FORWARD rule"
+
+    run_in_host_netns iptables -S NETAVARK-TR-1D8721804F16F
+    assert "${lines[1]}" == "-A NETAVARK-TR-1D8721804F16F -s 10.88.0.0/16 -j ACCEPT" "trusted rule 1"
+    assert "${lines[2]}" == "-A NETAVARK-TR-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT" "trusted rule 2"
+
+    run_netavark --file ${TESTSDIR}/testfiles/trusted.json teardown $(get_container_netns_path)
+
+    run_in_host_netns iptables -S FORWARD
+    assert "${#lines[@]}" = 2 "trusted FORWARD rule removed on teardown"
+    expected_rc=1 run_in_host_netns iptables -nvL NETAVARK-TR-1D8721804F16F
+}
+
+@test "$fw_driver - skip sysctl" {
+    run_in_host_netns sh -c "echo 0 > /proc/sys/net/ipv4/ip_forward"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --skip-sysctl $(get_container_netns_path)
+
+    run_in_host_netns cat /proc/sys/net/ipv4/ip_forward
+    assert "$output" == "0" "ip_forward sysctl was not changed"
+}
+
+@test "$fw_driver - bridge port options" {
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-options.json setup $(get_container_netns_path)
+
+    run_in_host_netns ip -j -d link show master podman0
+    assert_json "$output" ".[].linkinfo.info_slave_data.hairpin" "==" "false" "hairpin is off"
+    assert_json "$output" ".[].linkinfo.info_slave_data.guard" "==" "true" "bpdu guard is on"
+    assert_json "$output" ".[].linkinfo.info_slave_data.learning" "==" "false" "learning is off"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-port-options.json teardown $(get_container_netns_path)
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(sed 's/"hairpin": "false"/"hairpin": "true"/' ${TESTSDIR}/testfiles/bridge-port-options.json)"
+    assert_json ".error" "hairpin=true cannot be used together with learning=false" "invalid combination is rejected"
+}
+
+@test "$fw_driver - port forwarding with userspace proxy" {
+    run_netavark --file ${TESTSDIR}/testfiles/port-proxy.json setup $(get_container_netns_path)
+    assert_json ".podman.port_proxies[0].host_port" "8080" "proxy mapping is reported"
+
+    port_is_bound $HOST_NS_PID 8080 tcp
+    run_in_host_netns iptables -S NETAVARK-HOSTPORT-DNAT -t nat
+    assert "$output" !~ "8080" "no DNAT rule for the proxied port"
+
+    run_netavark --file ${TESTSDIR}/testfiles/port-proxy.json teardown $(get_container_netns_path)
+    port_is_free $HOST_NS_PID 8080 tcp
+
+    # the old response format does not know about the proxy
+    run_netavark --file ${TESTSDIR}/testfiles/port-proxy.json setup --response-version 1 $(get_container_netns_path)
+    assert_json '.podman | has("port_proxies")' "false" "port_proxies omitted in version 1"
+    run_netavark --file ${TESTSDIR}/testfiles/port-proxy.json teardown $(get_container_netns_path)
+}
+
+@test "$fw_driver - lease ipam" {
+    config="$NETAVARK_TMPDIR/config"
+    run_netavark --config "$config" --file ${TESTSDIR}/testfiles/lease.json setup $(get_container_netns_path)
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "first free address is leased"
+    run_netavark --config "$config" --file ${TESTSDIR}/testfiles/lease.json teardown $(get_container_netns_path)
+
+    # another container must not get the address of the released lease
+    second=$(sed 's/6ce776ea58b5/a1b2c3d4e5f6/;s/"eth0"/"eth1"/' ${TESTSDIR}/testfiles/lease.json)
+    run_netavark --config "$config" setup $(get_container_netns_path) <<<"$second"
+    assert_json ".podman.interfaces.eth1.subnets[0].ipnet" "10.88.0.3/16" "next address for second container"
+    run_netavark --config "$config" teardown $(get_container_netns_path) <<<"$second"
+
+    # the first container gets its address back
+    run_netavark --config "$config" --file ${TESTSDIR}/testfiles/lease.json setup $(get_container_netns_path)
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "previous address is reused"
+    run_netavark --config "$config" --file ${TESTSDIR}/testfiles/lease.json teardown $(get_container_netns_path)
+
+    # the lease is only taken by the setup, not when another network fails to validate
+    other=$(jq '.network_info.podman.name="podman2" |
+        .network_info.podman.options={"mtu":"abc"} | .network_info={"podman2":.network_info.podman} |
+        .networks={"podman2":{"interface_name":"eth1"}}' ${TESTSDIR}/testfiles/simplebridge.json)
+    invalid=$(jq --argjson other "$other" '.network_info += $other.network_info | .networks += $other.networks |
+        .container_id="a0b1c2d3e4f5"' ${TESTSDIR}/testfiles/lease.json)
+    expected_rc=1 run_netavark --config "$config" setup $(get_container_netns_path) <<<"$invalid"
+    run_helper cat "$config/ipam-lease/podman.json"
+    assert "$output" !~ "a0b1c2d3e4f5" "no lease after the validation failed"
+}
+
+@test "$fw_driver - forward policy" {
+    run_netavark --file ${TESTSDIR}/testfiles/forward-policy.json setup $(get_container_netns_path)
+
+    run_in_host_netns iptables -S FORWARD
+    assert "${lines[1]}" == "-A FORWARD -s 10.88.0.0/16 -j NETAVARK-PL-1D8721804F16F" "policy FORWARD rule is first"
+
+    run_in_host_netns iptables -S NETAVARK-PL-1D8721804F16F
+    assert "${lines[1]}" == "-A NETAVARK-PL-1D8721804F16F -m conntrack --ctstate RELATED,ESTABLISHED -j RETURN" "policy rule 1"
+    assert "${lines[2]}" == "-A NETAVARK-PL-1D8721804F16F -d 10.88.0.0/16 -p tcp -m tcp --dport 80 -j RETURN" "policy rule 2"
+    assert "${lines[3]}" == "-A NETAVARK-PL-1D8721804F16F -p udp -m udp --dport 53 -j RETURN" "policy rule 3"
+    assert "${lines[4]}" == "-A NETAVARK-PL-1D8721804F16F -p icmp -j RETURN" "policy rule 4"
+    assert "${lines[5]}" == "-A NETAVARK-PL-1D8721804F16F -j DROP" "default action"
+
+    run_netavark --file ${TESTSDIR}/testfiles/forward-policy.json teardown $(get_container_netns_path)
+
+    run_in_host_netns iptables -S FORWARD
+    assert "${#lines[@]}" = 2 "policy FORWARD rule removed on teardown"
+    expected_rc=1 run_in_host_netns iptables -nvL NETAVARK-PL-1D8721804F16F
+}
+
+@test "$fw_driver - forward policy with invalid allow entry" {
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(sed 's|tcp/80@10.88.0.0/16|tcp/http|' ${TESTSDIR}/testfiles/forward-policy.json)"
+    assert_json ".field" "options.allow" "error field"
+    assert_json ".error" "invalid allow entry \"tcp/http\": invalid port \"http\": invalid digit found in string" "error message"
+}
+
+@test "$fw_driver - static neighbors" {
+    run_netavark --file ${TESTSDIR}/testfiles/static-neighbors.json setup $(get_container_netns_path)
+
+    run_in_container_netns ip neigh show dev eth0
+    assert "${lines[0]}" == "10.88.0.10 lladdr aa:bb:cc:dd:ee:ff PERMANENT" "first neighbor"
+    assert "${lines[1]}" == "10.88.0.11 lladdr aa:bb:cc:dd:ee:00 PERMANENT" "second neighbor"
+
+    run_netavark --file ${TESTSDIR}/testfiles/static-neighbors.json teardown $(get_container_netns_path)
+    run_in_container_netns ip neigh show
+    assert "$output" == "" "neighbors removed with the interface"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(sed 's/10.88.0.11=/10.89.0.11=/' ${TESTSDIR}/testfiles/static-neighbors.json)"
+    assert_json ".error" "invalid static neighbor \"10.89.0.11=aa:bb:cc:dd:ee:00\": ip is not part of a network subnet" "off-link neighbor is rejected"
+}
+
+@test "$fw_driver - firewall rules in container namespace" {
+    run_netavark --file ${TESTSDIR}/testfiles/firewall-netns.json setup $(get_container_netns_path)
+
+    run_in_container_netns iptables -t nat -S POSTROUTING
+    assert "$output" =~ "-A POSTROUTING -s 10.88.0.0/16 -j NETAVARK-1D8721804F16F" "nat rule in container namespace"
+    run_in_host_netns iptables -t nat -S POSTROUTING
+    assert "$output" !~ "NETAVARK-1D8721804F16F" "no nat rule on the host"
+
+    run_netavark --file ${TESTSDIR}/testfiles/firewall-netns.json teardown $(get_container_netns_path)
+
+    run_in_container_netns iptables -t nat -S POSTROUTING
+    assert "$output" !~ "NETAVARK-1D8721804F16F" "nat rule removed on teardown"
+    expected_rc=1 run_in_container_netns iptables -t nat -nvL NETAVARK-1D8721804F16F
+}
+
+@test "$fw_driver - reload firewall rules" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    # simulate a firewall reload which removed all rules
+    run_in_host_netns iptables -t nat -F POSTROUTING
+    run_in_host_netns iptables -t nat -F NETAVARK-1D8721804F16F
+    run_in_host_netns iptables -F NETAVARK_FORWARD
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json reload $(get_container_netns_path)
+    assert "$output" == "" "reload prints nothing"
+
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "${lines[2]}" =~ "-A POSTROUTING -s 10.88.0.0/16 -j NETAVARK-1D8721804F16F" "POSTROUTING container rule restored"
+    run_in_host_netns iptables -S NETAVARK-1D8721804F16F -t nat
+    assert "${lines[2]}" == "-A NETAVARK-1D8721804F16F ! -d 224.0.0.0/4 -j MASQUERADE" "MASQUERADE rule restored"
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${lines[2]}" == "-A NETAVARK_FORWARD -s 10.88.0.0/16 -j ACCEPT" "NETAVARK_FORWARD rule restored"
+
+    # a second reload must not duplicate rules
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json reload $(get_container_netns_path)
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${#lines[@]}" = 3 "no duplicated NETAVARK_FORWARD rules"
+
+    # the container interface is untouched
+    run_in_container_netns ip addr show eth0
+    assert "$output" =~ "10.88.0.2/16" "container address still set"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+}
+
+@test "$fw_driver - two networks setup in parallel" {
+    for parallel in 1 2; do
+        run_netavark --file ${TESTSDIR}/testfiles/two-networks.json setup --max-parallel $parallel $(get_container_netns_path)
+        result="$output"
+        assert_json "$result" ".t1.interfaces.eth0.subnets[0].ipnet" == "10.89.1.2/24" "t1 address with --max-parallel $parallel"
+        assert_json "$result" ".t2.interfaces.eth1.subnets[0].ipnet" == "10.89.2.2/24" "t2 address with --max-parallel $parallel"
+
+        run_in_host_netns iptables -S -t nat
+        assert "$output" =~ "--to-destination 10.89.1.2:8080" "eth0 port fw rule exists"
+        assert "$output" =~ "--to-destination 10.89.2.2:8080" "eth1 port fw rule exists"
+
+        run_netavark --file ${TESTSDIR}/testfiles/two-networks.json teardown $(get_container_netns_path)
+    done
+
+    expected_rc=2 run_netavark --file ${TESTSDIR}/testfiles/two-networks.json setup --max-parallel 0 $(get_container_netns_path)
+    assert "$output" =~ "must be at least 1" "zero is rejected"
+}
+
+@test "$fw_driver - two networks share a bridge setup in parallel" {
+    config=$(jq '.network_info.t2.network_interface="podman2"' ${TESTSDIR}/testfiles/two-networks.json)
+    # both setups want to create the bridge at the same time
+    for i in 1 2 3 4 5; do
+        run_netavark --config "$NETAVARK_TMPDIR/config" setup --max-parallel 2 $(get_container_netns_path) <<<"$config"
+        result="$output"
+        assert_json "$result" ".t1.interfaces.eth0.subnets[0].ipnet" == "10.89.1.2/24" "t1 address in run $i"
+        assert_json "$result" ".t2.interfaces.eth1.subnets[0].ipnet" == "10.89.2.2/24" "t2 address in run $i"
+
+        run_in_host_netns ip addr show podman2
+        assert "$output" =~ "10.89.1.1/24" "t1 gateway on the bridge in run $i"
+        assert "$output" =~ "10.89.2.1/24" "t2 gateway on the bridge in run $i"
+
+        run_netavark --config "$NETAVARK_TMPDIR/config" teardown $(get_container_netns_path) <<<"$config"
+        expected_rc=1 run_in_host_netns ip link show podman2
+    done
+}
+
+@test "$fw_driver - route metric selects default gateway" {
+    config=$(jq '.network_info.t1.options={"route_metric":"200"} | .network_info.t2.options={"route_metric":"150"}' ${TESTSDIR}/testfiles/two-networks.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    result="$output"
+    assert_json "$result" ".t2.default_gateways[0]" == "10.89.2.1" "lowest metric network is the default gateway"
+    assert_json "$result" ".t1 | has(\"default_gateways\")" == "false" "no default gateway for higher metric network"
+
+    run_in_container_netns ip -4 route show default
+    assert "${lines[0]}" == "default via 10.89.2.1 dev eth1 proto static metric 150 " "preferred default route"
+    assert "${lines[1]}" == "default via 10.89.1.1 dev eth0 proto static metric 200 " "second default route"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.t1.options={"route_metric":"-1"}' ${TESTSDIR}/testfiles/two-networks.json)"
+    assert_json ".field" "options.route_metric" "error field"
+    assert_json ".error" "invalid route_metric -1: must be between 0 and 4294967295" "negative metric is rejected"
+}
+
+@test "$fw_driver - persist firewall rules" {
+    rules_dir=$NETAVARK_TMPDIR/rules
+    run_netavark --persist-rules-dir $rules_dir --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    run cat $rules_dir/netavark.rules
+    assert "$output" =~ "\*nat" "nat table"
+    assert "$output" =~ ":NETAVARK-1D8721804F16F - \[0:0\]" "network chain"
+    assert "$output" =~ "-A POSTROUTING -s 10.88.0.0/16 -j NETAVARK-1D8721804F16F" "jump rule"
+    assert "$output" =~ "-A NETAVARK-1D8721804F16F ! -d 224.0.0.0/4 -j MASQUERADE" "masquerade rule"
+
+    # restore the rules after they were flushed
+    run_in_host_netns iptables -t nat -F POSTROUTING
+    run_in_host_netns iptables -t nat -F NETAVARK-1D8721804F16F
+    run_in_host_netns sh -c "iptables-restore --noflush < $rules_dir/netavark.rules"
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "$output" =~ "-A POSTROUTING -s 10.88.0.0/16 -j NETAVARK-1D8721804F16F" "jump rule restored"
+
+    run_netavark --persist-rules-dir $rules_dir --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    run cat $rules_dir/netavark.rules
+    assert "$output" !~ "NETAVARK-1D8721804F16F" "network chain removed after teardown"
+}
+
+@test "$fw_driver - computed gateway position" {
+    config=$(jq 'del(.network_info.podman.subnets[0].gateway) | .network_info.podman.options={"gateway_position":"last"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces.eth0.subnets[0].gateway" "10.88.255.254" "gateway in response"
+
+    run_in_host_netns ip addr show podman0
+    assert "$output" =~ "10.88.255.254/16" "gateway address on the bridge"
+    run_in_container_netns ip -4 route show default
+    assert "$output" =~ "default via 10.88.255.254" "default route via the gateway"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.subnets[0].gateway="10.88.255.255"' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".field" "subnets.gateway" "error field"
+    assert_json ".error" "gateway 10.88.255.255 is not a usable address of subnet 10.88.0.0/16" "broadcast gateway is rejected"
+}
+
+@test "$fw_driver - second container on a bridge only adds its veth" {
+    config1=$(cat ${TESTSDIR}/testfiles/simplebridge.json)
+    config2=$(jq '.container_id="aed1ab81e3a5" | .networks.podman.static_ips=["10.88.0.3"]' <<<"$config1")
+
+    RUST_LOG=debug run_netavark setup $(get_container_netns_path) <<<"$config1"
+    assert "$output" !~ "Firewall rules of network podman are already set up" "first container sets up the network"
+
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    forward_rules="$output"
+
+    create_container_ns
+    RUST_LOG=debug run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    assert "$output" =~ "Firewall rules of network podman are already set up" "second container skips the network setup"
+
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "$output" == "$forward_rules" "network rules not added again"
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "${#lines[@]}" = 3 "no duplicated POSTROUTING rules"
+
+    run_in_container_netns ping -c 1 10.88.0.3
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+    run_in_host_netns ip link show podman0
+
+    # missing rules of the network are added again, even with other containers on the bridge
+    run_in_host_netns iptables -t nat -F NETAVARK-1D8721804F16F
+    RUST_LOG=debug run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    assert "$output" !~ "Firewall rules of network podman are already set up" "network rules are set up again"
+    run_in_host_netns iptables -t nat -S NETAVARK-1D8721804F16F
+    assert "$output" =~ "-j MASQUERADE" "masquerade rule restored"
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config1"
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "$fw_driver - disable ipv6 on created interfaces" {
+    config=$(jq '.network_info.podman.options={"disable_ipv6":"true"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns cat /proc/sys/net/ipv6/conf/podman0/disable_ipv6
+    assert "$output" == "1" "ipv6 disabled on the bridge"
+    run_in_container_netns cat /proc/sys/net/ipv6/conf/eth0/disable_ipv6
+    assert "$output" == "1" "ipv6 disabled on the container veth"
+
+    run_in_host_netns ip -6 addr show podman0
+    assert "$output" == "" "no ipv6 address on the bridge"
+    run_in_container_netns ip -6 addr show eth0
+    assert "$output" == "" "no link-local address on the container veth"
+    run_in_host_netns ip -6 -o addr show
+    assert "$output" !~ "veth" "no ipv6 address on the host veth"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info[].options={"disable_ipv6":"true"}' ${TESTSDIR}/testfiles/dualstack-bridge.json)"
+    assert_json ".field" "options.disable_ipv6" "error field"
+    assert_json ".error" "disable_ipv6 cannot be used with ipv6 subnets" "ipv6 subnets are rejected"
+}
+
+@test "$fw_driver - conntrack zone" {
+    config=$(jq '.network_info.podman.options={"conntrack_zone":"7"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -t raw -S PREROUTING
+    assert "${lines[1]}" == "-A PREROUTING -i podman0 -j NETAVARK-CT-1D8721804F16F" "raw PREROUTING jumps to the zone chain"
+    run_in_host_netns iptables -t raw -S NETAVARK-CT-1D8721804F16F
+    assert "${lines[1]}" == "-A NETAVARK-CT-1D8721804F16F -j CT --zone-orig 7" "zone chain sets the zone"
+
+    run_in_container_netns ping -c 1 10.88.0.1
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t raw -S PREROUTING
+    assert "${#lines[@]}" = 1 "zone rule removed from raw PREROUTING"
+    expected_rc=1 run_in_host_netns iptables -t raw -S NETAVARK-CT-1D8721804F16F
+
+    for zone in 0 65536 foo; do
+        expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq --arg z $zone '.network_info.podman.options={"conntrack_zone":$z}' ${TESTSDIR}/testfiles/simplebridge.json)"
+        assert_json ".field" "options.conntrack_zone" "error field for zone $zone"
+    done
+    assert_json ".error" "unable to parse \"conntrack_zone\": invalid digit found in string" "non numeric zone"
+}
+
+@test "$fw_driver - leave the container interface down" {
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"bring_up":"false"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "bring_up=false is not supported with the default routes of a network with a gateway, routes need an interface which is up" "gateway needs the interface up"
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.internal=true | .network_info.podman.options={"bring_up":"false","gateways":"mgmt=10.88.0.254","policy_routes":"mgmt:100:from=10.88.1.0/24"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "bring_up=false is not supported with policy_routes, routes need an interface which is up" "policy routes need the interface up"
+
+    config=$(jq '.network_info.podman.internal=true | .network_info.podman.options={"bring_up":"false"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces.eth0.admin_state" "down" "admin state in the response"
+
+    run_in_container_netns ip -j link show eth0
+    assert_json '.[].flags | index("UP")' "null" "container interface is down"
+    run_in_container_netns ip -4 addr show eth0
+    assert "$output" =~ "10.88.0.2/16" "address is configured"
+    run_in_container_netns ip -4 route show default
+    assert "$output" == "" "no default route on the internal network"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".podman.interfaces.eth0.admin_state" "up" "interface is up by default"
+}
+
+@test "$fw_driver - masquerade source ports" {
+    for ports in random random-fully; do
+        config=$(jq --arg p $ports '.network_info.podman.options={"masquerade_ports":$p}' ${TESTSDIR}/testfiles/simplebridge.json)
+        run_netavark setup $(get_container_netns_path) <<<"$config"
+        run_in_host_netns iptables -t nat -S NETAVARK-1D8721804F16F
+        assert "${lines[2]}" == "-A NETAVARK-1D8721804F16F ! -d 224.0.0.0/4 -j MASQUERADE --$ports" "masquerade rule with --$ports"
+
+        run_netavark teardown $(get_container_netns_path) <<<"$config"
+        expected_rc=1 run_in_host_netns iptables -t nat -S NETAVARK-1D8721804F16F
+    done
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"masquerade_ports":"sequential"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "unknown masquerade_ports \"sequential\", must be preserve, random or random-fully" "invalid masquerade_ports"
+}
+
+@test "$fw_driver - policy routes via a secondary gateway" {
+    config=$(jq '.network_info.podman.options={"gateways":"mgmt=10.88.0.254","policy_routes":"mgmt:100:from=10.88.1.0/24,mgmt:101:fwmark=0x10"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.policy_routes[0].gateway" "10.88.0.254" "gateway in the response"
+    assert_json ".podman.policy_routes[0].table" "100" "table in the response"
+    assert_json ".podman.policy_routes[1].fwmark" "16" "fwmark in the response"
+
+    run_in_container_netns ip route show table 100
+    assert "$output" =~ "default via 10.88.0.254 dev eth0" "default route in table 100"
+    run_in_container_netns ip route show table 101
+    assert "$output" =~ "default via 10.88.0.254 dev eth0" "default route in table 101"
+    run_in_container_netns ip rule
+    assert "$output" =~ "from 10.88.1.0/24 lookup 100" "source rule"
+    assert "$output" =~ "fwmark 0x10 lookup 101" "fwmark rule"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_container_netns ip rule
+    assert "$output" !~ "lookup 10[01]" "rules are removed"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"gateways":"mgmt=10.88.0.254","policy_routes":"mgmt:254"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "invalid policy route \"mgmt:254\": table 254 is reserved" "reserved table"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"gateways":"mgmt=10.89.0.1"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "invalid gateway \"mgmt=10.89.0.1\": ip is not part of a network subnet" "unreachable gateway"
+}
+
+@test "$fw_driver - setup without port forwarding" {
+    config=$(jq '.port_mappings=[{"host_ip":"","container_port":80,"host_port":8080,"range":1,"protocol":"tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup --no-port-forward $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "network is still set up"
+
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "$output" !~ "8080" "no DNAT rule for the port mapping"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "$output" =~ "--dport 8080" "DNAT rule without the flag"
+}
+
+@test "$fw_driver - routed bridge mode" {
+    config=$(jq '.network_info.podman.options={"mode":"routed"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/32" "host address in the response"
+    assert_json ".podman.host_routes[0].destination" "10.88.0.2/32" "host route in the response"
+    assert_json ".podman.host_routes[0].interface" "podman0" "host route interface in the response"
+
+    run_in_host_netns ip -4 addr show podman0
+    assert "$output" =~ "10.88.0.1/32" "bridge has a host address"
+    run_in_host_netns ip -4 route show dev podman0
+    assert "$output" == "10.88.0.2 proto static scope link" "route to the container"
+
+    run_in_container_netns ip -4 addr show eth0
+    assert "$output" =~ "10.88.0.2/32" "container has a host address"
+    run_in_container_netns ip -4 route
+    assert "${lines[0]}" == "default via 10.88.0.1 dev eth0 proto static metric 100" "default route"
+    assert "${lines[1]}" == "10.88.0.1 dev eth0 proto static scope link" "route to the gateway"
+
+    # the firewall still uses the subnet
+    run_in_host_netns iptables -t nat -S NETAVARK-1D8721804F16F
+    assert "${lines[1]}" == "-A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT" "subnet rule"
+
+    run_in_container_netns ping -c 1 10.88.0.1
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns ip link show podman0
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"mode":"l3"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "unknown mode \"l3\", must be bridge or routed" "invalid mode"
+}
+
+@test "$fw_driver - rename conflicting container interface" {
+    run_in_container_netns ip link add eth0 type dummy
+    run_in_container_netns ip link add eth1 type dummy
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --interface-conflict rename $(get_container_netns_path)
+    assert_json ".podman.interfaces | keys[0]" "eth2" "next free name in the response"
+    run_in_container_netns ip -4 addr show eth2
+    assert "$output" =~ "10.88.0.2/16" "address on the renamed interface"
+    run_in_container_netns ip -j link show eth0
+    assert_json '.[].link_type' "ether" "existing interface is untouched"
+
+    # the teardown with the original config removes the renamed interface
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    expected_rc=1 run_in_container_netns ip link show eth2
+    run_in_container_netns ip link show eth0
+}
+
+@test "$fw_driver - nat64" {
+    config=$(jq '.network_info.podman1.options={"nat64":"true"}' ${TESTSDIR}/testfiles/ipv6-bridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "nat64 interface nat64 does not exist, the NAT64 translator (e.g. tayga) must be running" "translator must be running"
+
+    # stand-in for the tun interface of the translator
+    run_in_host_netns ip link add nat64 type dummy
+    run_in_host_netns ip link set nat64 up
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman1.nat64_prefix" "64:ff9b::/96" "prefix in the response"
+
+    run_in_host_netns ip -6 route show 64:ff9b::/96
+    assert "$output" =~ "64:ff9b::/96 dev nat64" "prefix route to the translator"
+    run_in_host_netns iptables -t nat -S POSTROUTING
+    assert "$output" =~ "-A POSTROUTING -s 192.168.255.0/24 -j NETAVARK-" "translated traffic is masqueraded"
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "$output" =~ "-A NETAVARK_FORWARD -s 192.168.255.0/24 -j ACCEPT" "translated traffic is forwarded"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t nat -S POSTROUTING
+    assert "$output" !~ "192.168.255.0/24" "masquerade rule removed"
+    run_in_host_netns ip -6 route show 64:ff9b::/96
+    assert "$output" == "" "prefix route removed with the last network"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"nat64":"true"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "nat64 requires a non internal network with only ipv6 subnets" "ipv4 subnets are rejected"
+}
+
+@test "$fw_driver - failed setup removes the interfaces it created" {
+    # fail the firewall setup after the bridge and the veth pair were created
+    mkdir -p $NETAVARK_TMPDIR/bin
+    cat >$NETAVARK_TMPDIR/bin/iptables <<EOF
+#!/bin/sh
+case "\$*" in
+*"-A NETAVARK_FORWARD"*) echo "injected failure" >&2; exit 1 ;;
+esac
+exec $(command -v iptables) "\$@"
+EOF
+    chmod +x $NETAVARK_TMPDIR/bin/iptables
+
+    run_in_host_netns ip link add nat64 type dummy
+    run_in_host_netns ip link set nat64 up
+    config=$(jq '.network_info.podman1.options={"nat64":"true"}' ${TESTSDIR}/testfiles/ipv6-bridge.json)
+    expected_rc=1 NETAVARK_IPTABLES_PATH=$NETAVARK_TMPDIR/bin/iptables run_netavark --config $NETAVARK_TMPDIR/config setup $(get_container_netns_path) <<<"$config"
+    assert "$output" =~ "injected failure" "setup failed in the firewall"
+
+    expected_rc=1 run_in_container_netns ip link show eth0
+    run_in_host_netns ip -o link show type veth
+    assert "$output" == "" "no host veth left"
+    expected_rc=1 run_in_host_netns ip link show podman1
+    expected_rc=1 run_helper test -e $NETAVARK_TMPDIR/config/bridges/podman1
+    run_in_host_netns ip -6 route show 64:ff9b::/96
+    assert "$output" == "" "no nat64 route left"
+}
+
+@test "$fw_driver - skip the conntrack state rule" {
+    config=$(jq '.network_info.podman.options={"ctstate_accept":"false"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "$output" !~ "ctstate" "no conntrack state rule"
+    assert "${lines[1]}" == "-A NETAVARK_FORWARD -s 10.88.0.0/16 -j ACCEPT" "outgoing rule is still added"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${lines[1]}" == "-A NETAVARK_FORWARD -d 10.88.0.0/16 -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT" "conntrack state rule by default"
+}
+
+@test "$fw_driver - verify connectivity after setup" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --verify-connectivity $(get_container_netns_path)
+    assert_json ".podman.interfaces.eth0.subnets[0].gateway" "10.88.0.1" "gateway in the response"
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    # the gateway does not answer
+    run_in_host_netns sysctl -w net.ipv4.icmp_echo_ignore_all=1
+    expected_rc=113 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --verify-connectivity $(get_container_netns_path)
+    assert_json ".error" "no connectivity: gateway 10.88.0.1 did not answer within 2s" "unreachable gateway"
+    expected_rc=1 run_in_container_netns ip link show eth0
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --verify-connectivity=warn $(get_container_netns_path)
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "setup succeeds with warn"
+}
+
+@test "$fw_driver - setup with a namespace fd" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --netns-fd 5 5<$(get_container_netns_path)
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "address in the response"
+    run_in_container_netns ip -o addr show eth0
+    assert "$output" =~ "10.88.0.2/16" "address configured in the namespace of the fd"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown --netns-fd 5 5<$(get_container_netns_path)
+    expected_rc=1 run_in_container_netns ip link show eth0
+
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --netns-fd 5 5</proc/self/ns/uts
+    assert_json ".error" "--netns-fd 5 is not a network namespace" "uts namespace fd is rejected"
+}
+
+@test "$fw_driver - bridge group_fwd_mask" {
+    config=$(jq '.network_info.podman.options={"group_fwd_mask":"0x4000"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -j -d link show podman0
+    assert_json "$output" ".[].linkinfo.info_data.group_fwd_mask" "==" "0x4000" "LLDP frames are forwarded"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"group_fwd_mask":"0x1"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "invalid group_fwd_mask 0x1: the bits 0-2 (STP, pause and LACP) cannot be forwarded" "restricted groups are rejected"
+}
+
+@test "$fw_driver - drain port mappings before teardown" {
+    config=$(jq '.port_mappings=[{"host_ip":"","container_port":80,"host_port":8080,"range":1,"protocol":"tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    nsenter -n -m -w -t $HOST_NS_PID $NETAVARK teardown --drain-timeout 3 $(get_container_netns_path) <<<"$config" &
+    teardown_pid=$!
+    sleep 1
+
+    # new connections are no longer forwarded, the container is still connected
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "$output" !~ "--dport 8080" "DNAT rule removed while draining"
+    run_in_container_netns ip -o addr show eth0
+    assert "$output" =~ "10.88.0.2/16" "interface kept while draining"
+
+    wait $teardown_pid
+    expected_rc=1 run_in_container_netns ip link show eth0
+}
+
+@test "$fw_driver - per container traffic accounting" {
+    config=$(jq '.network_info.podman.options={"accounting":"true"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -t mangle -S POSTROUTING
+    assert "$output" =~ "-A POSTROUTING -j NETAVARK-ACCOUNTING" "jump to the accounting chain"
+    run_in_host_netns iptables -t mangle -S NETAVARK-ACCOUNTING
+    assert "${lines[1]}" == "-A NETAVARK-ACCOUNTING -s 10.88.0.2/32 -m comment --comment \"accounting name: podman id: 6ce776ea58b5\" -j RETURN" "container accounting rule"
+
+    run_in_host_netns ip link add dummy0 type dummy
+    run_in_host_netns ip link set dummy0 up
+    run_in_host_netns ip route add 10.99.0.0/24 dev dummy0
+    # forwarded by the host, nobody answers
+    expected_rc=1 run_in_container_netns ping -c 3 -i 0.2 -W 1 10.99.0.5
+    run_netavark stats
+    assert_json '.["6ce776ea58b5"].podman.packets' "3" "echo requests are counted"
+    assert_json '.["6ce776ea58b5"].podman.bytes' "252" "bytes are counted"
+
+    run_netavark stats --container-id other
+    assert "$output" == "{}" "other containers have no counters"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t mangle -S NETAVARK-ACCOUNTING
+    assert "$output" !~ "10.88.0.2" "accounting rule removed"
+}
+
+@test "$fw_driver - setup response keyed by interface name" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path) --response-key interface_name
+    result="$output"
+    assert_json "$result" 'has("podman")' == "false" "response not keyed by network name"
+    assert_json "$result" ".eth0.interfaces.eth0.subnets[0].ipnet" == "10.88.0.2/16" "response keyed by interface name"
+}
+
+@test "$fw_driver - bridge vlan shared by networks" {
+    config10=$(jq '.network_info.podman.options={"vlan":"10"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    config20=$(jq '.container_id="aed1ab81e3a5" | .networks={"podman2": {"interface_name": "eth0", "static_ips": ["10.89.0.2"]}} |
+        .network_info={"podman2": (.network_info.podman | .name="podman2" | .options={"vlan":"20"} |
+        .subnets=[{"gateway": "10.89.0.1", "subnet": "10.89.0.0/24"}])}' ${TESTSDIR}/testfiles/simplebridge.json)
+    config10b=$(jq '.container_id="c1a4b9e1f2d3" | .networks.podman.static_ips=["10.88.0.3"]' <<<"$config10")
+
+    run_netavark setup $(get_container_netns_path) <<<"$config10"
+    create_container_ns
+    run_netavark setup $(get_container_netns_path 1) <<<"$config20"
+    create_container_ns
+    run_netavark setup $(get_container_netns_path 2) <<<"$config10b"
+
+    run_in_host_netns ip -d link show podman0
+    assert "$output" =~ "vlan_filtering 1" "vlan filtering enabled on the bridge"
+    run_in_host_netns ip addr show podman0.10
+    assert "$output" =~ "10.88.0.1/16" "gateway address on the vlan 10 interface"
+    run_in_host_netns ip addr show podman0.20
+    assert "$output" =~ "10.89.0.1/24" "gateway address on the vlan 20 interface"
+
+    run_in_host_netns bridge vlan show
+    assert "$output" =~ "10 PVID Egress Untagged" "container port untagged in vlan 10"
+    assert "$output" =~ "20 PVID Egress Untagged" "container port untagged in vlan 20"
+
+    run_in_container_netns 2 ping -c 1 -W 1 10.88.0.2
+    run_in_container_netns 1 ping -c 1 -W 1 10.89.0.1
+
+    # the vlan stays until its last container is gone
+    run_netavark teardown $(get_container_netns_path) <<<"$config10"
+    run_in_host_netns ip link show podman0.10
+    run_netavark teardown $(get_container_netns_path 2) <<<"$config10b"
+    expected_rc=1 run_in_host_netns ip link show podman0.10
+    run_in_host_netns ip link show podman0.20
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config20"
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "$fw_driver - bridge vlan validation" {
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"vlan":"4095"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "invalid vlan 4095: must be between 1 and 4094" "vlan id out of range"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"vlan":"10","mode":"routed"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "vlan cannot be used with routed mode" "vlan with routed mode"
+}
+
+@test "$fw_driver - gc removes the networks of orphaned containers" {
+    config1=$(cat ${TESTSDIR}/testfiles/simplebridge.json)
+    config2=$(jq '.container_id="aed1ab81e3a5" | .networks.podman.static_ips=["10.88.0.3"]' <<<"$config1")
+
+    run_netavark --config "$NETAVARK_TMPDIR/config" setup $(get_container_netns_path) <<<"$config1"
+    create_container_ns
+    run_netavark --config "$NETAVARK_TMPDIR/config" setup $(get_container_netns_path 1) <<<"$config2"
+
+    run_netavark --config "$NETAVARK_TMPDIR/config" gc
+    assert "$output" == "[]" "no orphaned containers while the namespaces exist"
+
+    kill -9 "${CONTAINER_NS_PIDS[1]}"
+    wait "${CONTAINER_NS_PIDS[1]}" 2>/dev/null || true
+    run_netavark --config "$NETAVARK_TMPDIR/config" gc --dry-run
+    assert_json ".[0].container_id" "aed1ab81e3a5" "orphaned container found"
+    assert_json ".[0].removed" "false" "nothing removed with --dry-run"
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "$output" =~ "10.88.0.0/16" "network rules still there"
+
+    run_netavark --config "$NETAVARK_TMPDIR/config" gc
+    assert_json ".[0].container_id" "aed1ab81e3a5" "orphaned container removed"
+    assert_json ".[0].removed" "true" "orphaned container removed"
+    run_netavark --config "$NETAVARK_TMPDIR/config" gc
+    assert "$output" == "[]" "the orphaned container is only removed once"
+
+    # the bridge is still used by the first container
+    run_in_host_netns ip link show podman0
+    run_in_container_netns ping -c 1 10.88.0.1
+
+    run_netavark --config "$NETAVARK_TMPDIR/config" teardown $(get_container_netns_path) <<<"$config1"
+    assert "$(ls -A $NETAVARK_TMPDIR/config/containers)" == "" "state removed on teardown"
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "$fw_driver - static ip conflict" {
+    run_in_host_netns ip link add conflict0 type veth peer name conflict1
+    run_in_host_netns ip addr add 10.88.0.2/24 dev conflict0
+
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --static-ip-conflict error $(get_container_netns_path)
+    assert_json ".error" "static ip 10.88.0.2 of network podman is already assigned to interface conflict0 on the host" "conflict with a host address"
+    expected_rc=1 run_in_host_netns ip link show podman0
+
+    run_in_host_netns ip addr flush dev conflict0
+    run_in_host_netns ip neigh add 10.88.0.2 lladdr aa:bb:cc:dd:ee:ff dev conflict0 nud permanent
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --static-ip-conflict error $(get_container_netns_path)
+    assert_json ".error" "static ip 10.88.0.2 of network podman is already used by aa:bb:cc:dd:ee:ff on interface conflict0" "conflict with a neighbor"
+
+    # the default only warns
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "setup with a conflict"
+}
+
+@test "$fw_driver - emit script" {
+    config=$(jq '.port_mappings=[{"host_ip":"","container_port":80,"host_port":8080,"range":1,"protocol":"tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup --emit-script $(get_container_netns_path) <<<"$config"
+    script="$output"
+    assert "$script" =~ "# network podman: bridge podman0" "comment with the network"
+    assert "$script" =~ "nsenter --net=\"\\\$NETNS\" ip addr add 10.88.0.2/16 dev eth0" "container address"
+    assert "$script" =~ "iptables -w -t nat -A NETAVARK-HOSTPORT-DNAT" "port forwarding rule"
+
+    # nothing is set up by netavark itself
+    expected_rc=1 run_in_host_netns ip link show podman0
+    expected_rc=1 run_in_container_netns ip link show eth0
+
+    run_in_host_netns sh -c "$script"
+    run_in_container_netns ip -4 addr show eth0
+    assert "$output" =~ "10.88.0.2/16" "address set by the script"
+    run_in_container_netns ping -c 1 10.88.0.1
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "$output" =~ "--dport 8080" "port forwarding rule added by the script"
+
+    # the teardown removes it like a normal setup
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns ip link show podman0
+
+    config=$(jq '.network_info.podman.options.vlan="10"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup --emit-script $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "vlan is not supported with --emit-script" "runtime options are rejected"
+
+    # the addresses of the lease ipam are only looked up, no lease is taken
+    config="$NETAVARK_TMPDIR/config"
+    run_netavark --config "$config" --file ${TESTSDIR}/testfiles/lease.json setup --emit-script $(get_container_netns_path)
+    assert "$output" =~ "ip addr add 10.88.0.2/16 dev eth0" "address of the lease ipam"
+    expected_rc=2 run_helper ls "$config/ipam-lease"
+}
+
+@test "$fw_driver - rate limit published ports" {
+    config=$(jq '.port_mappings=[{"host_ip":"","container_port":80,"host_port":8080,"range":1,"protocol":"tcp"}] |
+        .network_info.podman.options={"rate_limit":"true","rate_limit_rate":"5/minute","rate_limit_burst":"3"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${lines[1]}" =~ "-A NETAVARK_FORWARD -d 10.88.0.2/32 -p tcp -m tcp --dport 80 -m conntrack --ctstate NEW -m hashlimit --hashlimit-above 5/min --hashlimit-burst 3" "rate limit rule first in the chain"
+    assert "${lines[1]}" =~ "-m comment --comment \"rate limit name: podman id: 6ce776ea58b5\" -j DROP" "rate limit rule comment"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "$output" !~ "hashlimit" "rate limit rule removed"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"rate_limit":"true","rate_limit_rate":"10/week"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "invalid rate_limit_rate \"10/week\": must be a number followed by /second, /minute, /hour or /day" "invalid rate"
+}
+
+@test "$fw_driver - interface order" {
+    config=$(jq '.networks.podman.interface_index=1 |
+        .networks.podman2={"interface_name": "eth1", "static_ips": ["10.89.0.2"], "interface_index": 0} |
+        .network_info.podman2=(.network_info.podman | .name="podman2" | .network_interface="podman1" |
+        .subnets=[{"gateway": "10.89.0.1", "subnet": "10.89.0.0/24"}])' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    result="$output"
+    assert_json "$result" ".podman2.interface_index" == "0" "network with the lowest index first"
+    assert_json "$result" ".podman.interface_index" == "1" "network with the higher index second"
+    # the default --max-parallel sets them up in parallel, the interfaces are still created in order
+    run_in_container_netns ip -j link show
+    assert_json '[.[] | select(.ifname != "lo") | .ifname] | join(" ")' "eth1 eth0" "interfaces created in the order of the networks"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns ip link show podman1
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.networks.podman2=.networks.podman' <<<"$config")"
+    assert_json ".error" "interface_index 1 is used by the networks podman and podman2" "duplicate index"
+}
+
+@test "$fw_driver - l2_only bridge" {
+    config=$(jq '.network_info.podman.options={"mode":"l2_only"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    config2=$(jq '.container_id="aed1ab81e3a5"' <<<"$config")
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    result="$output"
+    assert_json "$result" ".podman.bridge" == "podman0" "bridge name in the response"
+    assert_json "$result" ".podman.interfaces.eth0.subnets" == "[]" "no container addresses"
+
+    create_container_ns
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+
+    run_in_host_netns ip -4 addr show podman0
+    assert "$output" == "" "no address on the bridge"
+    run_in_container_netns ip -4 route show
+    assert "$output" == "" "no routes in the container"
+    run_in_host_netns iptables -S
+    assert "$output" !~ "10.88.0.0/16" "no firewall rules"
+
+    # the containers are connected on layer 2
+    run_in_container_netns ip addr add 192.168.10.1/24 dev eth0
+    run_in_container_netns 1 ip addr add 192.168.10.2/24 dev eth0
+    run_in_container_netns 1 ping -c 1 192.168.10.1
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns ip link show podman0
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options.vlan="10"' <<<"$config")"
+    assert_json ".error" "vlan cannot be used with l2_only mode" "vlan with l2_only mode"
+}
+
+@test "$fw_driver - bridge cleanup" {
+    # the bridge created by netavark is removed with the last container
+    run_netavark --config "$NETAVARK_TMPDIR/config" --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert "$(ls $NETAVARK_TMPDIR/config/bridges)" == "podman0" "bridge recorded"
+    run_netavark --config "$NETAVARK_TMPDIR/config" --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    expected_rc=1 run_in_host_netns ip link show podman0
+    assert "$(ls -A $NETAVARK_TMPDIR/config/bridges)" == "" "bridge record removed"
+
+    # a bridge without a record, e.g. created by an older version, is removed as well
+    run_netavark --config "$NETAVARK_TMPDIR/config" --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    rm $NETAVARK_TMPDIR/config/bridges/podman0
+    run_netavark --config "$NETAVARK_TMPDIR/config" --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    expected_rc=1 run_in_host_netns ip link show podman0
+
+    # a bridge created by someone else after ours is kept
+    run_netavark --config "$NETAVARK_TMPDIR/config" --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns ip link del podman0
+    run_in_host_netns ip link add podman0 type bridge
+    run_in_container_netns ip link del eth0
+    run_netavark --config "$NETAVARK_TMPDIR/config" --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_netavark --config "$NETAVARK_TMPDIR/config" --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    run_in_host_netns ip -j link show master podman0
+    assert "$output" == "[]" "no interfaces left on the bridge"
+
+    # unless every unused bridge should be removed
+    config=$(jq '.network_info.podman.options={"bridge_cleanup":"unused"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark --config "$NETAVARK_TMPDIR/config" setup $(get_container_netns_path) <<<"$config"
+    run_netavark --config "$NETAVARK_TMPDIR/config" teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns ip link show podman0
+
+    config=$(jq '.network_info.podman.options={"bridge_cleanup":"never"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark --config "$NETAVARK_TMPDIR/config" setup $(get_container_netns_path) <<<"$config"
+    run_netavark --config "$NETAVARK_TMPDIR/config" teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns ip link show podman0
+}
+
+@test "$fw_driver - packet marking" {
+    config=$(jq '.network_info.podman.options={"dscp":"46","fwmark":"0x10/0xff"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -t mangle -S
+    assert "$output" =~ "-A PREROUTING -s 10.88.0.0/16 -i podman0 -j NETAVARK-MK-" "jump to the mark chain"
+    assert "$output" =~ "-j DSCP --set-dscp 0x2e" "dscp rule"
+    assert "$output" =~ "-j MARK --set-xmark 0x10/0xff" "fwmark rule"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t mangle -S
+    assert "$output" !~ "NETAVARK-MK-" "mark rules removed"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"dscp":"64"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "invalid dscp 64: must be between 0 and 63" "invalid dscp"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"fwmark":"0x2000"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "invalid fwmark 0x2000: the bit 0x2000 is used for the port forwarding" "port forwarding mark bit"
+}
+
+@test "$fw_driver - port forward to a selected container ip" {
+    config=$(jq '.networks.podman.static_ips=["10.88.0.2","10.88.0.3"] |
+        .port_mappings=[{"host_ip":"","container_port":80,"host_port":8080,"range":1,"protocol":"tcp","container_ip":"10.88.0.3"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -t nat -S
+    assert "$output" =~ "--to-destination 10.88.0.3:80" "dnat to the selected address"
+    assert "$output" !~ "--to-destination 10.88.0.2:80" "no dnat to the first address"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.port_mappings[0].container_ip="10.88.0.4"' <<<"$config")"
+    assert_json ".error" "container_ip 10.88.0.4 of port 8080 is not assigned to the container on network podman" "address not assigned"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.port_mappings[0].container_ip="10.99.0.4"' <<<"$config")"
+    assert_json ".error" "container_ip 10.99.0.4 of port 8080 is not in a subnet of the container networks" "address outside of the subnets"
+}
+
+@test "$fw_driver - iptables binary path" {
+    config=$(cat ${TESTSDIR}/testfiles/simplebridge.json)
+    mkdir -p $NETAVARK_TMPDIR/bin
+    ln -s $(command -v iptables) $NETAVARK_TMPDIR/bin/iptables
+    NETAVARK_IPTABLES_PATH=$NETAVARK_TMPDIR/bin/iptables run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t nat -S
+    assert "$output" =~ "NETAVARK-1D8721804F16F" "rules added with the configured binary"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    expected_rc=1 NETAVARK_IPTABLES_PATH=$NETAVARK_TMPDIR/bin/missing run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid NETAVARK_IPTABLES_PATH $NETAVARK_TMPDIR/bin/missing: No such file or directory (os error 2)" "missing binary"
+}
+
+@test "$fw_driver - lease ipam pool" {
+    config="$NETAVARK_TMPDIR/config"
+    first=$(jq '.network_info.podman.ipam_options.pool="flat"' ${TESTSDIR}/testfiles/lease.json)
+    second=$(jq '.container_id="a1b2c3d4e5f6" | .network_info.podman.name="podman2" |
+        .network_info.podman.network_interface="podman1" | .networks.podman2=.networks.podman | del(.networks.podman) |
+        .network_info.podman2=.network_info.podman | del(.network_info.podman)' <<<"$first")
+    run_netavark --config "$config" setup $(get_container_netns_path) <<<"$first"
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "first address of the pool"
+
+    create_container_ns
+    run_netavark --config "$config" setup $(get_container_netns_path 1) <<<"$second"
+    assert_json ".podman2.interfaces.eth0.subnets[0].ipnet" "10.88.0.3/16" "other network of the pool gets the next address"
+    assert "$(cat $config/ipam-lease/pools/flat.json)" =~ "podman2/a1b2c3d4e5f6" "one lease file for the pool"
+
+    run_netavark --config "$config" teardown $(get_container_netns_path 1) <<<"$second"
+    run_netavark --config "$config" teardown $(get_container_netns_path) <<<"$first"
+
+    expected_rc=1 run_netavark --config "$config" setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.ipam_options={"driver":"host-local","pool":"flat"}' <<<"$first")"
+    assert_json ".error" "an address pool requires the lease ipam driver" "pool without lease ipam"
+}
+
+@test "$fw_driver - inspect" {
+    config="$NETAVARK_TMPDIR/config"
+    mapping=$(jq '.port_mappings=[{"host_ip":"","container_port":80,"host_port":8080,"range":1,"protocol":"tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark --config "$config" setup $(get_container_netns_path) <<<"$mapping"
+
+    run_netavark --config "$config" inspect
+    result="$output"
+    assert_json "$result" ".containers[0].container_id" == "6ce776ea58b5" "recorded container"
+    assert_json "$result" ".containers[0].namespace_exists" == "true" "namespace exists"
+    assert_json "$result" ".bridges[0].name" == "podman0" "bridge of the network"
+    assert_json "$result" ".bridges[0].created_by_netavark" == "true" "bridge created by netavark"
+    assert_json "$result" ".port_mappings[0].host_port" == "8080" "port mapping"
+    assert_json "$result" ".port_mappings[0].container_id" == "6ce776ea58b5" "container of the DNAT rule"
+    assert_json "$result" ".port_mappings[0].destination" == "10.88.0.2:80" "DNAT destination"
+    assert_json "$result" '.chains | map(select(.name == "NETAVARK-1D8721804F16F")) | length' == "1" "network chain"
+    assert_json "$result" ".issues" == "[]" "no issues"
+
+    # the chain of a network without container, the missing bridge and DNAT rule are reported
+    run_in_host_netns ip link del podman0
+    run_in_host_netns iptables -t nat -F NETAVARK-DN-1D8721804F16F
+    run_in_host_netns iptables -t nat -N NETAVARK-0123456789ABC
+    run_netavark --config "$config" inspect
+    result="$output"
+    assert_json "$result" ".issues[0]" == "bridge podman0 of network podman does not exist" "missing bridge"
+    assert_json "$result" ".issues[1]" == "port mapping 8080/tcp of container 6ce776ea58b5 has no DNAT rule" "missing DNAT rule"
+    assert_json "$result" ".issues[2]" == "iptables chain NETAVARK-0123456789ABC in table nat does not belong to a network of a recorded container" "orphaned chain"
+
+    run_in_host_netns iptables -t nat -X NETAVARK-0123456789ABC
+    run_netavark --config "$config" teardown $(get_container_netns_path) <<<"$mapping"
+}
+
+@test "$fw_driver - read-only mode" {
+    config="$NETAVARK_TMPDIR/config"
+    expected_rc=1 run_netavark --read-only --config "$config" --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".error" "read-only mode: refused to change a link" "setup is refused"
+    expected_rc=1 run_in_host_netns ip link show podman0
+    expected_rc=2 run_helper ls "$config/containers"
+
+    run_netavark --config "$config" --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    expected_rc=1 run_netavark --read-only --config "$config" --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    assert "$output" =~ "read-only mode: refused to delete a link" "teardown is refused"
+    run_in_host_netns ip link show podman0
+    run_in_host_netns iptables -t nat -S NETAVARK-1D8721804F16F
+
+    run_netavark --read-only --config "$config" inspect
+    assert_json ".containers[0].container_id" == "6ce776ea58b5" "inspect works in read-only mode"
+
+    run_netavark --config "$config" --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+}
+
+@test "$fw_driver - inter-container communication" {
+    modprobe br_netfilter || skip "the br_netfilter module is not available"
+    config=$(jq '.network_info.podman.options={"icc":"false"}' ${TESTSDIR}/testfiles/simplebridge.json)
+
+    # without br_netfilter the rules would not see the traffic of the bridge
+    run_in_host_netns sysctl -w net.bridge.bridge-nf-call-iptables=0
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "icc=false requires net.bridge.bridge-nf-call-iptables=1, load the br_netfilter kernel module and enable the sysctl" "br_netfilter is required"
+    expected_rc=1 run_in_host_netns ip link show podman0
+
+    run_in_host_netns sysctl -w net.bridge.bridge-nf-call-iptables=1
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S FORWARD
+    assert "$output" =~ "-A FORWARD -i podman0 -o podman0 -j NETAVARK-IC-1D8721804F16F" "jump to the icc chain"
+    run_in_host_netns iptables -S NETAVARK-IC-1D8721804F16F
+    assert "${lines[1]}" == "-A NETAVARK-IC-1D8721804F16F -m conntrack --ctstate DNAT -j RETURN" "published ports allowed"
+    assert "${lines[2]}" == "-A NETAVARK-IC-1D8721804F16F -s 10.88.0.0/16 -d 10.88.0.0/16 -j DROP" "container traffic dropped"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S
+    assert "$output" !~ "NETAVARK-IC-" "icc rules removed"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.options={"icc":"false","trusted":"true"}' ${TESTSDIR}/testfiles/simplebridge.json)"
+    assert_json ".error" "icc=false cannot be used together with trusted=true" "icc with trusted"
 }
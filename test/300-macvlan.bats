@@ -332,3 +332,76 @@ EOF
    run_in_container_netns ip -o link show
    assert "${#lines[@]}" == 2 "only two interfaces (lo, eth0) in the netns, the tmp macvlan interface should be gone"
 }
+
+@test "macvlan invalid ipv6_mode" {
+
+   read -r -d '\0' config <<EOF
+{
+   "container_id": "someID",
+   "container_name": "someName",
+   "networks": {
+      "podman": {
+         "static_ips": [
+            "10.88.0.2"
+         ],
+         "interface_name": "eth0"
+      }
+   },
+   "network_info": {
+      "podman": {
+         "name": "podman",
+         "id": "2f259bab93aaaaa2542ba43ef33eb990d0999ee1b9924b557b7be53c0b7a1bb9",
+         "driver": "macvlan",
+         "network_interface": "dummy0",
+         "subnets": [
+            {
+               "subnet": "10.88.0.0/16",
+               "gateway": "10.88.0.1"
+            }
+         ],
+         "ipv6_enabled": false,
+         "internal": false,
+         "dns_enabled": false,
+         "options": {
+            "ipv6_mode": "abc"
+         },
+         "ipam_options": {
+            "driver": "host-local"
+         }
+      }
+   }
+}\0
+EOF
+
+   expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+   assert_json ".error" "invalid ipv6_mode \"abc\"" "ipv6_mode error"
+}
+
+@test "macvlan host routes" {
+    config=$(jq '.network_info.podman.options={"host_routes":"true"}' ${TESTSDIR}/testfiles/macvlan.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip route show 10.88.0.2
+    assert "$output" =~ "10.88.0.2 dev dummy0 proto static scope link" "host route to the container address"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns ip route show 10.88.0.2
+    assert "$output" == "" "host route removed on teardown"
+
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$(jq '.network_info.podman.driver="ipvlan"' <<<"$config")"
+    assert_json ".error" "host_routes is only supported for macvlan networks" "ipvlan is rejected"
+}
+
+@test "macvlan host routes are rolled back when a route fails" {
+    config=$(jq '.network_info.podman.options={"host_routes":"true"} |
+        .network_info.podman.subnets += [{"subnet":"fd10:88::/64"}] |
+        .networks.podman.static_ips += ["fd10:88::2"]' ${TESTSDIR}/testfiles/macvlan.json)
+    # the ipv6 route fails after the ipv4 one was added
+    run_in_host_netns sysctl -w net.ipv6.conf.dummy0.disable_ipv6=1
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "add host route to the container address: Netlink error: Permission denied (os error 13)" "route error"
+
+    run_in_host_netns ip route show 10.88.0.2
+    assert "$output" == "" "added host route removed"
+    expected_rc=1 run_in_container_netns ip link show eth0
+}